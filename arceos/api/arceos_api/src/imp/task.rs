@@ -26,6 +26,8 @@ pub fn ax_exit(_exit_code: i32) -> ! {
 cfg_task! {
     use core::time::Duration;
 
+    pub use axtask::TaskSnapshot as AxTaskSnapshot;
+
     /// A handle to a task.
     pub struct AxTaskHandle {
         inner: axtask::AxTaskRef,
@@ -82,6 +84,10 @@ cfg_task! {
         }
     }
 
+    pub fn ax_task_snapshot() -> alloc::vec::Vec<AxTaskSnapshot> {
+        axtask::task_snapshot()
+    }
+
     pub fn ax_wait_queue_wait(
         wq: &AxWaitQueueHandle,
         until_condition: impl Fn() -> bool,
@@ -121,6 +121,7 @@ pub mod task {
         @cfg "multitask";
         pub type AxTaskHandle;
         pub type AxWaitQueueHandle;
+        pub type AxTaskSnapshot;
     }
 
     define_api! {
@@ -155,6 +156,9 @@ pub mod task {
         pub fn ax_wait_for_exit(task: AxTaskHandle) -> Option<i32>;
         /// Sets the priority of the current task.
         pub fn ax_set_current_priority(prio: isize) -> crate::AxResult;
+        /// Returns the snapshots of all live tasks, including their states,
+        /// names, CPU times and context switch counts.
+        pub fn ax_task_snapshot() -> alloc::vec::Vec<AxTaskSnapshot>;
 
         /// Blocks the current task and put it into the wait queue, until the
         /// given condition becomes true, or the the given duration has elapsed
@@ -48,10 +48,13 @@ typedef struct {{
             "pthread_attr_t",
             "pthread_mutex_t",
             "pthread_mutexattr_t",
+            "cpu_set_t",
             "epoll_event",
             "iovec",
             "clockid_t",
             "rlimit",
+            "rusage",
+            "tms",
             "aibuf",
         ];
         let allow_vars = [
@@ -66,6 +69,7 @@ typedef struct {{
             "EPOLL_CTL_.*",
             "EPOLL.*",
             "RLIMIT_.*",
+            "RUSAGE_.*",
             "EAI_.*",
             "MAXADDRS",
         ];
@@ -11,6 +11,7 @@ use crate::ctypes;
 
 pub const AX_FILE_LIMIT: usize = 1024;
 
+/// A file-like object in the file descriptor table.
 #[allow(dead_code)]
 pub trait FileLike: Send + Sync {
     fn read(&self, buf: &mut [u8]) -> LinuxResult<usize>;
@@ -39,6 +40,8 @@ pub fn get_file_like(fd: c_int) -> LinuxResult<Arc<dyn FileLike>> {
         .ok_or(LinuxError::EBADF)
 }
 
+/// Add a file-like object into the file descriptor table, and return its
+/// `fd`. Return `EMFILE` if it already has the maximum number of files open.
 pub fn add_file_like(f: Arc<dyn FileLike>) -> LinuxResult<c_int> {
     Ok(FD_TABLE.write().add(f).ok_or(LinuxError::EMFILE)? as c_int)
 }
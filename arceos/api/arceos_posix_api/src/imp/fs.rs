@@ -116,6 +116,16 @@ pub fn sys_open(filename: *const c_char, flags: c_int, mode: ctypes::mode_t) ->
     })
 }
 
+/// Get the node of the file indicated by `fd`, e.g., to map it into memory.
+///
+/// The file must be opened for reading, and also for writing if `writable` is
+/// `true`. Return `EACCES` otherwise.
+pub fn get_file_node(fd: c_int, writable: bool) -> LinuxResult<axfs::fops::FileNode> {
+    let file = File::from_fd(fd)?;
+    let node = file.inner.lock().node(writable)?.clone();
+    Ok(node)
+}
+
 /// Set the position of the file indicated by `fd`.
 ///
 /// Return its position after seek.
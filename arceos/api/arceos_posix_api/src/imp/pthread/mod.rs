@@ -94,7 +94,11 @@ impl Pthread {
         }
 
         let thread = unsafe { Box::from_raw(ptr as *mut Pthread) };
-        thread.inner.join();
+        if thread.inner.join().is_none() {
+            // Killed before the thread exits, keep it joinable.
+            let _ = Box::into_raw(thread);
+            return Err(LinuxError::EINTR);
+        }
         let tid = thread.inner.id().as_u64();
         let retval = unsafe { *thread.retval.result.get() };
         TID_TO_PTHREAD.write().remove(&tid);
@@ -1,6 +1,6 @@
 use crate::{ctypes, utils::check_null_mut_ptr};
 
-use axerrno::LinuxResult;
+use axerrno::{LinuxError, LinuxResult};
 use axsync::Mutex;
 
 use core::ffi::c_int;
@@ -20,7 +20,8 @@ impl PthreadMutex {
     }
 
     fn lock(&self) -> LinuxResult {
-        let _guard = ManuallyDrop::new(self.0.lock());
+        let guard = self.0.lock_killable().map_err(|_| LinuxError::EINTR)?;
+        let _guard = ManuallyDrop::new(guard);
         Ok(())
     }
 
@@ -1,6 +1,52 @@
 use crate::ctypes;
+use crate::utils::check_null_mut_ptr;
 use axerrno::LinuxError;
-use core::ffi::c_int;
+use core::ffi::{c_int, c_long};
+use core::time::Duration;
+
+/// CPU times and context switch counts of a thread or the whole process.
+#[derive(Default)]
+pub(crate) struct CpuUsage {
+    pub utime: Duration,
+    pub stime: Duration,
+    pub nvcsw: u64,
+    pub nivcsw: u64,
+}
+
+impl CpuUsage {
+    /// Returns the CPU usage of the calling thread if `thread` is true,
+    /// otherwise the CPU usage of all threads except the idle ones.
+    pub fn get(thread: bool) -> Self {
+        #[cfg(feature = "multitask")]
+        {
+            let mut usage = Self::default();
+            let mut add = |stat: axtask::TaskStat| {
+                usage.utime += stat.user_time;
+                usage.stime += stat.kernel_time;
+                usage.nvcsw += stat.voluntary_switches;
+                usage.nivcsw += stat.involuntary_switches;
+            };
+            if thread {
+                add(axtask::current().stat());
+            } else {
+                axtask::task_snapshot()
+                    .into_iter()
+                    .filter(|t| !t.is_idle)
+                    .for_each(|t| add(t.stat));
+            }
+            usage
+        }
+        #[cfg(not(feature = "multitask"))]
+        {
+            // The only thread has been running in kernel mode since booting.
+            let _ = thread;
+            Self {
+                stime: axhal::time::monotonic_time(),
+                ..Default::default()
+            }
+        }
+    }
+}
 
 /// Get resource limitations
 ///
@@ -49,3 +95,32 @@ pub unsafe fn sys_setrlimit(resource: c_int, rlimits: *mut crate::ctypes::rlimit
         Ok(0)
     })
 }
+
+/// Get resource usage
+///
+/// `RUSAGE_SELF` reports the usage of all threads, and `RUSAGE_THREAD` reports
+/// the usage of the calling thread. There are no child processes, so the usage
+/// of `RUSAGE_CHILDREN` is always zero.
+pub unsafe fn sys_getrusage(who: c_int, usage: *mut ctypes::rusage) -> c_int {
+    debug!("sys_getrusage <= {} {:#x}", who, usage as usize);
+    syscall_body!(sys_getrusage, {
+        check_null_mut_ptr(usage)?;
+        let cpu_usage = if who == ctypes::RUSAGE_SELF as c_int {
+            CpuUsage::get(false)
+        } else if who == ctypes::RUSAGE_THREAD as c_int {
+            CpuUsage::get(true)
+        } else if who == ctypes::RUSAGE_CHILDREN as c_int {
+            CpuUsage::default()
+        } else {
+            return Err(LinuxError::EINVAL);
+        };
+        unsafe {
+            core::ptr::write_bytes(usage, 0, 1);
+            (*usage).ru_utime = cpu_usage.utime.into();
+            (*usage).ru_stime = cpu_usage.stime.into();
+            (*usage).ru_nvcsw = cpu_usage.nvcsw as c_long;
+            (*usage).ru_nivcsw = cpu_usage.nivcsw as c_long;
+        }
+        Ok(0)
+    })
+}
@@ -15,6 +15,8 @@ pub fn sys_sysconf(name: c_int) -> c_long {
             ctypes::_SC_PAGE_SIZE => Ok(PAGE_SIZE_4K),
             // Total physical pages
             ctypes::_SC_PHYS_PAGES => Ok(axconfig::PHYS_MEMORY_SIZE / PAGE_SIZE_4K),
+            // Clock ticks per second
+            ctypes::_SC_CLK_TCK => Ok(super::time::CLK_TCK as usize),
             // Number of processors in use
             ctypes::_SC_NPROCESSORS_ONLN => Ok(axconfig::SMP),
             // Avaliable physical pages
@@ -1,5 +1,10 @@
 use core::ffi::c_int;
 
+use axerrno::{LinuxError, LinuxResult};
+
+use crate::ctypes;
+use crate::utils::{check_null_mut_ptr, check_null_ptr};
+
 /// Relinquish the CPU, and switches to another task.
 ///
 /// For single-threaded configuration (`multitask` feature is disabled), we just
@@ -30,6 +35,99 @@ pub fn sys_getpid() -> c_int {
     )
 }
 
+/// Only the current thread is supported, `pid` must be 0 or the current thread ID.
+fn check_sched_pid(pid: c_int) -> LinuxResult {
+    if pid == 0 || pid == sys_getpid() {
+        Ok(())
+    } else {
+        Err(LinuxError::ESRCH)
+    }
+}
+
+/// Set the CPU affinity mask of a thread.
+///
+/// Only the current thread is supported, `pid` must be 0 or the current thread
+/// ID. CPUs that do not exist are ignored, but at least one existing CPU must be
+/// in `mask`.
+pub unsafe fn sys_sched_setaffinity(
+    pid: c_int,
+    cpusetsize: ctypes::size_t,
+    mask: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!(
+        "sys_sched_setaffinity <= {} {} {:#x}",
+        pid, cpusetsize, mask as usize
+    );
+    syscall_body!(sys_sched_setaffinity, {
+        check_null_ptr(mask)?;
+        check_sched_pid(pid)?;
+        // Only `cpusetsize` bytes are provided by the caller. The bits of the
+        // words are in the byte order of the little-endian targets.
+        let size = (cpusetsize as usize).min(core::mem::size_of::<ctypes::cpu_set_t>());
+        let bytes = unsafe { core::slice::from_raw_parts(mask as *const u8, size) };
+        let is_set = |cpu_id: usize| {
+            bytes
+                .get(cpu_id / 8)
+                .is_some_and(|byte| byte & (1 << (cpu_id % 8)) != 0)
+        };
+
+        #[cfg(feature = "multitask")]
+        {
+            let mut cpumask = axtask::AxCpuMask::new();
+            for cpu_id in 0..axconfig::SMP {
+                cpumask.set(cpu_id, is_set(cpu_id));
+            }
+            if !axtask::set_current_affinity(cpumask) {
+                return Err(LinuxError::EINVAL);
+            }
+        }
+        #[cfg(not(feature = "multitask"))]
+        if !is_set(axhal::cpu::this_cpu_id()) {
+            // The only thread cannot be migrated to other CPUs.
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(0)
+    })
+}
+
+/// Get the CPU affinity mask of a thread.
+///
+/// Only the current thread is supported, `pid` must be 0 or the current thread
+/// ID.
+pub unsafe fn sys_sched_getaffinity(
+    pid: c_int,
+    cpusetsize: ctypes::size_t,
+    mask: *mut ctypes::cpu_set_t,
+) -> c_int {
+    debug!(
+        "sys_sched_getaffinity <= {} {} {:#x}",
+        pid, cpusetsize, mask as usize
+    );
+    syscall_body!(sys_sched_getaffinity, {
+        check_null_mut_ptr(mask)?;
+        check_sched_pid(pid)?;
+        let size = (cpusetsize as usize).min(core::mem::size_of::<ctypes::cpu_set_t>());
+        if size * 8 < axconfig::SMP {
+            return Err(LinuxError::EINVAL);
+        }
+
+        #[cfg(feature = "multitask")]
+        let cpumask = axtask::current().cpumask();
+        let bytes = unsafe { core::slice::from_raw_parts_mut(mask as *mut u8, size) };
+        bytes.fill(0);
+        for cpu_id in 0..axconfig::SMP {
+            #[cfg(feature = "multitask")]
+            let allowed = cpumask.get(cpu_id);
+            #[cfg(not(feature = "multitask"))]
+            let allowed = cpu_id == axhal::cpu::this_cpu_id();
+            if allowed {
+                bytes[cpu_id / 8] |= 1 << (cpu_id % 8);
+            }
+        }
+        Ok(0)
+    })
+}
+
 /// Exit current task
 pub fn sys_exit(exit_code: c_int) -> ! {
     debug!("sys_exit <= {}", exit_code);
@@ -5,6 +5,14 @@ use core::time::Duration;
 use crate::ctypes;
 use crate::ctypes::{CLOCK_MONOTONIC, CLOCK_REALTIME};
 
+/// The number of clock ticks per second, used by `times` and
+/// `sysconf(_SC_CLK_TCK)`.
+pub(crate) const CLK_TCK: u64 = 100;
+
+fn duration_to_clock_ticks(d: Duration) -> ctypes::clock_t {
+    (d.as_nanos() * CLK_TCK as u128 / 1_000_000_000) as ctypes::clock_t
+}
+
 impl From<ctypes::timespec> for Duration {
     fn from(ts: ctypes::timespec) -> Self {
         Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
@@ -90,3 +98,25 @@ pub unsafe fn sys_nanosleep(req: *const ctypes::timespec, rem: *mut ctypes::time
         Ok(0)
     })
 }
+
+/// Get process times
+///
+/// Returns the number of clock ticks since booting. Only the times of the
+/// current process are reported, as there are no child processes.
+pub unsafe fn sys_times(buf: *mut ctypes::tms) -> ctypes::clock_t {
+    debug!("sys_times <= {:#x}", buf as usize);
+    syscall_body!(sys_times, {
+        if !buf.is_null() {
+            let usage = super::resources::CpuUsage::get(false);
+            unsafe {
+                *buf = ctypes::tms {
+                    tms_utime: duration_to_clock_ticks(usage.utime),
+                    tms_stime: duration_to_clock_ticks(usage.stime),
+                    tms_cutime: 0,
+                    tms_cstime: 0,
+                };
+            }
+        }
+        Ok(duration_to_clock_ticks(axhal::time::monotonic_time()))
+    })
+}
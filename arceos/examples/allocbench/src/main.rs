@@ -0,0 +1,80 @@
+//! Benchmark of the global memory allocator.
+//!
+//! Each thread keeps a number of live objects, and repeatedly replaces a
+//! random one of them by a new object of a random small size. Run it with
+//! multiple CPUs and different allocators to compare them:
+//!
+//! ```bash
+//! make A=examples/allocbench SMP=4 FEATURES=alloc-tlsf run
+//! make A=examples/allocbench SMP=4 FEATURES=alloc-slab run
+//! make A=examples/allocbench SMP=4 FEATURES=alloc-buddy run
+//! make A=examples/allocbench SMP=4 FEATURES=alloc-tlsf,alloc-percpu-cache run
+//! ```
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use std::thread;
+use std::time::Instant;
+
+const NUM_THREADS: usize = 4;
+const NUM_LIVE_OBJECTS: usize = 256;
+const NUM_ROUNDS: usize = 200_000;
+const MAX_OBJECT_SIZE: usize = 1024;
+
+/// A xorshift pseudo-random number generator.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+fn worker(id: usize) -> u64 {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d ^ (id as u64 + 1));
+    let mut objects: Vec<Option<Box<[u8]>>> = (0..NUM_LIVE_OBJECTS).map(|_| None).collect();
+    let start = Instant::now();
+    for i in 0..NUM_ROUNDS {
+        let size = rng.next() % MAX_OBJECT_SIZE + 1;
+        let mut object = alloc::vec![0u8; size].into_boxed_slice();
+        object[size - 1] = i as u8;
+        objects[rng.next() % NUM_LIVE_OBJECTS] = Some(object);
+    }
+    drop(objects);
+    start.elapsed().as_micros() as u64
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    println!("Running allocator benchmark with {} threads...", NUM_THREADS);
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..NUM_THREADS)
+        .map(|id| thread::spawn(move || worker(id)))
+        .collect();
+    for (id, worker) in workers.into_iter().enumerate() {
+        let us = worker.join().unwrap();
+        println!("  thread {}: {} rounds in {} us", id, NUM_ROUNDS, us);
+    }
+    let elapsed = start.elapsed();
+
+    let total_ops = (NUM_THREADS * NUM_ROUNDS * 2) as u128;
+    println!(
+        "{} allocations and frees in {:?}, {} ops/ms",
+        total_ops,
+        elapsed,
+        total_ops * 1000 / elapsed.as_micros().max(1)
+    );
+    println!("Allocator benchmark run OK!");
+}
@@ -0,0 +1,133 @@
+//! Tests of the copy-on-write address space cloning ([`AddrSpace::clone_cow`]).
+
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+use crate::{query, read_byte, RW};
+
+const BASE: VirtAddr = VirtAddr::from_usize(0x1000_0000);
+const NUM_PAGES: usize = 4;
+
+fn is_populated(aspace: &AddrSpace, vaddr: VirtAddr) -> bool {
+    aspace
+        .page_table()
+        .query(vaddr)
+        .is_ok_and(|(_, flags, _)| !flags.is_empty())
+}
+
+/// Fills each page of the parent with its index, and forks the child.
+///
+/// If `populate` is `false`, the last page is not populated before the fork.
+fn fork(populate: bool) -> (AddrSpace, AddrSpace) {
+    let mut parent = axmm::new_user_aspace().unwrap();
+    parent
+        .map_alloc(BASE, NUM_PAGES * PAGE_SIZE_4K, RW, populate)
+        .unwrap();
+    for i in 0..NUM_PAGES {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        if !populate {
+            if i == NUM_PAGES - 1 {
+                break;
+            }
+            assert!(parent.handle_page_fault(vaddr, MappingFlags::WRITE));
+        }
+        parent.write(vaddr, &[i as u8; PAGE_SIZE_4K]).unwrap();
+    }
+    let child = parent.clone_cow().unwrap();
+    (parent, child)
+}
+
+pub fn test_fork() {
+    let (mut parent, mut child) = fork(true);
+
+    // The frames are shared read-only.
+    for i in 0..NUM_PAGES {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        let (frame, flags, _) = query(&parent, vaddr);
+        let (child_frame, child_flags, _) = query(&child, vaddr);
+        assert_eq!(frame, child_frame);
+        assert_eq!(flags, RW - MappingFlags::WRITE);
+        assert_eq!(child_flags, RW - MappingFlags::WRITE);
+        assert_eq!(read_byte(&child, vaddr), i as u8);
+    }
+
+    // A write by the parent copies the frame, the child sees the old data.
+    let (orig_frame, ..) = query(&parent, BASE);
+    parent.write(BASE, b"parent").unwrap();
+    let (parent_frame, parent_flags, _) = query(&parent, BASE);
+    assert_ne!(parent_frame, orig_frame);
+    assert_eq!(parent_flags, RW);
+    assert_eq!(query(&child, BASE).0, orig_frame);
+    assert_eq!(read_byte(&parent, BASE), b'p');
+    assert_eq!(read_byte(&child, BASE), 0);
+    assert_eq!(read_byte(&child, BASE + PAGE_SIZE_4K - 1), 0);
+
+    // The child is the last owner of the frame, which is reused in place.
+    child.write(BASE, b"child").unwrap();
+    let (child_frame, child_flags, _) = query(&child, BASE);
+    assert_eq!(child_frame, orig_frame);
+    assert_eq!(child_flags, RW);
+    assert_eq!(read_byte(&child, BASE), b'c');
+    assert_eq!(read_byte(&parent, BASE), b'p');
+
+    // A write fault by the child copies the frame too.
+    let vaddr = BASE + PAGE_SIZE_4K;
+    let (orig_frame, ..) = query(&parent, vaddr);
+    assert!(child.handle_page_fault(vaddr, MappingFlags::WRITE));
+    let (child_frame, child_flags, _) = query(&child, vaddr);
+    assert_ne!(child_frame, orig_frame);
+    assert_eq!(child_flags, RW);
+    assert_eq!(read_byte(&child, vaddr), 1);
+    assert!(parent.handle_page_fault(vaddr, MappingFlags::WRITE));
+    assert_eq!(query(&parent, vaddr).0, orig_frame);
+
+    // A fault of an access not allowed by the area is not handled.
+    let vaddr = BASE + 2 * PAGE_SIZE_4K;
+    assert!(!child.handle_page_fault(vaddr, MappingFlags::EXECUTE));
+
+    // The shared frames are released by the last owner.
+    parent.clear().unwrap();
+    for i in 0..NUM_PAGES {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        assert_eq!(
+            read_byte(&child, vaddr),
+            if i == 0 { b'c' } else { i as u8 }
+        );
+    }
+    child.clear().unwrap();
+}
+
+pub fn test_lazy_pages() {
+    let (mut parent, mut child) = fork(false);
+    for i in 0..NUM_PAGES - 1 {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        assert_eq!(query(&parent, vaddr).0, query(&child, vaddr).0);
+        assert_eq!(read_byte(&child, vaddr), i as u8);
+    }
+
+    // The page not populated yet is allocated separately on demand.
+    let vaddr = BASE + (NUM_PAGES - 1) * PAGE_SIZE_4K;
+    assert!(!is_populated(&child, vaddr));
+    assert!(child.handle_page_fault(vaddr, MappingFlags::WRITE));
+    assert!(!is_populated(&parent, vaddr));
+    assert!(parent.handle_page_fault(vaddr, MappingFlags::READ));
+    assert_ne!(query(&parent, vaddr).0, query(&child, vaddr).0);
+
+    // The frame is shared by three address spaces, and kept until the last
+    // one releases it.
+    let mut grandchild = child.clone_cow().unwrap();
+    let vaddr = BASE + PAGE_SIZE_4K;
+    let (frame, ..) = query(&parent, vaddr);
+    assert_eq!(query(&grandchild, vaddr).0, frame);
+    child.clear().unwrap();
+    assert_eq!(read_byte(&grandchild, vaddr), 1);
+    assert!(parent.handle_page_fault(vaddr, MappingFlags::WRITE));
+    assert_ne!(query(&parent, vaddr).0, frame);
+    assert!(grandchild.handle_page_fault(vaddr, MappingFlags::WRITE));
+    assert_eq!(query(&grandchild, vaddr).0, frame);
+    assert_eq!(read_byte(&grandchild, vaddr), 1);
+
+    parent.clear().unwrap();
+    grandchild.clear().unwrap();
+}
@@ -0,0 +1,85 @@
+//! Tests of the operations on a part of the huge pages, which split them.
+
+use axhal::paging::{MappingFlags, PageSize};
+use axmm::AddrSpace;
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use crate::{query, read_byte, RW};
+
+const BASE: VirtAddr = VirtAddr::from_usize(0x1000_0000);
+const HUGE_SIZE: usize = 0x20_0000;
+const NUM_HUGE_PAGES: usize = 2;
+
+/// Maps the populated huge pages, and returns their frames.
+fn map_huge(aspace: &mut AddrSpace) -> [PhysAddr; NUM_HUGE_PAGES] {
+    aspace
+        .map_alloc(BASE, NUM_HUGE_PAGES * HUGE_SIZE, RW, true)
+        .unwrap();
+    core::array::from_fn(|i| {
+        let (frame, flags, page_size) = query(aspace, BASE + i * HUGE_SIZE);
+        assert_eq!(flags, RW);
+        assert_eq!(page_size, PageSize::Size2M);
+        frame
+    })
+}
+
+pub fn test_protect_4k_page() {
+    let mut aspace = axmm::new_user_aspace().unwrap();
+    let frames = map_huge(&mut aspace);
+    let page = BASE + 3 * PAGE_SIZE_4K;
+    aspace.write(page, b"huge").unwrap();
+
+    let ro = RW - MappingFlags::WRITE;
+    aspace.protect(page, PAGE_SIZE_4K, ro).unwrap();
+
+    // Split into 4K pages of the same frame, only the protected one changes.
+    for i in 0..HUGE_SIZE / PAGE_SIZE_4K {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        let (frame, flags, page_size) = query(&aspace, vaddr);
+        assert_eq!(frame, frames[0] + i * PAGE_SIZE_4K);
+        assert_eq!(flags, if vaddr == page { ro } else { RW });
+        assert_eq!(page_size, PageSize::Size4K);
+    }
+    assert_eq!(read_byte(&aspace, page), b'h');
+    // The other huge page is not affected.
+    assert_eq!(query(&aspace, BASE + HUGE_SIZE).2, PageSize::Size2M);
+
+    // A write fault on the protected page is not handled, while a fault on a
+    // mapped page, e.g., raced with the splitting, is.
+    assert!(!aspace.handle_page_fault(page, MappingFlags::WRITE));
+    assert!(aspace.handle_page_fault(page + PAGE_SIZE_4K, MappingFlags::WRITE));
+}
+
+pub fn test_unmap_part() {
+    let mut aspace = axmm::new_user_aspace().unwrap();
+    let frames = map_huge(&mut aspace);
+    let before = BASE + HUGE_SIZE - 3 * PAGE_SIZE_4K;
+    let after = BASE + HUGE_SIZE + PAGE_SIZE_4K;
+    aspace.write(before, b"before").unwrap();
+    aspace.write(after, b"after").unwrap();
+
+    // Unmap the last 2 pages of the first huge page and the first page of the
+    // second one.
+    let start = before + PAGE_SIZE_4K;
+    aspace.unmap(start, 3 * PAGE_SIZE_4K).unwrap();
+    for i in 0..3 {
+        assert!(aspace.page_table().query(start + i * PAGE_SIZE_4K).is_err());
+    }
+
+    // The rest are kept, and split into 4K pages of the same frames.
+    assert_eq!(query(&aspace, BASE), (frames[0], RW, PageSize::Size4K));
+    assert_eq!(
+        query(&aspace, before),
+        (
+            frames[0] + HUGE_SIZE - 3 * PAGE_SIZE_4K,
+            RW,
+            PageSize::Size4K
+        )
+    );
+    assert_eq!(
+        query(&aspace, after),
+        (frames[1] + PAGE_SIZE_4K, RW, PageSize::Size4K)
+    );
+    assert_eq!(read_byte(&aspace, before), b'b');
+    assert_eq!(read_byte(&aspace, after), b'a');
+}
@@ -0,0 +1,77 @@
+//! In-kernel tests of the virtual memory management ([`axmm`]).
+//!
+//! The tests create their own user address spaces and access them through the
+//! kernel, so no user program is needed:
+//!
+//! ```bash
+//! make A=examples/mmtest run
+//! ```
+//!
+//! Swapping is tested on the RAM disk, which is the only block device.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+extern crate alloc;
+
+mod cow;
+mod huge;
+mod shm;
+mod swap;
+
+use axhal::paging::{MappingFlags, PageSize};
+use axmm::AddrSpace;
+use memory_addr::{PhysAddr, VirtAddr};
+
+/// The flags of the user data mappings.
+const RW: MappingFlags = MappingFlags::READ
+    .union(MappingFlags::WRITE)
+    .union(MappingFlags::USER);
+
+/// Returns the frame, flags and page size mapped at `vaddr`.
+fn query(aspace: &AddrSpace, vaddr: VirtAddr) -> (PhysAddr, MappingFlags, PageSize) {
+    aspace
+        .page_table()
+        .query(vaddr)
+        .unwrap_or_else(|e| panic!("{:#x} is not mapped: {:?}", vaddr, e))
+}
+
+/// Reads the byte at `vaddr` through the kernel.
+fn read_byte(aspace: &AddrSpace, vaddr: VirtAddr) -> u8 {
+    let mut buf = [0];
+    aspace.read(vaddr, &mut buf).unwrap();
+    buf[0]
+}
+
+/// Returns the number of free pages, after running `f` once so that the heap
+/// has grown enough for it.
+fn free_pages_after_warmup(f: impl Fn()) -> usize {
+    f();
+    axalloc::global_allocator().available_pages()
+}
+
+fn run_test(name: &str, f: impl Fn()) {
+    println!("test {} ...", name);
+    let free_pages = free_pages_after_warmup(&f);
+    f();
+    // All the frames and page tables are released.
+    assert_eq!(axalloc::global_allocator().available_pages(), free_pages);
+    println!("test {} ok", name);
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    println!("Running memory management tests...");
+    run_test("cow_fork", cow::test_fork);
+    run_test("cow_lazy_pages", cow::test_lazy_pages);
+    run_test("huge_protect_4k_page", huge::test_protect_4k_page);
+    run_test("huge_unmap_part", huge::test_unmap_part);
+    run_test("shm_open_unlink", shm::test_open_unlink);
+    run_test("shm_map_two_aspaces", shm::test_map_two_aspaces);
+    run_test("shm_read_only", shm::test_read_only);
+    run_test("swap_out_in", swap::test_swap_out_in);
+    println!("Memory management tests run OK!");
+}
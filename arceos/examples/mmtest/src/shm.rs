@@ -0,0 +1,126 @@
+//! Tests of the shared memory objects ([`SharedMemory`]).
+
+use alloc::sync::Arc;
+
+use axerrno::AxError;
+use axhal::paging::MappingFlags;
+use axmm::SharedMemory;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+use crate::{query, read_byte, RW};
+
+const BASE: VirtAddr = VirtAddr::from_usize(0x1000_0000);
+const OTHER_BASE: VirtAddr = VirtAddr::from_usize(0x2000_0000);
+const NUM_PAGES: usize = 2;
+const NAME: &str = "mmtest";
+
+pub fn test_open_unlink() {
+    let shm = SharedMemory::open(NAME, true, false).unwrap();
+    assert_eq!(shm.size(), 0);
+    shm.resize(NUM_PAGES * PAGE_SIZE_4K).unwrap();
+
+    // The same object is opened by name until it is unlinked.
+    let opened = SharedMemory::open(NAME, false, false).unwrap();
+    assert!(Arc::ptr_eq(&shm, &opened));
+    assert_eq!(opened.size(), NUM_PAGES * PAGE_SIZE_4K);
+    assert!(matches!(
+        SharedMemory::open(NAME, true, true),
+        Err(AxError::AlreadyExists)
+    ));
+    drop(opened);
+
+    let mut aspace = axmm::new_user_aspace().unwrap();
+    aspace
+        .map_shared(BASE, NUM_PAGES * PAGE_SIZE_4K, RW, shm.clone(), 0)
+        .unwrap();
+    aspace.write(BASE, b"shared").unwrap();
+
+    SharedMemory::unlink(NAME).unwrap();
+    assert!(matches!(
+        SharedMemory::open(NAME, false, false),
+        Err(AxError::NotFound)
+    ));
+    assert!(matches!(SharedMemory::unlink(NAME), Err(AxError::NotFound)));
+
+    // The object is kept while it is still mapped.
+    let weak = Arc::downgrade(&shm);
+    drop(shm);
+    assert!(weak.upgrade().is_some());
+    let mut buf = [0; 6];
+    aspace.read(BASE, &mut buf).unwrap();
+    assert_eq!(&buf, b"shared");
+
+    // A new object is created with the same name.
+    let shm = SharedMemory::open(NAME, true, true).unwrap();
+    assert!(!Arc::ptr_eq(&shm, &weak.upgrade().unwrap()));
+    SharedMemory::unlink(NAME).unwrap();
+    drop(shm);
+
+    // Dropped after it is unmapped.
+    drop(aspace);
+    assert!(weak.upgrade().is_none());
+}
+
+pub fn test_map_two_aspaces() {
+    let shm = SharedMemory::new(NUM_PAGES * PAGE_SIZE_4K).unwrap();
+    let weak = Arc::downgrade(&shm);
+
+    // Mapped at different addresses, and the second page only in the other.
+    let mut aspace = axmm::new_user_aspace().unwrap();
+    let mut other = axmm::new_user_aspace().unwrap();
+    aspace
+        .map_shared(BASE, NUM_PAGES * PAGE_SIZE_4K, RW, shm.clone(), 0)
+        .unwrap();
+    other
+        .map_shared(OTHER_BASE, PAGE_SIZE_4K, RW, shm.clone(), PAGE_SIZE_4K)
+        .unwrap();
+    let page = BASE + PAGE_SIZE_4K;
+    assert_eq!(query(&aspace, page).0, query(&other, OTHER_BASE).0);
+    assert_ne!(query(&aspace, BASE).0, query(&other, OTHER_BASE).0);
+
+    // The frames are filled with zero, and the writes are seen by each other.
+    assert_eq!(read_byte(&other, OTHER_BASE), 0);
+    aspace.write(page, &[1]).unwrap();
+    assert_eq!(read_byte(&other, OTHER_BASE), 1);
+    other.write(OTHER_BASE + 1, &[2]).unwrap();
+    assert_eq!(read_byte(&aspace, page + 1), 2);
+    // Never copied on write, unlike the private mappings.
+    assert_eq!(query(&aspace, page).0, query(&other, OTHER_BASE).0);
+
+    // Out of the object.
+    assert!(other
+        .map_shared(
+            BASE,
+            PAGE_SIZE_4K,
+            RW,
+            shm.clone(),
+            NUM_PAGES * PAGE_SIZE_4K
+        )
+        .is_err());
+
+    // Like `IPC_RMID` while attached, the frames outlive the last reference
+    // until the last mapping is removed.
+    drop(shm);
+    aspace.unmap(BASE, NUM_PAGES * PAGE_SIZE_4K).unwrap();
+    assert!(weak.upgrade().is_some());
+    assert_eq!(read_byte(&other, OTHER_BASE + 1), 2);
+    other.unmap(OTHER_BASE, PAGE_SIZE_4K).unwrap();
+    assert!(weak.upgrade().is_none());
+}
+
+pub fn test_read_only() {
+    let shm = SharedMemory::new(PAGE_SIZE_4K).unwrap();
+    let mut aspace = axmm::new_user_aspace().unwrap();
+    let mut other = axmm::new_user_aspace().unwrap();
+    let ro = RW - MappingFlags::WRITE;
+    aspace
+        .map_shared(BASE, PAGE_SIZE_4K, RW, shm.clone(), 0)
+        .unwrap();
+    other.map_shared(BASE, PAGE_SIZE_4K, ro, shm, 0).unwrap();
+    assert_eq!(query(&other, BASE).1, ro);
+
+    // A write fault on the read-only mapping is not handled.
+    assert!(!other.handle_page_fault(BASE, MappingFlags::WRITE));
+    aspace.write(BASE, &[3]).unwrap();
+    assert_eq!(read_byte(&other, BASE), 3);
+}
@@ -0,0 +1,47 @@
+//! Tests of swapping the lazy allocation mappings ([`axmm::swap`]), to the RAM
+//! disk set up by the runtime.
+
+use axhal::paging::MappingFlags;
+use axmm::swap;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+use crate::{read_byte, RW};
+
+const BASE: VirtAddr = VirtAddr::from_usize(0x1000_0000);
+const NUM_PAGES: usize = 8;
+
+pub fn test_swap_out_in() {
+    assert!(swap::total_pages() >= NUM_PAGES, "swap is not enabled");
+    let used = swap::used_pages();
+    let mut aspace = axmm::new_user_aspace().unwrap();
+    aspace
+        .map_alloc(BASE, NUM_PAGES * PAGE_SIZE_4K, RW, false)
+        .unwrap();
+    for i in 0..NUM_PAGES {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        assert!(aspace.handle_page_fault(vaddr, MappingFlags::WRITE));
+        aspace.write(vaddr, &[i as u8 + 1; PAGE_SIZE_4K]).unwrap();
+    }
+
+    // Made inactive by the first scan, and swapped out by the second.
+    assert_eq!(aspace.reclaim(NUM_PAGES), NUM_PAGES);
+    assert_eq!(swap::used_pages(), used + NUM_PAGES);
+    for i in 0..NUM_PAGES {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        let (_, flags, _) = aspace.page_table().query(vaddr).unwrap();
+        assert!(flags.is_empty());
+    }
+
+    // Read back on the page faults.
+    for i in 0..NUM_PAGES {
+        let vaddr = BASE + i * PAGE_SIZE_4K;
+        assert!(aspace.handle_page_fault(vaddr, MappingFlags::READ));
+        assert_eq!(read_byte(&aspace, vaddr + PAGE_SIZE_4K - 1), i as u8 + 1);
+    }
+    assert_eq!(swap::used_pages(), used);
+
+    // The slots are released with the address space.
+    assert_eq!(aspace.reclaim(NUM_PAGES), NUM_PAGES);
+    drop(aspace);
+    assert_eq!(swap::used_pages(), used);
+}
@@ -26,6 +26,8 @@ const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("mkdir", do_mkdir),
     ("pwd", do_pwd),
     ("rm", do_rm),
+    #[cfg(all(feature = "axstd", feature = "multitask"))]
+    ("top", do_top),
     ("uname", do_uname),
 ];
 
@@ -260,6 +262,54 @@ fn do_uname(_args: &str) {
     );
 }
 
+#[cfg(all(feature = "axstd", feature = "multitask"))]
+fn do_top(args: &str) {
+    use std::os::arceos::api::task::{ax_task_snapshot, AxTaskSnapshot};
+    use std::time::{Duration, Instant};
+
+    let secs = if args.is_empty() {
+        1
+    } else if let Ok(secs) = args.parse::<u64>() {
+        secs
+    } else {
+        print_err!("top", args, "invalid interval");
+        return;
+    };
+
+    // Sample the CPU time of each task twice to calculate the CPU usage.
+    let cpu_time = |t: &AxTaskSnapshot| t.stat.user_time + t.stat.kernel_time;
+    let before = ax_task_snapshot();
+    let start = Instant::now();
+    std::thread::sleep(Duration::from_secs(secs));
+    let after = ax_task_snapshot();
+    let elapsed = start.elapsed().as_nanos().max(1);
+
+    println!(
+        "{:>5} {:>3} {:<8} {:>6} {:>10} {:>10} {:>8} {:>8}  NAME",
+        "ID", "CPU", "STATE", "%CPU", "UTIME(ms)", "STIME(ms)", "NVCSW", "NIVCSW"
+    );
+    for task in &after {
+        let prev = before
+            .iter()
+            .find(|t| t.id == task.id)
+            .map_or(Duration::ZERO, cpu_time);
+        let usage = (cpu_time(task).saturating_sub(prev)).as_nanos() * 1000 / elapsed;
+        println!(
+            "{:>5} {:>3} {:<8} {:>4}.{} {:>10} {:>10} {:>8} {:>8}  {}",
+            task.id.as_u64(),
+            task.stat.last_cpu,
+            std::format!("{:?}", task.state),
+            usage / 10,
+            usage % 10,
+            task.stat.user_time.as_millis(),
+            task.stat.kernel_time.as_millis(),
+            task.stat.voluntary_switches,
+            task.stat.involuntary_switches,
+            task.name,
+        );
+    }
+}
+
 fn do_help(_args: &str) {
     println!("Available commands:");
     for (name, _) in CMD_TABLE {
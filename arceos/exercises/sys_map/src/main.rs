@@ -11,6 +11,7 @@ extern crate axlog;
 mod task;
 mod syscall;
 mod loader;
+mod shm;
 
 use axstd::io;
 use axhal::paging::MappingFlags;
@@ -22,17 +23,31 @@ use alloc::string::String;
 use alloc::collections::BTreeMap;
 use axmm::AddrSpace;
 use loader::load_user_app;
+use axtask::TaskExtRef;
+use axhal::trap::{register_trap_handler, PAGE_FAULT};
 
 const USER_STACK_SIZE: usize = 0x10000;
 const KERNEL_STACK_SIZE: usize = 0x40000; // 256 KiB
 
+/// The user apps to run in order, each one should exit with 0.
+const USER_APPS: &[&str] = &["/sbin/mapfile", "/sbin/mmap_test", "/sbin/shm_test"];
+
 #[cfg_attr(feature = "axstd", no_mangle)]
 fn main() {
+    for app in USER_APPS {
+        let exit_code = run_user_app(app);
+        ax_println!("{} exit [{:?}]", app, exit_code);
+        assert_eq!(exit_code, Some(0), "{} failed!", app);
+    }
+    ax_println!("monolithic kernel exit [{:?}] normally!", Some(0));
+}
+
+fn run_user_app(path: &str) -> Option<i32> {
     // A new address space for user app.
     let mut uspace = axmm::new_user_aspace().unwrap();
 
     // Load user app binary file into address space.
-    let entry = match load_user_app("/sbin/mapfile", &mut uspace) {
+    let entry = match load_user_app(path, &mut uspace) {
         Ok(e) => e,
         Err(err) => panic!("Cannot load app! {:?}", err),
     };
@@ -49,8 +64,7 @@ fn main() {
     );
 
     // Wait for user process to exit ...
-    let exit_code = user_task.join();
-    ax_println!("monolithic kernel exit [{:?}] normally!", exit_code);
+    user_task.join()
 }
 
 fn init_user_stack(uspace: &mut AddrSpace, populating: bool) -> io::Result<VirtAddr> {
@@ -80,3 +94,23 @@ fn init_user_stack(uspace: &mut AddrSpace, populating: bool) -> io::Result<VirtA
 
     Ok(ustack_pointer.into())
 }
+
+#[register_trap_handler(PAGE_FAULT)]
+fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool) -> bool {
+    if is_user {
+        axtask::current().account_kernel_entry();
+        if !axtask::current()
+            .task_ext()
+            .aspace
+            .lock()
+            .handle_page_fault(vaddr, access_flags)
+        {
+            ax_println!("{}: segmentation fault, exit!", axtask::current().id_name());
+            axtask::exit(-1);
+        }
+        axtask::current().account_user_entry();
+        true
+    } else {
+        false
+    }
+}
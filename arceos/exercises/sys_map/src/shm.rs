@@ -0,0 +1,195 @@
+//! POSIX and System V shared memory, backed by [`SharedMemory`].
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::ffi::c_int;
+
+use arceos_posix_api::{self as api, ctypes, FileLike};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::mem::VirtAddr;
+use axhal::paging::MappingFlags;
+use axio::PollState;
+use axmm::SharedMemory;
+use axsync::Mutex;
+use axtask::{current, TaskExtRef};
+use memory_addr::{align_up_4k, MemoryAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+/// The directory of the POSIX shared memory objects.
+pub const SHM_DIR: &str = "/dev/shm/";
+
+const IPC_PRIVATE: i32 = 0;
+const IPC_CREAT: i32 = 0o1000;
+const IPC_EXCL: i32 = 0o2000;
+const IPC_RMID: i32 = 0;
+const SHM_RDONLY: i32 = 0o10000;
+
+/// The file opened by `shm_open`.
+pub struct ShmFile {
+    shm: Arc<SharedMemory>,
+}
+
+impl ShmFile {
+    /// Returns the shared memory object of the file.
+    pub fn shm(&self) -> &Arc<SharedMemory> {
+        &self.shm
+    }
+}
+
+impl FileLike for ShmFile {
+    fn read(&self, _buf: &mut [u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        let st_mode = 0o100000 | 0o600u32; // S_IFREG | rw-------
+        Ok(ctypes::stat {
+            st_ino: 1,
+            st_nlink: 1,
+            st_mode,
+            st_uid: 1000,
+            st_gid: 1000,
+            st_size: self.shm.size() as _,
+            st_blksize: PAGE_SIZE_4K as _,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: true,
+            writable: true,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
+
+/// Returns the name of the shared memory object at `path`, if it is in
+/// [`SHM_DIR`].
+pub fn shm_name(path: &str) -> Option<&str> {
+    path.strip_prefix(SHM_DIR).filter(|name| !name.is_empty())
+}
+
+/// Opens the shared memory object `name`, and returns the new file descriptor.
+pub fn shm_open(name: &str, flags: c_int) -> LinuxResult<c_int> {
+    let flags = flags as u32;
+    let shm = SharedMemory::open(
+        name,
+        flags & ctypes::O_CREAT != 0,
+        flags & ctypes::O_CREAT != 0 && flags & ctypes::O_EXCL != 0,
+    )?;
+    if flags & ctypes::O_TRUNC != 0 {
+        shm.resize(0)?;
+    }
+    api::add_file_like(Arc::new(ShmFile { shm }))
+}
+
+/// Returns the shared memory object opened as `fd`, if it is.
+pub fn shm_of_fd(fd: c_int) -> LinuxResult<Option<Arc<SharedMemory>>> {
+    let file = api::get_file_like(fd)?;
+    Ok(file
+        .into_any()
+        .downcast::<ShmFile>()
+        .ok()
+        .map(|f| f.shm().clone()))
+}
+
+struct SysVTable {
+    segments: BTreeMap<i32, (i32, Arc<SharedMemory>)>,
+    next_id: i32,
+}
+
+static SYSV_SEGMENTS: Mutex<SysVTable> = Mutex::new(SysVTable {
+    segments: BTreeMap::new(),
+    next_id: 1,
+});
+
+pub fn sys_shmget(key: i32, size: usize, shmflg: i32) -> LinuxResult<isize> {
+    let mut table = SYSV_SEGMENTS.lock();
+    if key != IPC_PRIVATE {
+        let found = table.segments.iter().find(|(_, (k, _))| *k == key);
+        if let Some((&id, (_, shm))) = found {
+            if shmflg & IPC_CREAT != 0 && shmflg & IPC_EXCL != 0 {
+                return Err(LinuxError::EEXIST);
+            }
+            if size > shm.size() {
+                return Err(LinuxError::EINVAL);
+            }
+            return Ok(id as _);
+        }
+        if shmflg & IPC_CREAT == 0 {
+            return Err(LinuxError::ENOENT);
+        }
+    }
+    if size == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let shm = SharedMemory::new(size)?;
+    let id = table.next_id;
+    table.next_id += 1;
+    table.segments.insert(id, (key, shm));
+    Ok(id as _)
+}
+
+pub fn sys_shmat(shmid: i32, addr: usize, shmflg: i32) -> LinuxResult<isize> {
+    let shm = match SYSV_SEGMENTS.lock().segments.get(&shmid) {
+        Some((_, shm)) => shm.clone(),
+        None => return Err(LinuxError::EINVAL),
+    };
+    let size = align_up_4k(shm.size());
+    let mut flags = MappingFlags::READ | MappingFlags::USER;
+    if shmflg & SHM_RDONLY == 0 {
+        flags |= MappingFlags::WRITE;
+    }
+
+    let curr = current();
+    let mut aspace = curr.task_ext().aspace.lock();
+    let start = if addr != 0 {
+        let start = VirtAddr::from(addr);
+        if !start.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        start
+    } else {
+        let limit = VirtAddrRange::new(aspace.base() + PAGE_SIZE_4K, aspace.end());
+        aspace
+            .find_free_area(aspace.base(), size, limit)
+            .ok_or(LinuxError::ENOMEM)?
+    };
+    aspace.map_shared(start, size, flags, shm, 0)?;
+    Ok(start.as_usize() as _)
+}
+
+pub fn sys_shmdt(addr: usize) -> LinuxResult<isize> {
+    let start = VirtAddr::from(addr);
+    let curr = current();
+    let mut aspace = curr.task_ext().aspace.lock();
+    match aspace.find_area(start) {
+        Some(area) if area.start == start => {
+            aspace.unmap(start, area.size())?;
+            Ok(0)
+        }
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+pub fn sys_shmctl(shmid: i32, cmd: i32, _buf: usize) -> LinuxResult<isize> {
+    match cmd {
+        // The segment is dropped after it is detached from all address spaces.
+        IPC_RMID => match SYSV_SEGMENTS.lock().segments.remove(&shmid) {
+            Some(_) => Ok(0),
+            None => Err(LinuxError::EINVAL),
+        },
+        _ => Err(LinuxError::EINVAL),
+    }
+}
@@ -1,15 +1,21 @@
 #![allow(dead_code)]
 
-use core::ffi::{c_void, c_char, c_int};
+use core::ffi::{c_void, c_char, c_int, CStr};
 use axhal::arch::TrapFrame;
 use axhal::trap::{register_trap_handler, SYSCALL};
 use axerrno::LinuxError;
 use axtask::current;
 use axtask::TaskExtRef;
 use axhal::paging::MappingFlags;
+use axhal::mem::VirtAddr;
+use memory_addr::{align_up_4k, is_aligned_4k, MemoryAddr, VirtAddrRange, PAGE_SIZE_4K};
 use arceos_posix_api as api;
+use axmm::SharedMemory;
+use crate::shm;
 
 const SYS_IOCTL: usize = 29;
+const SYS_UNLINKAT: usize = 35;
+const SYS_FTRUNCATE: usize = 46;
 const SYS_OPENAT: usize = 56;
 const SYS_CLOSE: usize = 57;
 const SYS_READ: usize = 63;
@@ -18,7 +24,13 @@ const SYS_WRITEV: usize = 66;
 const SYS_EXIT: usize = 93;
 const SYS_EXIT_GROUP: usize = 94;
 const SYS_SET_TID_ADDRESS: usize = 96;
+const SYS_SHMGET: usize = 194;
+const SYS_SHMCTL: usize = 195;
+const SYS_SHMAT: usize = 196;
+const SYS_SHMDT: usize = 197;
+const SYS_MUNMAP: usize = 215;
 const SYS_MMAP: usize = 222;
+const SYS_MSYNC: usize = 227;
 
 const AT_FDCWD: i32 = -100;
 
@@ -98,6 +110,7 @@ bitflags::bitflags! {
 
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
+    axtask::current().account_kernel_entry();
     ax_println!("handle_syscall [{}] ...", syscall_num);
     let ret = match syscall_num {
          SYS_IOCTL => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
@@ -123,31 +136,169 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
             tf.arg4() as _,
             tf.arg5() as _,
         ),
+        SYS_MUNMAP => sys_munmap(tf.arg0() as _, tf.arg1() as _),
+        SYS_MSYNC => sys_msync(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        SYS_FTRUNCATE => sys_ftruncate(tf.arg0() as _, tf.arg1() as _),
+        SYS_UNLINKAT => sys_unlinkat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        SYS_SHMGET => sys_shmget(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        SYS_SHMAT => sys_shmat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        SYS_SHMDT => sys_shmdt(tf.arg0() as _),
+        SYS_SHMCTL => sys_shmctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         _ => {
             ax_println!("Unimplemented syscall: {}", syscall_num);
             -LinuxError::ENOSYS.code() as _
         }
     };
+    // Returning to the user space, where a killed task holds no resources.
+    axtask::exit_if_killed();
+    axtask::current().account_user_entry();
     ret
 }
 
-#[allow(unused_variables)]
 fn sys_mmap(
     addr: *mut usize,
     length: usize,
     prot: i32,
     flags: i32,
     fd: i32,
-    _offset: isize,
+    offset: isize,
 ) -> isize {
-    unimplemented!("no sys_mmap!");
+    syscall_body!(sys_mmap, {
+        let prot = MmapProt::from_bits_truncate(prot);
+        let flags = MmapFlags::from_bits_truncate(flags);
+        if length == 0 || offset < 0 || !is_aligned_4k(offset as usize) {
+            return Err(LinuxError::EINVAL);
+        }
+        if flags.contains(MmapFlags::MAP_SHARED) == flags.contains(MmapFlags::MAP_PRIVATE) {
+            return Err(LinuxError::EINVAL);
+        }
+        let size = align_up_4k(length);
+
+        let curr = current();
+        let mut aspace = curr.task_ext().aspace.lock();
+        let start = if flags.contains(MmapFlags::MAP_FIXED) {
+            let start = VirtAddr::from(addr as usize);
+            if !start.is_aligned_4k() {
+                return Err(LinuxError::EINVAL);
+            }
+            aspace.unmap(start, size)?;
+            start
+        } else {
+            // Never map the first page, so that NULL is not a valid address.
+            let limit = VirtAddrRange::new(aspace.base() + PAGE_SIZE_4K, aspace.end());
+            let hint = VirtAddr::from(addr as usize).align_down_4k();
+            aspace
+                .find_free_area(hint, size, limit)
+                .ok_or(LinuxError::ENOMEM)?
+        };
+
+        let mapping_flags = MappingFlags::from(prot);
+        if flags.contains(MmapFlags::MAP_ANONYMOUS) {
+            aspace.map_alloc(start, size, mapping_flags, false)?;
+        } else if let Some(shm) = shm::shm_of_fd(fd)? {
+            // The private mappings of shared memory objects are not supported.
+            if !flags.contains(MmapFlags::MAP_SHARED) {
+                return Err(LinuxError::EINVAL);
+            }
+            aspace.map_shared(start, size, mapping_flags, shm, offset as usize)?;
+        } else {
+            let shared = flags.contains(MmapFlags::MAP_SHARED);
+            let writable = shared && prot.contains(MmapProt::PROT_WRITE);
+            let node = api::get_file_node(fd, writable)?;
+            aspace.map_file(start, size, mapping_flags, node, offset as u64, shared)?;
+        }
+        Ok(start.as_usize())
+    })
+}
+
+fn sys_munmap(addr: *mut usize, length: usize) -> isize {
+    syscall_body!(sys_munmap, {
+        let start = VirtAddr::from(addr as usize);
+        if !start.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        let curr = current();
+        curr.task_ext()
+            .aspace
+            .lock()
+            .unmap(start, align_up_4k(length))?;
+        Ok(0)
+    })
+}
+
+fn sys_msync(addr: *mut usize, length: usize, _flags: i32) -> isize {
+    syscall_body!(sys_msync, {
+        let start = VirtAddr::from(addr as usize);
+        if !start.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        let curr = current();
+        curr.task_ext()
+            .aspace
+            .lock()
+            .sync(start, align_up_4k(length))?;
+        Ok(0)
+    })
+}
+
+/// Returns the path as a string, or `None` if it is not valid UTF-8.
+fn path_str<'a>(path: *const c_char) -> Option<&'a str> {
+    if path.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(path) }.to_str().ok()
 }
 
 fn sys_openat(dfd: c_int, fname: *const c_char, flags: c_int, mode: api::ctypes::mode_t) -> isize {
     assert_eq!(dfd, AT_FDCWD);
+    if let Some(name) = path_str(fname).and_then(shm::shm_name) {
+        return syscall_body!(sys_openat, shm::shm_open(name, flags));
+    }
     api::sys_open(fname, flags, mode) as isize
 }
 
+fn sys_unlinkat(dfd: c_int, fname: *const c_char, _flags: c_int) -> isize {
+    syscall_body!(sys_unlinkat, {
+        assert_eq!(dfd, AT_FDCWD);
+        // Only the shared memory objects can be removed, e.g., by `shm_unlink`.
+        match path_str(fname).and_then(shm::shm_name) {
+            Some(name) => SharedMemory::unlink(name)?,
+            None => return Err(LinuxError::ENOSYS),
+        }
+        Ok(0)
+    })
+}
+
+fn sys_ftruncate(fd: c_int, length: isize) -> isize {
+    syscall_body!(sys_ftruncate, {
+        if length < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        // Only the shared memory objects can be resized.
+        match shm::shm_of_fd(fd)? {
+            Some(shm) => shm.resize(length as usize)?,
+            None => return Err(LinuxError::EINVAL),
+        }
+        Ok(0)
+    })
+}
+
+fn sys_shmget(key: i32, size: usize, shmflg: i32) -> isize {
+    syscall_body!(sys_shmget, shm::sys_shmget(key, size, shmflg))
+}
+
+fn sys_shmat(shmid: i32, addr: usize, shmflg: i32) -> isize {
+    syscall_body!(sys_shmat, shm::sys_shmat(shmid, addr, shmflg))
+}
+
+fn sys_shmdt(addr: usize) -> isize {
+    syscall_body!(sys_shmdt, shm::sys_shmdt(addr))
+}
+
+fn sys_shmctl(shmid: i32, cmd: i32, buf: usize) -> isize {
+    syscall_body!(sys_shmctl, shm::sys_shmctl(shmid, cmd, buf))
+}
+
 fn sys_close(fd: i32) -> isize {
     api::sys_close(fd) as isize
 }
@@ -59,6 +59,7 @@ pub fn spawn_user_task(aspace: Arc<Mutex<AddrSpace>>, uctx: UspaceContext) -> Ax
                 curr.task_ext().uctx.get_sp(),
                 kstack_top,
             );
+            curr.account_user_entry();
             unsafe { curr.task_ext().uctx.enter_uspace(kstack_top) };
         },
         "userboot".into(),
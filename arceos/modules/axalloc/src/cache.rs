@@ -0,0 +1,143 @@
+//! Per-CPU caches of small objects, enabled by the `percpu-cache` feature.
+//!
+//! Small allocations are rounded up to one of the [`NUM_CLASSES`] size
+//! classes, from [`MIN_CLASS_SIZE`] to [`MAX_CLASS_SIZE`] bytes, if they are
+//! aligned to at most [`MIN_CLASS_SIZE`] bytes. Each CPU has a
+//! magazine of free objects for each size class, so most allocations and
+//! deallocations do not take the lock of the byte allocator. When a magazine
+//! is empty, it is refilled by [`BATCH_SIZE`] objects with the lock taken
+//! once, and when it is full, half of it is flushed back in the same way.
+//!
+//! An object freed on another CPU goes to the magazine of that CPU. The
+//! objects in the magazines are counted as used by the byte allocator.
+//!
+//! The caches are bypassed if the `debug` feature is enabled, so that every
+//! freed block is checked and poisoned.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use allocator::{AllocResult, ByteAllocator};
+
+use crate::GlobalAllocator;
+
+/// The size of the smallest size class.
+pub const MIN_CLASS_SIZE: usize = 16;
+
+/// The number of size classes.
+pub const NUM_CLASSES: usize = 8;
+
+/// The size of the largest size class, larger allocations are not cached.
+pub const MAX_CLASS_SIZE: usize = MIN_CLASS_SIZE << (NUM_CLASSES - 1);
+
+/// The maximum number of objects in each magazine.
+const MAGAZINE_SIZE: usize = 32;
+
+/// The number of objects refilled or flushed at a time.
+pub const BATCH_SIZE: usize = MAGAZINE_SIZE / 2;
+
+struct Magazine {
+    objs: [*mut u8; MAGAZINE_SIZE],
+    len: usize,
+}
+
+struct CpuCache {
+    mags: [Magazine; NUM_CLASSES],
+}
+
+impl Magazine {
+    const EMPTY: Self = Self {
+        objs: [core::ptr::null_mut(); MAGAZINE_SIZE],
+        len: 0,
+    };
+}
+
+#[percpu::def_percpu]
+static CPU_CACHE: CpuCache = CpuCache {
+    mags: [Magazine::EMPTY; NUM_CLASSES],
+};
+
+/// Returns the size class of the allocation, or `None` if it is not cached.
+pub(crate) fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(MIN_CLASS_SIZE);
+    if cfg!(feature = "debug") || size > MAX_CLASS_SIZE || layout.align() > MIN_CLASS_SIZE {
+        return None;
+    }
+    Some((size.next_power_of_two() / MIN_CLASS_SIZE).trailing_zeros() as usize)
+}
+
+/// Returns the layout of the objects of the size class.
+fn class_layout(class: usize) -> Layout {
+    Layout::from_size_align(MIN_CLASS_SIZE << class, MIN_CLASS_SIZE).unwrap()
+}
+
+/// Allocates an object of the size class from the magazine of the current
+/// CPU, refills the magazine if it is empty.
+pub(crate) fn alloc(galloc: &GlobalAllocator, class: usize) -> AllocResult<NonNull<u8>> {
+    let _guard = kernel_guard::NoPreemptIrqSave::new();
+    // Safety: preemption and IRQs are disabled.
+    let mag = unsafe { &mut CPU_CACHE.current_ref_mut_raw().mags[class] };
+    if mag.len == 0 {
+        let layout = class_layout(class);
+        let mut balloc = galloc.balloc.lock();
+        mag.objs[0] = galloc.alloc_heap(&mut balloc, layout)?.as_ptr();
+        mag.len = 1;
+        // Refill without expanding the heap, as the first one has succeeded.
+        while mag.len < BATCH_SIZE {
+            match balloc.alloc(layout) {
+                Ok(ptr) => mag.objs[mag.len] = ptr.as_ptr(),
+                Err(_) => break,
+            }
+            mag.len += 1;
+        }
+    }
+    mag.len -= 1;
+    // Safety: only non-null pointers are put into the magazines.
+    Ok(unsafe { NonNull::new_unchecked(mag.objs[mag.len]) })
+}
+
+/// Puts an object of the size class into the magazine of the current CPU,
+/// flushes half of the magazine if it is full.
+pub(crate) fn dealloc(galloc: &GlobalAllocator, ptr: NonNull<u8>, class: usize) {
+    let _guard = kernel_guard::NoPreemptIrqSave::new();
+    // Safety: preemption and IRQs are disabled.
+    let mag = unsafe { &mut CPU_CACHE.current_ref_mut_raw().mags[class] };
+    if mag.len == MAGAZINE_SIZE {
+        flush(galloc, mag, class, BATCH_SIZE);
+    }
+    mag.objs[mag.len] = ptr.as_ptr();
+    mag.len += 1;
+}
+
+/// Gives the oldest `count` objects in the magazine back to the byte
+/// allocator.
+fn flush(galloc: &GlobalAllocator, mag: &mut Magazine, class: usize, count: usize) {
+    let layout = class_layout(class);
+    let mut balloc = galloc.balloc.lock();
+    for &obj in &mag.objs[..count] {
+        // Safety: only non-null pointers are put into the magazines.
+        balloc.dealloc(unsafe { NonNull::new_unchecked(obj) }, layout);
+    }
+    mag.objs.copy_within(count..mag.len, 0);
+    mag.len -= count;
+}
+
+/// Gives all the cached objects of the current CPU back to the byte
+/// allocator, returns the number of bytes freed.
+///
+/// The caches of other CPUs are not flushed.
+pub fn flush_local() -> usize {
+    let galloc = crate::global_allocator();
+    let _guard = kernel_guard::NoPreemptIrqSave::new();
+    // Safety: preemption and IRQs are disabled.
+    let cache = unsafe { CPU_CACHE.current_ref_mut_raw() };
+    let mut freed = 0;
+    for (class, mag) in cache.mags.iter_mut().enumerate() {
+        let len = mag.len;
+        if len > 0 {
+            flush(galloc, mag, class, len);
+            freed += len * class_layout(class).size();
+        }
+    }
+    freed
+}
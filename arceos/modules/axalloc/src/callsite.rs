@@ -0,0 +1,242 @@
+//! Tracking live allocations by call sites, enabled by the `stats-callsite`
+//! feature.
+//!
+//! Each allocation is prefixed with a header, which records the call site of
+//! the allocation. A call site is identified by the first [`CALLSITE_DEPTH`]
+//! return addresses on the stack above the allocator, found by walking the
+//! frame pointers from the frame of the allocator's entry function (e.g.,
+//! [`GlobalAlloc::alloc`]). Some frames of `alloc` may still be recorded
+//! first, e.g., the cold paths of growing a `Vec`, so the depth is large
+//! enough to reach their callers. The addresses can be resolved by
+//! `addr2line`.
+//!
+//! The walk relies on every frame saving the frame pointer, so the kernel
+//! must be built with `-C force-frame-pointers=yes`. The build scripts add it
+//! to `RUSTFLAGS` if the feature is selected.
+//!
+//! The call sites are recorded in a fixed-size table, the allocations are
+//! counted as an unknown call site when the table is full.
+//!
+//! [`GlobalAlloc::alloc`]: core::alloc::GlobalAlloc::alloc
+
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::NonNull;
+
+use kspin::SpinNoIrq;
+
+/// The number of return addresses that identify a call site.
+pub const CALLSITE_DEPTH: usize = 8;
+
+/// The maximum number of call sites that can be recorded.
+const MAX_CALLSITES: usize = 512;
+
+/// Stop walking if a frame is larger than this, as the frame pointer is
+/// probably invalid.
+const MAX_FRAME_SIZE: usize = 0x10000;
+
+/// The minimum size of the header before each allocation.
+const HEADER_SIZE: usize = 2 * size_of::<usize>();
+
+/// The slot for the allocations whose call site is unknown.
+const UNKNOWN: usize = 0;
+
+#[derive(Clone, Copy)]
+struct Callsite {
+    addrs: [usize; CALLSITE_DEPTH],
+    live_allocs: usize,
+    live_bytes: usize,
+}
+
+impl Callsite {
+    const EMPTY: Self = Self {
+        addrs: [0; CALLSITE_DEPTH],
+        live_allocs: 0,
+        live_bytes: 0,
+    };
+}
+
+/// An open-addressing hash table of call sites, slot [`UNKNOWN`] is reserved.
+struct CallsiteTable {
+    slots: [Callsite; MAX_CALLSITES],
+}
+
+static CALLSITES: SpinNoIrq<CallsiteTable> = SpinNoIrq::new(CallsiteTable {
+    slots: [Callsite::EMPTY; MAX_CALLSITES],
+});
+
+impl CallsiteTable {
+    /// Returns the slot index of the call site, adding it if not found.
+    fn find_or_insert(&mut self, addrs: &[usize; CALLSITE_DEPTH]) -> usize {
+        if addrs[0] == 0 {
+            return UNKNOWN;
+        }
+        let hash = addrs
+            .iter()
+            .fold(0usize, |h, &a| h.rotate_left(5) ^ a)
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize);
+        let mut idx = hash % (MAX_CALLSITES - 1) + 1;
+        for _ in 1..MAX_CALLSITES {
+            let slot = &mut self.slots[idx];
+            if slot.addrs == *addrs {
+                return idx;
+            }
+            if slot.addrs[0] == 0 {
+                slot.addrs = *addrs;
+                return idx;
+            }
+            idx = idx % (MAX_CALLSITES - 1) + 1;
+        }
+        UNKNOWN // the table is full
+    }
+}
+
+/// Returns the frame pointer of the current function, it is inlined into the
+/// caller.
+#[inline(always)]
+pub(crate) fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "x86_64")] {
+                core::arch::asm!("mov {}, rbp", out(reg) fp);
+            } else if #[cfg(target_arch = "aarch64")] {
+                core::arch::asm!("mov {}, x29", out(reg) fp);
+            } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+                core::arch::asm!("mv {}, s0", out(reg) fp);
+            } else {
+                fp = 0;
+            }
+        }
+    }
+    fp
+}
+
+/// Returns the return address and the caller's frame pointer of the frame.
+///
+/// # Safety
+///
+/// `fp` must be a valid frame pointer.
+unsafe fn unwind_frame(fp: usize) -> (usize, usize) {
+    let fp = fp as *const usize;
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+            (*fp.sub(1), *fp.sub(2))
+        } else {
+            (*fp.add(1), *fp)
+        }
+    }
+}
+
+/// Returns the return addresses from the frame `entry_fp` of the allocator's
+/// entry function, the first one is in its caller.
+pub(crate) fn capture_callsite(entry_fp: usize) -> [usize; CALLSITE_DEPTH] {
+    let mut addrs = [0; CALLSITE_DEPTH];
+    let mut fp = entry_fp;
+    for addr in addrs.iter_mut() {
+        if fp == 0 || fp % size_of::<usize>() != 0 {
+            break;
+        }
+        // Safety: the frame pointers are valid if the kernel is built with
+        // them, and the callers' frames must be above the current one.
+        let (ra, next_fp) = unsafe { unwind_frame(fp) };
+        *addr = ra;
+        if next_fp <= fp || next_fp - fp > MAX_FRAME_SIZE {
+            break;
+        }
+        fp = next_fp;
+    }
+    addrs
+}
+
+/// Returns the layout of the allocation with the header, and the offset of
+/// the user data.
+pub(crate) fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = HEADER_SIZE.max(layout.align());
+    let size = layout.size().checked_add(offset)?;
+    let outer = Layout::from_size_align(size, layout.align()).ok()?;
+    Some((outer, offset))
+}
+
+/// Records the call site of a new allocation in the header, returns the
+/// pointer to the user data.
+///
+/// `entry_fp` is the frame pointer of the allocator's entry function.
+pub(crate) fn on_alloc(
+    base: NonNull<u8>,
+    offset: usize,
+    size: usize,
+    entry_fp: usize,
+) -> NonNull<u8> {
+    let addrs = capture_callsite(entry_fp);
+    let idx = {
+        let mut table = CALLSITES.lock();
+        let idx = table.find_or_insert(&addrs);
+        table.slots[idx].live_allocs += 1;
+        table.slots[idx].live_bytes += size;
+        idx
+    };
+    unsafe {
+        let ptr = base.as_ptr().add(offset);
+        (ptr as *mut usize).sub(1).write(idx);
+        NonNull::new_unchecked(ptr)
+    }
+}
+
+/// Removes the allocation from its call site, returns the pointer to the
+/// header.
+pub(crate) fn on_dealloc(ptr: NonNull<u8>, offset: usize, size: usize) -> NonNull<u8> {
+    unsafe {
+        let idx = (ptr.as_ptr() as *const usize).sub(1).read();
+        let mut table = CALLSITES.lock();
+        if let Some(slot) = table.slots.get_mut(idx) {
+            slot.live_allocs = slot.live_allocs.saturating_sub(1);
+            slot.live_bytes = slot.live_bytes.saturating_sub(size);
+        }
+        NonNull::new_unchecked(ptr.as_ptr().sub(offset))
+    }
+}
+
+/// Prints at most `n` call sites with the most live bytes.
+pub(crate) fn dump_top(n: usize) {
+    const MAX_TOP: usize = 16;
+    let mut top = [(0, Callsite::EMPTY); MAX_TOP];
+    let n = n.min(MAX_TOP);
+    if n == 0 {
+        return;
+    }
+    let mut count = 0;
+    {
+        // Select the top call sites with the table locked, and print them
+        // after unlocking, as printing may allocate.
+        let table = CALLSITES.lock();
+        for (idx, slot) in table.slots.iter().enumerate() {
+            if slot.live_allocs == 0 {
+                continue;
+            }
+            let pos = top[..count]
+                .iter()
+                .position(|(_, s)| s.live_bytes < slot.live_bytes)
+                .unwrap_or(count);
+            if pos < n {
+                top.copy_within(pos..n - 1, pos + 1);
+                top[pos] = (idx, *slot);
+                count = (count + 1).min(n);
+            }
+        }
+    }
+    warn!("Top {} call sites of live allocations:", count);
+    for (idx, slot) in &top[..count] {
+        if *idx == UNKNOWN {
+            warn!(
+                "  {} bytes in {} allocations from unknown call sites",
+                slot.live_bytes, slot.live_allocs
+            );
+        } else {
+            warn!(
+                "  {} bytes in {} allocations from {:#x?}",
+                slot.live_bytes, slot.live_allocs, slot.addrs
+            );
+        }
+    }
+}
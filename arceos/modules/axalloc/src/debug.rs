@@ -0,0 +1,537 @@
+//! Debug mode of the heap allocator, enabled by the `debug` feature.
+//!
+//! Each block allocated by [`DebugByteAllocator`] has a header right before
+//! the user data, and is surrounded by red zones filled with
+//! [`REDZONE_BYTE`]:
+//!
+//! ```text
+//! | front red zone | header | user data | rear red zone |
+//! ```
+//!
+//! When a block is freed, its header and red zones are checked, and the user
+//! data is filled with [`POISON_BYTE`]. Reading `0x6b6b6b6b` from a pointer
+//! usually means a use after free. The freed block is kept in a quarantine for
+//! a while before it is given back to the inner allocator, and its poison is
+//! checked when it leaves the quarantine, to detect writes after free.
+//!
+//! The corruption found on free causes a panic. All the live and quarantined
+//! blocks can also be checked by [`check_heap`], e.g., periodically in a timer.
+//!
+//! If the `debug-guard-pages` feature is enabled, allocations of at least
+//! [`GUARD_PAGE_THRESHOLD`] bytes are placed at the end of their own pages,
+//! followed by an inaccessible guard page, so that overflows cause page faults
+//! immediately. The pages are also made inaccessible after free. The page
+//! permissions are changed by [`GuardPageIf`], which must be implemented by the
+//! kernel if paging is enabled, and guard pages are used only after
+//! [`enable_guard_pages`] is called.
+
+use core::alloc::Layout;
+use core::fmt;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+
+use allocator::{AllocResult, BaseAllocator, ByteAllocator};
+
+/// The size of each red zone in bytes.
+pub const REDZONE_SIZE: usize = 16;
+
+/// The byte that fills the red zones.
+pub const REDZONE_BYTE: u8 = 0xfd;
+
+/// The byte that fills the freed blocks.
+pub const POISON_BYTE: u8 = 0x6b;
+
+/// The number of freed blocks kept in the quarantine.
+pub(crate) const QUARANTINE_LEN: usize = 256;
+
+const MAGIC_LIVE: usize = 0xa110_c8ed;
+const MAGIC_FREED: usize = 0xf4ee_d0ed;
+#[cfg(feature = "debug-guard-pages")]
+const MAGIC_GUARDED: usize = 0x6a4d_ba6e;
+
+#[repr(C)]
+struct BlockHeader {
+    prev: *mut BlockHeader,
+    next: *mut BlockHeader,
+    size: usize,
+    /// The offset of the user data from the start of the block.
+    offset: usize,
+    magic: usize,
+}
+
+const HEADER_SIZE: usize = size_of::<BlockHeader>();
+
+/// Returns the header of the block whose user data starts at `ptr`.
+fn header_of(ptr: NonNull<u8>) -> *mut BlockHeader {
+    ptr.as_ptr().wrapping_sub(HEADER_SIZE) as *mut BlockHeader
+}
+
+/// Returns the layout of the block with the red zones and the header, and the
+/// offset of the user data.
+fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let align = layout.align().max(align_of::<BlockHeader>());
+    let offset = (REDZONE_SIZE + HEADER_SIZE).next_multiple_of(align);
+    let size = offset
+        .checked_add(layout.size())?
+        .checked_add(REDZONE_SIZE)?;
+    let outer = Layout::from_size_align(size, align).ok()?;
+    Some((outer, offset))
+}
+
+/// A kind of heap corruption.
+#[derive(Debug)]
+enum Corruption {
+    BadMagic(usize),
+    DoubleFree,
+    SizeMismatch(usize),
+    /// The red zone is overwritten at the offset from the user data.
+    RedZone(isize),
+    /// The freed block is overwritten at the offset from the user data.
+    WriteAfterFree(usize),
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadMagic(magic) => write!(f, "invalid header magic {:#x}", magic),
+            Self::DoubleFree => write!(f, "double free"),
+            Self::SizeMismatch(size) => write!(f, "freed with a different size {}", size),
+            Self::RedZone(offset) => write!(f, "red zone overwritten at offset {}", offset),
+            Self::WriteAfterFree(offset) => write!(f, "written after free at offset {}", offset),
+        }
+    }
+}
+
+/// Returns the offset of the first byte in `[start, start + len)` that is not
+/// `byte`.
+///
+/// # Safety
+///
+/// The range must be valid for reads.
+unsafe fn find_mismatch(start: *const u8, len: usize, byte: u8) -> Option<usize> {
+    core::slice::from_raw_parts(start, len)
+        .iter()
+        .position(|&b| b != byte)
+}
+
+/// Checks the red zones of the block, the rear red zone ends at `end`.
+///
+/// # Safety
+///
+/// The header must be valid.
+unsafe fn check_redzones(ptr: NonNull<u8>, end: usize) -> Result<(), Corruption> {
+    let header = &*header_of(ptr);
+    let front = ptr.as_ptr().sub(header.offset);
+    if let Some(i) = find_mismatch(front, header.offset - HEADER_SIZE, REDZONE_BYTE) {
+        return Err(Corruption::RedZone(i as isize - header.offset as isize));
+    }
+    let rear = ptr.as_ptr().add(header.size);
+    if let Some(i) = find_mismatch(rear, end - rear as usize, REDZONE_BYTE) {
+        return Err(Corruption::RedZone((header.size + i) as isize));
+    }
+    Ok(())
+}
+
+/// Checks the header and red zones of a live block.
+///
+/// # Safety
+///
+/// The block must be allocated by [`DebugByteAllocator`].
+unsafe fn check_live(ptr: NonNull<u8>) -> Result<(), Corruption> {
+    let header = &*header_of(ptr);
+    match header.magic {
+        MAGIC_LIVE => {}
+        MAGIC_FREED => return Err(Corruption::DoubleFree),
+        magic => return Err(Corruption::BadMagic(magic)),
+    }
+    check_redzones(ptr, ptr.as_ptr() as usize + header.size + REDZONE_SIZE)
+}
+
+/// Checks the poison of a quarantined block.
+///
+/// # Safety
+///
+/// The block must be in the quarantine.
+unsafe fn check_freed(ptr: NonNull<u8>, size: usize) -> Result<(), Corruption> {
+    let header = &*header_of(ptr);
+    if header.magic != MAGIC_FREED {
+        return Err(Corruption::BadMagic(header.magic));
+    }
+    match find_mismatch(ptr.as_ptr(), size, POISON_BYTE) {
+        Some(i) => Err(Corruption::WriteAfterFree(i)),
+        None => Ok(()),
+    }
+}
+
+fn report(ptr: NonNull<u8>, size: usize, corruption: Corruption) -> ! {
+    panic!(
+        "heap corruption: {} in block {:#x} of {} bytes",
+        corruption,
+        ptr.as_ptr() as usize,
+        size
+    );
+}
+
+/// A ring buffer that drops the oldest item when it is full.
+struct Ring<T, const N: usize> {
+    items: [Option<T>; N],
+    /// The index of the oldest item.
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> Ring<T, N> {
+    const fn new() -> Self {
+        Self {
+            items: [None; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Pushes an item, returns the oldest item if the ring is full.
+    fn push(&mut self, item: T) -> Option<T> {
+        let evicted = if self.len == N { self.pop() } else { None };
+        self.items[(self.head + self.len) % N] = Some(item);
+        self.len += 1;
+        evicted
+    }
+
+    /// Removes the oldest item.
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        item
+    }
+
+    fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).filter_map(move |i| self.items[(self.head + i) % N])
+    }
+}
+
+/// A byte allocator that wraps another one, and detects heap corruption by
+/// red zones and poisoning, as described in the [module-level docs](self).
+pub struct DebugByteAllocator<A> {
+    inner: A,
+    /// The list of live blocks for heap walking.
+    live: *mut BlockHeader,
+    /// The freed blocks, which are not given back to the inner allocator yet.
+    quarantine: Ring<(NonNull<u8>, Layout), QUARANTINE_LEN>,
+}
+
+// Safety: the blocks in the list and the quarantine are owned by the allocator.
+unsafe impl<A: Send> Send for DebugByteAllocator<A> {}
+
+impl<A> DebugByteAllocator<A> {
+    /// Creates a new [`DebugByteAllocator`] that wraps `inner`.
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            live: ptr::null_mut(),
+            quarantine: Ring::new(),
+        }
+    }
+}
+
+impl<A: ByteAllocator> DebugByteAllocator<A> {
+    unsafe fn link(&mut self, header: *mut BlockHeader) {
+        (*header).prev = ptr::null_mut();
+        (*header).next = self.live;
+        if !self.live.is_null() {
+            (*self.live).prev = header;
+        }
+        self.live = header;
+    }
+
+    unsafe fn unlink(&mut self, header: *mut BlockHeader) {
+        let (prev, next) = ((*header).prev, (*header).next);
+        if prev.is_null() {
+            self.live = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+
+    /// Gives a quarantined block back to the inner allocator.
+    fn release(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if let Err(c) = unsafe { check_freed(ptr, layout.size()) } {
+            report(ptr, layout.size(), c);
+        }
+        let (outer, offset) = outer_layout(layout).unwrap();
+        let base = unsafe { NonNull::new_unchecked(ptr.as_ptr().sub(offset)) };
+        self.inner.dealloc(base, outer);
+    }
+
+    /// Gives all quarantined blocks back to the inner allocator.
+    fn flush_quarantine(&mut self) {
+        while let Some((ptr, layout)) = self.quarantine.pop() {
+            self.release(ptr, layout);
+        }
+    }
+
+    /// Checks all the live and quarantined blocks, returns the number of
+    /// corrupted blocks found.
+    ///
+    /// The corruption is reported by logging instead of panicking.
+    pub fn check(&self) -> usize {
+        let mut corrupted = 0;
+        let mut header = self.live;
+        while !header.is_null() {
+            unsafe {
+                let ptr = NonNull::new_unchecked((header as *mut u8).add(HEADER_SIZE));
+                if let Err(c) = check_live(ptr) {
+                    error!(
+                        "heap corruption: {} in block {:#x} of {} bytes",
+                        c,
+                        ptr.as_ptr() as usize,
+                        (*header).size
+                    );
+                    corrupted += 1;
+                    if matches!(c, Corruption::BadMagic(_) | Corruption::DoubleFree) {
+                        // The links may be broken too.
+                        error!("stop walking the heap as the block header is corrupted");
+                        break;
+                    }
+                }
+                header = (*header).next;
+            }
+        }
+        for (ptr, layout) in self.quarantine.iter() {
+            if let Err(c) = unsafe { check_freed(ptr, layout.size()) } {
+                error!(
+                    "heap corruption: {} in freed block {:#x} of {} bytes",
+                    c,
+                    ptr.as_ptr() as usize,
+                    layout.size()
+                );
+                corrupted += 1;
+            }
+        }
+        corrupted
+    }
+}
+
+impl<A: ByteAllocator> BaseAllocator for DebugByteAllocator<A> {
+    fn init(&mut self, start: usize, size: usize) {
+        self.inner.init(start, size)
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        self.inner.add_memory(start, size)
+    }
+}
+
+impl<A: ByteAllocator> ByteAllocator for DebugByteAllocator<A> {
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let (outer, offset) = outer_layout(layout).ok_or(allocator::AllocError::InvalidParam)?;
+        let base = match self.inner.alloc(outer) {
+            Ok(base) => base,
+            Err(_) if self.quarantine.len > 0 => {
+                self.flush_quarantine();
+                self.inner.alloc(outer)?
+            }
+            Err(e) => return Err(e),
+        };
+        unsafe {
+            let ptr = NonNull::new_unchecked(base.as_ptr().add(offset));
+            let header = header_of(ptr);
+            ptr::write_bytes(base.as_ptr(), REDZONE_BYTE, offset - HEADER_SIZE);
+            header.write(BlockHeader {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                size: layout.size(),
+                offset,
+                magic: MAGIC_LIVE,
+            });
+            ptr::write_bytes(ptr.as_ptr().add(layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+            self.link(header);
+            Ok(ptr)
+        }
+    }
+
+    fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+        unsafe {
+            let header = header_of(pos);
+            if let Err(c) = check_live(pos) {
+                report(pos, layout.size(), c);
+            }
+            if (*header).size != layout.size() {
+                report(pos, (*header).size, Corruption::SizeMismatch(layout.size()));
+            }
+            self.unlink(header);
+            (*header).magic = MAGIC_FREED;
+            ptr::write_bytes(pos.as_ptr(), POISON_BYTE, layout.size());
+        }
+        if let Some((ptr, layout)) = self.quarantine.push((pos, layout)) {
+            self.release(ptr, layout);
+        }
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.inner.total_bytes()
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.inner.used_bytes()
+    }
+
+    fn available_bytes(&self) -> usize {
+        self.inner.available_bytes()
+    }
+}
+
+/// Checks all the live and quarantined blocks of the global allocator, returns
+/// the number of corrupted blocks found.
+///
+/// The corruption is reported by logging instead of panicking. The large
+/// blocks on their own pages are not checked, as they are protected by guard
+/// pages.
+pub fn check_heap() -> usize {
+    crate::global_allocator().balloc.lock().check()
+}
+
+#[cfg(feature = "debug-guard-pages")]
+pub use self::guard::*;
+
+#[cfg(feature = "debug-guard-pages")]
+mod guard {
+    use core::alloc::Layout;
+    use core::mem::align_of;
+    use core::ptr::{self, NonNull};
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use kspin::SpinNoIrq;
+
+    use super::{check_redzones, header_of, report, BlockHeader, Corruption, Ring};
+    use super::{HEADER_SIZE, MAGIC_FREED, MAGIC_GUARDED, REDZONE_BYTE};
+    use crate::{GlobalAllocator, PAGE_SIZE};
+
+    /// The minimum size of allocations that are placed on their own pages.
+    pub const GUARD_PAGE_THRESHOLD: usize = PAGE_SIZE;
+
+    /// The number of freed page blocks kept inaccessible.
+    const PAGE_QUARANTINE_LEN: usize = 16;
+
+    /// The interface to change the page permissions, which must be implemented
+    /// by the kernel if the `debug-guard-pages` feature is enabled.
+    #[crate_interface::def_interface]
+    pub trait GuardPageIf {
+        /// Makes the pages in `[vaddr, vaddr + size)` readable and writable if
+        /// `accessible` is `true`, or inaccessible otherwise.
+        ///
+        /// Returns `false` if the permissions cannot be changed now.
+        fn set_pages_accessible(vaddr: usize, size: usize, accessible: bool) -> bool;
+    }
+
+    static GUARD_PAGES_ENABLED: AtomicBool = AtomicBool::new(false);
+
+    /// The freed page blocks, as `(start, num_pages)` without the guard page.
+    static PAGE_QUARANTINE: SpinNoIrq<Ring<(usize, usize), PAGE_QUARANTINE_LEN>> =
+        SpinNoIrq::new(Ring::new());
+
+    /// Starts to place large allocations on their own pages.
+    ///
+    /// It should be called after the kernel page table is set up.
+    pub fn enable_guard_pages() {
+        info!("heap guard pages enabled");
+        GUARD_PAGES_ENABLED.store(true, Ordering::Release);
+    }
+
+    fn set_accessible(vaddr: usize, size: usize, accessible: bool) -> bool {
+        crate_interface::call_interface!(GuardPageIf::set_pages_accessible, vaddr, size, accessible)
+    }
+
+    /// Returns the number of pages for the block, without the guard page.
+    fn num_data_pages(layout: Layout) -> usize {
+        let align = layout.align().max(align_of::<BlockHeader>());
+        (HEADER_SIZE + layout.size() + align).div_ceil(PAGE_SIZE)
+    }
+
+    pub(crate) fn use_guard_pages(layout: Layout) -> bool {
+        layout.size() >= GUARD_PAGE_THRESHOLD
+            && layout.align() <= PAGE_SIZE
+            && GUARD_PAGES_ENABLED.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if the block is placed on its own pages.
+    pub(crate) fn is_guarded(ptr: NonNull<u8>) -> bool {
+        unsafe { (*header_of(ptr)).magic == MAGIC_GUARDED }
+    }
+
+    /// Allocates the block on its own pages, followed by a guard page.
+    ///
+    /// Returns `None` if the guard page cannot be set.
+    pub(crate) fn alloc_guarded(galloc: &GlobalAllocator, layout: Layout) -> Option<NonNull<u8>> {
+        let num_pages = num_data_pages(layout);
+        let start = galloc.alloc_pages(num_pages + 1, PAGE_SIZE).ok()?;
+        let guard = start + num_pages * PAGE_SIZE;
+        if !set_accessible(guard, PAGE_SIZE, false) {
+            galloc.dealloc_pages(start, num_pages + 1);
+            return None;
+        }
+        let align = layout.align().max(align_of::<BlockHeader>());
+        let pos = (guard - layout.size()) & !(align - 1);
+        unsafe {
+            ptr::write_bytes(start as *mut u8, REDZONE_BYTE, pos - HEADER_SIZE - start);
+            (pos as *mut BlockHeader).sub(1).write(BlockHeader {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                size: layout.size(),
+                offset: pos - start,
+                magic: MAGIC_GUARDED,
+            });
+            ptr::write_bytes(
+                (pos + layout.size()) as *mut u8,
+                REDZONE_BYTE,
+                guard - pos - layout.size(),
+            );
+            Some(NonNull::new_unchecked(pos as *mut u8))
+        }
+    }
+
+    /// Frees the block on its own pages. The pages are made inaccessible and
+    /// kept in a quarantine for a while.
+    pub(crate) fn dealloc_guarded(galloc: &GlobalAllocator, ptr: NonNull<u8>, layout: Layout) {
+        let num_pages = num_data_pages(layout);
+        let start = unsafe {
+            let header = header_of(ptr);
+            let start = ptr.as_ptr() as usize - (*header).offset;
+            if (*header).size != layout.size() {
+                report(ptr, (*header).size, Corruption::SizeMismatch(layout.size()));
+            }
+            if let Err(c) = check_redzones(ptr, start + num_pages * PAGE_SIZE) {
+                report(ptr, layout.size(), c);
+            }
+            (*header).magic = MAGIC_FREED;
+            start
+        };
+        if !set_accessible(start, num_pages * PAGE_SIZE, false) {
+            release_guarded(galloc, start, num_pages);
+            return;
+        }
+        let evicted = PAGE_QUARANTINE.lock().push((start, num_pages));
+        if let Some((start, num_pages)) = evicted {
+            release_guarded(galloc, start, num_pages);
+        }
+    }
+
+    fn release_guarded(galloc: &GlobalAllocator, start: usize, num_pages: usize) {
+        let size = (num_pages + 1) * PAGE_SIZE;
+        if set_accessible(start, size, true) {
+            galloc.dealloc_pages(start, num_pages + 1);
+        } else {
+            warn!(
+                "cannot restore the guard pages [{:#x}, {:#x}), leaked",
+                start,
+                start + size
+            );
+        }
+    }
+}
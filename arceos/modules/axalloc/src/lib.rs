@@ -4,24 +4,61 @@
 //! [`core::alloc::GlobalAlloc`]. A static global variable of type
 //! [`GlobalAllocator`] is defined with the `#[global_allocator]` attribute, to
 //! be registered as the standard library’s default allocator.
+//!
+//! Before the heap is initialized by [`global_init`], the allocations can be
+//! served by an early bump allocator from a small region given to
+//! [`global_early_init`], so that the kernel can allocate during the boot.
+//!
+//! # Cargo Features
+//!
+//! - `tlsf`, `slab`, `buddy`, `segfit`: Enable the byte allocators, which can
+//!   be selected at runtime, see [`registry`]. The default one is
+//!   [`DefaultByteAllocator`].
+//! - `stats`: Record heap statistics, see [`stats`].
+//! - `stats-callsite`: Also track live allocations by call sites, which adds a
+//!   header to each allocation. It also enables the `stats` feature.
+//! - `percpu-cache`: Cache small objects per CPU in front of the byte
+//!   allocator, see [`cache`].
+//! - `oom-return-null`, `oom-kill-task`: Select the default OOM policy, see
+//!   [`oom::OomPolicy`]. It panics by default.
+//! - `debug`: Detect heap corruption by red zones and poisoning, see [`debug`].
+//! - `debug-guard-pages`: Also place large allocations on their own pages
+//!   with guard pages. The kernel must implement [`debug::GuardPageIf`]. It
+//!   also enables the `debug` feature.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
 extern crate alloc;
 
+#[cfg(feature = "percpu-cache")]
+pub mod cache;
+#[cfg(feature = "stats-callsite")]
+mod callsite;
+#[cfg(feature = "debug")]
+pub mod debug;
+pub mod oom;
 mod page;
+pub mod registry;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(test)]
+mod tests;
 
-use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
+use buddy_page_allocator::BuddyPageAllocator;
+use bump_allocator::EarlyAllocator;
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use kspin::SpinNoIrq;
 
 const PAGE_SIZE: usize = 0x1000;
 const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
 
 pub use page::GlobalPage;
+pub use registry::{register_byte_allocator, select_byte_allocator};
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "slab")] {
@@ -30,12 +67,20 @@ cfg_if::cfg_if! {
     } else if #[cfg(feature = "buddy")] {
         /// The default byte allocator.
         pub type DefaultByteAllocator = allocator::BuddyByteAllocator;
+    } else if #[cfg(feature = "segfit")] {
+        /// The default byte allocator.
+        pub type DefaultByteAllocator = segfit_allocator::SegFitByteAllocator;
     } else if #[cfg(feature = "tlsf")] {
         /// The default byte allocator.
         pub type DefaultByteAllocator = allocator::TlsfByteAllocator;
     }
 }
 
+#[cfg(feature = "debug")]
+type HeapAllocator = debug::DebugByteAllocator<registry::SelectedByteAllocator>;
+#[cfg(not(feature = "debug"))]
+type HeapAllocator = registry::SelectedByteAllocator;
+
 /// The global allocator used by ArceOS.
 ///
 /// It combines a [`ByteAllocator`] and a [`PageAllocator`] into a simple
@@ -43,35 +88,61 @@ cfg_if::cfg_if! {
 /// there is no memory, asks the page allocator for more memory and adds it to
 /// the byte allocator.
 ///
-/// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
-/// [`BitmapPageAllocator`] is used as the page allocator.
+/// The byte allocator is selected from the [`registry`] when the heap is
+/// initialized, while [`BuddyPageAllocator`] is used as the page allocator,
+/// which serves large aligned blocks (e.g., 2M and 1G huge pages) efficiently.
 ///
-/// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
+/// Until it is initialized, the allocations are served by an [`EarlyAllocator`]
+/// from the region given to [`early_init`]. The blocks allocated there are
+/// always given back to the early allocator, even after the initialization.
+///
+/// [`early_init`]: GlobalAllocator::early_init
 pub struct GlobalAllocator {
-    balloc: SpinNoIrq<DefaultByteAllocator>,
-    palloc: SpinNoIrq<BitmapPageAllocator<PAGE_SIZE>>,
+    balloc: SpinNoIrq<HeapAllocator>,
+    palloc: SpinNoIrq<BuddyPageAllocator<PAGE_SIZE>>,
+    early: SpinNoIrq<EarlyAllocator<PAGE_SIZE>>,
+    early_start: AtomicUsize,
+    early_end: AtomicUsize,
+    initialized: AtomicBool,
 }
 
 impl GlobalAllocator {
     /// Creates an empty [`GlobalAllocator`].
     pub const fn new() -> Self {
         Self {
-            balloc: SpinNoIrq::new(DefaultByteAllocator::new()),
-            palloc: SpinNoIrq::new(BitmapPageAllocator::new()),
+            #[cfg(feature = "debug")]
+            balloc: SpinNoIrq::new(debug::DebugByteAllocator::new(
+                registry::SelectedByteAllocator::new(),
+            )),
+            #[cfg(not(feature = "debug"))]
+            balloc: SpinNoIrq::new(registry::SelectedByteAllocator::new()),
+            palloc: SpinNoIrq::new(BuddyPageAllocator::new()),
+            early: SpinNoIrq::new(EarlyAllocator::new()),
+            early_start: AtomicUsize::new(0),
+            early_end: AtomicUsize::new(0),
+            initialized: AtomicBool::new(false),
         }
     }
 
-    /// Returns the name of the allocator.
-    pub const fn name(&self) -> &'static str {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "slab")] {
-                "slab"
-            } else if #[cfg(feature = "buddy")] {
-                "buddy"
-            } else if #[cfg(feature = "tlsf")] {
-                "TLSF"
-            }
-        }
+    /// Returns the name of the selected byte allocator.
+    pub fn name(&self) -> &'static str {
+        registry::selected_name()
+    }
+
+    /// Lets the allocator serve the allocations from the given region until
+    /// it is initialized by [`init`].
+    ///
+    /// The region is never given to the byte or the page allocator, so it must
+    /// not overlap with the ones given to [`init`] and [`add_memory`]. It
+    /// should be called only once, and before any allocation.
+    ///
+    /// [`init`]: GlobalAllocator::init
+    /// [`add_memory`]: GlobalAllocator::add_memory
+    pub fn early_init(&self, start_vaddr: usize, size: usize) {
+        assert!(!self.initialized.load(Ordering::Acquire));
+        self.early.lock().init(start_vaddr, size);
+        self.early_start.store(start_vaddr, Ordering::Release);
+        self.early_end.store(start_vaddr + size, Ordering::Release);
     }
 
     /// Initializes the allocator with the given region.
@@ -79,14 +150,31 @@ impl GlobalAllocator {
     /// It firstly adds the whole region to the page allocator, then allocates
     /// a small region (32 KB) to initialize the byte allocator. Therefore,
     /// the given region must be larger than 32 KB.
+    ///
+    /// The later allocations are served by the byte and the page allocator,
+    /// instead of the early allocator.
     pub fn init(&self, start_vaddr: usize, size: usize) {
         assert!(size > MIN_HEAP_SIZE);
         let init_heap_size = MIN_HEAP_SIZE;
         self.palloc.lock().init(start_vaddr, size);
         let heap_ptr = self
+            .palloc
+            .lock()
             .alloc_pages(init_heap_size / PAGE_SIZE, PAGE_SIZE)
             .unwrap();
         self.balloc.lock().init(heap_ptr, init_heap_size);
+        self.initialized.store(true, Ordering::Release);
+    }
+
+    /// Whether the allocations are still served by the early allocator.
+    fn is_early(&self) -> bool {
+        !self.initialized.load(Ordering::Acquire)
+    }
+
+    /// Whether `pos` is allocated from the early allocator.
+    fn is_early_block(&self, pos: usize) -> bool {
+        (self.early_start.load(Ordering::Acquire)..self.early_end.load(Ordering::Acquire))
+            .contains(&pos)
     }
 
     /// Add the given region to the allocator.
@@ -102,9 +190,72 @@ impl GlobalAllocator {
     /// It firstly tries to allocate from the byte allocator. If there is no
     /// memory, it asks the page allocator for more memory and adds it to the
     /// byte allocator.
+    #[cfg_attr(feature = "stats-callsite", inline(never))]
     pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        self.alloc_from(layout, entry_frame())
+    }
+
+    /// Allocates for [`alloc`], `entry_fp` is the frame pointer of the entry
+    /// function, whose caller is recorded as the call site.
+    ///
+    /// [`alloc`]: GlobalAllocator::alloc
+    fn alloc_from(&self, layout: Layout, entry_fp: usize) -> AllocResult<NonNull<u8>> {
+        if self.is_early() {
+            return self.early.lock().alloc(layout);
+        }
+
+        #[cfg(feature = "stats-callsite")]
+        let ptr = {
+            let (outer, offset) = callsite::outer_layout(layout).ok_or(AllocError::InvalidParam)?;
+            self.alloc_bytes(outer)
+                .map(|base| callsite::on_alloc(base, offset, layout.size(), entry_fp))
+        };
+        #[cfg(not(feature = "stats-callsite"))]
+        let ptr = {
+            let _ = entry_fp;
+            self.alloc_bytes(layout)
+        };
+
+        #[cfg(feature = "stats")]
+        match ptr {
+            Ok(_) => stats::record_alloc(layout.size()),
+            Err(_) => stats::record_failure(),
+        }
+        ptr
+    }
+
+    /// Allocates from the heap, calls the reclaim callbacks and retries if
+    /// there is no memory.
+    fn alloc_bytes(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let mut result = self.try_alloc_bytes(layout);
+        for _ in 0..oom::MAX_RECLAIM_RETRIES {
+            if !matches!(result, Err(AllocError::NoMemory)) || oom::reclaim(layout.size()) == 0 {
+                break;
+            }
+            result = self.try_alloc_bytes(layout);
+        }
+        result
+    }
+
+    fn try_alloc_bytes(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "debug-guard-pages")]
+        if debug::use_guard_pages(layout) {
+            if let Some(ptr) = debug::alloc_guarded(self, layout) {
+                return Ok(ptr);
+            }
+        }
+
+        #[cfg(feature = "percpu-cache")]
+        if let Some(class) = cache::size_class(layout) {
+            return cache::alloc(self, class);
+        }
+        self.alloc_heap(&mut self.balloc.lock(), layout)
+    }
+
+    /// Allocates from the locked byte allocator, expands the heap if there is
+    /// no memory.
+    fn alloc_heap(&self, balloc: &mut HeapAllocator, layout: Layout) -> AllocResult<NonNull<u8>> {
         // simple two-level allocator: if no heap memory, allocate from the page allocator.
-        let mut balloc = self.balloc.lock();
         loop {
             if let Ok(ptr) = balloc.alloc(layout) {
                 return Ok(ptr);
@@ -133,6 +284,31 @@ impl GlobalAllocator {
     ///
     /// [`alloc`]: GlobalAllocator::alloc
     pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
+        if self.is_early_block(pos.as_ptr() as usize) {
+            return self.early.lock().dealloc(pos, layout);
+        }
+
+        #[cfg(feature = "stats")]
+        stats::record_free(layout.size());
+        #[cfg(feature = "stats-callsite")]
+        {
+            let (outer, offset) = callsite::outer_layout(layout).unwrap();
+            let base = callsite::on_dealloc(pos, offset, layout.size());
+            self.dealloc_bytes(base, outer)
+        }
+        #[cfg(not(feature = "stats-callsite"))]
+        self.dealloc_bytes(pos, layout)
+    }
+
+    fn dealloc_bytes(&self, pos: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "percpu-cache")]
+        if let Some(class) = cache::size_class(layout) {
+            return cache::dealloc(self, pos, class);
+        }
+        #[cfg(feature = "debug-guard-pages")]
+        if debug::is_guarded(pos) {
+            return debug::dealloc_guarded(self, pos, layout);
+        }
         self.balloc.lock().dealloc(pos, layout)
     }
 
@@ -143,6 +319,9 @@ impl GlobalAllocator {
     /// `align_pow2` must be a power of 2, and the returned region bound will be
     /// aligned to it.
     pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if self.is_early() {
+            return self.early.lock().alloc_pages(num_pages, align_pow2);
+        }
         self.palloc.lock().alloc_pages(num_pages, align_pow2)
     }
 
@@ -154,6 +333,9 @@ impl GlobalAllocator {
     ///
     /// [`alloc_pages`]: GlobalAllocator::alloc_pages
     pub fn dealloc_pages(&self, pos: usize, num_pages: usize) {
+        if self.is_early_block(pos) {
+            return self.early.lock().dealloc_pages(pos, num_pages);
+        }
         self.palloc.lock().dealloc_pages(pos, num_pages)
     }
 
@@ -178,15 +360,47 @@ impl GlobalAllocator {
     }
 }
 
+/// Returns the frame pointer of the allocator's entry function, if the call
+/// sites are tracked.
+#[inline(always)]
+fn entry_frame() -> usize {
+    #[cfg(feature = "stats-callsite")]
+    return callsite::frame_pointer();
+    #[cfg(not(feature = "stats-callsite"))]
+    0
+}
+
+// The methods are the entry functions of the allocator, so `alloc_zeroed` and
+// `realloc` are not left to the default implementations, which would call
+// `alloc` and be recorded as the call sites.
 unsafe impl GlobalAlloc for GlobalAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if let Ok(ptr) = GlobalAllocator::alloc(self, layout) {
+        if let Ok(ptr) = self.alloc_from(layout, entry_frame()) {
             ptr.as_ptr()
         } else {
-            alloc::alloc::handle_alloc_error(layout)
+            oom::out_of_memory(layout)
         }
     }
 
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.alloc_from(layout, entry_frame()) {
+            core::ptr::write_bytes(ptr.as_ptr(), 0, layout.size());
+            ptr.as_ptr()
+        } else {
+            oom::out_of_memory(layout)
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let Ok(new_ptr) = self.alloc_from(new_layout, entry_frame()) else {
+            return oom::out_of_memory(new_layout);
+        };
+        core::ptr::copy_nonoverlapping(ptr, new_ptr.as_ptr(), layout.size().min(new_size));
+        GlobalAlloc::dealloc(self, ptr, layout);
+        new_ptr.as_ptr()
+    }
+
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         GlobalAllocator::dealloc(self, NonNull::new(ptr).expect("dealloc null ptr"), layout)
     }
@@ -200,6 +414,22 @@ pub fn global_allocator() -> &'static GlobalAllocator {
     &GLOBAL_ALLOCATOR
 }
 
+/// Lets the global allocator serve the allocations from the given memory
+/// region until it is initialized by [`global_init`].
+///
+/// The region must not overlap with the ones given to [`global_init`] and
+/// [`global_add_memory`], and is never reused after the initialization.
+///
+/// This function should be called only once, and before any allocation.
+pub fn global_early_init(start_vaddr: usize, size: usize) {
+    debug!(
+        "initialize early allocator at: [{:#x}, {:#x})",
+        start_vaddr,
+        start_vaddr + size
+    );
+    GLOBAL_ALLOCATOR.early_init(start_vaddr, size);
+}
+
 /// Initializes the global allocator with the given memory region.
 ///
 /// Note that the memory region bounds are just numbers, and the allocator
@@ -207,7 +437,8 @@ pub fn global_allocator() -> &'static GlobalAllocator {
 /// is valid and not being used by others, so that the allocated memory is also
 /// valid.
 ///
-/// This function should be called only once, and before any allocation.
+/// This function should be called only once. The allocations before it are
+/// only allowed after [`global_early_init`].
 pub fn global_init(start_vaddr: usize, size: usize) {
     debug!(
         "initialize global allocator at: [{:#x}, {:#x})",
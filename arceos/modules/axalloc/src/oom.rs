@@ -0,0 +1,150 @@
+//! Memory pressure and out-of-memory (OOM) handling.
+//!
+//! When the heap cannot be expanded, [`GlobalAllocator::alloc`] calls the
+//! reclaim callbacks registered by [`register_reclaimer`] (e.g., to shrink a
+//! page cache or drop network buffers), and retries if some memory has been
+//! reclaimed. If the allocation still fails, the allocation by
+//! [`GlobalAlloc::alloc`] is handled by the [`OomPolicy`], which is selected
+//! by the `oom-*` features and can be changed by [`set_oom_policy`].
+//!
+//! [`GlobalAllocator::alloc`]: crate::GlobalAllocator::alloc
+//! [`GlobalAlloc::alloc`]: core::alloc::GlobalAlloc::alloc
+
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use kspin::SpinNoIrq;
+
+/// A reclaim callback, which tries to free at least the given number of bytes
+/// on the heap, and returns the number of bytes freed.
+pub type ReclaimFn = fn(usize) -> usize;
+
+/// The maximum number of reclaim callbacks.
+const MAX_RECLAIMERS: usize = 8;
+
+/// The maximum number of retries after reclaiming for an allocation.
+pub(crate) const MAX_RECLAIM_RETRIES: usize = 4;
+
+static RECLAIMERS: SpinNoIrq<[Option<ReclaimFn>; MAX_RECLAIMERS]> =
+    SpinNoIrq::new([None; MAX_RECLAIMERS]);
+
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+
+/// What to do if an allocation fails after reclaiming.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomPolicy {
+    /// Panic in [`handle_alloc_error`](alloc::alloc::handle_alloc_error).
+    Panic = 0,
+    /// Return a null pointer, so that the C callers (e.g., `malloc`) can handle
+    /// the failure. Note that the Rust callers usually panic on it.
+    ReturnNull = 1,
+    /// Mark the allocating task as killed by the function set by
+    /// [`set_oom_killer`], and return a null pointer. The task exits at its
+    /// next safe point, as it may hold locks in the allocator. Panic if the
+    /// task cannot be killed.
+    KillTask = 2,
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "oom-kill-task")] {
+        const DEFAULT_OOM_POLICY: OomPolicy = OomPolicy::KillTask;
+    } else if #[cfg(feature = "oom-return-null")] {
+        const DEFAULT_OOM_POLICY: OomPolicy = OomPolicy::ReturnNull;
+    } else {
+        const DEFAULT_OOM_POLICY: OomPolicy = OomPolicy::Panic;
+    }
+}
+
+static OOM_POLICY: AtomicU8 = AtomicU8::new(DEFAULT_OOM_POLICY as u8);
+static OOM_KILLER: SpinNoIrq<Option<fn(Layout) -> bool>> = SpinNoIrq::new(None);
+
+/// Registers a callback to be called when the heap runs out of memory.
+///
+/// The callbacks are called without any allocator locks held, so they can
+/// free memory. They are not called recursively, so an allocation in a
+/// callback, or on other CPUs at the same time, fails without reclaiming.
+///
+/// Returns `false` if there are too many callbacks.
+pub fn register_reclaimer(reclaimer: ReclaimFn) -> bool {
+    match RECLAIMERS.lock().iter_mut().find(|r| r.is_none()) {
+        Some(slot) => {
+            *slot = Some(reclaimer);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Calls the reclaim callbacks until `size` bytes are freed, returns the
+/// number of bytes freed.
+pub(crate) fn reclaim(size: usize) -> usize {
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    #[cfg(feature = "percpu-cache")]
+    let mut freed = crate::cache::flush_local();
+    #[cfg(not(feature = "percpu-cache"))]
+    let mut freed = 0;
+    // Copy the callbacks out, so that they can register others.
+    let reclaimers = *RECLAIMERS.lock();
+    for reclaimer in reclaimers.into_iter().flatten() {
+        if freed >= size {
+            break;
+        }
+        freed += reclaimer(size - freed);
+    }
+    RECLAIMING.store(false, Ordering::Release);
+    if freed > 0 {
+        warn!(
+            "reclaimed {} bytes for an allocation of {} bytes",
+            freed, size
+        );
+    }
+    freed
+}
+
+/// Returns the current OOM policy.
+pub fn oom_policy() -> OomPolicy {
+    match OOM_POLICY.load(Ordering::Relaxed) {
+        1 => OomPolicy::ReturnNull,
+        2 => OomPolicy::KillTask,
+        _ => OomPolicy::Panic,
+    }
+}
+
+/// Sets the OOM policy.
+pub fn set_oom_policy(policy: OomPolicy) {
+    OOM_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Sets the function to kill the current task for [`OomPolicy::KillTask`].
+///
+/// The function should only mark the task as killed and return `true`, or
+/// return `false` if the task cannot be killed. It must not exit the task, as
+/// the caller of the allocator may hold any locks. It must not allocate
+/// memory.
+pub fn set_oom_killer(killer: fn(Layout) -> bool) {
+    *OOM_KILLER.lock() = Some(killer);
+}
+
+/// Handles the failed allocation by the OOM policy, returns a null pointer if
+/// the policy allows.
+pub(crate) fn out_of_memory(layout: Layout) -> *mut u8 {
+    error!(
+        "out of memory: failed to allocate {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+    match oom_policy() {
+        OomPolicy::Panic => {}
+        OomPolicy::ReturnNull => return core::ptr::null_mut(),
+        OomPolicy::KillTask => {
+            let killer = *OOM_KILLER.lock();
+            if killer.is_some_and(|killer| killer(layout)) {
+                return core::ptr::null_mut();
+            }
+        }
+    }
+    alloc::alloc::handle_alloc_error(layout)
+}
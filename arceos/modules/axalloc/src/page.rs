@@ -43,6 +43,17 @@ impl GlobalPage {
             .map_err(alloc_err_to_ax_err)
     }
 
+    /// Allocate a huge page of `size` bytes (e.g., 2M or 1G), which is
+    /// physically contiguous and aligned to its size.
+    ///
+    /// `size` must be a power of two and a multiple of 4K.
+    pub fn alloc_huge(size: usize) -> AxResult<Self> {
+        if !size.is_power_of_two() || size < PAGE_SIZE {
+            return Err(AxError::InvalidInput);
+        }
+        Self::alloc_contiguous(size / PAGE_SIZE, size)
+    }
+
     /// Get the start virtual address of this page.
     pub fn start_vaddr(&self) -> VirtAddr {
         self.start_vaddr
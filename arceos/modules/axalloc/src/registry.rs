@@ -0,0 +1,250 @@
+//! Selecting the byte allocator at runtime.
+//!
+//! The byte allocators are kept in a registry by names as trait objects. The
+//! built-in ones are enabled by the `tlsf`, `slab`, `buddy` and `segfit`
+//! features, and the kernel can register others by
+//! [`register_byte_allocator`]. One of them is selected by
+//! [`select_byte_allocator`], e.g., from the kernel command line, before the
+//! heap is initialized by [`global_init`](crate::global_init). If none is
+//! selected, [`DefaultByteAllocator`](crate::DefaultByteAllocator) is used.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator};
+use kspin::SpinNoIrq;
+
+/// A byte allocator as a trait object.
+pub type DynByteAllocator = dyn ByteAllocator + Send;
+
+/// The name to select [`DefaultByteAllocator`](crate::DefaultByteAllocator).
+pub const DEFAULT_NAME: &str = "default";
+
+/// The maximum number of byte allocators registered by the kernel.
+const MAX_ALLOCATORS: usize = 8;
+
+/// The names of the built-in byte allocators.
+const BUILTIN_NAMES: &[&str] = &[
+    #[cfg(feature = "tlsf")]
+    "tlsf",
+    #[cfg(feature = "slab")]
+    "slab",
+    #[cfg(feature = "buddy")]
+    "buddy",
+    #[cfg(feature = "segfit")]
+    "segfit",
+];
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "slab")] {
+        const DEFAULT_BUILTIN: &str = "slab";
+    } else if #[cfg(feature = "buddy")] {
+        const DEFAULT_BUILTIN: &str = "buddy";
+    } else if #[cfg(feature = "segfit")] {
+        const DEFAULT_BUILTIN: &str = "segfit";
+    } else if #[cfg(feature = "tlsf")] {
+        const DEFAULT_BUILTIN: &str = "tlsf";
+    }
+}
+
+struct Registry {
+    allocators: [Option<(&'static str, &'static mut DynByteAllocator)>; MAX_ALLOCATORS],
+    selected: &'static str,
+    /// The selected allocator has been taken by the heap.
+    taken: bool,
+}
+
+impl Registry {
+    fn find_name(&self, name: &str) -> Option<&'static str> {
+        let registered = self.allocators.iter().flatten().map(|(n, _)| *n);
+        registered
+            .chain(BUILTIN_NAMES.iter().copied())
+            .find(|n| *n == name)
+    }
+}
+
+const NO_ALLOCATOR: Option<(&str, &mut DynByteAllocator)> = None;
+
+static REGISTRY: SpinNoIrq<Registry> = SpinNoIrq::new(Registry {
+    allocators: [NO_ALLOCATOR; MAX_ALLOCATORS],
+    selected: DEFAULT_BUILTIN,
+    taken: false,
+});
+
+/// Registers a byte allocator by the name, so that it can be selected by
+/// [`select_byte_allocator`].
+///
+/// The allocator is not initialized until it is selected and the heap is
+/// initialized. Returns `false` if the name has been used, there are too many
+/// allocators, or the heap has been initialized.
+pub fn register_byte_allocator(name: &'static str, alloc: &'static mut DynByteAllocator) -> bool {
+    let mut registry = REGISTRY.lock();
+    if registry.taken || name == DEFAULT_NAME || registry.find_name(name).is_some() {
+        return false;
+    }
+    match registry.allocators.iter_mut().find(|a| a.is_none()) {
+        Some(slot) => {
+            *slot = Some((name, alloc));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Selects the byte allocator by the name, [`DEFAULT_NAME`] selects
+/// [`DefaultByteAllocator`](crate::DefaultByteAllocator).
+///
+/// It must be called before [`global_init`](crate::global_init). Returns
+/// `false` if no allocator has the name, or the heap has been initialized.
+pub fn select_byte_allocator(name: &str) -> bool {
+    let mut registry = REGISTRY.lock();
+    if registry.taken {
+        return false;
+    }
+    let name = if name == DEFAULT_NAME {
+        DEFAULT_BUILTIN
+    } else {
+        match registry.find_name(name) {
+            Some(name) => name,
+            None => return false,
+        }
+    };
+    registry.selected = name;
+    true
+}
+
+/// Returns the name of the selected byte allocator.
+pub fn selected_name() -> &'static str {
+    REGISTRY.lock().selected
+}
+
+/// Returns the names of all the byte allocators that can be selected.
+pub fn byte_allocator_names() -> impl Iterator<Item = &'static str> {
+    let registry = REGISTRY.lock();
+    let registered: [Option<&'static str>; MAX_ALLOCATORS] =
+        core::array::from_fn(|i| registry.allocators[i].as_ref().map(|(n, _)| *n));
+    BUILTIN_NAMES
+        .iter()
+        .copied()
+        .chain(registered.into_iter().flatten())
+}
+
+/// Returns the built-in byte allocator with the name.
+///
+/// # Safety
+///
+/// It must be called at most once for each name.
+unsafe fn builtin(name: &str) -> Option<&'static mut DynByteAllocator> {
+    use core::ptr::addr_of_mut;
+    match name {
+        #[cfg(feature = "tlsf")]
+        "tlsf" => {
+            static mut TLSF: allocator::TlsfByteAllocator = allocator::TlsfByteAllocator::new();
+            Some(&mut *addr_of_mut!(TLSF))
+        }
+        #[cfg(feature = "slab")]
+        "slab" => {
+            static mut SLAB: allocator::SlabByteAllocator = allocator::SlabByteAllocator::new();
+            Some(&mut *addr_of_mut!(SLAB))
+        }
+        #[cfg(feature = "buddy")]
+        "buddy" => {
+            static mut BUDDY: allocator::BuddyByteAllocator = allocator::BuddyByteAllocator::new();
+            Some(&mut *addr_of_mut!(BUDDY))
+        }
+        #[cfg(feature = "segfit")]
+        "segfit" => {
+            static mut SEGFIT: segfit_allocator::SegFitByteAllocator =
+                segfit_allocator::SegFitByteAllocator::new();
+            Some(&mut *addr_of_mut!(SEGFIT))
+        }
+        _ => None,
+    }
+}
+
+/// Takes the selected byte allocator out of the registry, which can only be
+/// done once.
+fn take_selected() -> (&'static str, &'static mut DynByteAllocator) {
+    let mut registry = REGISTRY.lock();
+    assert!(!registry.taken, "the byte allocator has been taken");
+    registry.taken = true;
+    let name = registry.selected;
+    let registered = registry
+        .allocators
+        .iter_mut()
+        .find(|a| matches!(a, Some((n, _)) if *n == name))
+        .and_then(|a| a.take());
+    match registered {
+        Some(entry) => entry,
+        // Safety: the built-in allocator is only taken here, once.
+        None => (name, unsafe { builtin(name) }.unwrap()),
+    }
+}
+
+/// The byte allocator of the heap, which forwards to the selected one after
+/// initialization.
+pub(crate) struct SelectedByteAllocator {
+    /// The selected allocator, taken from the registry as `&'static mut`.
+    inner: Option<NonNull<DynByteAllocator>>,
+}
+
+unsafe impl Send for SelectedByteAllocator {}
+
+impl SelectedByteAllocator {
+    pub const fn new() -> Self {
+        Self { inner: None }
+    }
+
+    fn get(&self) -> Option<&DynByteAllocator> {
+        // Safety: it is the only reference to the selected allocator.
+        self.inner.map(|inner| unsafe { &*inner.as_ptr() })
+    }
+
+    fn get_mut(&mut self) -> Option<&mut DynByteAllocator> {
+        // Safety: it is the only reference to the selected allocator.
+        self.inner.map(|inner| unsafe { &mut *inner.as_ptr() })
+    }
+}
+
+impl BaseAllocator for SelectedByteAllocator {
+    fn init(&mut self, start: usize, size: usize) {
+        let (name, inner) = take_selected();
+        debug!("use {} byte allocator", name);
+        inner.init(start, size);
+        self.inner = Some(NonNull::from(inner));
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        match self.get_mut() {
+            Some(inner) => inner.add_memory(start, size),
+            None => Err(AllocError::InvalidParam),
+        }
+    }
+}
+
+impl ByteAllocator for SelectedByteAllocator {
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        match self.get_mut() {
+            Some(inner) => inner.alloc(layout),
+            None => Err(AllocError::NoMemory),
+        }
+    }
+
+    fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+        if let Some(inner) = self.get_mut() {
+            inner.dealloc(pos, layout)
+        }
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.get().map_or(0, |inner| inner.total_bytes())
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.get().map_or(0, |inner| inner.used_bytes())
+    }
+
+    fn available_bytes(&self) -> usize {
+        self.get().map_or(0, |inner| inner.available_bytes())
+    }
+}
@@ -0,0 +1,143 @@
+//! Heap statistics, enabled by the `stats` feature.
+//!
+//! The allocations of [`GlobalAllocator::alloc`](crate::GlobalAllocator::alloc)
+//! are counted by size classes, and the current and peak heap usage are
+//! recorded. If the `stats-callsite` feature is also enabled, the live
+//! allocations are tracked by their call sites, and [`dump`] prints the call
+//! sites that hold the most memory, which helps to find memory leaks.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of size classes.
+///
+/// Size class 0 contains the allocations of at most 8 bytes, and size class
+/// `i` contains the allocations of `(4 << i, 8 << i]` bytes. The last class
+/// also contains all larger allocations.
+pub const NUM_SIZE_CLASSES: usize = 16;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+static ALLOC_COUNTS: [AtomicUsize; NUM_SIZE_CLASSES] = [ZERO; NUM_SIZE_CLASSES];
+static FREE_COUNTS: [AtomicUsize; NUM_SIZE_CLASSES] = [ZERO; NUM_SIZE_CLASSES];
+static FAILED_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// A snapshot of the heap statistics, returned by [`heap_stats`].
+#[derive(Debug, Clone)]
+pub struct HeapStats {
+    /// The number of allocations of each size class.
+    pub alloc_counts: [usize; NUM_SIZE_CLASSES],
+    /// The number of deallocations of each size class.
+    pub free_counts: [usize; NUM_SIZE_CLASSES],
+    /// The number of failed allocations.
+    pub failed_allocs: usize,
+    /// The number of bytes currently allocated.
+    pub current_bytes: usize,
+    /// The maximum of `current_bytes` since boot or the last [`reset_peak`].
+    pub peak_bytes: usize,
+}
+
+impl HeapStats {
+    /// Returns the total number of allocations.
+    pub fn total_allocs(&self) -> usize {
+        self.alloc_counts.iter().sum()
+    }
+
+    /// Returns the number of allocations that have not been freed.
+    pub fn live_allocs(&self) -> usize {
+        self.total_allocs()
+            .saturating_sub(self.free_counts.iter().sum())
+    }
+}
+
+/// Returns the size class of an allocation of `size` bytes.
+pub const fn size_class(size: usize) -> usize {
+    if size <= 8 {
+        return 0;
+    }
+    let class = (usize::BITS - (size - 1).leading_zeros()) as usize - 3;
+    if class < NUM_SIZE_CLASSES {
+        class
+    } else {
+        NUM_SIZE_CLASSES - 1
+    }
+}
+
+pub(crate) fn record_alloc(size: usize) {
+    ALLOC_COUNTS[size_class(size)].fetch_add(1, Ordering::Relaxed);
+    let current = CURRENT_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+}
+
+pub(crate) fn record_free(size: usize) {
+    FREE_COUNTS[size_class(size)].fetch_add(1, Ordering::Relaxed);
+    CURRENT_BYTES.fetch_sub(size, Ordering::Relaxed);
+}
+
+pub(crate) fn record_failure() {
+    FAILED_ALLOCS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns a snapshot of the heap statistics.
+pub fn heap_stats() -> HeapStats {
+    let load = |counts: &[AtomicUsize; NUM_SIZE_CLASSES]| -> [usize; NUM_SIZE_CLASSES] {
+        core::array::from_fn(|i| counts[i].load(Ordering::Relaxed))
+    };
+    HeapStats {
+        alloc_counts: load(&ALLOC_COUNTS),
+        free_counts: load(&FREE_COUNTS),
+        failed_allocs: FAILED_ALLOCS.load(Ordering::Relaxed),
+        current_bytes: CURRENT_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// Resets the peak usage to the current usage.
+pub fn reset_peak() {
+    PEAK_BYTES.store(CURRENT_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+/// Prints the heap statistics, and the call sites with the most live bytes if
+/// the `stats-callsite` feature is enabled.
+///
+/// The report is printed at the `warn` level, so that it is not filtered out
+/// by the default log level.
+pub fn dump() {
+    let stats = heap_stats();
+    warn!("Heap statistics:");
+    warn!(
+        "  current: {} bytes, peak: {} bytes",
+        stats.current_bytes, stats.peak_bytes
+    );
+    warn!(
+        "  allocations: {} total, {} live, {} failed",
+        stats.total_allocs(),
+        stats.live_allocs(),
+        stats.failed_allocs
+    );
+    for class in 0..NUM_SIZE_CLASSES {
+        let (allocs, frees) = (stats.alloc_counts[class], stats.free_counts[class]);
+        if allocs == 0 {
+            continue;
+        }
+        if class == NUM_SIZE_CLASSES - 1 {
+            warn!(
+                "  > {:>6} bytes: {} allocs, {} frees",
+                4 << class,
+                allocs,
+                frees
+            );
+        } else {
+            warn!(
+                "  <= {:>5} bytes: {} allocs, {} frees",
+                8 << class,
+                allocs,
+                frees
+            );
+        }
+    }
+    #[cfg(feature = "stats-callsite")]
+    crate::callsite::dump_top(10);
+}
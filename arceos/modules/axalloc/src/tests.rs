@@ -0,0 +1,264 @@
+#[cfg(feature = "stats-callsite")]
+mod callsite {
+    use core::mem::size_of;
+
+    use crate::callsite::{capture_callsite, CALLSITE_DEPTH};
+
+    /// Builds the frames with the return addresses `ras` on `stack`, each
+    /// frame is the caller of the previous one. Returns the frame pointer of
+    /// the first frame.
+    fn fake_frames(stack: &mut [usize], ras: &[usize]) -> usize {
+        const FRAME_WORDS: usize = 4;
+        assert!(stack.len() >= (ras.len() + 1) * FRAME_WORDS);
+        let base = stack.as_mut_ptr() as usize;
+        let fp_of = |i: usize| base + (i * FRAME_WORDS + 2) * size_of::<usize>();
+        for (i, &ra) in ras.iter().enumerate() {
+            let fp = fp_of(i) as *mut usize;
+            let next_fp = if i + 1 < ras.len() { fp_of(i + 1) } else { 0 };
+            unsafe {
+                if cfg!(any(target_arch = "riscv32", target_arch = "riscv64")) {
+                    fp.sub(1).write(ra);
+                    fp.sub(2).write(next_fp);
+                } else {
+                    fp.add(1).write(ra);
+                    fp.write(next_fp);
+                }
+            }
+        }
+        fp_of(0)
+    }
+
+    #[test]
+    fn test_top_frame_is_caller_of_entry() {
+        let mut stack = [0; 64];
+        let ras = [0x1000, 0x2000, 0x3000];
+        let entry_fp = fake_frames(&mut stack, &ras);
+        let addrs = capture_callsite(entry_fp);
+        // The entry function itself is not recorded, the top frame is the
+        // return address into its caller.
+        assert_eq!(addrs[0], 0x1000);
+        assert_eq!(&addrs[..3], &ras);
+        assert!(addrs[3..].iter().all(|&a| a == 0));
+    }
+
+    #[test]
+    fn test_depth_is_limited() {
+        let mut stack = [0; 128];
+        let ras: [usize; CALLSITE_DEPTH + 4] = core::array::from_fn(|i| 0x1000 * (i + 1));
+        let entry_fp = fake_frames(&mut stack, &ras);
+        assert_eq!(capture_callsite(entry_fp), ras[..CALLSITE_DEPTH]);
+    }
+
+    #[test]
+    fn test_invalid_frame_pointer() {
+        assert_eq!(capture_callsite(0), [0; CALLSITE_DEPTH]);
+        assert_eq!(capture_callsite(0x1001), [0; CALLSITE_DEPTH]);
+    }
+}
+
+#[cfg(feature = "debug")]
+mod debug {
+    use core::alloc::Layout;
+    use core::cell::RefCell;
+    use core::ptr::NonNull;
+    use std::rc::Rc;
+
+    use allocator::{AllocResult, BaseAllocator, ByteAllocator};
+
+    use crate::debug::{DebugByteAllocator, POISON_BYTE, QUARANTINE_LEN, REDZONE_SIZE};
+
+    /// The blocks allocated from and given back to the inner allocator.
+    #[derive(Default)]
+    struct Log {
+        allocated: Vec<usize>,
+        freed: Vec<usize>,
+    }
+
+    /// The inner allocator backed by the host heap.
+    #[derive(Default)]
+    struct HostAllocator {
+        used: usize,
+        log: Rc<RefCell<Log>>,
+    }
+
+    impl BaseAllocator for HostAllocator {
+        fn init(&mut self, _start: usize, _size: usize) {}
+
+        fn add_memory(&mut self, _start: usize, _size: usize) -> AllocResult {
+            Ok(())
+        }
+    }
+
+    impl ByteAllocator for HostAllocator {
+        fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+            let ptr = NonNull::new(unsafe { std::alloc::alloc(layout) })
+                .ok_or(allocator::AllocError::NoMemory)?;
+            self.used += layout.size();
+            self.log.borrow_mut().allocated.push(ptr.as_ptr() as usize);
+            Ok(ptr)
+        }
+
+        fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+            self.used -= layout.size();
+            self.log.borrow_mut().freed.push(pos.as_ptr() as usize);
+            unsafe { std::alloc::dealloc(pos.as_ptr(), layout) }
+        }
+
+        fn total_bytes(&self) -> usize {
+            usize::MAX
+        }
+
+        fn used_bytes(&self) -> usize {
+            self.used
+        }
+
+        fn available_bytes(&self) -> usize {
+            usize::MAX - self.used
+        }
+    }
+
+    fn new_allocator() -> DebugByteAllocator<HostAllocator> {
+        DebugByteAllocator::new(HostAllocator::default())
+    }
+
+    fn new_allocator_with_log() -> (DebugByteAllocator<HostAllocator>, Rc<RefCell<Log>>) {
+        let inner = HostAllocator::default();
+        let log = inner.log.clone();
+        (DebugByteAllocator::new(inner), log)
+    }
+
+    const LAYOUT: Layout = Layout::new::<[u64; 4]>();
+
+    #[test]
+    fn test_alloc_dealloc() {
+        let mut heap = new_allocator();
+        let ptrs: Vec<_> = (0..16).map(|_| heap.alloc(LAYOUT).unwrap()).collect();
+        for &ptr in &ptrs {
+            assert_eq!(ptr.as_ptr() as usize % LAYOUT.align(), 0);
+            unsafe { ptr.as_ptr().write_bytes(0xaa, LAYOUT.size()) };
+        }
+        assert_eq!(heap.check(), 0);
+        for ptr in ptrs {
+            heap.dealloc(ptr, LAYOUT);
+        }
+        assert_eq!(heap.check(), 0);
+    }
+
+    #[test]
+    fn test_redzone_overflow() {
+        let mut heap = new_allocator();
+        let ptr = heap.alloc(LAYOUT).unwrap();
+        unsafe { ptr.as_ptr().add(LAYOUT.size()).write(0) };
+        assert_eq!(heap.check(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "red zone overwritten at offset 32")]
+    fn test_redzone_overflow_on_dealloc() {
+        let mut heap = new_allocator();
+        let ptr = heap.alloc(LAYOUT).unwrap();
+        unsafe { ptr.as_ptr().add(LAYOUT.size()).write(0) };
+        heap.dealloc(ptr, LAYOUT);
+    }
+
+    #[test]
+    #[should_panic(expected = "red zone overwritten at offset -")]
+    fn test_redzone_underflow_on_dealloc() {
+        let (mut heap, log) = new_allocator_with_log();
+        let ptr = heap.alloc(LAYOUT).unwrap();
+        // The front red zone starts at the block from the inner allocator.
+        let front = log.borrow().allocated[0] as *mut u8;
+        assert!(front as usize + REDZONE_SIZE <= ptr.as_ptr() as usize);
+        unsafe { front.add(REDZONE_SIZE - 1).write(0) };
+        heap.dealloc(ptr, LAYOUT);
+    }
+
+    #[test]
+    fn test_use_after_free() {
+        let mut heap = new_allocator();
+        let ptr = heap.alloc(LAYOUT).unwrap();
+        heap.dealloc(ptr, LAYOUT);
+        let data = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), LAYOUT.size()) };
+        assert!(data.iter().all(|&b| b == POISON_BYTE));
+        assert_eq!(heap.check(), 0);
+
+        unsafe { ptr.as_ptr().add(8).write(0) };
+        assert_eq!(heap.check(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "written after free at offset 8")]
+    fn test_use_after_free_on_eviction() {
+        let mut heap = new_allocator();
+        let ptr = heap.alloc(LAYOUT).unwrap();
+        heap.dealloc(ptr, LAYOUT);
+        unsafe { ptr.as_ptr().add(8).write(0) };
+        for _ in 0..QUARANTINE_LEN {
+            let ptr = heap.alloc(LAYOUT).unwrap();
+            heap.dealloc(ptr, LAYOUT);
+        }
+    }
+
+    #[test]
+    fn test_quarantine_eviction() {
+        let (mut heap, log) = new_allocator_with_log();
+        let ptrs: Vec<_> = (0..QUARANTINE_LEN + 2)
+            .map(|_| heap.alloc(LAYOUT).unwrap())
+            .collect();
+        for &ptr in &ptrs[..QUARANTINE_LEN] {
+            heap.dealloc(ptr, LAYOUT);
+        }
+        // All the freed blocks are still kept in the quarantine.
+        assert!(log.borrow().freed.is_empty());
+
+        // The oldest blocks are given back to the inner allocator.
+        heap.dealloc(ptrs[QUARANTINE_LEN], LAYOUT);
+        assert_eq!(log.borrow().freed, log.borrow().allocated[..1]);
+        heap.dealloc(ptrs[QUARANTINE_LEN + 1], LAYOUT);
+        assert_eq!(log.borrow().freed, log.borrow().allocated[..2]);
+        assert_eq!(heap.check(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn test_double_free() {
+        let mut heap = new_allocator();
+        let ptr = heap.alloc(LAYOUT).unwrap();
+        heap.dealloc(ptr, LAYOUT);
+        heap.dealloc(ptr, LAYOUT);
+    }
+}
+
+mod early {
+    use core::alloc::Layout;
+    use core::sync::atomic::Ordering;
+
+    use allocator::ByteAllocator;
+
+    use crate::{GlobalAllocator, PAGE_SIZE};
+
+    const EARLY_SIZE: usize = 16 * PAGE_SIZE;
+
+    #[test]
+    fn test_early_blocks_not_freed_into_heap() {
+        let region = Layout::from_size_align(EARLY_SIZE, PAGE_SIZE).unwrap();
+        let start = unsafe { std::alloc::alloc(region) } as usize;
+        let early = start..start + EARLY_SIZE;
+        let allocator = GlobalAllocator::new();
+        allocator.early_init(start, EARLY_SIZE);
+
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = allocator.alloc(layout).unwrap();
+        assert!(early.contains(&(ptr.as_ptr() as usize)));
+        let page = allocator.alloc_pages(1, PAGE_SIZE).unwrap();
+        assert!(early.contains(&page));
+
+        // The heap is not set up, so freeing the early blocks into it after
+        // the handoff would fail.
+        allocator.initialized.store(true, Ordering::Release);
+        allocator.dealloc(ptr, layout);
+        allocator.dealloc_pages(page, 1);
+        assert_eq!(allocator.early.lock().used_bytes(), 0);
+        unsafe { std::alloc::dealloc(start as *mut u8, region) };
+    }
+}
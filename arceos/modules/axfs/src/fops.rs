@@ -19,6 +19,8 @@ pub type DirEntry = axfs_vfs::VfsDirEntry;
 pub type FileAttr = axfs_vfs::VfsNodeAttr;
 /// Alias of [`axfs_vfs::VfsNodePerm`].
 pub type FilePerm = axfs_vfs::VfsNodePerm;
+/// Alias of [`axfs_vfs::VfsNodeRef`].
+pub type FileNode = axfs_vfs::VfsNodeRef;
 
 /// An opened file object, with open permissions and a cursor.
 pub struct File {
@@ -246,6 +248,19 @@ impl File {
     pub fn get_attr(&self) -> AxResult<FileAttr> {
         self.access_node(Cap::empty())?.get_attr()
     }
+
+    /// Gets the underlying node of the file, e.g., to map it into memory.
+    ///
+    /// The file must be opened for reading, and also for writing if
+    /// `writable` is `true`.
+    pub fn node(&self, writable: bool) -> AxResult<&FileNode> {
+        let cap = if writable {
+            Cap::READ | Cap::WRITE
+        } else {
+            Cap::READ
+        };
+        self.access_node(cap)
+    }
 }
 
 impl Directory {
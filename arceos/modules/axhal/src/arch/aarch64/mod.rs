@@ -35,6 +35,18 @@ pub fn wait_for_irqs() {
     aarch64_cpu::asm::wfi();
 }
 
+/// Enables interrupts and waits for the next one, atomically.
+///
+/// An interrupt that arrives after interrupts are disabled still wakes up the
+/// CPU, so the caller can check for wake-up events with interrupts disabled,
+/// and then call it without missing the interrupt that reports the event.
+#[inline]
+pub fn enable_irqs_and_wait() {
+    // WFI wakes up on a pending IRQ even if it is masked.
+    aarch64_cpu::asm::wfi();
+    enable_irqs();
+}
+
 /// Halt the current CPU.
 #[inline]
 pub fn halt() {
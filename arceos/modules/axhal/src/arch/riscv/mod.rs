@@ -38,6 +38,18 @@ pub fn wait_for_irqs() {
     riscv::asm::wfi()
 }
 
+/// Enables interrupts and waits for the next one, atomically.
+///
+/// An interrupt that arrives after interrupts are disabled still wakes up the
+/// CPU, so the caller can check for wake-up events with interrupts disabled,
+/// and then call it without missing the interrupt that reports the event.
+#[inline]
+pub fn enable_irqs_and_wait() {
+    // WFI wakes up on a pending interrupt even if it is disabled.
+    riscv::asm::wfi();
+    enable_irqs();
+}
+
 /// Halt the current CPU.
 #[inline]
 pub fn halt() {
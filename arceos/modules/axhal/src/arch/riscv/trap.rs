@@ -21,6 +21,9 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bo
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(stval::read());
+    if !is_user {
+        crate::trap::check_stack_guard(vaddr);
+    }
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
         panic!(
             "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
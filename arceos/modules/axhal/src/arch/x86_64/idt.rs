@@ -6,6 +6,10 @@ use x86_64::structures::DescriptorTablePointer;
 
 const NUM_INT: usize = 256;
 
+/// The index of the interrupt stack table (IST) entry used by the double fault
+/// handler.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
 /// A wrapper of the Interrupt Descriptor Table (IDT).
 #[repr(transparent)]
 pub struct IdtStruct {
@@ -35,6 +39,14 @@ impl IdtStruct {
             #[allow(clippy::missing_transmute_annotations)]
             entries[i].set_handler_fn(unsafe { core::mem::transmute(ENTRIES[i]) });
         }
+        // Handle double faults on a known good stack, so that a kernel stack
+        // overflow can be reported rather than causing a triple fault.
+        unsafe {
+            #[allow(clippy::missing_transmute_annotations)]
+            entries[8]
+                .set_handler_fn(core::mem::transmute(ENTRIES[8]))
+                .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        }
         idt
     }
 
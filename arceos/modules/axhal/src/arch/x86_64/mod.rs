@@ -13,7 +13,7 @@ use x86_64::instructions::interrupts;
 
 pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame};
 pub use self::gdt::GdtStruct;
-pub use self::idt::IdtStruct;
+pub use self::idt::{IdtStruct, DOUBLE_FAULT_IST_INDEX};
 pub use x86_64::structures::tss::TaskStateSegment;
 
 /// Allows the current CPU to respond to interrupts.
@@ -48,6 +48,22 @@ pub fn wait_for_irqs() {
     }
 }
 
+/// Enables interrupts and waits for the next one, atomically.
+///
+/// An interrupt that arrives after interrupts are disabled still wakes up the
+/// CPU, so the caller can check for wake-up events with interrupts disabled,
+/// and then call it without missing the interrupt that reports the event.
+#[inline]
+pub fn enable_irqs_and_wait() {
+    if cfg!(target_os = "none") {
+        // STI takes effect after the next instruction, so no IRQ can be
+        // handled before HLT.
+        interrupts::enable_and_hlt()
+    } else {
+        core::hint::spin_loop()
+    }
+}
+
 /// Halt the current CPU.
 #[inline]
 pub fn halt() {
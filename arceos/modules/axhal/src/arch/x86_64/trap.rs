@@ -13,6 +13,9 @@ fn handle_page_fault(tf: &TrapFrame) {
     let access_flags = err_code_to_flags(tf.error_code)
         .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
     let vaddr = va!(unsafe { cr2() });
+    if !tf.is_user() {
+        crate::trap::check_stack_guard(vaddr);
+    }
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
         panic!(
             "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
@@ -31,6 +34,14 @@ fn x86_trap_handler(tf: &TrapFrame) {
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
+        DOUBLE_FAULT_VECTOR => {
+            // A kernel stack overflow ends up here, as the #PF cannot be
+            // delivered on the faulting stack.
+            if !tf.is_user() {
+                crate::trap::check_stack_guard(va!(unsafe { cr2() }));
+            }
+            panic!("#DF @ {:#x}:\n{:#x?}", tf.rip, tf);
+        }
         GENERAL_PROTECTION_FAULT_VECTOR => {
             panic!(
                 "#GP @ {:#x}, error_code={:#x}:\n{:#x?}",
@@ -6,6 +6,36 @@ use crate::time;
 static PARK_MILLER_LEHMER_SEED: SpinNoIrq<u32> = SpinNoIrq::new(0);
 const RAND_MAX: u64 = 2_147_483_647;
 
+const MAX_SHUTDOWN_HOOKS: usize = 8;
+
+static SHUTDOWN_HOOKS: SpinNoIrq<[Option<fn()>; MAX_SHUTDOWN_HOOKS]> =
+    SpinNoIrq::new([None; MAX_SHUTDOWN_HOOKS]);
+
+/// Registers a function to be called by [`terminate`] before shutting down.
+///
+/// Returns `false` if there are too many hooks.
+pub fn register_shutdown_hook(hook: fn()) -> bool {
+    match SHUTDOWN_HOOKS.lock().iter_mut().find(|h| h.is_none()) {
+        Some(slot) => {
+            *slot = Some(hook);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Shutdown the whole system, including all CPUs, after calling the hooks
+/// registered by [`register_shutdown_hook`].
+pub fn terminate() -> ! {
+    // Take the hooks out, so that they are called only once even if a hook
+    // terminates the system again.
+    let hooks = core::mem::replace(&mut *SHUTDOWN_HOOKS.lock(), [None; MAX_SHUTDOWN_HOOKS]);
+    for hook in hooks.into_iter().flatten() {
+        hook();
+    }
+    super::platform::misc::terminate()
+}
+
 pub fn random() -> u128 {
 	let mut seed = PARK_MILLER_LEHMER_SEED.lock();
     if *seed == 0 {
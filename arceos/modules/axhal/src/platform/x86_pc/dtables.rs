@@ -1,7 +1,21 @@
 //! Description tables (per-CPU GDT, per-CPU ISS, IDT)
 
-use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment};
+use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment, DOUBLE_FAULT_IST_INDEX};
 use lazyinit::LazyInit;
+use x86_64::VirtAddr;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 0x4000;
+
+#[repr(align(16))]
+struct DoubleFaultStack([u8; DOUBLE_FAULT_STACK_SIZE]);
+
+impl DoubleFaultStack {
+    const EMPTY: Self = Self([0; DOUBLE_FAULT_STACK_SIZE]);
+}
+
+/// Per-CPU stacks for the double fault handler, see [`DOUBLE_FAULT_IST_INDEX`].
+static mut DOUBLE_FAULT_STACKS: [DoubleFaultStack; axconfig::SMP] =
+    [DoubleFaultStack::EMPTY; axconfig::SMP];
 
 static IDT: LazyInit<IdtStruct> = LazyInit::new();
 
@@ -16,7 +30,11 @@ fn init_percpu() {
         IDT.load();
         let tss = TSS.current_ref_mut_raw();
         let gdt = GDT.current_ref_mut_raw();
-        tss.init_once(TaskStateSegment::new());
+        let mut tss_struct = TaskStateSegment::new();
+        let df_stack = core::ptr::addr_of!(DOUBLE_FAULT_STACKS[crate::cpu::this_cpu_id()]);
+        tss_struct.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::new((df_stack as usize + DOUBLE_FAULT_STACK_SIZE) as u64);
+        tss.init_once(tss_struct);
         gdt.init_once(GdtStruct::new(tss));
         gdt.load();
         gdt.load_tss();
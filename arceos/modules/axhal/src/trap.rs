@@ -17,6 +17,13 @@ pub static IRQ: [fn(usize) -> bool];
 #[def_trap_handler]
 pub static PAGE_FAULT: [fn(VirtAddr, MappingFlags, bool) -> bool];
 
+/// A slice of kernel stack guard checkers.
+///
+/// A checker calls the given function with the stack information if the
+/// faulting address hits the guard page of a kernel stack.
+#[def_trap_handler]
+pub static STACK_GUARD: [fn(VirtAddr, &mut dyn FnMut(KernelStackInfo))];
+
 /// A slice of syscall handler functions.
 #[cfg(feature = "uspace")]
 #[def_trap_handler]
@@ -38,6 +45,29 @@ macro_rules! handle_trap {
     }}
 }
 
+/// The kernel stack whose guard page has been hit, see [`STACK_GUARD`].
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStackInfo<'a> {
+    /// The name of the task that owns the stack.
+    pub task_name: &'a str,
+    /// The lowest address of the stack.
+    pub bottom: VirtAddr,
+    /// The highest address (exclusive) of the stack.
+    pub top: VirtAddr,
+}
+
+/// Panics if the kernel-mode fault at `vaddr` is a kernel stack overflow.
+pub(crate) fn check_stack_guard(vaddr: VirtAddr) {
+    for checker in STACK_GUARD {
+        checker(vaddr, &mut |stack| {
+            panic!(
+                "Kernel stack overflow in task {:?}: fault_vaddr={:#x}, stack=[{:#x}, {:#x})",
+                stack.task_name, vaddr, stack.bottom, stack.top,
+            )
+        });
+    }
+}
+
 /// Call the external syscall handler.
 #[cfg(feature = "uspace")]
 pub(crate) fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
@@ -6,19 +6,27 @@ use axhal::{
     paging::{MappingFlags, PageTable},
 };
 use memory_addr::{
-    is_aligned_4k, pa, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K,
+    align_up_4k, is_aligned_4k, pa, va, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange,
+    PAGE_SIZE_4K,
 };
 use memory_set::{MemoryArea, MemorySet};
-use crate::backend::Backend;
+use crate::backend::{split_huge_edges, split_huge_pages, unshare_page, Backend};
+use crate::frame;
+use crate::SharedMemory;
 use crate::paging_err_to_ax_err;
 use crate::mapping_err_to_ax_err;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+#[cfg(feature = "swap")]
+use crate::swap::SwapState;
 
 /// The virtual memory address space.
 pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    #[cfg(feature = "swap")]
+    swap: SwapState,
 }
 
 impl AddrSpace {
@@ -59,6 +67,8 @@ impl AddrSpace {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| AxError::NoMemory)?,
+            #[cfg(feature = "swap")]
+            swap: SwapState::new(),
         })
     }
 
@@ -77,6 +87,85 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Creates a copy of the address space, e.g., for `fork`.
+    ///
+    /// The frames of the allocation mappings and private file mappings are
+    /// shared with the new address space instead of copied. They are mapped
+    /// read-only in both address spaces, and copied on the first write
+    /// (copy-on-write). The frames of the shared file mappings are shared on
+    /// write, so are the shared memory mappings. The pages not populated yet
+    /// are allocated on demand separately.
+    ///
+    /// The kernel mappings are also copied if the address space does not
+    /// overlap the kernel address space.
+    pub fn clone_cow(&mut self) -> AxResult<Self> {
+        let mut new_aspace = Self::new_empty(self.base(), self.size())?;
+        let kernel_range = VirtAddrRange::from_start_size(
+            va!(axconfig::KERNEL_ASPACE_BASE),
+            axconfig::KERNEL_ASPACE_SIZE,
+        );
+        if !self.va_range.overlaps(kernel_range) {
+            new_aspace.copy_mappings_from(&crate::kernel_aspace().lock())?;
+        }
+        // The swapped out pages are read back to be shared.
+        #[cfg(feature = "swap")]
+        self.restore_swapped(self.base(), self.size())?;
+
+        for area in self.areas.iter() {
+            let backend = match area.backend() {
+                // The shared frames are mapped below, instead of populated.
+                Backend::Alloc { .. } => Backend::new_alloc(false),
+                backend => backend.clone(),
+            };
+            let new_area = MemoryArea::new(area.start(), area.size(), area.flags(), backend);
+            new_aspace
+                .areas
+                .map(new_area, &mut new_aspace.pt, false)
+                .map_err(mapping_err_to_ax_err)?;
+            // The frames of the shared memory are mapped by the backend.
+            if matches!(
+                area.backend(),
+                Backend::Linear { .. } | Backend::Shared { .. }
+            ) {
+                continue;
+            }
+
+            // The frames are shared page by page.
+            if !split_huge_pages(&mut self.pt, area.start(), area.size()) {
+                return ax_err!(NoMemory, "failed to split huge pages");
+            }
+            let shared = area.backend().is_shared();
+            for vaddr in PageIter4K::new(area.start(), area.end()).unwrap() {
+                let Ok((frame, flags, _)) = self.pt.query(vaddr) else {
+                    continue;
+                };
+                if flags.is_empty() {
+                    continue; // Not populated yet.
+                }
+                let new_flags = if shared {
+                    flags
+                } else {
+                    let cow_flags = flags - MappingFlags::WRITE;
+                    if flags.contains(MappingFlags::WRITE) {
+                        let (_, tlb) = self
+                            .pt
+                            .protect(vaddr, cow_flags)
+                            .map_err(paging_err_to_ax_err)?;
+                        tlb.flush();
+                    }
+                    cow_flags
+                };
+                frame::share_frame(frame);
+                let (_, tlb) = new_aspace
+                    .pt
+                    .remap(vaddr, frame, new_flags)
+                    .map_err(paging_err_to_ax_err)?;
+                tlb.ignore();
+            }
+        }
+        Ok(new_aspace)
+    }
+
     /// Finds a free area that can accommodate the given size.
     ///
     /// The search starts from the given hint address, and the area should be within the given limit range.
@@ -94,7 +183,9 @@ impl AddrSpace {
     /// Add a new linear mapping.
     ///
     /// The mapping is linear, i.e., `start_vaddr` is mapped to `start_paddr`,
-    /// and `start_vaddr + size` is mapped to `start_paddr + size`.
+    /// and `start_vaddr + size` is mapped to `start_paddr + size`. Only 4K
+    /// pages are used, so that the mapping is never split when a part of it is
+    /// unmapped or protected, e.g., the kernel mappings.
     ///
     /// The `flags` parameter indicates the mapping permissions and attributes.
     ///
@@ -158,6 +249,84 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Add a new file mapping.
+    ///
+    /// `start` is mapped to `offset` of the file `node`, and the pages are
+    /// read from the file on demand. If `shared` is `true`, the changes are
+    /// written back to the file when unmapped or synchronized by
+    /// [`AddrSpace::sync`], otherwise they are private to the mapping. See
+    /// [`Backend`] for more details.
+    ///
+    /// The `flags` parameter indicates the mapping permissions and attributes.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    #[cfg(feature = "fs")]
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        node: axfs_vfs::VfsNodeRef,
+        offset: u64,
+        shared: bool,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) || !is_aligned_4k(offset as usize) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let backend = Backend::new_file(node, start, offset, shared);
+        let area = MemoryArea::new(start, size, flags, backend);
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Add a new shared memory mapping.
+    ///
+    /// `start` is mapped to `offset` of the shared memory object `shm`, which
+    /// can be also mapped into other address spaces at different addresses.
+    /// See [`SharedMemory`] for more details.
+    ///
+    /// The `flags` parameter indicates the mapping permissions and attributes.
+    ///
+    /// Returns an error if the address range is out of the address space or
+    /// the object, or not aligned.
+    pub fn map_shared(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        shm: Arc<SharedMemory>,
+        offset: usize,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) || !is_aligned_4k(offset) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        if offset + size > align_up_4k(shm.size()) {
+            return ax_err!(InvalidInput, "out of the shared memory");
+        }
+
+        let area = MemoryArea::new(start, size, flags, Backend::new_shared(shm, start, offset));
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Returns the range of the memory area that contains the given address,
+    /// e.g., to unmap a whole mapping.
+    pub fn find_area(&self, vaddr: VirtAddr) -> Option<VirtAddrRange> {
+        self.areas.find(vaddr).map(|area| area.va_range())
+    }
+
     /// Removes mappings within the specified virtual address range.
     ///
     /// Returns an error if the address range is out of the address space or not
@@ -170,10 +339,106 @@ impl AddrSpace {
             return ax_err!(InvalidInput, "address not aligned");
         }
 
-        self.pt
-            .unmap_region(start, size, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        if self
+            .areas
+            .overlaps(VirtAddrRange::from_start_size(start, size))
+        {
+            #[cfg(feature = "swap")]
+            self.swap.release(start, size);
+            // Release the frames and write back the files by the backends.
+            self.areas
+                .unmap(start, size, &mut self.pt)
+                .map_err(mapping_err_to_ax_err)?;
+        } else {
+            if !split_huge_edges(&mut self.pt, start, size) {
+                return ax_err!(NoMemory, "failed to split huge pages");
+            }
+            self.pt
+                .unmap_region(start, size, true)
+                .map_err(paging_err_to_ax_err)?
+                .ignore();
+        }
+        Ok(())
+    }
+
+    /// Writes the changes of the shared file mappings within the specified
+    /// virtual address range back to the files, e.g., for `msync`.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn sync(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let end = start + size;
+        for area in self.areas.iter() {
+            let area_start = area.start().max(start);
+            let area_end = area.end().min(end);
+            if area_start < area_end
+                && !area
+                    .backend()
+                    .sync(area_start, area_end - area_start, &mut self.pt)
+            {
+                return ax_err!(Io, "failed to write back");
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes all the mappings of the memory areas, and releases their
+    /// physical frames.
+    ///
+    /// The frames shared with other address spaces (copy-on-write) are
+    /// deallocated when the last of them releases it.
+    pub fn clear(&mut self) -> AxResult {
+        #[cfg(feature = "swap")]
+        self.swap.release(self.base(), self.size());
+        self.areas
+            .clear(&mut self.pt)
+            .map_err(mapping_err_to_ax_err)
+    }
+
+    /// Prepares the pages in the range to be modified directly.
+    ///
+    /// The pages shared by copy-on-write are given their own frames, and the
+    /// pages of the shared mappings are marked dirty.
+    fn prepare_write(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        let end_align_up = (start + size).align_up_4k();
+        #[cfg(feature = "swap")]
+        self.restore_swapped(start.align_down_4k(), end_align_up - start.align_down_4k())?;
+        for vaddr in PageIter4K::new(start.align_down_4k(), end_align_up)
+            .expect("Failed to create page iterator")
+        {
+            let Ok((frame, flags, _)) = self.pt.query(vaddr) else {
+                continue;
+            };
+            if flags.is_empty() {
+                continue;
+            }
+            match self.areas.find(vaddr) {
+                Some(area) if area.backend().is_shared() => {
+                    let area_flags = area.flags();
+                    if area_flags.contains(MappingFlags::WRITE)
+                        && !flags.contains(MappingFlags::WRITE)
+                    {
+                        let (_, tlb) = self
+                            .pt
+                            .protect(vaddr, area_flags)
+                            .map_err(paging_err_to_ax_err)?;
+                        tlb.flush();
+                    }
+                }
+                _ => {
+                    if frame::is_shared(frame) && !unshare_page(vaddr, frame, flags, &mut self.pt) {
+                        return ax_err!(NoMemory);
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
@@ -229,7 +494,14 @@ impl AddrSpace {
     ///
     /// * `start_vaddr` - The start virtual address to write.
     /// * `buf` - The buffer to write to the address space.
-    pub fn write(&self, start: VirtAddr, buf: &[u8]) -> AxResult {
+    ///
+    /// The copy-on-write pages are copied before written, and the pages of the
+    /// shared file mappings are marked dirty.
+    pub fn write(&mut self, start: VirtAddr, buf: &[u8]) -> AxResult {
+        if !self.contains_range(start, buf.len()) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        self.prepare_write(start, buf.len())?;
         self.process_area_data(start, buf.len(), |dst, offset, write_size| unsafe {
             core::ptr::copy_nonoverlapping(buf.as_ptr().add(offset), dst.as_mut_ptr(), write_size);
         })
@@ -247,10 +519,30 @@ impl AddrSpace {
             return ax_err!(InvalidInput, "address not aligned");
         }
 
+        // The inactive and swapped out pages are mapped with empty flags.
+        #[cfg(feature = "swap")]
+        self.restore_swapped(start, size)?;
+        if !split_huge_edges(&mut self.pt, start, size) {
+            return ax_err!(NoMemory, "failed to split huge pages");
+        }
         self.pt
             .protect_region(start, size, flags, true)
             .map_err(paging_err_to_ax_err)?
             .ignore();
+        if flags.contains(MappingFlags::WRITE) {
+            // Keep the copy-on-write pages read-only.
+            for vaddr in PageIter4K::new(start, start + size).unwrap() {
+                if let Ok((frame, _, _)) = self.pt.query(vaddr) {
+                    if frame::is_shared(frame) {
+                        let (_, tlb) = self
+                            .pt
+                            .protect(vaddr, flags - MappingFlags::WRITE)
+                            .map_err(paging_err_to_ax_err)?;
+                        tlb.flush();
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
@@ -267,14 +559,56 @@ impl AddrSpace {
         if let Some(area) = self.areas.find(vaddr) {
             let orig_flags = area.flags();
             if orig_flags.contains(access_flags) {
-                return area
+                // Already mapped by another CPU, e.g., the access raced with
+                // the splitting of a huge page, which remaps it.
+                if matches!(self.pt.query(vaddr), Ok((_, flags, _)) if flags.contains(access_flags))
+                {
+                    return true;
+                }
+                #[cfg(feature = "swap")]
+                {
+                    self.swap.balance(&mut self.pt);
+                    if let Some(handled) =
+                        self.swap.handle_page_fault(vaddr, orig_flags, &mut self.pt)
+                    {
+                        return handled;
+                    }
+                }
+                let handled = area
                     .backend()
                     .handle_page_fault(vaddr, orig_flags, &mut self.pt);
+                // Only the pages of the lazy allocation mappings are swappable.
+                #[cfg(feature = "swap")]
+                if handled && matches!(area.backend(), Backend::Alloc { populate: false }) {
+                    self.swap.track(vaddr.align_down_4k());
+                }
+                return handled;
             }
         }
         false
     }
 
+    /// Swaps out at most `max_pages` cold pages of the lazy allocation
+    /// mappings, and returns the number of pages swapped out (requires the
+    /// `swap` feature).
+    ///
+    /// It is also called on the page faults when the free pages are fewer
+    /// than the low watermark, see [`crate::swap`] for more details.
+    #[cfg(feature = "swap")]
+    pub fn reclaim(&mut self, max_pages: usize) -> usize {
+        self.swap.reclaim(max_pages, &mut self.pt)
+    }
+
+    /// Maps back the inactive and swapped out pages in the range, before they
+    /// are accessed or changed directly.
+    #[cfg(feature = "swap")]
+    fn restore_swapped(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        let areas = &self.areas;
+        self.swap.restore(start, size, &mut self.pt, |vaddr| {
+            areas.find(vaddr).map(|area| area.flags())
+        })
+    }
+
     pub fn translated_byte_buffer(
         &self,
         vaddr: VirtAddr,
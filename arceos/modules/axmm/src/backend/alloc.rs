@@ -1,22 +1,50 @@
-use axalloc::global_allocator;
-use axhal::mem::{phys_to_virt, virt_to_phys};
 use axhal::paging::{MappingFlags, PageSize, PageTable};
-use memory_addr::{PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
+use super::huge::{split_huge_edges, HUGE_PAGE_SIZES};
 use super::Backend;
+use crate::frame::{
+    alloc_frame, alloc_huge_frame, copy_frame, dealloc_frame, dealloc_huge_frame, is_shared,
+};
 
-fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
-    let vaddr = VirtAddr::from(global_allocator().alloc_pages(1, PAGE_SIZE_4K).ok()?);
-    if zeroed {
-        unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, PAGE_SIZE_4K) };
+/// Allocates a zeroed frame for the page at `vaddr` within the range ending at
+/// `end`, which is huge if the alignment and the size allow and there is
+/// enough contiguous memory.
+fn alloc_page(vaddr: VirtAddr, end: VirtAddr) -> Option<(PhysAddr, PageSize)> {
+    for page_size in HUGE_PAGE_SIZES {
+        if vaddr.is_aligned(page_size) && end - vaddr >= page_size.into() {
+            if let Some(frame) = alloc_huge_frame(page_size) {
+                return Some((frame, page_size));
+            }
+        }
     }
-    let paddr = virt_to_phys(vaddr);
-    Some(paddr)
+    alloc_frame(true).map(|frame| (frame, PageSize::Size4K))
 }
 
-fn dealloc_frame(frame: PhysAddr) {
-    let vaddr = phys_to_virt(frame);
-    global_allocator().dealloc_pages(vaddr.as_usize(), 1);
+/// Maps the page to its own copy of `frame` with `flags`, if `frame` is
+/// shared with other mappings (copy-on-write).
+///
+/// Returns `false` if there is no memory.
+pub(crate) fn unshare_page(
+    vaddr: VirtAddr,
+    frame: PhysAddr,
+    flags: MappingFlags,
+    pt: &mut PageTable,
+) -> bool {
+    let frame = if is_shared(frame) {
+        // Copy before releasing the reference, as the last owner may write
+        // to the frame once it is released.
+        let Some(new_frame) = copy_frame(frame) else {
+            return false;
+        };
+        dealloc_frame(frame);
+        new_frame
+    } else {
+        frame
+    };
+    pt.remap(vaddr, frame, flags)
+        .map(|(_, tlb)| tlb.flush())
+        .is_ok()
 }
 
 impl Backend {
@@ -41,15 +69,21 @@ impl Backend {
             populate
         );
         if populate {
-            // allocate all possible physical frames for populated mapping.
-            for addr in PageIter4K::new(start, start + size).unwrap() {
-                if let Some(frame) = alloc_frame(true) {
-                    if let Ok(tlb) = pt.map(addr, frame, PageSize::Size4K, flags) {
-                        tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
-                    } else {
-                        return false;
-                    }
+            // allocate all possible physical frames for populated mapping, use
+            // huge pages where possible.
+            let end = start + size;
+            let mut addr = start;
+            while addr < end {
+                let Some((frame, page_size)) = alloc_page(addr, end) else {
+                    addr += PAGE_SIZE_4K;
+                    continue;
+                };
+                if let Ok(tlb) = pt.map(addr, frame, page_size, flags) {
+                    tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
+                } else {
+                    return false;
                 }
+                addr += page_size.into();
             }
             true
         } else {
@@ -69,17 +103,25 @@ impl Backend {
         _populate: bool,
     ) -> bool {
         debug!("unmap_alloc: [{:#x}, {:#x})", start, start + size);
-        for addr in PageIter4K::new(start, start + size).unwrap() {
+        if !split_huge_edges(pt, start, size) {
+            return false;
+        }
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
             if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
                 // Deallocate the physical frame if there is a mapping in the
                 // page table.
+                tlb.flush();
                 if page_size.is_huge() {
-                    return false;
+                    dealloc_huge_frame(frame, page_size);
+                } else {
+                    dealloc_frame(frame);
                 }
-                tlb.flush();
-                dealloc_frame(frame);
+                addr += page_size.into();
             } else {
                 // Deallocation is needn't if the page is not mapped.
+                addr += PAGE_SIZE_4K;
             }
         }
         true
@@ -92,8 +134,16 @@ impl Backend {
         pt: &mut PageTable,
         populate: bool,
     ) -> bool {
+        if let Ok((frame, flags, _)) = pt.query(vaddr.align_down_4k()) {
+            if !flags.is_empty() {
+                // A write to a copy-on-write page, which is mapped read-only.
+                let cow = orig_flags.contains(MappingFlags::WRITE)
+                    && !flags.contains(MappingFlags::WRITE);
+                return cow && unshare_page(vaddr, frame, orig_flags, pt);
+            }
+        }
         if populate {
-            false // Populated mappings should not trigger page faults.
+            false // Populated mappings only trigger page faults for copy-on-write.
         } else if let Some(frame) = alloc_frame(true) {
             // Allocate a physical frame lazily and map it to the fault address.
             // `vaddr` does not need to be aligned. It will be automatically
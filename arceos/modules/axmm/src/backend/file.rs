@@ -0,0 +1,207 @@
+use axfs_vfs::{VfsNodeRef, VfsResult};
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageTable};
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::{unshare_page, Backend};
+use crate::frame::{alloc_frame, dealloc_frame};
+
+fn file_offset(vaddr: VirtAddr, start: VirtAddr, offset: u64) -> u64 {
+    offset + (vaddr - start) as u64
+}
+
+fn frame_buf(frame: PhysAddr) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) }
+}
+
+/// Reads the page at `offset` of the file, the rest after the end of file is
+/// left unchanged.
+fn read_page(node: &VfsNodeRef, offset: u64, buf: &mut [u8]) -> VfsResult {
+    let mut read = 0;
+    while read < buf.len() {
+        let n = node.read_at(offset + read as u64, &mut buf[read..])?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    Ok(())
+}
+
+/// Writes the page back to the file at `offset`, without extending the file.
+fn write_page(node: &VfsNodeRef, offset: u64, buf: &[u8]) -> VfsResult {
+    let file_size = node.get_attr()?.size();
+    if offset >= file_size {
+        return Ok(());
+    }
+    let len = buf.len().min((file_size - offset) as usize);
+    let mut written = 0;
+    while written < len {
+        let n = node.write_at(offset + written as u64, &buf[written..len])?;
+        if n == 0 {
+            break;
+        }
+        written += n;
+    }
+    Ok(())
+}
+
+impl Backend {
+    /// Creates a new file mapping backend.
+    ///
+    /// The page at `start` is mapped to `offset` of the file `node`. If
+    /// `shared` is `true`, the changes are written back to the file,
+    /// otherwise they are private to the mapping.
+    pub fn new_file(node: VfsNodeRef, start: VirtAddr, offset: u64, shared: bool) -> Self {
+        Self::File {
+            node,
+            start,
+            offset,
+            shared,
+        }
+    }
+
+    pub(crate) fn map_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        debug!("map_file: [{:#x}, {:#x}) {:?}", start, start + size, flags);
+        // Map to a empty entry, the pages are read from the file on demand.
+        pt.map_region(
+            start,
+            |_| 0.into(),
+            size,
+            MappingFlags::empty(),
+            false,
+            false,
+        )
+        .map(|tlb| tlb.ignore())
+        .is_ok()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn unmap_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        node: &VfsNodeRef,
+        file_start: VirtAddr,
+        offset: u64,
+        shared: bool,
+    ) -> bool {
+        debug!("unmap_file: [{:#x}, {:#x})", start, start + size);
+        if shared && !self.sync_file(start, size, pt, node, file_start, offset) {
+            warn!(
+                "unmap_file: failed to write back [{:#x}, {:#x})",
+                start,
+                start + size
+            );
+        }
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
+                if page_size.is_huge() {
+                    return false;
+                }
+                tlb.flush();
+                dealloc_frame(frame);
+            }
+        }
+        true
+    }
+
+    /// Writes the dirty pages back to the file, and marks them clean.
+    ///
+    /// The clean pages of the shared mappings are mapped read-only, so a page
+    /// is dirty if it is mapped writable.
+    pub(crate) fn sync_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        node: &VfsNodeRef,
+        file_start: VirtAddr,
+        offset: u64,
+    ) -> bool {
+        let mut ok = true;
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let Ok((frame, flags, _)) = pt.query(addr) else {
+                continue;
+            };
+            if !flags.contains(MappingFlags::WRITE) {
+                continue;
+            }
+            if let Err(e) = write_page(
+                node,
+                file_offset(addr, file_start, offset),
+                frame_buf(frame),
+            ) {
+                warn!("sync_file: failed to write back {:#x}: {:?}", addr, e);
+                ok = false;
+                continue;
+            }
+            match pt.protect(addr, flags - MappingFlags::WRITE) {
+                Ok((_, tlb)) => tlb.flush(),
+                Err(_) => ok = false,
+            }
+        }
+        ok
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn handle_page_fault_file(
+        &self,
+        vaddr: VirtAddr,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+        node: &VfsNodeRef,
+        start: VirtAddr,
+        offset: u64,
+        shared: bool,
+    ) -> bool {
+        let vaddr = vaddr.align_down_4k();
+        if let Ok((frame, flags, _)) = pt.query(vaddr) {
+            if !flags.is_empty() {
+                if !orig_flags.contains(MappingFlags::WRITE) || flags.contains(MappingFlags::WRITE)
+                {
+                    return false;
+                }
+                return if shared {
+                    // The first write to a clean page, which is dirty from now on.
+                    pt.protect(vaddr, orig_flags)
+                        .map(|(_, tlb)| tlb.flush())
+                        .is_ok()
+                } else {
+                    // The private pages diverge from the file, and from the
+                    // copy-on-write address spaces on write.
+                    unshare_page(vaddr, frame, orig_flags, pt)
+                };
+            }
+        }
+
+        let Some(frame) = alloc_frame(true) else {
+            return false;
+        };
+        if let Err(e) = read_page(node, file_offset(vaddr, start, offset), frame_buf(frame)) {
+            warn!(
+                "handle_page_fault_file: failed to read {:#x}: {:?}",
+                vaddr, e
+            );
+            dealloc_frame(frame);
+            return false;
+        }
+        // Map the clean pages of the shared mappings read-only, to find the
+        // dirty pages by write faults.
+        let flags = if shared {
+            orig_flags - MappingFlags::WRITE
+        } else {
+            orig_flags
+        };
+        pt.remap(vaddr, frame, flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+    }
+}
@@ -0,0 +1,82 @@
+//! Splitting of the huge pages, for the operations on a part of them.
+
+use axhal::paging::{PageSize, PageTable};
+use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// The huge page sizes, from the largest one.
+pub(crate) const HUGE_PAGE_SIZES: [PageSize; 2] = [PageSize::Size1G, PageSize::Size2M];
+
+/// Splits the huge page mapped at `vaddr` into the pages of the next smaller
+/// size, which are mapped to the same frame with the same flags.
+///
+/// The huge page is unmapped and flushed before the smaller pages are mapped
+/// (break-before-make, as required by AArch64), so the pages are not mapped
+/// for a while. It is done with the address space locked, and an access from
+/// another CPU in the meantime faults and waits for the lock, then finds the
+/// page mapped and returns, see [`AddrSpace::handle_page_fault`]. Therefore,
+/// only the mappings that can fault safely are split, i.e., the user ones.
+/// The kernel mappings by [`AddrSpace::map_linear`] use 4K pages only.
+///
+/// [`AddrSpace::handle_page_fault`]: crate::AddrSpace::handle_page_fault
+/// [`AddrSpace::map_linear`]: crate::AddrSpace::map_linear
+fn split_huge_page(pt: &mut PageTable, vaddr: VirtAddr, page_size: PageSize) -> bool {
+    let sub_size = match page_size {
+        PageSize::Size1G => PageSize::Size2M,
+        PageSize::Size2M => PageSize::Size4K,
+        PageSize::Size4K => return true,
+    };
+    let base = vaddr.align_down(page_size);
+    let Ok((frame, flags, _)) = pt.query(base) else {
+        return false;
+    };
+    match pt.unmap(base) {
+        Ok((_, _, tlb)) => tlb.flush(),
+        Err(_) => return false,
+    }
+    let sub_bytes: usize = sub_size.into();
+    let count = usize::from(page_size) / sub_bytes;
+    for i in 0..count {
+        match pt.map(base + i * sub_bytes, frame + i * sub_bytes, sub_size, flags) {
+            Ok(tlb) => tlb.ignore(), // The old huge entry is already flushed.
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+/// Splits the huge pages crossing the boundaries of `[start, start + size)`,
+/// so that the range can be unmapped or protected without affecting the
+/// pages out of it.
+pub(crate) fn split_huge_edges(pt: &mut PageTable, start: VirtAddr, size: usize) -> bool {
+    for vaddr in [start, start + size] {
+        while let Ok((_, _, page_size)) = pt.query(vaddr) {
+            if !page_size.is_huge() || vaddr.is_aligned(page_size) {
+                break;
+            }
+            if !split_huge_page(pt, vaddr, page_size) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Splits all the huge pages within `[start, start + size)` into 4K pages,
+/// e.g., before their frames are shared page by page.
+pub(crate) fn split_huge_pages(pt: &mut PageTable, start: VirtAddr, size: usize) -> bool {
+    if !split_huge_edges(pt, start, size) {
+        return false;
+    }
+    let mut vaddr = start;
+    while vaddr < start + size {
+        match pt.query(vaddr) {
+            Ok((_, _, page_size)) if page_size.is_huge() => {
+                if !split_huge_page(pt, vaddr, page_size) {
+                    return false;
+                }
+            }
+            _ => vaddr += PAGE_SIZE_4K,
+        }
+    }
+    true
+}
@@ -1,21 +1,40 @@
 //! Memory mapping backends.
 #![allow(dead_code)]
 
+use alloc::sync::Arc;
+#[cfg(feature = "fs")]
+use axfs_vfs::VfsNodeRef;
 use axhal::paging::{MappingFlags, PageTable};
 use memory_addr::VirtAddr;
 use memory_set::MappingBackend;
 
+use crate::SharedMemory;
+
 mod alloc;
+#[cfg(feature = "fs")]
+mod file;
+mod huge;
 mod linear;
+mod shared;
+
+pub(crate) use self::alloc::unshare_page;
+pub(crate) use self::huge::{split_huge_edges, split_huge_pages};
 
 /// A unified enum type for different memory mapping backends.
 ///
-/// Currently, two backends are implemented:
+/// Currently, the following backends are implemented:
 ///
 /// - **Linear**: used for linear mappings. The target physical frames are
 ///   contiguous and their addresses should be known when creating the mapping.
 /// - **Allocation**: used in general, or for lazy mappings. The target physical
-///   frames are obtained from the global allocator.
+///   frames are obtained from the global allocator. The frames can be shared
+///   copy-on-write by [`AddrSpace::clone_cow`](crate::AddrSpace::clone_cow).
+/// - **File**: used for file mappings (requires the `fs` feature). The target
+///   physical frames are obtained from the global allocator, and filled with
+///   the file contents on demand.
+/// - **Shared**: used for shared memory. The target physical frames are owned
+///   by a [`SharedMemory`] object, which can be mapped into several address
+///   spaces.
 #[derive(Clone)]
 pub enum Backend {
     /// Linear mapping backend.
@@ -23,6 +42,8 @@ pub enum Backend {
     /// The offset between the virtual address and the physical address is
     /// constant, which is specified by `pa_va_offset`. For example, the virtual
     /// address `vaddr` is mapped to the physical address `vaddr - pa_va_offset`.
+    ///
+    /// Huge pages are used where the alignment and the size allow.
     Linear {
         /// `vaddr - paddr`.
         pa_va_offset: usize,
@@ -33,10 +54,54 @@ pub enum Backend {
     /// mapping is created, and no page faults are triggered during the memory
     /// access. Otherwise, the physical frames are allocated on demand (by
     /// handling page faults).
+    ///
+    /// The populated mappings use huge pages where the alignment and the size
+    /// allow, and there is enough contiguous memory. The huge pages are split
+    /// when a part of them is unmapped or protected.
+    ///
+    /// The frames shared by copy-on-write are mapped read-only, and copied on
+    /// the first write fault.
     Alloc {
         /// Whether to populate the physical frames when creating the mapping.
         populate: bool,
     },
+    /// File mapping backend.
+    ///
+    /// The pages are read from the file on page faults. If `shared` is
+    /// `true`, the dirty pages are written back to the file when unmapped or
+    /// synchronized by [`AddrSpace::sync`](crate::AddrSpace::sync). The clean
+    /// pages are mapped read-only, and become dirty on the first write fault.
+    /// Otherwise, the changes are private to the mapping and never written
+    /// back.
+    ///
+    /// The shared mappings of the same file do not share the pages, they only
+    /// see the changes of each other after synchronized.
+    #[cfg(feature = "fs")]
+    File {
+        /// The mapped file.
+        node: VfsNodeRef,
+        /// The virtual address mapped to `offset` of the file. It may be out
+        /// of the area if the area is split.
+        start: VirtAddr,
+        /// The file offset mapped at `start`.
+        offset: u64,
+        /// Whether the changes are written back to the file.
+        shared: bool,
+    },
+    /// Shared memory mapping backend.
+    ///
+    /// The frames of the [`SharedMemory`] object are mapped when the mapping is
+    /// created, and no page faults are triggered during the memory access.
+    /// They are not deallocated when unmapped, but when the object is dropped.
+    Shared {
+        /// The shared memory object.
+        shm: Arc<SharedMemory>,
+        /// The virtual address mapped to `offset` of the object. It may be out
+        /// of the area if the area is split.
+        start: VirtAddr,
+        /// The offset of the object mapped at `start`.
+        offset: usize,
+    },
 }
 
 impl MappingBackend for Backend {
@@ -47,6 +112,13 @@ impl MappingBackend for Backend {
         match *self {
             Self::Linear { pa_va_offset } => self.map_linear(start, size, flags, pt, pa_va_offset),
             Self::Alloc { populate } => self.map_alloc(start, size, flags, pt, populate),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.map_file(start, size, flags, pt),
+            Self::Shared {
+                ref shm,
+                start: shm_start,
+                offset,
+            } => self.map_shared(start, size, flags, pt, shm, shm_start, offset),
         }
     }
 
@@ -54,6 +126,14 @@ impl MappingBackend for Backend {
         match *self {
             Self::Linear { pa_va_offset } => self.unmap_linear(start, size, pt, pa_va_offset),
             Self::Alloc { populate } => self.unmap_alloc(start, size, pt, populate),
+            #[cfg(feature = "fs")]
+            Self::File {
+                ref node,
+                start: file_start,
+                offset,
+                shared,
+            } => self.unmap_file(start, size, pt, node, file_start, offset, shared),
+            Self::Shared { .. } => self.unmap_shared(start, size, pt),
         }
     }
 
@@ -64,6 +144,9 @@ impl MappingBackend for Backend {
         new_flags: Self::Flags,
         page_table: &mut Self::PageTable,
     ) -> bool {
+        if !split_huge_edges(page_table, start, size) {
+            return false;
+        }
         page_table
             .protect_region(start, size, new_flags, true)
             .map(|tlb| tlb.ignore())
@@ -83,6 +166,41 @@ impl Backend {
             Self::Alloc { populate } => {
                 self.handle_page_fault_alloc(vaddr, orig_flags, page_table, populate)
             }
+            #[cfg(feature = "fs")]
+            Self::File {
+                ref node,
+                start,
+                offset,
+                shared,
+            } => self
+                .handle_page_fault_file(vaddr, orig_flags, page_table, node, start, offset, shared),
+            Self::Shared { .. } => false, // Shared memory mappings should not trigger page faults.
+        }
+    }
+
+    /// Whether the frames are shared with the copies of the mapping on write,
+    /// instead of copied on write.
+    pub(crate) fn is_shared(&self) -> bool {
+        match *self {
+            #[cfg(feature = "fs")]
+            Self::File { shared, .. } => shared,
+            Self::Shared { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Writes the changes in the range back to the file, for shared file
+    /// mappings.
+    pub(crate) fn sync(&self, start: VirtAddr, size: usize, page_table: &mut PageTable) -> bool {
+        match *self {
+            #[cfg(feature = "fs")]
+            Self::File {
+                ref node,
+                start: file_start,
+                offset,
+                shared: true,
+            } => self.sync_file(start, size, page_table, node, file_start, offset),
+            _ => true,
         }
     }
 }
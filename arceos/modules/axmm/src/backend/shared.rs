@@ -0,0 +1,55 @@
+use alloc::sync::Arc;
+
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use memory_addr::{PageIter4K, VirtAddr, PAGE_SIZE_4K};
+
+use super::Backend;
+use crate::SharedMemory;
+
+impl Backend {
+    /// Creates a new shared memory mapping backend.
+    ///
+    /// The page at `start` is mapped to `offset` of the shared memory object.
+    pub fn new_shared(shm: Arc<SharedMemory>, start: VirtAddr, offset: usize) -> Self {
+        Self::Shared { shm, start, offset }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn map_shared(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+        shm: &SharedMemory,
+        shm_start: VirtAddr,
+        offset: usize,
+    ) -> bool {
+        debug!(
+            "map_shared: [{:#x}, {:#x}) {:?}",
+            start,
+            start + size,
+            flags
+        );
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let idx = (offset + (addr - shm_start)) / PAGE_SIZE_4K;
+            let Some(frame) = shm.frame(idx) else {
+                return false;
+            };
+            if let Ok(tlb) = pt.map(addr, frame, PageSize::Size4K, flags) {
+                tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
+            } else {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn unmap_shared(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        debug!("unmap_shared: [{:#x}, {:#x})", start, start + size);
+        // The frames are owned by the shared memory object.
+        pt.unmap_region(start, size, true)
+            .map(|tlb| tlb.ignore())
+            .is_ok()
+    }
+}
@@ -0,0 +1,89 @@
+//! Physical frames of the mappings, and their reference counts.
+//!
+//! A frame is owned by one mapping when allocated. It can be shared by more
+//! mappings, e.g., by copy-on-write address spaces, and is deallocated when
+//! the last reference is released. Only the frames with more than one
+//! reference are recorded.
+//!
+//! Huge frames are never shared, the huge pages are split into 4K pages
+//! before their frames are shared. The 4K frames of a split huge frame can be
+//! deallocated separately, as the buddy page allocator accepts any range of
+//! the allocated pages.
+
+use alloc::collections::BTreeMap;
+
+use axalloc::global_allocator;
+use axhal::mem::{phys_to_virt, virt_to_phys};
+use axhal::paging::PageSize;
+use kspin::SpinNoIrq;
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+static SHARED_FRAMES: SpinNoIrq<BTreeMap<PhysAddr, usize>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Allocates a physical frame, and fills it with zero if `zeroed` is `true`.
+pub(crate) fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
+    let vaddr = VirtAddr::from(global_allocator().alloc_pages(1, PAGE_SIZE_4K).ok()?);
+    if zeroed {
+        unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, PAGE_SIZE_4K) };
+    }
+    let paddr = virt_to_phys(vaddr);
+    Some(paddr)
+}
+
+/// Allocates a huge physical frame of `page_size`, aligned to its size, and
+/// fills it with zero.
+pub(crate) fn alloc_huge_frame(page_size: PageSize) -> Option<PhysAddr> {
+    let size: usize = page_size.into();
+    let vaddr = VirtAddr::from(
+        global_allocator()
+            .alloc_pages(size / PAGE_SIZE_4K, size)
+            .ok()?,
+    );
+    unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, size) };
+    Some(virt_to_phys(vaddr))
+}
+
+/// Deallocates a huge physical frame of `page_size`.
+pub(crate) fn dealloc_huge_frame(frame: PhysAddr, page_size: PageSize) {
+    let size: usize = page_size.into();
+    global_allocator().dealloc_pages(phys_to_virt(frame).as_usize(), size / PAGE_SIZE_4K);
+}
+
+/// Allocates a physical frame with the same content as `frame`.
+pub(crate) fn copy_frame(frame: PhysAddr) -> Option<PhysAddr> {
+    let new_frame = alloc_frame(false)?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            phys_to_virt(frame).as_ptr(),
+            phys_to_virt(new_frame).as_mut_ptr(),
+            PAGE_SIZE_4K,
+        )
+    };
+    Some(new_frame)
+}
+
+/// Adds a reference to the frame.
+pub(crate) fn share_frame(frame: PhysAddr) {
+    *SHARED_FRAMES.lock().entry(frame).or_insert(1) += 1;
+}
+
+/// Returns whether the frame is referenced more than once.
+pub(crate) fn is_shared(frame: PhysAddr) -> bool {
+    SHARED_FRAMES.lock().contains_key(&frame)
+}
+
+/// Releases a reference to the frame, and deallocates it if it is the last
+/// one.
+pub(crate) fn dealloc_frame(frame: PhysAddr) {
+    let mut shared = SHARED_FRAMES.lock();
+    if let Some(count) = shared.get_mut(&frame) {
+        *count -= 1;
+        if *count == 1 {
+            shared.remove(&frame);
+        }
+        return;
+    }
+    drop(shared);
+    let vaddr = phys_to_virt(frame);
+    global_allocator().dealloc_pages(vaddr.as_usize(), 1);
+}
@@ -1,6 +1,12 @@
 //! [ArceOS](https://github.com/arceos-org/arceos) memory management module.
+//!
+//! # Cargo Features
+//!
+//! - `fs`: Enable file mappings, see [`AddrSpace::map_file`].
+//! - `swap`: Enable swapping the anonymous pages to a swap device, e.g., a
+//!   file (with `fs`), see [`swap`].
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
@@ -8,8 +14,15 @@ extern crate alloc;
 
 mod aspace;
 mod backend;
+mod frame;
+mod shm;
+#[cfg(feature = "swap")]
+pub mod swap;
+#[cfg(test)]
+mod tests;
 
 pub use self::aspace::AddrSpace;
+pub use self::shm::SharedMemory;
 
 use axerrno::{AxError, AxResult};
 use axhal::mem::phys_to_virt;
@@ -0,0 +1,108 @@
+//! Shared memory objects, which can be mapped into several address spaces.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use kspin::SpinNoIrq;
+use memory_addr::{align_up_4k, PhysAddr, PAGE_SIZE_4K};
+
+use crate::frame::{alloc_frame, dealloc_frame};
+
+static NAMED_OBJECTS: SpinNoIrq<BTreeMap<String, Arc<SharedMemory>>> =
+    SpinNoIrq::new(BTreeMap::new());
+
+struct Inner {
+    frames: Vec<PhysAddr>,
+    size: usize,
+}
+
+/// A shared memory object, which is a set of physical frames.
+///
+/// It can be mapped into several address spaces at different addresses by
+/// [`AddrSpace::map_shared`](crate::AddrSpace::map_shared), and the frames
+/// are deallocated when the object is dropped, i.e., when it is neither
+/// mapped nor referenced by name.
+pub struct SharedMemory {
+    inner: SpinNoIrq<Inner>,
+}
+
+impl SharedMemory {
+    /// Creates a new shared memory object of `size` bytes, filled with zero.
+    pub fn new(size: usize) -> AxResult<Arc<Self>> {
+        let shm = Arc::new(Self {
+            inner: SpinNoIrq::new(Inner {
+                frames: Vec::new(),
+                size: 0,
+            }),
+        });
+        shm.resize(size)?;
+        Ok(shm)
+    }
+
+    /// Opens the shared memory object by name, e.g., for `shm_open`.
+    ///
+    /// If it does not exist and `create` is `true`, an empty object is
+    /// created. Returns [`AlreadyExists`](axerrno::AxError::AlreadyExists) if
+    /// it exists and `exclusive` is `true`.
+    pub fn open(name: &str, create: bool, exclusive: bool) -> AxResult<Arc<Self>> {
+        let mut objects = NAMED_OBJECTS.lock();
+        match objects.get(name) {
+            Some(_) if exclusive => ax_err!(AlreadyExists),
+            Some(shm) => Ok(shm.clone()),
+            None if create => {
+                let shm = Self::new(0)?;
+                objects.insert(name.into(), shm.clone());
+                Ok(shm)
+            }
+            None => ax_err!(NotFound),
+        }
+    }
+
+    /// Removes the name of the shared memory object, e.g., for `shm_unlink`.
+    ///
+    /// The object is dropped after it is unmapped from all address spaces.
+    pub fn unlink(name: &str) -> AxResult {
+        match NAMED_OBJECTS.lock().remove(name) {
+            Some(_) => Ok(()),
+            None => ax_err!(NotFound),
+        }
+    }
+
+    /// Returns the size of the object in bytes.
+    pub fn size(&self) -> usize {
+        self.inner.lock().size
+    }
+
+    /// Changes the size of the object, e.g., for `ftruncate`.
+    ///
+    /// The new frames are filled with zero. The frames are never deallocated
+    /// when shrinking, as they may be still mapped.
+    pub fn resize(&self, size: usize) -> AxResult {
+        let mut inner = self.inner.lock();
+        let num_frames = align_up_4k(size) / PAGE_SIZE_4K;
+        while inner.frames.len() < num_frames {
+            let Some(frame) = alloc_frame(true) else {
+                return ax_err!(NoMemory);
+            };
+            inner.frames.push(frame);
+        }
+        inner.size = size;
+        Ok(())
+    }
+
+    /// Returns the frame of the object at page `idx`.
+    pub(crate) fn frame(&self, idx: usize) -> Option<PhysAddr> {
+        self.inner.lock().frames.get(idx).copied()
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        for &frame in &self.inner.lock().frames {
+            dealloc_frame(frame);
+        }
+    }
+}
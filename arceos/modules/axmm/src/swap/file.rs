@@ -0,0 +1,56 @@
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::VfsNodeRef;
+use memory_addr::PAGE_SIZE_4K;
+
+use super::SwapDevice;
+
+/// A swap area in a file.
+pub struct FileSwapDevice {
+    node: VfsNodeRef,
+    num_pages: usize,
+}
+
+impl FileSwapDevice {
+    /// Creates a swap area of `size` bytes in the file, which is resized
+    /// to `size`.
+    pub fn new(node: VfsNodeRef, size: usize) -> AxResult<Self> {
+        node.truncate(size as u64)?;
+        Ok(Self {
+            node,
+            num_pages: size / PAGE_SIZE_4K,
+        })
+    }
+}
+
+impl SwapDevice for FileSwapDevice {
+    fn num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn read_page(&self, idx: usize, buf: &mut [u8]) -> AxResult {
+        let offset = (idx * PAGE_SIZE_4K) as u64;
+        let mut read = 0;
+        while read < buf.len() {
+            match self.node.read_at(offset + read as u64, &mut buf[read..])? {
+                0 => return ax_err!(UnexpectedEof),
+                n => read += n,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_page(&self, idx: usize, buf: &[u8]) -> AxResult {
+        let offset = (idx * PAGE_SIZE_4K) as u64;
+        let mut written = 0;
+        while written < buf.len() {
+            match self
+                .node
+                .write_at(offset + written as u64, &buf[written..])?
+            {
+                0 => return ax_err!(WriteZero),
+                n => written += n,
+            }
+        }
+        Ok(())
+    }
+}
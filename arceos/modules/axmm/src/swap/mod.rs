@@ -0,0 +1,360 @@
+//! Swapping of the anonymous pages to a swap area (requires the `swap`
+//! feature).
+//!
+//! The pages of the lazy allocation mappings are tracked by their address
+//! spaces once populated, and aged by a clock scan with a software reference
+//! bit:
+//!
+//! - An **active** page is made **inactive** by the scan, i.e., mapped with
+//!   empty flags while its frame is kept, so that the next access traps.
+//! - An inactive page accessed again is mapped back and becomes active on the
+//!   page fault.
+//! - An inactive page met by the scan again is written to the swap area and
+//!   its frame is released, it is read back on the next page fault.
+//!
+//! The scan is run by [`AddrSpace::reclaim`], which is called on the page
+//! faults when the free pages are fewer than the low watermark, see
+//! [`set_low_watermark`]. The frames shared by copy-on-write are never
+//! swapped out.
+//!
+//! Swapping is enabled by [`init_swap`] with a [`SwapDevice`], e.g., a
+//! `FileSwapDevice` (with the `fs` feature), or a block device set up by the
+//! runtime.
+//!
+//! [`AddrSpace::reclaim`]: crate::AddrSpace::reclaim
+
+#[cfg(feature = "fs")]
+mod file;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axalloc::global_allocator;
+use axerrno::{ax_err, AxResult};
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageTable};
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use crate::frame::{alloc_frame, dealloc_frame, is_shared};
+
+#[cfg(feature = "fs")]
+pub use self::file::FileSwapDevice;
+
+/// The number of pages to reclaim on each page fault under memory pressure.
+const RECLAIM_BATCH: usize = 32;
+
+/// The default low watermark, in pages.
+const DEFAULT_LOW_WATERMARK: usize = 256;
+
+static SWAP_DEVICE: LazyInit<Box<dyn SwapDevice>> = LazyInit::new();
+static SWAP_SLOTS: SpinNoIrq<SwapSlots> = SpinNoIrq::new(SwapSlots::new());
+static LOW_WATERMARK: AtomicUsize = AtomicUsize::new(DEFAULT_LOW_WATERMARK);
+
+/// A device to store the swapped out pages, e.g., a block device or a file.
+pub trait SwapDevice: Send + Sync {
+    /// Returns the number of pages that can be stored.
+    fn num_pages(&self) -> usize;
+    /// Reads the page at slot `idx` into `buf`, which is one page.
+    fn read_page(&self, idx: usize, buf: &mut [u8]) -> AxResult;
+    /// Writes `buf`, which is one page, to slot `idx`.
+    fn write_page(&self, idx: usize, buf: &[u8]) -> AxResult;
+}
+
+/// The allocation bitmap of the slots in the swap area.
+pub(crate) struct SwapSlots {
+    bitmap: Vec<u64>,
+    total: usize,
+    used: usize,
+    next: usize,
+}
+
+impl SwapSlots {
+    pub(crate) const fn new() -> Self {
+        Self {
+            bitmap: Vec::new(),
+            total: 0,
+            used: 0,
+            next: 0,
+        }
+    }
+
+    pub(crate) fn init(&mut self, total: usize) {
+        self.bitmap = alloc::vec![0; total.div_ceil(64)];
+        self.total = total;
+    }
+
+    pub(crate) fn alloc(&mut self) -> Option<usize> {
+        if self.used == self.total {
+            return None;
+        }
+        for i in 0..self.total {
+            let idx = (self.next + i) % self.total;
+            let (word, bit) = (idx / 64, idx % 64);
+            if self.bitmap[word] & (1 << bit) == 0 {
+                self.bitmap[word] |= 1 << bit;
+                self.used += 1;
+                self.next = idx + 1;
+                return Some(idx);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn dealloc(&mut self, idx: usize) {
+        let (word, bit) = (idx / 64, idx % 64);
+        if self.bitmap[word] & (1 << bit) != 0 {
+            self.bitmap[word] &= !(1 << bit);
+            self.used -= 1;
+        }
+    }
+
+    pub(crate) fn used(&self) -> usize {
+        self.used
+    }
+}
+
+/// Enables swapping to the given device.
+///
+/// # Panics
+///
+/// Panics if swapping is already enabled.
+pub fn init_swap(device: Box<dyn SwapDevice>) {
+    info!("Initialize swap area: {} pages", device.num_pages());
+    SWAP_SLOTS.lock().init(device.num_pages());
+    SWAP_DEVICE.init_once(device);
+}
+
+/// Sets the number of free pages below which the pages are reclaimed on page
+/// faults.
+pub fn set_low_watermark(pages: usize) {
+    LOW_WATERMARK.store(pages, Ordering::Relaxed);
+}
+
+/// Returns the number of pages in the swap area.
+pub fn total_pages() -> usize {
+    SWAP_SLOTS.lock().total
+}
+
+/// Returns the number of pages swapped out.
+pub fn used_pages() -> usize {
+    SWAP_SLOTS.lock().used()
+}
+
+/// Whether the pages should be reclaimed, i.e., swapping is enabled and the
+/// free pages are fewer than the low watermark.
+fn under_pressure() -> bool {
+    SWAP_DEVICE.get().is_some()
+        && global_allocator().available_pages() < LOW_WATERMARK.load(Ordering::Relaxed)
+}
+
+fn frame_buf(frame: PhysAddr) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) }
+}
+
+/// Writes the frame to a new slot of the swap area, and returns the slot.
+fn swap_out(frame: PhysAddr) -> Option<usize> {
+    let device = SWAP_DEVICE.get()?;
+    let idx = SWAP_SLOTS.lock().alloc()?;
+    if let Err(e) = device.write_page(idx, frame_buf(frame)) {
+        warn!("swap_out: failed to write slot {}: {:?}", idx, e);
+        SWAP_SLOTS.lock().dealloc(idx);
+        return None;
+    }
+    Some(idx)
+}
+
+/// Reads the page at slot `idx` of the swap area to a new frame, and releases
+/// the slot.
+fn swap_in(idx: usize) -> AxResult<PhysAddr> {
+    let Some(device) = SWAP_DEVICE.get() else {
+        return ax_err!(BadState, "swap is not enabled");
+    };
+    let Some(frame) = alloc_frame(false) else {
+        return ax_err!(NoMemory);
+    };
+    if let Err(e) = device.read_page(idx, frame_buf(frame)) {
+        dealloc_frame(frame);
+        return Err(e);
+    }
+    SWAP_SLOTS.lock().dealloc(idx);
+    Ok(frame)
+}
+
+/// The state of a tracked page.
+enum PageState {
+    /// Mapped, and accessed since the last scan.
+    Active,
+    /// Mapped with empty flags to the frame, to detect the next access.
+    Inactive(PhysAddr),
+    /// Stored at the slot of the swap area.
+    Swapped(usize),
+}
+
+/// The swappable pages of an address space, and the clock hand.
+pub(crate) struct SwapState {
+    pages: BTreeMap<VirtAddr, PageState>,
+    hand: VirtAddr,
+}
+
+impl SwapState {
+    pub(crate) const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            hand: VirtAddr::from_usize(0),
+        }
+    }
+
+    /// Starts tracking the page at `vaddr`, which is just populated.
+    pub(crate) fn track(&mut self, vaddr: VirtAddr) {
+        self.pages.entry(vaddr).or_insert(PageState::Active);
+    }
+
+    /// Handles the page fault on an inactive or swapped out page, by mapping
+    /// it back with `flags`.
+    ///
+    /// Returns `None` if the page is not such one, and the page fault should
+    /// be handled by the backend.
+    pub(crate) fn handle_page_fault(
+        &mut self,
+        vaddr: VirtAddr,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> Option<bool> {
+        let vaddr = vaddr.align_down_4k();
+        let state = self.pages.get_mut(&vaddr)?;
+        let frame = match *state {
+            PageState::Active => return None,
+            PageState::Inactive(frame) => frame,
+            PageState::Swapped(idx) => match swap_in(idx) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    warn!("swap_in: failed to read {:#x}: {:?}", vaddr, e);
+                    return Some(false);
+                }
+            },
+        };
+        *state = PageState::Active;
+        Some(
+            pt.remap(vaddr, frame, flags)
+                .map(|(_, tlb)| tlb.flush())
+                .is_ok(),
+        )
+    }
+
+    /// Maps back all the inactive and swapped out pages in the range, with
+    /// the flags given by `flags_of`.
+    pub(crate) fn restore(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        flags_of: impl Fn(VirtAddr) -> Option<MappingFlags>,
+    ) -> AxResult {
+        let pages: Vec<VirtAddr> = self
+            .pages
+            .range(start..start + size)
+            .filter(|(_, state)| !matches!(state, PageState::Active))
+            .map(|(&vaddr, _)| vaddr)
+            .collect();
+        for vaddr in pages {
+            let Some(flags) = flags_of(vaddr) else {
+                continue;
+            };
+            if self.handle_page_fault(vaddr, flags, pt) != Some(true) {
+                return ax_err!(NoMemory, "failed to swap in");
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops tracking the pages in the range, which are being unmapped.
+    ///
+    /// The frames of the inactive pages and the slots of the swapped out pages
+    /// are released, as they are not mapped in the page table.
+    pub(crate) fn release(&mut self, start: VirtAddr, size: usize) {
+        let pages: Vec<VirtAddr> = self
+            .pages
+            .range(start..start + size)
+            .map(|(&vaddr, _)| vaddr)
+            .collect();
+        for vaddr in pages {
+            match self.pages.remove(&vaddr) {
+                Some(PageState::Inactive(frame)) => dealloc_frame(frame),
+                Some(PageState::Swapped(idx)) => SWAP_SLOTS.lock().dealloc(idx),
+                _ => {}
+            }
+        }
+    }
+
+    /// Reclaims some pages if the free pages are fewer than the low watermark.
+    pub(crate) fn balance(&mut self, pt: &mut PageTable) {
+        if under_pressure() {
+            self.reclaim(RECLAIM_BATCH, pt);
+        }
+    }
+
+    /// Runs the clock scan until `max_pages` pages are swapped out, or each
+    /// page is scanned twice. Returns the number of pages swapped out.
+    pub(crate) fn reclaim(&mut self, max_pages: usize, pt: &mut PageTable) -> usize {
+        if SWAP_DEVICE.get().is_none() {
+            return 0;
+        }
+        let mut reclaimed = 0;
+        for _ in 0..self.pages.len() * 2 {
+            if reclaimed >= max_pages {
+                break;
+            }
+            let Some(vaddr) = self
+                .pages
+                .range(self.hand..)
+                .chain(self.pages.iter())
+                .find(|(_, state)| !matches!(state, PageState::Swapped(_)))
+                .map(|(&vaddr, _)| vaddr)
+            else {
+                break; // All the pages are swapped out.
+            };
+            self.hand = vaddr + PAGE_SIZE_4K;
+            let state = self.pages.get_mut(&vaddr).unwrap();
+            match *state {
+                PageState::Active => {
+                    // Clear the reference bit.
+                    let Ok((frame, flags, _)) = pt.query(vaddr) else {
+                        continue;
+                    };
+                    if flags.is_empty() || is_shared(frame) {
+                        continue;
+                    }
+                    if let Ok((_, tlb)) = pt.protect(vaddr, MappingFlags::empty()) {
+                        tlb.flush();
+                        *state = PageState::Inactive(frame);
+                    }
+                }
+                PageState::Inactive(frame) => {
+                    let Some(idx) = swap_out(frame) else {
+                        break; // The swap area is full.
+                    };
+                    // Map to a empty entry, like the pages not populated yet.
+                    match pt.remap(vaddr, 0.into(), MappingFlags::empty()) {
+                        Ok((_, tlb)) => tlb.flush(),
+                        Err(_) => {
+                            SWAP_SLOTS.lock().dealloc(idx);
+                            continue;
+                        }
+                    }
+                    dealloc_frame(frame);
+                    *state = PageState::Swapped(idx);
+                    reclaimed += 1;
+                }
+                PageState::Swapped(_) => {}
+            }
+        }
+        if reclaimed > 0 {
+            debug!("swapped out {} pages", reclaimed);
+        }
+        reclaimed
+    }
+}
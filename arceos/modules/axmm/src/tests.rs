@@ -0,0 +1,48 @@
+#[cfg(feature = "swap")]
+mod swap {
+    use crate::swap::SwapSlots;
+
+    /// Not a multiple of the bits in a word of the bitmap.
+    const NUM_SLOTS: usize = 130;
+
+    fn full_slots() -> SwapSlots {
+        let mut slots = SwapSlots::new();
+        slots.init(NUM_SLOTS);
+        for i in 0..NUM_SLOTS {
+            assert_eq!(slots.alloc(), Some(i));
+        }
+        slots
+    }
+
+    #[test]
+    fn test_alloc_all() {
+        let mut slots = SwapSlots::new();
+        assert_eq!(slots.alloc(), None); // no swap area
+        let mut slots = full_slots();
+        assert_eq!(slots.used(), NUM_SLOTS);
+        assert_eq!(slots.alloc(), None);
+    }
+
+    #[test]
+    fn test_reuse_freed() {
+        let mut slots = full_slots();
+        slots.dealloc(64);
+        slots.dealloc(3);
+        assert_eq!(slots.used(), NUM_SLOTS - 2);
+        // Searched from the one after the last allocated, wrapping around.
+        assert_eq!(slots.alloc(), Some(3));
+        assert_eq!(slots.alloc(), Some(64));
+        assert_eq!(slots.alloc(), None);
+    }
+
+    #[test]
+    fn test_dealloc_twice() {
+        let mut slots = SwapSlots::new();
+        slots.init(NUM_SLOTS);
+        let idx = slots.alloc().unwrap();
+        slots.dealloc(idx);
+        slots.dealloc(idx);
+        assert_eq!(slots.used(), 0);
+        assert_eq!(slots.alloc(), Some(idx + 1));
+    }
+}
@@ -14,6 +14,9 @@
 //!
 //! - `smoltcp`: Use [smoltcp] as the underlying network stack. This is enabled
 //!   by default.
+//! - `irq`, `multitask`: If both are enabled, the asynchronous socket
+//!   operations that would block sleep on a timer between interface polls,
+//!   instead of yielding to other futures.
 //!
 //! [smoltcp]: https://github.com/smoltcp-rs/smoltcp
 
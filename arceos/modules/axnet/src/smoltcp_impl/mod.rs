@@ -6,13 +6,16 @@ mod tcp;
 mod udp;
 
 use alloc::vec;
+use alloc::vec::Vec;
 use core::cell::RefCell;
 use core::ops::DerefMut;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Poll, Waker};
 
 use axdriver::prelude::*;
 use axdriver_net::{DevError, NetBufPtr};
 use axhal::time::{wall_time_nanos, NANOS_PER_MICROS};
-use axsync::Mutex;
+use axsync::{spin::SpinNoIrq, Mutex};
 use lazyinit::LazyInit;
 use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
 use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
@@ -50,6 +53,17 @@ const UDP_RX_BUF_LEN: usize = 64 * 1024;
 const UDP_TX_BUF_LEN: usize = 64 * 1024;
 const LISTEN_QUEUE_SIZE: usize = 512;
 
+/// How long an asynchronous socket operation waits before polling the
+/// interfaces by itself, if nobody else polls them.
+#[cfg(all(feature = "irq", feature = "multitask"))]
+const ASYNC_POLL_INTERVAL: core::time::Duration = core::time::Duration::from_millis(1);
+
+/// The number of interface polls that may have changed the socket states.
+static POLL_GENERATION: AtomicU64 = AtomicU64::new(0);
+/// The wakers of the asynchronous socket operations that would block, woken
+/// when the socket states may have changed.
+static POLL_WAKERS: SpinNoIrq<Vec<Waker>> = SpinNoIrq::new(Vec::new());
+
 static LISTEN_TABLE: LazyInit<ListenTable> = LazyInit::new();
 static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 static ETH0: LazyInit<InterfaceWrapper> = LazyInit::new();
@@ -175,7 +189,10 @@ impl InterfaceWrapper {
         let mut iface = self.iface.lock();
         let mut sockets = sockets.lock();
         let timestamp = Self::current_time();
-        iface.poll(timestamp, dev.deref_mut(), &mut sockets);
+        if iface.poll(timestamp, dev.deref_mut(), &mut sockets) {
+            drop(sockets);
+            wake_poll_waiters();
+        }
     }
 }
 
@@ -300,6 +317,58 @@ pub fn poll_interfaces() {
     SOCKET_SET.poll_interfaces();
 }
 
+fn wake_poll_waiters() {
+    POLL_GENERATION.fetch_add(1, Ordering::Release);
+    let wakers = core::mem::take(&mut *POLL_WAKERS.lock());
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+/// Waits until the socket states may have changed, used by asynchronous socket
+/// operations that would block.
+///
+/// The network devices are polled rather than interrupt-driven, so the future
+/// is woken after any interface poll that processed packets. If nobody else
+/// polls the interfaces, it completes after [`ASYNC_POLL_INTERVAL`], so that
+/// the caller polls them by itself. Without timers (the `irq` and `multitask`
+/// features), it only yields to other futures once.
+async fn wait_for_poll() {
+    let generation = POLL_GENERATION.load(Ordering::Acquire);
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    let mut timeout = axtask::future::sleep(ASYNC_POLL_INTERVAL);
+    #[cfg(not(all(feature = "irq", feature = "multitask")))]
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if POLL_GENERATION.load(Ordering::Acquire) != generation {
+            return Poll::Ready(());
+        }
+        {
+            let mut wakers = POLL_WAKERS.lock();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        // Check again, the interfaces may be polled before the waker is
+        // registered.
+        if POLL_GENERATION.load(Ordering::Acquire) != generation {
+            return Poll::Ready(());
+        }
+        #[cfg(all(feature = "irq", feature = "multitask"))]
+        return core::future::Future::poll(core::pin::Pin::new(&mut timeout), cx);
+        #[cfg(not(all(feature = "irq", feature = "multitask")))]
+        {
+            if core::mem::replace(&mut yielded, true) {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
+
 /// Benchmark raw socket transmit bandwidth.
 pub fn bench_transmit() {
     ETH0.dev.lock().bench_transmit_bandwidth();
@@ -11,7 +11,7 @@ use smoltcp::socket::tcp::{self, ConnectError, State};
 use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
 
 use super::addr::{from_core_sockaddr, into_core_sockaddr, is_unspecified, UNSPECIFIED_ENDPOINT};
-use super::{SocketSetWrapper, ETH0, LISTEN_TABLE, SOCKET_SET};
+use super::{wait_for_poll, SocketSetWrapper, ETH0, LISTEN_TABLE, SOCKET_SET};
 
 // State transitions:
 // CLOSED -(connect)-> BUSY -> CONNECTING -> CONNECTED -(shutdown)-> BUSY -> CLOSED
@@ -223,17 +223,17 @@ impl TcpSocket {
     ///
     /// It's must be called after [`bind`](Self::bind) and [`listen`](Self::listen).
     pub fn accept(&self) -> AxResult<TcpSocket> {
-        if !self.is_listening() {
-            return ax_err!(InvalidInput, "socket accept() failed: not listen");
-        }
+        let local_port = self.listening_port()?;
+        self.block_on(|| Self::accept_impl(local_port))
+    }
 
-        // SAFETY: `self.local_addr` should be initialized after `bind()`.
-        let local_port = unsafe { self.local_addr.get().read().port };
-        self.block_on(|| {
-            let (handle, (local_addr, peer_addr)) = LISTEN_TABLE.accept(local_port)?;
-            debug!("TCP socket accepted a new connection {}", peer_addr);
-            Ok(TcpSocket::new_connected(handle, local_addr, peer_addr))
-        })
+    /// Asynchronous version of [`accept`](Self::accept).
+    ///
+    /// Instead of blocking the calling thread, it yields to other futures until
+    /// a new TCP connection is established.
+    pub async fn accept_async(&self) -> AxResult<TcpSocket> {
+        let local_port = self.listening_port()?;
+        self.block_on_async(|| Self::accept_impl(local_port)).await
     }
 
     /// Close the connection.
@@ -271,65 +271,26 @@ impl TcpSocket {
 
     /// Receives data from the socket, stores it in the given buffer.
     pub fn recv(&self, buf: &mut [u8]) -> AxResult<usize> {
-        if self.is_connecting() {
-            return Err(AxError::WouldBlock);
-        } else if !self.is_connected() {
-            return ax_err!(NotConnected, "socket recv() failed");
-        }
+        let handle = self.stream_handle("socket recv() failed")?;
+        self.block_on(|| Self::recv_impl(handle, buf))
+    }
 
-        // SAFETY: `self.handle` should be initialized in a connected socket.
-        let handle = unsafe { self.handle.get().read().unwrap() };
-        self.block_on(|| {
-            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
-                if !socket.is_active() {
-                    // not open
-                    ax_err!(ConnectionRefused, "socket recv() failed")
-                } else if !socket.may_recv() {
-                    // connection closed
-                    Ok(0)
-                } else if socket.recv_queue() > 0 {
-                    // data available
-                    // TODO: use socket.recv(|buf| {...})
-                    let len = socket
-                        .recv_slice(buf)
-                        .map_err(|_| ax_err_type!(BadState, "socket recv() failed"))?;
-                    Ok(len)
-                } else {
-                    // no more data
-                    Err(AxError::WouldBlock)
-                }
-            })
-        })
+    /// Asynchronous version of [`recv`](Self::recv).
+    pub async fn recv_async(&self, buf: &mut [u8]) -> AxResult<usize> {
+        let handle = self.stream_handle("socket recv() failed")?;
+        self.block_on_async(|| Self::recv_impl(handle, buf)).await
     }
 
     /// Transmits data in the given buffer.
     pub fn send(&self, buf: &[u8]) -> AxResult<usize> {
-        if self.is_connecting() {
-            return Err(AxError::WouldBlock);
-        } else if !self.is_connected() {
-            return ax_err!(NotConnected, "socket send() failed");
-        }
+        let handle = self.stream_handle("socket send() failed")?;
+        self.block_on(|| Self::send_impl(handle, buf))
+    }
 
-        // SAFETY: `self.handle` should be initialized in a connected socket.
-        let handle = unsafe { self.handle.get().read().unwrap() };
-        self.block_on(|| {
-            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
-                if !socket.is_active() || !socket.may_send() {
-                    // closed by remote
-                    ax_err!(ConnectionReset, "socket send() failed")
-                } else if socket.can_send() {
-                    // connected, and the tx buffer is not full
-                    // TODO: use socket.send(|buf| {...})
-                    let len = socket
-                        .send_slice(buf)
-                        .map_err(|_| ax_err_type!(BadState, "socket send() failed"))?;
-                    Ok(len)
-                } else {
-                    // tx buffer is full
-                    Err(AxError::WouldBlock)
-                }
-            })
-        })
+    /// Asynchronous version of [`send`](Self::send).
+    pub async fn send_async(&self, buf: &[u8]) -> AxResult<usize> {
+        let handle = self.stream_handle("socket send() failed")?;
+        self.block_on_async(|| Self::send_impl(handle, buf)).await
     }
 
     /// Whether the socket is readable or writable.
@@ -387,6 +348,74 @@ impl TcpSocket {
         }
     }
 
+    /// Returns the local port of a listening socket.
+    fn listening_port(&self) -> AxResult<u16> {
+        if !self.is_listening() {
+            return ax_err!(InvalidInput, "socket accept() failed: not listen");
+        }
+        // SAFETY: `self.local_addr` should be initialized after `bind()`.
+        Ok(unsafe { self.local_addr.get().read().port })
+    }
+
+    /// Returns the handle of a connected socket, or `err_msg` is logged and
+    /// [`Err(NotConnected)`](AxError::NotConnected) is returned.
+    fn stream_handle(&self, err_msg: &str) -> AxResult<SocketHandle> {
+        if self.is_connecting() {
+            return Err(AxError::WouldBlock);
+        } else if !self.is_connected() {
+            return ax_err!(NotConnected, err_msg);
+        }
+        // SAFETY: `self.handle` should be initialized in a connected socket.
+        Ok(unsafe { self.handle.get().read().unwrap() })
+    }
+
+    fn accept_impl(local_port: u16) -> AxResult<TcpSocket> {
+        let (handle, (local_addr, peer_addr)) = LISTEN_TABLE.accept(local_port)?;
+        debug!("TCP socket accepted a new connection {}", peer_addr);
+        Ok(TcpSocket::new_connected(handle, local_addr, peer_addr))
+    }
+
+    fn recv_impl(handle: SocketHandle, buf: &mut [u8]) -> AxResult<usize> {
+        SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+            if !socket.is_active() {
+                // not open
+                ax_err!(ConnectionRefused, "socket recv() failed")
+            } else if !socket.may_recv() {
+                // connection closed
+                Ok(0)
+            } else if socket.recv_queue() > 0 {
+                // data available
+                // TODO: use socket.recv(|buf| {...})
+                let len = socket
+                    .recv_slice(buf)
+                    .map_err(|_| ax_err_type!(BadState, "socket recv() failed"))?;
+                Ok(len)
+            } else {
+                // no more data
+                Err(AxError::WouldBlock)
+            }
+        })
+    }
+
+    fn send_impl(handle: SocketHandle, buf: &[u8]) -> AxResult<usize> {
+        SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+            if !socket.is_active() || !socket.may_send() {
+                // closed by remote
+                ax_err!(ConnectionReset, "socket send() failed")
+            } else if socket.can_send() {
+                // connected, and the tx buffer is not full
+                // TODO: use socket.send(|buf| {...})
+                let len = socket
+                    .send_slice(buf)
+                    .map_err(|_| ax_err_type!(BadState, "socket send() failed"))?;
+                Ok(len)
+            } else {
+                // tx buffer is full
+                Err(AxError::WouldBlock)
+            }
+        })
+    }
+
     #[inline]
     fn is_connecting(&self) -> bool {
         self.get_state() == STATE_CONNECTING
@@ -491,6 +520,27 @@ impl TcpSocket {
             }
         }
     }
+
+    /// Asynchronous version of [`block_on`](Self::block_on), which waits for
+    /// the next interface poll without blocking the thread if the function
+    /// returns [`Err(WouldBlock)`](AxError::WouldBlock).
+    async fn block_on_async<F, T>(&self, mut f: F) -> AxResult<T>
+    where
+        F: FnMut() -> AxResult<T>,
+    {
+        if self.is_nonblocking() {
+            f()
+        } else {
+            loop {
+                SOCKET_SET.poll_interfaces();
+                match f() {
+                    Ok(t) => return Ok(t),
+                    Err(AxError::WouldBlock) => wait_for_poll().await,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
 }
 
 impl Drop for TcpSocket {
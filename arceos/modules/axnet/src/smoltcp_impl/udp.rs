@@ -11,7 +11,7 @@ use smoltcp::socket::udp::{self, BindError, SendError};
 use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
 
 use super::addr::{from_core_sockaddr, into_core_sockaddr, is_unspecified, UNSPECIFIED_ENDPOINT};
-use super::{SocketSetWrapper, SOCKET_SET};
+use super::{wait_for_poll, SocketSetWrapper, SOCKET_SET};
 
 /// A UDP socket that provides POSIX-like APIs.
 pub struct UdpSocket {
@@ -118,6 +118,29 @@ impl UdpSocket {
         })
     }
 
+    /// Asynchronous version of [`send_to`](Self::send_to).
+    pub async fn send_to_async(&self, buf: &[u8], remote_addr: SocketAddr) -> AxResult<usize> {
+        if remote_addr.port() == 0 || remote_addr.ip().is_unspecified() {
+            return ax_err!(InvalidInput, "socket send_to() failed: invalid address");
+        }
+        self.check_bound("socket send() failed")?;
+        let remote_endpoint = from_core_sockaddr(remote_addr);
+        self.block_on_async(|| self.try_send(buf, remote_endpoint))
+            .await
+    }
+
+    /// Asynchronous version of [`recv_from`](Self::recv_from).
+    pub async fn recv_from_async(&self, buf: &mut [u8]) -> AxResult<(usize, SocketAddr)> {
+        self.check_bound("socket send() failed")?;
+        self.block_on_async(|| {
+            self.try_recv(|socket| match socket.recv_slice(buf) {
+                Ok((len, meta)) => Ok((len, into_core_sockaddr(meta.endpoint))),
+                Err(_) => ax_err!(BadState, "socket recv_from() failed"),
+            })
+        })
+        .await
+    }
+
     /// Receives a single datagram message on the socket, without removing it from
     /// the queue. On success, returns the number of bytes read and the origin.
     pub fn peek_from(&self, buf: &mut [u8]) -> AxResult<(usize, SocketAddr)> {
@@ -206,49 +229,57 @@ impl UdpSocket {
         }
     }
 
-    fn send_impl(&self, buf: &[u8], remote_endpoint: IpEndpoint) -> AxResult<usize> {
+    fn check_bound(&self, err_msg: &str) -> AxResult {
         if self.local_addr.read().is_none() {
-            return ax_err!(NotConnected, "socket send() failed");
+            return ax_err!(NotConnected, err_msg);
         }
+        Ok(())
+    }
 
-        self.block_on(|| {
-            SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
-                if socket.can_send() {
-                    socket
-                        .send_slice(buf, remote_endpoint)
-                        .map_err(|e| match e {
-                            SendError::BufferFull => AxError::WouldBlock,
-                            SendError::Unaddressable => {
-                                ax_err_type!(ConnectionRefused, "socket send() failed")
-                            }
-                        })?;
-                    Ok(buf.len())
-                } else {
-                    // tx buffer is full
-                    Err(AxError::WouldBlock)
-                }
-            })
-        })
+    fn send_impl(&self, buf: &[u8], remote_endpoint: IpEndpoint) -> AxResult<usize> {
+        self.check_bound("socket send() failed")?;
+        self.block_on(|| self.try_send(buf, remote_endpoint))
     }
 
     fn recv_impl<F, T>(&self, mut op: F) -> AxResult<T>
     where
         F: FnMut(&mut udp::Socket) -> AxResult<T>,
     {
-        if self.local_addr.read().is_none() {
-            return ax_err!(NotConnected, "socket send() failed");
-        }
+        self.check_bound("socket send() failed")?;
+        self.block_on(|| self.try_recv(&mut op))
+    }
 
-        self.block_on(|| {
-            SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
-                if socket.can_recv() {
-                    // data available
-                    op(socket)
-                } else {
-                    // no more data
-                    Err(AxError::WouldBlock)
-                }
-            })
+    fn try_send(&self, buf: &[u8], remote_endpoint: IpEndpoint) -> AxResult<usize> {
+        SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
+            if socket.can_send() {
+                socket
+                    .send_slice(buf, remote_endpoint)
+                    .map_err(|e| match e {
+                        SendError::BufferFull => AxError::WouldBlock,
+                        SendError::Unaddressable => {
+                            ax_err_type!(ConnectionRefused, "socket send() failed")
+                        }
+                    })?;
+                Ok(buf.len())
+            } else {
+                // tx buffer is full
+                Err(AxError::WouldBlock)
+            }
+        })
+    }
+
+    fn try_recv<F, T>(&self, op: F) -> AxResult<T>
+    where
+        F: FnOnce(&mut udp::Socket) -> AxResult<T>,
+    {
+        SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
+            if socket.can_recv() {
+                // data available
+                op(socket)
+            } else {
+                // no more data
+                Err(AxError::WouldBlock)
+            }
         })
     }
 
@@ -269,6 +300,27 @@ impl UdpSocket {
             }
         }
     }
+
+    /// Asynchronous version of [`block_on`](Self::block_on), which waits for
+    /// the next interface poll without blocking the thread if the function
+    /// returns [`Err(WouldBlock)`](AxError::WouldBlock).
+    async fn block_on_async<F, T>(&self, mut f: F) -> AxResult<T>
+    where
+        F: FnMut() -> AxResult<T>,
+    {
+        if self.is_nonblocking() {
+            f()
+        } else {
+            loop {
+                SOCKET_SET.poll_interfaces();
+                match f() {
+                    Ok(t) => return Ok(t),
+                    Err(AxError::WouldBlock) => wait_for_poll().await,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
 }
 
 impl Drop for UdpSocket {
@@ -0,0 +1,100 @@
+//! Kernel command line, which is read from the `bootargs` property of the
+//! `/chosen` node in the device tree.
+//!
+//! The command line is a list of `key=value` arguments separated by spaces.
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// Reads a big-endian `u32` at `offset`.
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Returns the NUL-terminated string at `offset`.
+fn c_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Finds the `bootargs` property of the `/chosen` node in the flattened device
+/// tree.
+fn find_bootargs(fdt: &[u8]) -> Option<&str> {
+    let struct_off = be32(fdt, 8)? as usize;
+    let strings_off = be32(fdt, 12)? as usize;
+    let mut offset = struct_off;
+    let mut depth = 0;
+    let mut in_chosen = false;
+    loop {
+        let token = be32(fdt, offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                if in_chosen {
+                    return None; // properties are before the child nodes
+                }
+                let name = c_str(fdt, offset)?;
+                offset = align4(offset + name.len() + 1);
+                depth += 1;
+                in_chosen = depth == 2 && name == "chosen";
+            }
+            FDT_END_NODE => {
+                if in_chosen {
+                    return None;
+                }
+                depth -= 1;
+            }
+            FDT_PROP => {
+                let len = be32(fdt, offset)? as usize;
+                let name_off = be32(fdt, offset + 4)? as usize;
+                let value_off = offset + 8;
+                offset = align4(value_off + len);
+                if in_chosen && c_str(fdt, strings_off.checked_add(name_off)?)? == "bootargs" {
+                    return c_str(fdt, value_off);
+                }
+            }
+            FDT_NOP => {}
+            _ => return None, // FDT_END or invalid
+        }
+    }
+}
+
+/// Returns the kernel command line in the device tree blob at the physical
+/// address `dtb`, or `None` if there is no device tree or command line.
+///
+/// The device tree may be in the free memory, so the command line must be
+/// used before the memory allocator is initialized.
+pub fn bootargs(dtb: usize) -> Option<&'static str> {
+    if dtb == 0 {
+        return None;
+    }
+    let ptr = axhal::mem::phys_to_virt(dtb.into()).as_ptr();
+    // Safety: the bootloader passes a valid device tree blob, whose header is
+    // checked before accessing the whole blob.
+    let fdt = unsafe {
+        let header = core::slice::from_raw_parts(ptr, 8);
+        if be32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        core::slice::from_raw_parts(ptr, be32(header, 4)? as usize)
+    };
+    find_bootargs(fdt).filter(|args| !args.is_empty())
+}
+
+/// Returns the value of the argument `key=value` in the command line.
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+pub fn get_arg<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    cmdline.split_ascii_whitespace().find_map(|arg| {
+        let (k, v) = arg.split_once('=')?;
+        (k == key).then_some(v)
+    })
+}
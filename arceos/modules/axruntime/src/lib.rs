@@ -5,8 +5,16 @@
 //!
 //! # Cargo Features
 //!
-//! - `alloc`: Enable global memory allocator.
+//! - `alloc`: Enable global memory allocator. The allocations during the boot
+//!   are served by an early bump allocator until the heap is initialized.
+//! - `alloc-stats`: Record heap statistics, and print them at shutdown.
+//! - `alloc-debug`: Detect heap corruption by red zones and poisoning.
+//! - `alloc-debug-guard-pages`: Also place large allocations on their own
+//!   pages with guard pages, once paging is initialized.
+//! - `alt_alloc`: Use the bump allocator (`alt_axalloc`) as the global memory
+//!   allocator instead.
 //! - `paging`: Enable page table manipulation support.
+//! - `swap`: Enable swapping the anonymous pages to a block device.
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
@@ -21,12 +29,17 @@
 
 #[macro_use]
 extern crate axlog;
+#[cfg(feature = "swap")]
+extern crate alloc;
 
+mod cmdline;
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
 
 #[cfg(feature = "smp")]
 mod mp;
+#[cfg(feature = "swap")]
+mod swap;
 
 #[cfg(feature = "smp")]
 pub use self::mp::rust_main_secondary;
@@ -125,6 +138,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         chrono::DateTime::from_timestamp_nanos(axhal::time::wall_time_nanos() as _),
     );
 
+    #[cfg(feature = "alloc")]
+    init_early_allocator();
+
     axlog::init();
     axlog::set_max_level(option_env!("AX_LOG").unwrap_or("")); // no effect if set `log-level-*` features
     info!("Logging is enabled.");
@@ -141,11 +157,20 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         );
     }
 
+    let cmdline = cmdline::bootargs(dtb).unwrap_or_default();
+    if !cmdline.is_empty() {
+        info!("Kernel command line: {}", cmdline);
+    }
+
+    #[cfg(feature = "alloc")]
+    select_heap_allocator(cmdline);
     #[cfg(any(feature = "alloc", feature = "alt_alloc"))]
     init_allocator();
 
     #[cfg(feature = "paging")]
     axmm::init_memory_management();
+    #[cfg(feature = "alloc-debug-guard-pages")]
+    axalloc::debug::enable_guard_pages();
 
     info!("Initialize platform devices...");
     axhal::platform_init();
@@ -153,10 +178,13 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(feature = "multitask")]
     axtask::init_scheduler();
 
-    #[cfg(any(feature = "fs", feature = "net", feature = "display"))]
+    #[cfg(any(feature = "fs", feature = "net", feature = "display", feature = "swap"))]
     {
-        #[allow(unused_variables)]
-        let all_devices = axdriver::init_drivers();
+        #[allow(unused_variables, unused_mut)]
+        let mut all_devices = axdriver::init_drivers();
+
+        #[cfg(feature = "swap")]
+        self::swap::init_swap(&mut all_devices.block);
 
         #[cfg(feature = "fs")]
         axfs::init_filesystems(all_devices.block);
@@ -201,12 +229,48 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     }
 }
 
+/// The size of the static region serving the allocations before the heap is
+/// initialized.
+#[cfg(feature = "alloc")]
+const EARLY_HEAP_SIZE: usize = 0x10000; // 64 K
+
+/// Lets the global allocator serve the allocations from a static region until
+/// [`init_allocator`] is called. The blocks allocated there are never freed
+/// into the heap.
+#[cfg(feature = "alloc")]
+fn init_early_allocator() {
+    #[repr(align(4096))]
+    struct EarlyHeap([u8; EARLY_HEAP_SIZE]);
+
+    static mut EARLY_HEAP: EarlyHeap = EarlyHeap([0; EARLY_HEAP_SIZE]);
+    let start = unsafe { core::ptr::addr_of_mut!(EARLY_HEAP) } as usize;
+    axalloc::global_early_init(start, EARLY_HEAP_SIZE);
+}
+
+/// Selects the byte allocator of the heap by `axalloc=<name>` in the kernel
+/// command line, or the `heap-allocator` config.
+#[cfg(feature = "alloc")]
+fn select_heap_allocator(cmdline: &str) {
+    let name = cmdline::get_arg(cmdline, "axalloc").unwrap_or(axconfig::HEAP_ALLOCATOR);
+    if !axalloc::select_byte_allocator(name) {
+        warn!(
+            "unknown byte allocator {:?}, use {} instead",
+            name,
+            axalloc::global_allocator().name()
+        );
+    }
+}
+
 #[cfg(feature = "alloc")]
 fn init_allocator() {
     use axhal::mem::{memory_regions, phys_to_virt, MemRegionFlags};
 
     info!("Initialize global memory allocator...");
     info!("  use {} allocator.", axalloc::global_allocator().name());
+    #[cfg(feature = "alloc-stats")]
+    axhal::misc::register_shutdown_hook(axalloc::stats::dump);
+    #[cfg(feature = "multitask")]
+    axalloc::oom::set_oom_killer(oom_kill_current);
 
     let mut max_region_size = 0;
     let mut max_region_paddr = 0.into();
@@ -230,6 +294,52 @@ fn init_allocator() {
     }
 }
 
+/// Kills the current task on out of memory, returns `false` if it cannot be
+/// killed.
+///
+/// The task is only marked as killed, and exits at its next safe point, as
+/// the allocator may be called with any locks held.
+#[cfg(all(feature = "alloc", feature = "multitask"))]
+fn oom_kill_current(layout: core::alloc::Layout) -> bool {
+    let Some(curr) = axtask::current_may_uninit() else {
+        return false;
+    };
+    if !curr.kill() {
+        return false;
+    }
+    // Do not use `id_name()`, which allocates.
+    error!(
+        "out of memory: kill task {} ({}) allocating {} bytes",
+        curr.id().as_u64(),
+        curr.name(),
+        layout.size()
+    );
+    true
+}
+
+#[cfg(feature = "alloc-debug-guard-pages")]
+struct GuardPageIfImpl;
+
+#[cfg(feature = "alloc-debug-guard-pages")]
+#[crate_interface::impl_interface]
+impl axalloc::debug::GuardPageIf for GuardPageIfImpl {
+    fn set_pages_accessible(vaddr: usize, size: usize, accessible: bool) -> bool {
+        use axhal::paging::MappingFlags;
+
+        let flags = if accessible {
+            MappingFlags::READ | MappingFlags::WRITE
+        } else {
+            MappingFlags::empty()
+        };
+        // The allocator may be called with the address space locked, e.g.,
+        // when mapping new areas.
+        match axmm::kernel_aspace().try_lock() {
+            Some(mut aspace) => aspace.protect(vaddr.into(), size, flags).is_ok(),
+            None => false,
+        }
+    }
+}
+
 #[cfg(feature = "alt_alloc")]
 fn init_allocator() {
     use axhal::mem::{memory_regions, phys_to_virt, MemRegionFlags};
@@ -268,25 +378,50 @@ fn init_interrupt() {
         axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
 
     #[percpu::def_percpu]
-    static NEXT_DEADLINE: u64 = 0;
+    static NEXT_TICK: u64 = 0;
 
-    fn update_timer() {
+    /// Returns `true` if the periodic tick is due, and moves to the next tick.
+    fn tick_due() -> bool {
         let now_ns = axhal::time::monotonic_time_nanos();
         // Safety: we have disabled preemption in IRQ handler.
-        let mut deadline = unsafe { NEXT_DEADLINE.read_current_raw() };
-        if now_ns >= deadline {
-            deadline = now_ns + PERIODIC_INTERVAL_NANOS;
+        let next_tick = unsafe { NEXT_TICK.read_current_raw() };
+        if now_ns < next_tick {
+            return false;
         }
-        unsafe { NEXT_DEADLINE.write_current_raw(deadline + PERIODIC_INTERVAL_NANOS) };
-        axhal::time::set_oneshot_timer(deadline);
+        let mut next_tick = next_tick + PERIODIC_INTERVAL_NANOS;
+        if now_ns >= next_tick {
+            next_tick = now_ns + PERIODIC_INTERVAL_NANOS;
+        }
+        unsafe { NEXT_TICK.write_current_raw(next_tick) };
+        true
+    }
+
+    fn update_timer() {
+        // Safety: we have disabled preemption in IRQ handler.
+        let next_tick = unsafe { NEXT_TICK.read_current_raw() };
+        // Timed events may expire before the next tick.
+        #[cfg(feature = "multitask")]
+        axtask::on_timer_update(next_tick);
+        #[cfg(not(feature = "multitask"))]
+        axhal::time::set_oneshot_timer(next_tick);
     }
 
     axhal::irq::register_handler(TIMER_IRQ_NUM, || {
-        update_timer();
+        #[cfg_attr(not(feature = "multitask"), allow(unused_variables))]
+        let is_tick = tick_due();
         #[cfg(feature = "multitask")]
-        axtask::on_timer_tick();
+        if is_tick {
+            axtask::on_timer_tick();
+        } else {
+            axtask::on_timer_event();
+        }
+        update_timer();
     });
 
+    // Other CPUs send IPIs after they add tasks to the run queue of this CPU.
+    #[cfg(feature = "multitask")]
+    axhal::irq::register_handler(axhal::irq::IPI_IRQ_NUM, axtask::on_resched_ipi);
+
     // Enable IRQs before starting app
     axhal::arch::enable_irqs();
 }
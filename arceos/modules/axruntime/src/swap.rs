@@ -0,0 +1,92 @@
+//! Swapping to a block device.
+
+use alloc::boxed::Box;
+
+use axdriver::prelude::{AxBlockDevice, BaseDriverOps, BlockDriverOps};
+use axdriver::AxDeviceContainer;
+use axerrno::{ax_err, AxResult};
+use axhal::mem::PAGE_SIZE_4K;
+use axmm::swap::SwapDevice;
+use kspin::SpinNoIrq;
+
+/// A swap area on a whole block device.
+struct BlockSwapDevice {
+    dev: SpinNoIrq<AxBlockDevice>,
+    blocks_per_page: usize,
+}
+
+impl BlockSwapDevice {
+    /// Creates a swap area on the block device.
+    ///
+    /// Returns an error if the page size is not a multiple of the block size.
+    fn new(dev: AxBlockDevice) -> AxResult<Self> {
+        let block_size = dev.block_size();
+        if block_size == 0 || PAGE_SIZE_4K % block_size != 0 {
+            return ax_err!(Unsupported, "unsupported block size");
+        }
+        info!(
+            "swap on block device {}: {} blocks of {} bytes",
+            dev.device_name(),
+            dev.num_blocks(),
+            block_size
+        );
+        Ok(Self {
+            dev: SpinNoIrq::new(dev),
+            blocks_per_page: PAGE_SIZE_4K / block_size,
+        })
+    }
+}
+
+impl SwapDevice for BlockSwapDevice {
+    fn num_pages(&self) -> usize {
+        self.dev.lock().num_blocks() as usize / self.blocks_per_page
+    }
+
+    fn read_page(&self, idx: usize, buf: &mut [u8]) -> AxResult {
+        let mut dev = self.dev.lock();
+        let block_size = dev.block_size();
+        let first_block = (idx * self.blocks_per_page) as u64;
+        for (i, block) in buf.chunks_exact_mut(block_size).enumerate() {
+            if dev.read_block(first_block + i as u64, block).is_err() {
+                return ax_err!(Io, "failed to read block");
+            }
+        }
+        Ok(())
+    }
+
+    fn write_page(&self, idx: usize, buf: &[u8]) -> AxResult {
+        let mut dev = self.dev.lock();
+        let block_size = dev.block_size();
+        let first_block = (idx * self.blocks_per_page) as u64;
+        for (i, block) in buf.chunks_exact(block_size).enumerate() {
+            if dev.write_block(first_block + i as u64, block).is_err() {
+                return ax_err!(Io, "failed to write block");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Enables swapping to a block device.
+///
+/// With the `fs` feature, the first block device is left for the root file
+/// system, and the next one is used.
+pub fn init_swap(blk_devs: &mut AxDeviceContainer<AxBlockDevice>) {
+    info!("Initialize swap...");
+    #[cfg(feature = "fs")]
+    let root_dev = blk_devs.take_one();
+    let dev = blk_devs.take_one();
+    #[cfg(feature = "fs")]
+    if let Some(root_dev) = root_dev {
+        *blk_devs = AxDeviceContainer::from_one(root_dev);
+    }
+
+    let Some(dev) = dev else {
+        warn!("  no block device for swap");
+        return;
+    };
+    match BlockSwapDevice::new(dev) {
+        Ok(dev) => axmm::swap::init_swap(Box::new(dev)),
+        Err(e) => warn!("  cannot swap on the block device: {:?}", e),
+    }
+}
@@ -0,0 +1,86 @@
+//! A value which is initialized on the first access.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+
+use crate::Once;
+
+/// A value which is initialized on the first access, similar to
+/// [`std::sync::LazyLock`](https://doc.rust-lang.org/std/sync/struct.LazyLock.html).
+///
+/// The tasks that access the value while it is being initialized will block
+/// until the initialization completes, see [`Once`].
+pub struct LazyLock<T, F = fn() -> T> {
+    once: Once,
+    init: UnsafeCell<Option<F>>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Same unsafe impls as `std::sync::LazyLock`
+unsafe impl<T: Sync + Send, F: Send> Sync for LazyLock<T, F> {}
+
+impl<T, F: FnOnce() -> T> LazyLock<T, F> {
+    /// Creates a new lazy value with the given initializing function.
+    pub const fn new(f: F) -> Self {
+        Self {
+            once: Once::new(),
+            init: UnsafeCell::new(Some(f)),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Forces the evaluation of this lazy value and returns a reference to
+    /// the result.
+    pub fn force(this: &LazyLock<T, F>) -> &T {
+        this.once.call_once(|| {
+            // SAFETY: `call_once` runs the closure only once, and no one
+            // accesses the fields before it completes.
+            unsafe {
+                let f = (*this.init.get()).take().unwrap();
+                (*this.value.get()).write(f());
+            }
+        });
+        // SAFETY: the value is initialized when `call_once` returns.
+        unsafe { (*this.value.get()).assume_init_ref() }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for LazyLock<T, F> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        LazyLock::force(self)
+    }
+}
+
+impl<T: Default> Default for LazyLock<T> {
+    /// Creates a new lazy value using `Default` as the initializing function.
+    fn default() -> Self {
+        Self::new(T::default)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for LazyLock<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_tuple("LazyLock");
+        if self.once.is_completed() {
+            // SAFETY: the value is initialized.
+            d.field(unsafe { (*self.value.get()).assume_init_ref() });
+        } else {
+            d.field(&format_args!("<uninit>"));
+        }
+        d.finish()
+    }
+}
+
+impl<T, F> Drop for LazyLock<T, F> {
+    fn drop(&mut self) {
+        if self.once.is_completed() {
+            // SAFETY: the value is initialized, and is dropped only once.
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
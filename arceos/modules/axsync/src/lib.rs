@@ -3,25 +3,69 @@
 //! Currently supported primitives:
 //!
 //! - [`Mutex`]: A mutual exclusion primitive.
+//! - [`RwLock`]: A readers-writer lock.
+//! - [`Condvar`]: A condition variable.
+//! - [`Semaphore`]: A counting semaphore.
+//! - [`Barrier`]: A barrier to synchronize a number of tasks.
+//! - [`Once`] and [`LazyLock`]: One-time initialization.
 //! - mod [`spin`]: spinlocks imported from the [`kspin`] crate.
 //!
 //! # Cargo Features
 //!
 //! - `multitask`: For use in the multi-threaded environments. If the feature is
 //!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`]. This
-//!   feature is enabled by default.
+//!   feature is enabled by default. The primitives other than [`Mutex`] are
+//!   only available with this feature.
+//! - `irq`: Enable the timed waiting of [`Condvar`].
+//! - `lockdep`: Validate the acquisition order of [`Mutex`] and the spinlocks
+//!   in mod [`spin`] at runtime, see [`axtask::lockdep`]. The locks are
+//!   wrappers of the ones in [`kspin`] if the feature is enabled.
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 
+#[cfg(feature = "multitask")]
+extern crate alloc;
+
+#[cfg(not(feature = "lockdep"))]
 pub use kspin as spin;
 
+#[cfg(feature = "lockdep")]
+pub mod spin;
+
+#[cfg(feature = "multitask")]
+mod barrier;
+#[cfg(feature = "multitask")]
+mod condvar;
+#[cfg(feature = "multitask")]
+mod lazy_lock;
 #[cfg(feature = "multitask")]
 mod mutex;
+#[cfg(feature = "multitask")]
+mod once;
+#[cfg(feature = "multitask")]
+mod rwlock;
+#[cfg(feature = "multitask")]
+mod semaphore;
+
+#[cfg(test)]
+mod tests;
 
 #[cfg(feature = "multitask")]
 #[doc(cfg(feature = "multitask"))]
-pub use self::mutex::{Mutex, MutexGuard};
+pub use self::{
+    barrier::{Barrier, BarrierWaitResult},
+    condvar::Condvar,
+    lazy_lock::LazyLock,
+    mutex::{Mutex, MutexGuard},
+    once::Once,
+    rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    semaphore::{Semaphore, SemaphoreGuard},
+};
+
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[doc(cfg(all(feature = "multitask", feature = "irq")))]
+pub use self::condvar::WaitTimeoutResult;
 
 #[cfg(not(feature = "multitask"))]
 #[doc(cfg(not(feature = "multitask")))]
@@ -1,11 +1,15 @@
 //! A naïve sleeping mutex.
 
 use core::cell::UnsafeCell;
+use core::convert::Infallible;
 use core::fmt;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicU64, Ordering};
 
-use axtask::{current, WaitQueue};
+use axtask::{current, Interrupted, PiLock, WaitQueue};
+
+#[cfg(feature = "lockdep")]
+use axtask::lockdep::{LockKind, LockdepMap};
 
 /// A mutual exclusion primitive useful for protecting shared data, similar to
 /// [`std::sync::Mutex`](https://doc.rust-lang.org/std/sync/struct.Mutex.html).
@@ -13,9 +17,18 @@ use axtask::{current, WaitQueue};
 /// When the mutex is locked, the current task will block and be put into the
 /// wait queue. When the mutex is unlocked, all tasks waiting on the queue
 /// will be woken up.
+///
+/// The owner of the mutex inherits the highest priority of the waiting tasks,
+/// so that a low-priority owner cannot block a high-priority waiter
+/// indefinitely. The inherited priority is dropped when
+/// the mutex is unlocked, and the next owner inherits the priorities of the
+/// remaining waiters.
 pub struct Mutex<T: ?Sized> {
     wq: WaitQueue,
     owner_id: AtomicU64,
+    pi: PiLock,
+    #[cfg(feature = "lockdep")]
+    dep_map: LockdepMap,
     data: UnsafeCell<T>,
 }
 
@@ -34,10 +47,14 @@ unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
 impl<T> Mutex<T> {
     /// Creates a new [`Mutex`] wrapping the supplied data.
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             wq: WaitQueue::new(),
             owner_id: AtomicU64::new(0),
+            pi: PiLock::new(),
+            #[cfg(feature = "lockdep")]
+            dep_map: LockdepMap::new("Mutex", LockKind::Sleep),
             data: UnsafeCell::new(data),
         }
     }
@@ -68,7 +85,51 @@ impl<T: ?Sized> Mutex<T> {
     ///
     /// The returned value may be dereferenced for data access
     /// and the lock will be dropped when the guard falls out of scope.
+    ///
+    /// The waiting can not be cut short, as the caller always gets the lock.
+    /// Use [`Mutex::lock_killable`] if a killed task should give up.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn lock(&self) -> MutexGuard<T> {
+        let res = self.lock_with(|| {
+            self.wq.wait_until(|| !self.is_locked());
+            Ok::<_, Infallible>(())
+        });
+        match res {
+            Ok(guard) => guard,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Locks the [`Mutex`] like [`Mutex::lock`], but the waiting can be
+    /// interrupted.
+    ///
+    /// Returns [`Interrupted`] if the current task is interrupted or killed
+    /// before the lock is acquired.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock_interruptible(&self) -> Result<MutexGuard<T>, Interrupted> {
+        self.lock_with(|| self.wq.wait_until_interruptible(|| !self.is_locked()))
+    }
+
+    /// Locks the [`Mutex`] like [`Mutex::lock`], but the waiting can be cut
+    /// short by killing the current task.
+    ///
+    /// Returns [`Interrupted`] if the current task is killed before the lock
+    /// is acquired.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock_killable(&self) -> Result<MutexGuard<T>, Interrupted> {
+        self.lock_with(|| self.wq.wait_until_killable(|| !self.is_locked()))
+    }
+
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    fn lock_with<E, F>(&self, wait: F) -> Result<MutexGuard<T>, E>
+    where
+        F: Fn() -> Result<(), E>,
+    {
+        #[cfg(feature = "lockdep")]
+        {
+            axtask::lockdep::might_sleep();
+            self.dep_map.acquire();
+        }
         let current_id = current().id().as_u64();
         loop {
             // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
@@ -88,18 +149,26 @@ impl<T: ?Sized> Mutex<T> {
                         current().id_name()
                     );
                     // Wait until the lock looks unlocked before retrying
-                    self.wq.wait_until(|| !self.is_locked());
+                    if let Err(e) = self.wait_for_owner(&wait) {
+                        #[cfg(feature = "lockdep")]
+                        self.dep_map.release();
+                        return Err(e);
+                    }
                 }
             }
         }
-        MutexGuard {
+        // Safety: the lock has just been acquired, and `force_unlock` calls
+        // `released` when it is released.
+        unsafe { self.pi.acquired() };
+        Ok(MutexGuard {
             lock: self,
             data: unsafe { &mut *self.data.get() },
-        }
+        })
     }
 
     /// Try to lock this [`Mutex`], returning a lock guard if successful.
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
         let current_id = current().id().as_u64();
         // The reason for using a strong compare_exchange is explained here:
@@ -109,6 +178,10 @@ impl<T: ?Sized> Mutex<T> {
             .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            #[cfg(feature = "lockdep")]
+            self.dep_map.try_acquired();
+            // Safety: the same as in `lock_with`.
+            unsafe { self.pi.acquired() };
             Some(MutexGuard {
                 lock: self,
                 data: unsafe { &mut *self.data.get() },
@@ -133,9 +206,31 @@ impl<T: ?Sized> Mutex<T> {
             "{} tried to release mutex it doesn't own",
             current().id_name()
         );
+        // Safety: the lock has just been released by the current task.
+        self.pi.released();
+        #[cfg(feature = "lockdep")]
+        self.dep_map.release();
         self.wq.notify_one(true);
     }
 
+    /// Waits until the mutex looks unlocked, and lets the owner inherit the
+    /// priority of the current task in the meantime.
+    fn wait_for_owner<E, F>(&self, wait: &F) -> Result<(), E>
+    where
+        F: Fn() -> Result<(), E>,
+    {
+        // Safety: `end_wait` is called right after the waiting, while `self`
+        // is still borrowed.
+        unsafe {
+            if !self.pi.begin_wait(|| self.is_locked()) {
+                return Ok(()); // unlocked just now, try again
+            }
+            let res = wait();
+            self.pi.end_wait();
+            res
+        }
+    }
+
     /// Returns a mutable reference to the underlying data.
     ///
     /// Since this call borrows the [`Mutex`] mutably, and a mutable reference is guaranteed to be exclusive in
@@ -151,6 +246,7 @@ impl<T: ?Sized> Mutex<T> {
 
 impl<T: ?Sized + Default> Default for Mutex<T> {
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     fn default() -> Self {
         Self::new(Default::default())
     }
@@ -167,6 +263,15 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     }
 }
 
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Releases the lock, and returns the [`Mutex`] to lock it again.
+    pub(crate) fn unlock(guard: Self) -> &'a Mutex<T> {
+        let lock = guard.lock;
+        drop(guard);
+        lock
+    }
+}
+
 impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
     type Target = T;
     #[inline(always)]
@@ -199,11 +304,10 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
 
 #[cfg(test)]
 mod tests {
-    use crate::Mutex;
-    use axtask as thread;
-    use std::sync::Once;
+    use std::sync::atomic::{AtomicBool, Ordering};
 
-    static INIT: Once = Once::new();
+    use crate::Mutex;
+    use axtask::{self as thread, TaskState};
 
     fn may_interrupt() {
         // simulate interrupts
@@ -214,7 +318,7 @@ mod tests {
 
     #[test]
     fn lots_and_lots() {
-        INIT.call_once(thread::init_scheduler);
+        let _guard = crate::tests::init();
 
         const NUM_TASKS: u32 = 10;
         const NUM_ITERS: u32 = 10_000;
@@ -249,4 +353,124 @@ mod tests {
         assert_eq!(*M.lock(), NUM_ITERS * NUM_TASKS * 3);
         println!("Mutex test OK");
     }
+
+    #[test]
+    fn priority_inheritance() {
+        let _guard = crate::tests::init();
+
+        const HIGH_PRIO: isize = -10;
+        static M: Mutex<()> = Mutex::new(());
+
+        let curr = thread::current();
+        let base_prio = curr.priority();
+        let guard = M.lock();
+        let waiter = thread::spawn(|| {
+            // Raise the priority of the waiter, which cannot be done by
+            // `set_priority` with the FIFO scheduler.
+            let base_prio = thread::current().base_priority();
+            thread::set_priority_for_test(HIGH_PRIO);
+            drop(M.lock());
+            thread::set_priority_for_test(base_prio);
+        });
+
+        // Wait until the waiter blocks on the mutex.
+        while curr.priority() != HIGH_PRIO {
+            thread::yield_now();
+        }
+        drop(guard);
+        assert_eq!(curr.priority(), base_prio);
+        waiter.join();
+        assert!(!M.is_locked());
+        println!("Mutex priority inheritance test OK");
+    }
+
+    #[test]
+    fn priority_inheritance_handoff() {
+        let _guard = crate::tests::init();
+
+        const HIGH_PRIO: isize = -10;
+        static M: Mutex<()> = Mutex::new(());
+        static DONE: AtomicBool = AtomicBool::new(false);
+
+        let wait_blocked = |task: &thread::AxTaskRef| {
+            while task.state() != TaskState::Blocked {
+                thread::yield_now();
+            }
+        };
+
+        let curr = thread::current();
+        let base_prio = curr.priority();
+        let guard = M.lock();
+        // The low-priority waiter is the first one, and gets the mutex first.
+        let low = thread::spawn(|| {
+            let _guard = M.lock();
+            while !DONE.load(Ordering::Acquire) {
+                thread::yield_now();
+            }
+        });
+        wait_blocked(&low);
+        let high = thread::spawn(|| {
+            let base_prio = thread::current().base_priority();
+            thread::set_priority_for_test(HIGH_PRIO);
+            drop(M.lock());
+            thread::set_priority_for_test(base_prio);
+        });
+        wait_blocked(&high);
+        assert_eq!(curr.priority(), HIGH_PRIO);
+        assert_eq!(low.priority(), base_prio);
+
+        // Handed off to the low waiter, which inherits the priority of the
+        // remaining high waiter.
+        drop(guard);
+        assert_eq!(curr.priority(), base_prio);
+        while !M.is_locked() || low.priority() != HIGH_PRIO {
+            thread::yield_now();
+        }
+
+        DONE.store(true, Ordering::Release);
+        low.join();
+        high.join();
+        assert_eq!(low.priority(), base_prio);
+        assert!(!M.is_locked());
+        println!("Mutex priority inheritance handoff test OK");
+    }
+
+    #[test]
+    fn priority_inheritance_chain() {
+        let _guard = crate::tests::init();
+
+        const HIGH_PRIO: isize = -10;
+        static M1: Mutex<()> = Mutex::new(());
+        static M2: Mutex<()> = Mutex::new(());
+
+        let curr = thread::current();
+        let base_prio = curr.priority();
+        let guard = M1.lock();
+        // Holds M2 and waits for M1.
+        let middle = thread::spawn(|| {
+            let _guard = M2.lock();
+            drop(M1.lock());
+        });
+        while !M2.is_locked() || middle.state() != TaskState::Blocked {
+            thread::yield_now();
+        }
+        // Waits for M2 with a high priority.
+        let high = thread::spawn(|| {
+            let base_prio = thread::current().base_priority();
+            thread::set_priority_for_test(HIGH_PRIO);
+            drop(M2.lock());
+            thread::set_priority_for_test(base_prio);
+        });
+        while curr.priority() != HIGH_PRIO {
+            thread::yield_now();
+        }
+        assert_eq!(middle.priority(), HIGH_PRIO);
+
+        drop(guard);
+        assert_eq!(curr.priority(), base_prio);
+        middle.join();
+        high.join();
+        assert_eq!(middle.priority(), base_prio);
+        println!("Mutex priority inheritance chain test OK");
+    }
 }
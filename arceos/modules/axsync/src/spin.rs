@@ -0,0 +1,161 @@
+//! Spinlocks of the [`kspin`] crate, tracked by the lock dependency validator.
+//!
+//! This module replaces the re-exported [`kspin`] crate if the `lockdep`
+//! feature is enabled. The locks have the same API as the ones in [`kspin`].
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+use axtask::lockdep::{LockKind, LockdepMap};
+
+macro_rules! def_spinlock {
+    ($(#[$attr:meta])* $name:ident, $guard:ident) => {
+        $(#[$attr])*
+        pub struct $name<T: ?Sized> {
+            dep_map: LockdepMap,
+            inner: kspin::$name<T>,
+        }
+
+        /// A guard that provides mutable data access.
+        ///
+        /// When the guard falls out of scope it will release the lock.
+        pub struct $guard<'a, T: ?Sized + 'a> {
+            dep_map: &'a LockdepMap,
+            inner: kspin::$guard<'a, T>,
+        }
+
+        impl<T> $name<T> {
+            /// Creates a new spinlock wrapping the supplied data.
+            #[inline(always)]
+            #[track_caller]
+            pub const fn new(data: T) -> Self {
+                Self {
+                    dep_map: LockdepMap::new(stringify!($name), LockKind::Spin),
+                    inner: kspin::$name::new(data),
+                }
+            }
+
+            /// Consumes this spinlock and unwraps the underlying data.
+            #[inline(always)]
+            pub fn into_inner(self) -> T {
+                self.inner.into_inner()
+            }
+        }
+
+        impl<T: ?Sized> $name<T> {
+            /// Locks the spinlock and returns a guard that permits access to
+            /// the inner data.
+            #[inline(always)]
+            #[track_caller]
+            pub fn lock(&self) -> $guard<T> {
+                self.dep_map.acquire();
+                $guard {
+                    dep_map: &self.dep_map,
+                    inner: self.inner.lock(),
+                }
+            }
+
+            /// Returns `true` if the lock is currently held.
+            #[inline(always)]
+            pub fn is_locked(&self) -> bool {
+                self.inner.is_locked()
+            }
+
+            /// Try to lock this spinlock, returning a lock guard if successful.
+            #[inline(always)]
+            #[track_caller]
+            pub fn try_lock(&self) -> Option<$guard<T>> {
+                let inner = self.inner.try_lock()?;
+                self.dep_map.try_acquired();
+                Some($guard {
+                    dep_map: &self.dep_map,
+                    inner,
+                })
+            }
+
+            /// Force unlock this spinlock.
+            ///
+            /// # Safety
+            ///
+            /// This is *extremely* unsafe if the lock is not held by the
+            /// current thread.
+            #[inline(always)]
+            pub unsafe fn force_unlock(&self) {
+                self.dep_map.release();
+                self.inner.force_unlock();
+            }
+
+            /// Returns a mutable reference to the underlying data.
+            #[inline(always)]
+            pub fn get_mut(&mut self) -> &mut T {
+                self.inner.get_mut()
+            }
+        }
+
+        impl<T: Default> Default for $name<T> {
+            #[inline(always)]
+            #[track_caller]
+            fn default() -> Self {
+                Self::new(Default::default())
+            }
+        }
+
+        impl<T: ?Sized + fmt::Debug> fmt::Debug for $name<T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.inner.fmt(f)
+            }
+        }
+
+        impl<'a, T: ?Sized> Deref for $guard<'a, T> {
+            type Target = T;
+            #[inline(always)]
+            fn deref(&self) -> &T {
+                &self.inner
+            }
+        }
+
+        impl<'a, T: ?Sized> DerefMut for $guard<'a, T> {
+            #[inline(always)]
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.inner
+            }
+        }
+
+        impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for $guard<'a, T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Debug::fmt(&**self, f)
+            }
+        }
+
+        impl<'a, T: ?Sized> Drop for $guard<'a, T> {
+            /// The lock is released after the validator records the release.
+            #[inline(always)]
+            fn drop(&mut self) {
+                self.dep_map.release();
+            }
+        }
+    };
+}
+
+def_spinlock!(
+    /// A spinlock that disables preemption and local IRQs while trying to lock,
+    /// and re-enables them after unlocking.
+    SpinNoIrq,
+    SpinNoIrqGuard
+);
+
+def_spinlock!(
+    /// A spinlock that disables preemption while trying to lock, and re-enables
+    /// it after unlocking.
+    SpinNoPreempt,
+    SpinNoPreemptGuard
+);
+
+def_spinlock!(
+    /// A raw spinlock that does nothing while locking and unlocking.
+    ///
+    /// It must be used in the preemption and IRQ disabled context, or never be
+    /// used in interrupt handlers.
+    SpinRaw,
+    SpinRawGuard
+);
@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex as StdMutex, MutexGuard as StdMutexGuard, Once as StdOnce};
+
+use axtask as thread;
+
+use crate::{Barrier, Condvar, LazyLock, Mutex, RwLock, Semaphore};
+
+static INIT: StdOnce = StdOnce::new();
+static SERIAL: StdMutex<()> = StdMutex::new(());
+
+/// Initializes the scheduler, and returns a guard that serializes the tests,
+/// as they share the same scheduler.
+pub(crate) fn init() -> StdMutexGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    INIT.call_once(thread::init_scheduler);
+    guard
+}
+
+#[test]
+fn test_rwlock() {
+    let _guard = init();
+
+    const NUM_TASKS: usize = 10;
+    const NUM_ITERS: usize = 100;
+    static RW: RwLock<(usize, usize)> = RwLock::new((0, 0));
+
+    let tasks: Vec<_> = (0..NUM_TASKS)
+        .map(|i| {
+            thread::spawn(move || {
+                for _ in 0..NUM_ITERS {
+                    if i % 2 == 0 {
+                        let mut val = RW.write();
+                        val.0 += 1;
+                        thread::yield_now();
+                        val.1 += 1;
+                    } else {
+                        let val = RW.read();
+                        thread::yield_now();
+                        assert_eq!(val.0, val.1);
+                    }
+                }
+            })
+        })
+        .collect();
+    for t in tasks {
+        t.join();
+    }
+
+    let val = RW.read();
+    assert_eq!(*val, (NUM_TASKS / 2 * NUM_ITERS, NUM_TASKS / 2 * NUM_ITERS));
+    assert!(RW.try_write().is_none());
+    drop(val);
+    assert!(RW.try_write().is_some());
+    println!("RwLock test OK");
+}
+
+#[test]
+fn test_condvar() {
+    let _guard = init();
+
+    const NUM_ITEMS: usize = 100;
+    static QUEUE: Mutex<VecDeque<usize>> = Mutex::new(VecDeque::new());
+    static NOT_EMPTY: Condvar = Condvar::new();
+
+    let consumer = thread::spawn(|| {
+        for i in 0..NUM_ITEMS {
+            let mut queue = NOT_EMPTY.wait_while(QUEUE.lock(), |q| q.is_empty());
+            assert_eq!(queue.pop_front(), Some(i));
+        }
+    });
+    for i in 0..NUM_ITEMS {
+        QUEUE.lock().push_back(i);
+        NOT_EMPTY.notify_one();
+        if i % 3 == 0 {
+            thread::yield_now();
+        }
+    }
+    consumer.join();
+    assert!(QUEUE.lock().is_empty());
+    println!("Condvar test OK");
+}
+
+#[test]
+fn test_semaphore() {
+    let _guard = init();
+
+    const NUM_TASKS: usize = 10;
+    const NUM_PERMITS: usize = 3;
+    static SEM: Semaphore = Semaphore::new(NUM_PERMITS);
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+    let tasks: Vec<_> = (0..NUM_TASKS)
+        .map(|_| {
+            thread::spawn(|| {
+                let _permit = SEM.access();
+                let running = RUNNING.fetch_add(1, Ordering::Relaxed) + 1;
+                MAX_RUNNING.fetch_max(running, Ordering::Relaxed);
+                thread::yield_now();
+                RUNNING.fetch_sub(1, Ordering::Relaxed);
+            })
+        })
+        .collect();
+    for t in tasks {
+        t.join();
+    }
+
+    assert_eq!(MAX_RUNNING.load(Ordering::Relaxed), NUM_PERMITS);
+    assert_eq!(SEM.available_permits(), NUM_PERMITS);
+    println!("Semaphore test OK");
+}
+
+#[test]
+fn test_barrier() {
+    let _guard = init();
+
+    const NUM_TASKS: usize = 10;
+    static BARRIER: Barrier = Barrier::new(NUM_TASKS);
+    static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+    static LEADERS: AtomicUsize = AtomicUsize::new(0);
+
+    let tasks: Vec<_> = (0..NUM_TASKS)
+        .map(|_| {
+            thread::spawn(|| {
+                ARRIVED.fetch_add(1, Ordering::Relaxed);
+                if BARRIER.wait().is_leader() {
+                    LEADERS.fetch_add(1, Ordering::Relaxed);
+                }
+                // All tasks have arrived before anyone leaves.
+                assert_eq!(ARRIVED.load(Ordering::Relaxed), NUM_TASKS);
+            })
+        })
+        .collect();
+    for t in tasks {
+        t.join();
+    }
+
+    assert_eq!(LEADERS.load(Ordering::Relaxed), 1);
+    println!("Barrier test OK");
+}
+
+#[test]
+fn test_lazy_lock() {
+    let _guard = init();
+
+    const NUM_TASKS: usize = 10;
+    static INIT_COUNT: AtomicUsize = AtomicUsize::new(0);
+    static VALUE: LazyLock<usize> = LazyLock::new(|| {
+        INIT_COUNT.fetch_add(1, Ordering::Relaxed);
+        // Let other tasks wait for the initialization.
+        thread::yield_now();
+        42
+    });
+
+    let tasks: Vec<_> = (0..NUM_TASKS)
+        .map(|_| thread::spawn(|| assert_eq!(*VALUE, 42)))
+        .collect();
+    for t in tasks {
+        t.join();
+    }
+
+    assert_eq!(INIT_COUNT.load(Ordering::Relaxed), 1);
+    println!("LazyLock test OK");
+}
@@ -2,10 +2,21 @@
 
 use alloc::{string::String, sync::Arc};
 
-pub(crate) use crate::run_queue::{AxRunQueue, RUN_QUEUE};
+pub(crate) use crate::run_queue::current_run_queue;
 
 #[doc(cfg(feature = "multitask"))]
-pub use crate::task::{CurrentTask, TaskId, TaskInner};
+pub use crate::cpumask::AxCpuMask;
+#[cfg(feature = "test")]
+#[doc(hidden)]
+pub use crate::pi::set_priority_for_test;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::pi::PiLock;
+#[cfg(feature = "sched_rt")]
+pub use crate::sched_rt::{DeadlineParams, SchedPolicy, RT_PRIO_MAX, RT_PRIO_MIN};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::stat::{for_each_task, task_snapshot, TaskSnapshot, TaskStat};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::task::{CurrentTask, Interrupted, TaskId, TaskInner, TaskState};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task_ext::{TaskExtMut, TaskExtRef};
 #[doc(cfg(feature = "multitask"))]
@@ -14,8 +25,14 @@ pub use crate::wait_queue::WaitQueue;
 /// The reference type of a task.
 pub type AxTaskRef = Arc<AxTask>;
 
+/// The exit code of tasks that are killed by [`TaskInner::kill`].
+pub const EXIT_CODE_KILLED: i32 = i32::MIN;
+
 cfg_if::cfg_if! {
-    if #[cfg(feature = "sched_rr")] {
+    if #[cfg(feature = "sched_rt")] {
+        pub(crate) type AxTask = scheduler::CFSTask<TaskInner>;
+        pub(crate) type Scheduler = crate::sched_rt::RtScheduler;
+    } else if #[cfg(feature = "sched_rr")] {
         const MAX_TIME_SLICE: usize = 5;
         pub(crate) type AxTask = scheduler::RRTask<TaskInner, MAX_TIME_SLICE>;
         pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
@@ -86,13 +103,40 @@ pub fn init_scheduler_secondary() {
 #[doc(cfg(feature = "irq"))]
 pub fn on_timer_tick() {
     crate::timers::check_events();
-    RUN_QUEUE.lock().scheduler_timer_tick();
+    current_run_queue().scheduler_timer_tick();
+}
+
+/// Handles timer interrupts that are not periodic ticks, which are raised
+/// for the timed events, such as the expiration of [`timer`](crate::timer)s.
+///
+/// It only checks timed events, without advancing scheduler states.
+#[cfg(feature = "irq")]
+#[doc(cfg(feature = "irq"))]
+pub fn on_timer_event() {
+    crate::timers::check_events();
+}
+
+/// Programs the hardware timer of the current CPU at the end of a timer
+/// interrupt, for the next tick at `next_tick_ns` or the first timed event,
+/// whichever is earlier.
+#[cfg(feature = "irq")]
+#[doc(cfg(feature = "irq"))]
+pub fn on_timer_update(next_tick_ns: u64) {
+    crate::timers::program_next_event(next_tick_ns);
 }
 
-/// Adds the given task to the run queue, returns the task reference.
+/// Handles the reschedule IPIs, which are sent by other CPUs after they add
+/// tasks to the run queue of this CPU.
+#[cfg(feature = "irq")]
+#[doc(cfg(feature = "irq"))]
+pub fn on_resched_ipi() {
+    current_run_queue().resched_ipi();
+}
+
+/// Adds the given task to the run queue of a CPU, returns the task reference.
 pub fn spawn_task(task: TaskInner) -> AxTaskRef {
     let task_ref = task.into_arc();
-    RUN_QUEUE.lock().add_task(task_ref.clone());
+    crate::run_queue::add_task(task_ref.clone());
     task_ref
 }
 
@@ -106,6 +150,24 @@ where
     spawn_task(TaskInner::new(f, name, stack_size))
 }
 
+/// Spawns a new task with the given parameters, which is only allowed to run
+/// on the CPUs in `cpumask`.
+///
+/// Returns the task reference.
+pub fn spawn_raw_with_cpumask<F>(
+    f: F,
+    name: String,
+    stack_size: usize,
+    cpumask: AxCpuMask,
+) -> AxTaskRef
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = TaskInner::new(f, name, stack_size);
+    task.set_cpumask(cpumask);
+    spawn_task(task)
+}
+
 /// Spawns a new task with the default parameters.
 ///
 /// The default task name is an empty string. The default task stack size is
@@ -129,13 +191,52 @@ where
 ///
 /// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
 pub fn set_priority(prio: isize) -> bool {
-    RUN_QUEUE.lock().set_current_priority(prio)
+    current_run_queue().set_current_priority(prio)
+}
+
+/// Set the scheduling policy for current task.
+///
+/// Deadline tasks ([`SchedPolicy::Deadline`]) always run before real-time
+/// tasks ([`SchedPolicy::Fifo`] and [`SchedPolicy::RoundRobin`]), and real-time
+/// tasks always run before normal tasks. No admission control is performed, so
+/// the caller should make sure that the total utilization of the deadline tasks
+/// on a CPU does not exceed 100%.
+///
+/// The new policy takes effect the next time the current task is scheduled.
+///
+/// Returns `false` if the parameters of the policy are invalid.
+#[cfg(feature = "sched_rt")]
+pub fn set_sched_policy(policy: SchedPolicy) -> bool {
+    if !policy.is_valid() {
+        return false;
+    }
+    current().sched_entity().lock().set_policy(policy);
+    true
+}
+
+/// Set the CPU affinity mask for current task.
+///
+/// If the current CPU is not in the mask, the current task will be migrated
+/// to one of the allowed CPUs immediately.
+///
+/// Returns `false` if the mask contains no CPUs.
+pub fn set_current_affinity(cpumask: AxCpuMask) -> bool {
+    if cpumask.is_empty() {
+        return false;
+    }
+    current().set_cpumask(cpumask);
+    if !cpumask.get(axhal::cpu::this_cpu_id()) {
+        // The current task will be put into the run queue of an allowed CPU
+        // when it is rescheduled.
+        yield_now();
+    }
+    true
 }
 
 /// Current task gives up the CPU time voluntarily, and switches to another
 /// ready task.
 pub fn yield_now() {
-    RUN_QUEUE.lock().yield_current();
+    current_run_queue().yield_current();
 }
 
 /// Current task is going to sleep for the given duration.
@@ -147,17 +248,64 @@ pub fn sleep(dur: core::time::Duration) {
 
 /// Current task is going to sleep, it will be woken up at the given deadline.
 ///
+/// It returns early if the current task is killed, see [`TaskInner::kill`].
+///
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
 pub fn sleep_until(deadline: axhal::time::TimeValue) {
     #[cfg(feature = "irq")]
-    RUN_QUEUE.lock().sleep_until(deadline);
+    {
+        let curr = current();
+        curr.set_killable(true);
+        current_run_queue().sleep_until(deadline);
+        curr.set_killable(false);
+    }
     #[cfg(not(feature = "irq"))]
     axhal::time::busy_wait_until(deadline);
 }
 
+/// Current task is going to sleep for the given duration, or until it is
+/// interrupted.
+///
+/// Returns [`Interrupted`] if the current task is interrupted or killed before
+/// the duration has elapsed.
+#[cfg(feature = "irq")]
+pub fn sleep_interruptible(dur: core::time::Duration) -> Result<(), Interrupted> {
+    sleep_until_interruptible(axhal::time::wall_time() + dur)
+}
+
+/// Current task is going to sleep until the given deadline, or until it is
+/// interrupted.
+///
+/// Returns [`Interrupted`] if the current task is interrupted or killed before
+/// the deadline.
+#[cfg(feature = "irq")]
+pub fn sleep_until_interruptible(deadline: axhal::time::TimeValue) -> Result<(), Interrupted> {
+    let curr = current();
+    curr.set_interruptible(true);
+    current_run_queue().sleep_until(deadline);
+    curr.set_interruptible(false);
+    if curr.check_interrupted() {
+        Err(Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Exits the current task if it has been killed.
+///
+/// A killed task exits by itself at its next preemption point, but it may hold
+/// resources there. It should be called at the points where the task holds no
+/// resources, e.g., before returning to the user space, or in each iteration
+/// of the main loop of a kernel task.
+pub fn exit_if_killed() {
+    if current().is_killed() {
+        exit(EXIT_CODE_KILLED);
+    }
+}
+
 /// Exits the current task.
 pub fn exit(exit_code: i32) -> ! {
-    RUN_QUEUE.lock().exit_current(exit_code)
+    current_run_queue().exit_current(exit_code)
 }
 
 /// The idle task routine.
@@ -165,9 +313,11 @@ pub fn exit(exit_code: i32) -> ! {
 /// It runs an infinite loop that keeps calling [`yield_now()`].
 pub fn run_idle() -> ! {
     loop {
+        #[cfg(feature = "irq")]
+        crate::run_queue::clear_idle_wakeup();
         yield_now();
         debug!("idle task: waiting for IRQs...");
         #[cfg(feature = "irq")]
-        axhal::arch::wait_for_irqs();
+        crate::run_queue::idle_wait_for_irqs();
     }
 }
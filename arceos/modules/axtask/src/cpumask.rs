@@ -0,0 +1,100 @@
+//! CPU affinity masks.
+
+use core::fmt;
+
+const BITS_PER_WORD: usize = usize::BITS as usize;
+const NUM_WORDS: usize = axconfig::SMP.div_ceil(BITS_PER_WORD);
+
+/// A set of CPUs that a task is allowed to run on.
+///
+/// Each bit represents a CPU, only the CPUs less than [`axconfig::SMP`] can be
+/// in the set.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AxCpuMask {
+    bits: [usize; NUM_WORDS],
+}
+
+impl AxCpuMask {
+    /// Creates an empty mask, which contains no CPUs.
+    pub const fn new() -> Self {
+        Self {
+            bits: [0; NUM_WORDS],
+        }
+    }
+
+    /// Creates a mask that contains all CPUs.
+    pub const fn full() -> Self {
+        let mut mask = Self::new();
+        let mut i = 0;
+        while i < axconfig::SMP {
+            mask.bits[i / BITS_PER_WORD] |= 1 << (i % BITS_PER_WORD);
+            i += 1;
+        }
+        mask
+    }
+
+    /// Creates a mask that contains only the given CPU.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu_id` is not less than [`axconfig::SMP`].
+    pub const fn one_shot(cpu_id: usize) -> Self {
+        assert!(cpu_id < axconfig::SMP);
+        let mut mask = Self::new();
+        mask.bits[cpu_id / BITS_PER_WORD] = 1 << (cpu_id % BITS_PER_WORD);
+        mask
+    }
+
+    /// Returns whether the given CPU is in the mask.
+    pub const fn get(&self, cpu_id: usize) -> bool {
+        cpu_id < axconfig::SMP
+            && self.bits[cpu_id / BITS_PER_WORD] & (1 << (cpu_id % BITS_PER_WORD)) != 0
+    }
+
+    /// Adds the given CPU to the mask if `value` is true, otherwise removes it.
+    ///
+    /// CPUs that are not less than [`axconfig::SMP`] are ignored.
+    pub fn set(&mut self, cpu_id: usize, value: bool) {
+        if cpu_id >= axconfig::SMP {
+            return;
+        }
+        let bit = 1 << (cpu_id % BITS_PER_WORD);
+        if value {
+            self.bits[cpu_id / BITS_PER_WORD] |= bit;
+        } else {
+            self.bits[cpu_id / BITS_PER_WORD] &= !bit;
+        }
+    }
+
+    /// Returns whether the mask contains no CPUs.
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&w| w == 0)
+    }
+
+    /// Returns the number of CPUs in the mask.
+    pub fn len(&self) -> usize {
+        self.bits.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Returns the first CPU in the mask that is not less than `start`.
+    pub fn next_index(&self, start: usize) -> Option<usize> {
+        (start..axconfig::SMP).find(|&i| self.get(i))
+    }
+
+    /// Returns an iterator over the CPUs in the mask.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..axconfig::SMP).filter(|&i| self.get(i))
+    }
+}
+
+impl Default for AxCpuMask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for AxCpuMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,255 @@
+//! Futex-style waiting and waking keyed by address.
+//!
+//! A futex is identified by a [`FutexKey`]. The key of a shared futex is the
+//! physical address of a 32-bit word in memory, so that the tasks that map the
+//! same word at different virtual addresses share the same futex. The key of a
+//! private futex is the virtual address of the word in an address space. The
+//! keys are hashed into a fixed number of buckets. Each bucket has a [`WaitQueue`] for the waiting
+//! tasks, and the waiters of each key in the order they start waiting.
+//!
+//! A waiter keeps sleeping in the wait queue of the bucket where it starts
+//! waiting, even if it is moved to another key by [`futex_requeue`].
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::{current, AxTaskRef, WaitQueue};
+
+const NUM_BUCKETS: usize = 64;
+
+static FUTEX_TABLE: [FutexBucket; NUM_BUCKETS] = [FutexBucket::EMPTY; NUM_BUCKETS];
+
+/// The key of a futex.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum FutexKey {
+    /// A futex shared by address spaces, keyed by the physical address of the
+    /// word.
+    Shared(usize),
+    /// A futex private to an address space, keyed by the virtual address of
+    /// the word. `space` identifies the address space, e.g., its address.
+    Private {
+        /// The identifier of the address space.
+        space: usize,
+        /// The virtual address of the word.
+        addr: usize,
+    },
+}
+
+/// The error returned by [`futex_wait`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FutexError {
+    /// The futex word does not have the expected value (`EAGAIN`).
+    WouldBlock,
+    /// The timeout expired before the task is woken up (`ETIMEDOUT`).
+    TimedOut,
+    /// The task is interrupted or killed before it is woken up (`EINTR`).
+    Interrupted,
+}
+
+struct FutexWaiter {
+    /// The key of the futex that the waiter is waiting on, which may be
+    /// changed by [`futex_requeue`].
+    key: SpinNoIrq<FutexKey>,
+    /// The bucket whose wait queue the waiter is sleeping in.
+    bucket: usize,
+    /// The waiting task.
+    task: AxTaskRef,
+    woken: AtomicBool,
+}
+
+type WaiterQueues = BTreeMap<FutexKey, VecDeque<Arc<FutexWaiter>>>;
+
+struct FutexBucket {
+    wq: WaitQueue,
+    waiters: SpinNoIrq<WaiterQueues>,
+}
+
+impl FutexBucket {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        wq: WaitQueue::new(),
+        waiters: SpinNoIrq::new(BTreeMap::new()),
+    };
+}
+
+fn bucket_index(key: FutexKey) -> usize {
+    // Fibonacci hashing, which takes the high bits of the product, as the low
+    // bits of the key are usually zero.
+    const MULTIPLIER: u64 = 0x9e37_79b9_7f4a_7c15;
+    let key = match key {
+        FutexKey::Shared(addr) => addr,
+        FutexKey::Private { space, addr } => addr ^ space.rotate_left(usize::BITS / 2),
+    };
+    key.wrapping_mul(MULTIPLIER as usize) >> (usize::BITS - NUM_BUCKETS.trailing_zeros())
+}
+
+/// Wakes up at most `count` waiters of `key`, returns the number of them.
+fn wake_locked(waiters: &mut WaiterQueues, key: FutexKey, count: usize) -> usize {
+    let Some(queue) = waiters.get_mut(&key) else {
+        return 0;
+    };
+    let n = count.min(queue.len());
+    for waiter in queue.drain(..n) {
+        waiter.woken.store(true, Ordering::Release);
+        // Only this waiter, not every task sleeping in the same bucket.
+        FUTEX_TABLE[waiter.bucket]
+            .wq
+            .notify_task(true, &waiter.task);
+    }
+    if queue.is_empty() {
+        waiters.remove(&key);
+    }
+    n
+}
+
+/// Removes the waiter from the futex it is waiting on, returns `true` if it
+/// has been woken up.
+fn remove_waiter(waiter: &Arc<FutexWaiter>) -> bool {
+    loop {
+        let key = *waiter.key.lock();
+        let mut waiters = FUTEX_TABLE[bucket_index(key)].waiters.lock();
+        if *waiter.key.lock() != key {
+            continue; // requeued just now
+        }
+        if waiter.woken.load(Ordering::Acquire) {
+            return true;
+        }
+        if let Some(queue) = waiters.get_mut(&key) {
+            queue.retain(|w| !Arc::ptr_eq(w, waiter));
+            if queue.is_empty() {
+                waiters.remove(&key);
+            }
+        }
+        return false;
+    }
+}
+
+fn enqueue_current<F>(key: FutexKey, check: F) -> Result<Arc<FutexWaiter>, FutexError>
+where
+    F: FnOnce() -> bool,
+{
+    let bucket = bucket_index(key);
+    let mut waiters = FUTEX_TABLE[bucket].waiters.lock();
+    // Check the value with the bucket locked, so that we will not miss any
+    // wakeup between checking and waiting.
+    if !check() {
+        return Err(FutexError::WouldBlock);
+    }
+    let waiter = Arc::new(FutexWaiter {
+        key: SpinNoIrq::new(key),
+        bucket,
+        task: current().clone(),
+        woken: AtomicBool::new(false),
+    });
+    waiters.entry(key).or_default().push_back(waiter.clone());
+    Ok(waiter)
+}
+
+/// Blocks the current task on the futex `key`, until it is woken up by
+/// [`futex_wake`] or [`futex_requeue`], or the current task is interrupted.
+///
+/// `check` is called with the futex locked, and it should return `true` if the
+/// futex word still has the expected value. Otherwise, the current task does
+/// not block and [`FutexError::WouldBlock`] is returned.
+pub fn futex_wait<F>(key: FutexKey, check: F) -> Result<(), FutexError>
+where
+    F: FnOnce() -> bool,
+{
+    let waiter = enqueue_current(key, check)?;
+    let wq = &FUTEX_TABLE[waiter.bucket].wq;
+    let res = wq.wait_until_interruptible(|| waiter.woken.load(Ordering::Acquire));
+    if res.is_ok() || remove_waiter(&waiter) {
+        Ok(())
+    } else {
+        Err(FutexError::Interrupted)
+    }
+}
+
+/// Blocks the current task on the futex `key` like [`futex_wait`], but also
+/// returns [`FutexError::TimedOut`] if it is not woken up after the given
+/// duration.
+#[cfg(feature = "irq")]
+pub fn futex_wait_timeout<F>(
+    key: FutexKey,
+    check: F,
+    dur: core::time::Duration,
+) -> Result<(), FutexError>
+where
+    F: FnOnce() -> bool,
+{
+    let waiter = enqueue_current(key, check)?;
+    let wq = &FUTEX_TABLE[waiter.bucket].wq;
+    let res = wq.wait_timeout_until_interruptible(dur, || waiter.woken.load(Ordering::Acquire));
+    if res == Ok(false) || remove_waiter(&waiter) {
+        Ok(())
+    } else if res.is_err() {
+        Err(FutexError::Interrupted)
+    } else {
+        Err(FutexError::TimedOut)
+    }
+}
+
+/// Wakes up at most `count` tasks waiting on the futex `key`, in the order
+/// they start waiting.
+///
+/// Returns the number of tasks woken up.
+pub fn futex_wake(key: FutexKey, count: usize) -> usize {
+    let mut waiters = FUTEX_TABLE[bucket_index(key)].waiters.lock();
+    wake_locked(&mut waiters, key, count)
+}
+
+/// Wakes up at most `wake_count` tasks waiting on the futex `key`, and moves
+/// at most `requeue_count` of the remaining ones to wait on the futex
+/// `target`.
+///
+/// Returns the number of tasks woken up and the number of tasks requeued.
+pub fn futex_requeue(
+    key: FutexKey,
+    wake_count: usize,
+    target: FutexKey,
+    requeue_count: usize,
+) -> (usize, usize) {
+    let (src_bucket, dst_bucket) = (bucket_index(key), bucket_index(target));
+    // Lock the buckets in order to avoid deadlocks.
+    let (mut src, mut dst) = match src_bucket.cmp(&dst_bucket) {
+        core::cmp::Ordering::Equal => (FUTEX_TABLE[src_bucket].waiters.lock(), None),
+        core::cmp::Ordering::Less => {
+            let src = FUTEX_TABLE[src_bucket].waiters.lock();
+            (src, Some(FUTEX_TABLE[dst_bucket].waiters.lock()))
+        }
+        core::cmp::Ordering::Greater => {
+            let dst = FUTEX_TABLE[dst_bucket].waiters.lock();
+            (FUTEX_TABLE[src_bucket].waiters.lock(), Some(dst))
+        }
+    };
+
+    let woken = wake_locked(&mut src, key, wake_count);
+    if key == target {
+        return (woken, 0);
+    }
+    let moved: VecDeque<_> = match src.get_mut(&key) {
+        Some(queue) => {
+            let n = requeue_count.min(queue.len());
+            let moved = queue.drain(..n).collect();
+            if queue.is_empty() {
+                src.remove(&key);
+            }
+            moved
+        }
+        None => return (woken, 0),
+    };
+    let requeued = moved.len();
+    let dst = match dst.as_deref_mut() {
+        Some(dst) => dst,
+        None => &mut *src,
+    };
+    let queue = dst.entry(target).or_default();
+    for waiter in moved {
+        *waiter.key.lock() = target;
+        queue.push_back(waiter);
+    }
+    (woken, requeued)
+}
@@ -0,0 +1,404 @@
+//! A cooperative executor that runs several futures on one task.
+//!
+//! The futures are polled by the task that calls [`block_on`]. When none of
+//! them can make progress, the task blocks on a [`WaitQueue`] until one of
+//! the futures is woken.
+//!
+//! # Examples
+//!
+//! ```
+//! axtask::init_scheduler();
+//! let sum = axtask::future::block_on(async {
+//!     let a = axtask::future::spawn(async { 1 });
+//!     let b = axtask::future::spawn(async {
+//!         axtask::future::yield_now().await;
+//!         2
+//!     });
+//!     a.await + b.await
+//! });
+//! assert_eq!(sum, 3);
+//! ```
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::task::Wake;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use kspin::SpinNoIrq;
+
+use crate::WaitQueue;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// The executors that are running [`Executor::block_on`], indexed by the ID
+/// of the task that runs it.
+static CURRENT_EXECUTORS: SpinNoIrq<BTreeMap<u64, Arc<Shared>>> = SpinNoIrq::new(BTreeMap::new());
+
+struct Shared {
+    wq: WaitQueue,
+    /// IDs of the woken futures, in the order they are woken.
+    ready: SpinNoIrq<VecDeque<u64>>,
+    /// Spawned futures that are not completed, and their wakers.
+    futures: SpinNoIrq<BTreeMap<u64, (BoxFuture, Waker)>>,
+    next_id: AtomicU64,
+}
+
+impl Shared {
+    fn schedule(&self, id: u64) {
+        {
+            let mut ready = self.ready.lock();
+            if ready.contains(&id) {
+                return;
+            }
+            ready.push_back(id);
+        }
+        self.wq.notify_one(false);
+    }
+
+    fn poll_spawned(&self, id: u64) {
+        // Take the future out, so that it can spawn new futures when polled.
+        let Some((mut fut, waker)) = self.futures.lock().remove(&id) else {
+            return; // completed, or woken after completion
+        };
+        let mut cx = Context::from_waker(&waker);
+        if fut.as_mut().poll(&mut cx).is_pending() {
+            self.futures.lock().insert(id, (fut, waker));
+        }
+    }
+}
+
+struct FutureWaker {
+    id: u64,
+    // Do not keep the executor alive, as the executor owns the futures that
+    // may hold this waker.
+    exec: Weak<Shared>,
+}
+
+impl Wake for FutureWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(exec) = self.exec.upgrade() {
+            exec.schedule(self.id);
+        }
+    }
+}
+
+/// The waker of the future passed to [`Executor::block_on`].
+///
+/// Several tasks may run `block_on` on the same executor, so each call has its
+/// own waker, instead of an ID in the ready queue shared by all calls.
+struct MainWaker {
+    woken: AtomicBool,
+    exec: Weak<Shared>,
+}
+
+impl Wake for MainWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(exec) = self.exec.upgrade() {
+            // We do not know which of the waiting tasks runs this call.
+            exec.wq.notify_all(false);
+        }
+    }
+}
+
+/// Sets the executor of the current task, and restores the previous one when
+/// dropped.
+struct EnterGuard {
+    task_id: u64,
+    prev: Option<Arc<Shared>>,
+}
+
+impl EnterGuard {
+    fn enter(exec: &Arc<Shared>) -> Self {
+        let task_id = crate::current().id().as_u64();
+        let prev = CURRENT_EXECUTORS.lock().insert(task_id, exec.clone());
+        Self { task_id, prev }
+    }
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        let mut executors = CURRENT_EXECUTORS.lock();
+        match self.prev.take() {
+            Some(prev) => executors.insert(self.task_id, prev),
+            None => executors.remove(&self.task_id),
+        };
+    }
+}
+
+/// An executor that runs futures on the task that calls [`block_on`].
+///
+/// [`block_on`]: Executor::block_on
+pub struct Executor {
+    shared: Arc<Shared>,
+}
+
+impl Executor {
+    /// Creates a new executor with no futures.
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                wq: WaitQueue::new(),
+                ready: SpinNoIrq::new(VecDeque::new()),
+                futures: SpinNoIrq::new(BTreeMap::new()),
+                next_id: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Spawns a future onto the executor, and returns a [`JoinHandle`] to
+    /// await its output.
+    ///
+    /// The future is polled when some task runs [`block_on`] on the executor.
+    /// It can be called from any task.
+    ///
+    /// [`block_on`]: Executor::block_on
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        spawn_on(&self.shared, fut)
+    }
+
+    /// Runs the future to completion on the current task, and polls the
+    /// spawned futures in the meantime.
+    ///
+    /// Spawned futures that are not completed when it returns are kept in the
+    /// executor, and will be polled by the next `block_on`.
+    pub fn block_on<F: Future>(&self, fut: F) -> F::Output {
+        let shared = &self.shared;
+        let mut fut = pin!(fut);
+        let main = Arc::new(MainWaker {
+            woken: AtomicBool::new(true),
+            exec: Arc::downgrade(shared),
+        });
+        let waker = Waker::from(main.clone());
+        let mut cx = Context::from_waker(&waker);
+        let _guard = EnterGuard::enter(shared);
+
+        loop {
+            if main.woken.swap(false, Ordering::AcqRel) {
+                if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+            let ready = core::mem::take(&mut *shared.ready.lock());
+            for id in ready {
+                shared.poll_spawned(id);
+            }
+
+            let has_ready =
+                || main.woken.load(Ordering::Acquire) || !shared.ready.lock().is_empty();
+            if has_ready() {
+                // Some futures woke themselves, give other tasks a chance to
+                // run before polling them again.
+                crate::yield_now();
+            } else {
+                shared.wq.wait_until(has_ready);
+            }
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs the future to completion on the current task, with a new [`Executor`].
+///
+/// Futures spawned by [`spawn`] during the call run on the same task. The ones
+/// that are not completed when the given future completes are dropped.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    Executor::new().block_on(fut)
+}
+
+/// Spawns a future onto the executor that is running on the current task.
+///
+/// # Panics
+///
+/// Panics if the current task is not running [`block_on`] or
+/// [`Executor::block_on`].
+pub fn spawn<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let task_id = crate::current().id().as_u64();
+    let exec = CURRENT_EXECUTORS
+        .lock()
+        .get(&task_id)
+        .cloned()
+        .expect("spawn() called outside of block_on()");
+    spawn_on(&exec, fut)
+}
+
+fn spawn_on<F>(exec: &Arc<Shared>, fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let id = exec.next_id.fetch_add(1, Ordering::Relaxed);
+    let state = Arc::new(SpinNoIrq::new(JoinState {
+        output: None,
+        waker: None,
+    }));
+    let join_state = state.clone();
+    let task = async move {
+        let output = fut.await;
+        let waker = {
+            let mut state = join_state.lock();
+            state.output = Some(output);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    };
+    let waker = Waker::from(Arc::new(FutureWaker {
+        id,
+        exec: Arc::downgrade(exec),
+    }));
+    exec.futures.lock().insert(id, (Box::pin(task), waker));
+    exec.schedule(id);
+    JoinHandle { state }
+}
+
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// A handle to await the output of a spawned future.
+///
+/// Dropping the handle does not cancel the future.
+pub struct JoinHandle<T> {
+    state: Arc<SpinNoIrq<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Whether the future has completed and its output is not taken.
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().output.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A future that yields to other futures once, see [`yield_now`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Yields to other futures on the same executor.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// A future that completes at a deadline, see [`sleep`] and [`sleep_until`].
+///
+/// The timer that wakes the future is cancelled when it is dropped.
+#[cfg(feature = "irq")]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Sleep {
+    deadline: axhal::time::TimeValue,
+    /// The timer set on the first poll, and the waker to wake at the deadline.
+    timer: Option<(crate::timer::TimerHandle, Arc<SpinNoIrq<Waker>>)>,
+}
+
+#[cfg(feature = "irq")]
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if axhal::time::wall_time() >= self.deadline {
+            return Poll::Ready(());
+        }
+        match &self.timer {
+            Some((_, waker)) => {
+                // Keep the timer, only update the waker if it is polled with
+                // another one.
+                let mut waker = waker.lock();
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                let waker = Arc::new(SpinNoIrq::new(cx.waker().clone()));
+                let timer_waker = waker.clone();
+                let handle = crate::timer::set_timer(self.deadline, move || {
+                    timer_waker.lock().wake_by_ref();
+                });
+                self.timer = Some((handle, waker));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "irq")]
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some((handle, _)) = &self.timer {
+            handle.cancel();
+        }
+    }
+}
+
+/// Returns a future that completes after the given duration.
+#[cfg(feature = "irq")]
+pub fn sleep(dur: core::time::Duration) -> Sleep {
+    sleep_until(axhal::time::wall_time() + dur)
+}
+
+/// Returns a future that completes at the given deadline (in
+/// [`axhal::time::wall_time`]).
+#[cfg(feature = "irq")]
+pub fn sleep_until(deadline: axhal::time::TimeValue) -> Sleep {
+    Sleep {
+        deadline,
+        timer: None,
+    }
+}
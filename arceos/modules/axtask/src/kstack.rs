@@ -0,0 +1,138 @@
+//! Kernel stacks with guard pages, used if the `stack_guard` feature is enabled.
+//!
+//! Each stack is allocated from the linear-mapped physical pages, so that the
+//! buffers on the stack can still be passed to devices by [`virt_to_phys`].
+//! The page right below the stack is made inaccessible in the linear mapping
+//! to be the guard page:
+//!
+//! ```text
+//!   lower address                                      higher address
+//!   | landing pad |  guard page  |            stack            |
+//!                 (inaccessible) ^ bottom                  top ^
+//! ```
+//!
+//! On the architectures that save the trap frame on the current stack, a fault
+//! on the guard page faults again when the trap frame is saved, and the nested
+//! faults walk down the guard page until the trap frame fits in the landing
+//! pad, where the overflow is finally reported.
+//!
+//! There is no TLB shootdown to restore the guard pages on other CPUs, so the
+//! stacks of exited tasks are never freed. They are kept in a pool and reused
+//! by new tasks with the same stack size.
+//!
+//! [`virt_to_phys`]: axhal::mem::virt_to_phys
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use axhal::mem::PAGE_SIZE_4K;
+use axhal::paging::MappingFlags;
+use axhal::trap::{register_trap_handler, KernelStackInfo, STACK_GUARD};
+use kspin::SpinNoIrq;
+use memory_addr::VirtAddr;
+
+const GUARD_SIZE: usize = PAGE_SIZE_4K;
+const LANDING_PAD_SIZE: usize = 4 * PAGE_SIZE_4K;
+
+static STACK_POOL: SpinNoIrq<StackPool> = SpinNoIrq::new(StackPool::new());
+
+struct LiveStack {
+    top: VirtAddr,
+    owner: String,
+}
+
+struct StackPool {
+    /// The bottoms of the free stacks, indexed by the stack size.
+    free: BTreeMap<usize, Vec<VirtAddr>>,
+    /// Stacks in use, indexed by the stack bottom.
+    live: BTreeMap<usize, LiveStack>,
+}
+
+impl StackPool {
+    const fn new() -> Self {
+        Self {
+            free: BTreeMap::new(),
+            live: BTreeMap::new(),
+        }
+    }
+}
+
+/// Allocates the pages of a new stack with its landing pad and guard page, and
+/// returns the bottom of the stack.
+fn alloc_new(size: usize) -> AxResult<VirtAddr> {
+    let num_pages = (LANDING_PAD_SIZE + GUARD_SIZE + size) / PAGE_SIZE_4K;
+    let Ok(start) = axalloc::global_allocator().alloc_pages(num_pages, PAGE_SIZE_4K) else {
+        return ax_err!(NoMemory, "no memory for kernel stack");
+    };
+    let guard = start + LANDING_PAD_SIZE;
+    let res = axmm::kernel_aspace()
+        .lock()
+        .protect(guard.into(), GUARD_SIZE, MappingFlags::empty());
+    if let Err(e) = res {
+        axalloc::global_allocator().dealloc_pages(start, num_pages);
+        return Err(e);
+    }
+    Ok((guard + GUARD_SIZE).into())
+}
+
+/// A kernel stack with a guard page below it.
+pub(crate) struct TaskStack {
+    bottom: VirtAddr,
+    size: usize,
+}
+
+impl TaskStack {
+    /// Allocates a stack of `size` bytes for the task named `owner`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stack cannot be allocated.
+    pub fn alloc(size: usize, owner: &str) -> Self {
+        let reused = STACK_POOL.lock().free.get_mut(&size).and_then(Vec::pop);
+        let bottom = match reused {
+            Some(bottom) => bottom,
+            None => alloc_new(size)
+                .unwrap_or_else(|e| panic!("failed to allocate kernel stack: {:?}", e)),
+        };
+        STACK_POOL.lock().live.insert(
+            bottom.as_usize(),
+            LiveStack {
+                top: bottom + size,
+                owner: String::from(owner),
+            },
+        );
+        Self { bottom, size }
+    }
+
+    pub const fn top(&self) -> VirtAddr {
+        VirtAddr::from_usize(self.bottom.as_usize() + self.size)
+    }
+}
+
+impl Drop for TaskStack {
+    fn drop(&mut self) {
+        let mut pool = STACK_POOL.lock();
+        pool.live.remove(&self.bottom.as_usize());
+        pool.free.entry(self.size).or_default().push(self.bottom);
+    }
+}
+
+#[register_trap_handler(STACK_GUARD)]
+fn check_stack_guard(vaddr: VirtAddr, report: &mut dyn FnMut(KernelStackInfo)) {
+    // The fault may happen with the pool locked, do not deadlock.
+    let Some(pool) = STACK_POOL.try_lock() else {
+        return;
+    };
+    // The guard page is right below the first stack above `vaddr`.
+    if let Some((&bottom, stack)) = pool.live.range(vaddr.as_usize() + 1..).next() {
+        if vaddr.as_usize() >= bottom - GUARD_SIZE {
+            report(KernelStackInfo {
+                task_name: &stack.owner,
+                bottom: bottom.into(),
+                top: stack.top,
+            });
+        }
+    }
+}
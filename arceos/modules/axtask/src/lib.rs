@@ -4,14 +4,21 @@
 //! creation, scheduling, sleeping, termination, etc. The scheduler algorithm
 //! is configurable by cargo features.
 //!
+//! Each CPU has its own run queue. Newly spawned tasks are distributed to the
+//! run queues of the online CPUs in a round-robin fashion, and a CPU that runs
+//! out of ready tasks steals them from the run queues of other CPUs.
+//!
 //! # Cargo Features
 //!
 //! - `multitask`: Enable multi-task support. If it's enabled, complex task
 //!   management and scheduling is used, as well as more task-related APIs.
 //!   Otherwise, only a few APIs with naive implementation is available.
 //! - `irq`: Interrupts are enabled. If this feature is enabled, timer-based
-//!    APIs can be used, such as [`sleep`], [`sleep_until`], and
-//!    [`WaitQueue::wait_timeout`].
+//!    APIs can be used, such as [`sleep`], [`sleep_until`],
+//!    [`WaitQueue::wait_timeout`], and the high-resolution timers in
+//!    [`timer`].
+//! - `nohz`: Stop the periodic timer tick on idle CPUs, so that they sleep
+//!   until the next timer event. It also enables the `irq` feature.
 //! - `preempt`: Enable preemptive scheduling.
 //! - `sched_fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
 //!   `multitask` feature if it is enabled. This feature is enabled by default,
@@ -20,6 +27,16 @@
 //!   the `multitask` and `preempt` features if it is enabled.
 //! - `sched_cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
 //!   the `multitask` and `preempt` features if it is enabled.
+//! - `sched_rt`: Use a layered scheduler with deadline (EDF) and real-time
+//!   (FIFO and round-robin) scheduling classes on top of the [CFS][3]. The
+//!   scheduling policy of a task can be changed by [`set_sched_policy`]. It
+//!   also enables the `multitask` and `preempt` features if it is enabled.
+//! - `stack_guard`: Place an inaccessible guard page below each kernel stack
+//!   to catch stack overflows. It needs the kernel page table, and also enables
+//!   the `multitask` feature if it is enabled.
+//! - `lockdep`: Enable the lock dependency validator, which reports possible
+//!   deadlocks between tracked locks, see [`lockdep`] for details. It also
+//!   enables the `multitask` feature if it is enabled.
 //!
 //! [1]: scheduler::FifoScheduler
 //! [2]: scheduler::RRScheduler
@@ -32,6 +49,7 @@
 #![feature(const_mut_refs)]
 #![feature(const_ptr_is_null)]
 #![feature(const_unsafecell_get_mut)]
+#![cfg_attr(feature = "lockdep", feature(const_caller_location))]
 
 #[cfg(test)]
 mod tests;
@@ -42,12 +60,28 @@ cfg_if::cfg_if! {
         extern crate log;
         extern crate alloc;
 
+        mod cpumask;
+        #[cfg(feature = "lockdep")]
+        pub mod lockdep;
+        #[doc(cfg(feature = "multitask"))]
+        pub mod future;
+        #[doc(cfg(feature = "multitask"))]
+        pub mod futex;
+        #[cfg(feature = "stack_guard")]
+        mod kstack;
+        mod pi;
         mod run_queue;
+        #[cfg(feature = "sched_rt")]
+        mod sched_rt;
+        mod stat;
         mod task;
         mod task_ext;
         mod api;
         mod wait_queue;
 
+        #[cfg(feature = "irq")]
+        #[doc(cfg(all(feature = "multitask", feature = "irq")))]
+        pub mod timer;
         #[cfg(feature = "irq")]
         mod timers;
 
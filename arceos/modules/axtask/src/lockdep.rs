@@ -0,0 +1,311 @@
+//! Lock dependency validator, enabled by the `lockdep` feature.
+//!
+//! Each tracked lock carries a [`LockdepMap`]. Locks created at the same place
+//! share a lock class. When a task acquires a lock, a dependency is recorded
+//! from each class the task is holding to the class of the new lock. If the
+//! new dependency closes a cycle, the classes can be acquired in inverse
+//! orders by different tasks, which may deadlock.
+//!
+//! The validator reports such cycles, recursive locking, and sleeping while
+//! holding a spinlock. A report is printed with the locks held by the current
+//! task and where they were acquired, and the validator turns itself off after
+//! the first report. Reports never panic, the kind of the first one can be
+//! queried by [`first_report`].
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::current_may_uninit;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// The kind of the first report.
+static FIRST_REPORT: SpinNoIrq<Option<LockdepReport>> = SpinNoIrq::new(None);
+
+/// All lock classes seen so far, indexed by the class ID.
+static LOCK_CLASSES: SpinNoIrq<BTreeMap<usize, LockClass>> = SpinNoIrq::new(BTreeMap::new());
+
+/// The "lock" held when the run queue of the current CPU is being accessed,
+/// with IRQs and preemption disabled.
+pub(crate) static RUN_QUEUE_LOCK: LockdepMap = LockdepMap::new("RUN_QUEUE", LockKind::Spin);
+
+/// The kind of a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// A spinlock, the holder must not sleep.
+    Spin,
+    /// A sleeping lock, such as a mutex.
+    Sleep,
+}
+
+/// The kind of a problem reported by the validator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockdepReport {
+    /// A task tries to acquire a lock that it already holds.
+    RecursiveLocking,
+    /// A task acquires locks in the inverse order of an existing dependency
+    /// chain.
+    CircularDependency,
+    /// A task is going to sleep while holding a spinlock.
+    SleepWithSpinlock,
+}
+
+impl LockdepReport {
+    fn title(self) -> &'static str {
+        match self {
+            Self::RecursiveLocking => "possible recursive locking detected",
+            Self::CircularDependency => "possible circular locking dependency detected",
+            Self::SleepWithSpinlock => "sleeping function called while holding a spinlock",
+        }
+    }
+}
+
+/// The lock class information attached to a lock.
+pub struct LockdepMap {
+    name: &'static str,
+    kind: LockKind,
+    /// Where the lock is created, which also identifies the lock class.
+    class: &'static Location<'static>,
+}
+
+struct LockClass {
+    name: ClassName,
+    /// Classes that have been acquired while holding this class, and where
+    /// they are acquired for the first time.
+    after: BTreeMap<usize, &'static Location<'static>>,
+}
+
+/// A lock held by a task.
+pub(crate) struct HeldLock {
+    class: usize,
+    name: ClassName,
+    kind: LockKind,
+    acquired_at: &'static Location<'static>,
+}
+
+impl LockdepMap {
+    /// Creates the map of a lock named `name`, the lock class is identified by
+    /// the caller location.
+    #[track_caller]
+    pub const fn new(name: &'static str, kind: LockKind) -> Self {
+        Self {
+            name,
+            kind,
+            class: Location::caller(),
+        }
+    }
+
+    fn class_id(&self) -> usize {
+        self.class as *const _ as usize
+    }
+
+    fn class_name(&self) -> ClassName {
+        ClassName(self.name, self.class)
+    }
+
+    /// Validates and records the acquisition of the lock by the current task.
+    ///
+    /// It should be called before waiting for the lock, so that a possible
+    /// deadlock can be reported before it happens.
+    #[track_caller]
+    pub fn acquire(&self) {
+        self.acquire_at(Location::caller(), true);
+    }
+
+    /// Records a successful `try_lock` by the current task.
+    ///
+    /// No dependency is recorded, as `try_lock` cannot deadlock.
+    #[track_caller]
+    pub fn try_acquired(&self) {
+        self.acquire_at(Location::caller(), false);
+    }
+
+    /// Records the release of the lock by the current task.
+    pub fn release(&self) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(curr) = current_may_uninit() {
+            let class = self.class_id();
+            let mut held = curr.held_locks().lock();
+            if let Some(i) = held.iter().rposition(|h| h.class == class) {
+                held.remove(i);
+            }
+        }
+    }
+
+    fn acquire_at(&self, acquired_at: &'static Location<'static>, check: bool) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(curr) = current_may_uninit() else {
+            return; // before the scheduler is initialized
+        };
+        let class = self.class_id();
+        let mut held = curr.held_locks().lock();
+        if check {
+            let mut classes = LOCK_CLASSES.lock();
+            classes.entry(class).or_insert_with(|| LockClass {
+                name: self.class_name(),
+                after: BTreeMap::new(),
+            });
+            for h in held.iter() {
+                if h.class == class {
+                    report_begin(LockdepReport::RecursiveLocking);
+                    error!(
+                        "{} is trying to acquire lock {} at {}, but it already holds it:",
+                        curr.id_name(),
+                        self.class_name(),
+                        acquired_at
+                    );
+                    report_held_locks(&held);
+                    return;
+                }
+                if classes[&h.class].after.contains_key(&class) {
+                    continue;
+                }
+                if let Some(chain) = find_chain(&classes, class, h.class) {
+                    report_begin(LockdepReport::CircularDependency);
+                    error!(
+                        "{} is trying to acquire lock {} at {}, but it already holds lock {}.",
+                        curr.id_name(),
+                        self.class_name(),
+                        acquired_at,
+                        h.name
+                    );
+                    error!("The existing dependency chain is:");
+                    for pair in chain.windows(2) {
+                        error!(
+                            "  {} -> {}, first acquired at {}",
+                            classes[&pair[0]].name,
+                            classes[&pair[1]].name,
+                            classes[&pair[0]].after[&pair[1]]
+                        );
+                    }
+                    report_held_locks(&held);
+                    return;
+                }
+                classes
+                    .get_mut(&h.class)
+                    .unwrap()
+                    .after
+                    .insert(class, acquired_at);
+            }
+        }
+        held.push(HeldLock {
+            class,
+            name: self.class_name(),
+            kind: self.kind,
+            acquired_at,
+        });
+    }
+}
+
+/// Displays a lock class as the lock name and where the lock is created.
+#[derive(Clone, Copy)]
+struct ClassName(&'static str, &'static Location<'static>);
+
+impl fmt::Display for ClassName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (created at {})", self.0, self.1)
+    }
+}
+
+/// Finds a dependency chain from class `from` to class `to`.
+fn find_chain(classes: &BTreeMap<usize, LockClass>, from: usize, to: usize) -> Option<Vec<usize>> {
+    let mut chain = Vec::new();
+    let mut visited = Vec::new();
+    if dfs(classes, from, to, &mut chain, &mut visited) {
+        Some(chain)
+    } else {
+        None
+    }
+}
+
+fn dfs(
+    classes: &BTreeMap<usize, LockClass>,
+    curr: usize,
+    to: usize,
+    chain: &mut Vec<usize>,
+    visited: &mut Vec<usize>,
+) -> bool {
+    chain.push(curr);
+    if curr == to {
+        return true;
+    }
+    visited.push(curr);
+    if let Some(class) = classes.get(&curr) {
+        for &next in class.after.keys() {
+            if !visited.contains(&next) && dfs(classes, next, to, chain, visited) {
+                return true;
+            }
+        }
+    }
+    chain.pop();
+    false
+}
+
+/// Reports if the current task is holding any spinlock, since it is going to
+/// sleep.
+///
+/// It should be called by the blocking operations, such as locking a mutex.
+#[track_caller]
+pub fn might_sleep() {
+    check_sleep(Location::caller(), false);
+}
+
+/// Like [`might_sleep`], but called by the scheduler with [`RUN_QUEUE_LOCK`]
+/// held, which is not a violation.
+#[track_caller]
+pub(crate) fn might_sleep_in_scheduler() {
+    check_sleep(Location::caller(), true);
+}
+
+fn check_sleep(location: &'static Location<'static>, in_scheduler: bool) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(curr) = current_may_uninit() else {
+        return;
+    };
+    let held = curr.held_locks().lock();
+    let mut spins = held.iter().filter(|h| h.kind == LockKind::Spin).count();
+    if in_scheduler && held.iter().any(|h| h.class == RUN_QUEUE_LOCK.class_id()) {
+        spins -= 1;
+    }
+    if spins > 0 {
+        report_begin(LockdepReport::SleepWithSpinlock);
+        error!("{} is going to sleep at {}", curr.id_name(), location);
+        report_held_locks(&held);
+    }
+}
+
+/// Returns the kind of the first report, or `None` if nothing is reported.
+pub fn first_report() -> Option<LockdepReport> {
+    *FIRST_REPORT.lock()
+}
+
+fn report_begin(report: LockdepReport) {
+    // Only report once, as the lock state may be inconsistent afterwards.
+    ENABLED.store(false, Ordering::Relaxed);
+    FIRST_REPORT.lock().get_or_insert(report);
+    error!("======================================================");
+    error!("lockdep: {}", report.title());
+    error!("------------------------------------------------------");
+}
+
+fn report_held_locks(held: &[HeldLock]) {
+    error!("Locks held by the current task, in the order of acquisition:");
+    for (i, h) in held.iter().enumerate() {
+        error!(
+            "  #{}: {} ({:?}), acquired at {}",
+            i, h.name, h.kind, h.acquired_at
+        );
+    }
+    error!("lockdep: turning off the lock dependency validator");
+}
@@ -0,0 +1,344 @@
+//! Priority inheritance of the sleeping locks.
+//!
+//! The owner of a lock inherits the highest priority of the tasks waiting for
+//! it. The inheritance is transitive: if the owner is waiting for another
+//! lock, the owner of that lock inherits the priority as well, and so on along
+//! the chain of the blocked owners. When a lock is handed off to one of its
+//! waiters, the new owner inherits the priorities of the remaining waiters.
+//!
+//! The states are kept per lock ([`PiLock`]) and per task ([`TaskPi`]), and
+//! the waiters are linked through the tasks themselves, so the waiting does
+//! not allocate memory, and only the locks along the chain are taken. The
+//! locks are taken in the order of [`PiLock`], [`TaskPi::boosters`], and then
+//! the other fields of [`TaskPi`]. The lock that a task is waiting for is only
+//! try-locked with [`TaskPi::blocked_on`] held, see [`lock_blocked_on`].
+
+use alloc::sync::Arc;
+use core::ptr;
+use core::sync::atomic::{AtomicIsize, Ordering};
+
+use kspin::{SpinNoIrq, SpinNoIrqGuard};
+
+use crate::run_queue::update_task_priority;
+use crate::{current, AxTaskRef};
+
+/// The maximum length of the chains of blocked owners that the priorities
+/// are propagated along. A longer chain is most likely a deadlock.
+const MAX_CHAIN_LEN: usize = 16;
+
+/// The inherited priority of a task without boosters, lower than all
+/// priorities.
+const NO_PRIO: isize = isize::MAX;
+
+/// The priority inheritance state of a sleeping lock.
+///
+/// It is embedded in the lock, and follows the ownership changes of the lock
+/// through the unsafe methods, which must be called as described in their
+/// safety sections. Otherwise, the priorities of unrelated tasks may be
+/// raised, or never restored.
+pub struct PiLock {
+    inner: SpinNoIrq<PiLockInner>,
+}
+
+struct PiLockInner {
+    /// The owner recorded by [`PiLock::acquired`].
+    owner: Option<AxTaskRef>,
+    /// The first waiting task, the others are linked by
+    /// [`TaskPi::next_waiter`].
+    first_waiter: Option<AxTaskRef>,
+}
+
+/// The per-task states of the priority inheritance.
+pub(crate) struct TaskPi {
+    /// The highest priority of the boosters, or [`NO_PRIO`].
+    prio: AtomicIsize,
+    /// The first waiter of the locks owned by the task, the others are
+    /// linked by [`TaskPi::next_booster`].
+    boosters: SpinNoIrq<Option<AxTaskRef>>,
+    /// The lock that the task is waiting for, or null.
+    blocked_on: SpinNoIrq<*const PiLock>,
+    /// The next waiter of the same lock, protected by the lock.
+    next_waiter: SpinNoIrq<Option<AxTaskRef>>,
+    /// The next booster of the same owner, protected by its `boosters`.
+    next_booster: SpinNoIrq<Option<AxTaskRef>>,
+}
+
+type Link = fn(&TaskPi) -> &SpinNoIrq<Option<AxTaskRef>>;
+
+fn next_waiter(pi: &TaskPi) -> &SpinNoIrq<Option<AxTaskRef>> {
+    &pi.next_waiter
+}
+
+fn next_booster(pi: &TaskPi) -> &SpinNoIrq<Option<AxTaskRef>> {
+    &pi.next_booster
+}
+
+impl TaskPi {
+    pub const fn new() -> Self {
+        Self {
+            prio: AtomicIsize::new(NO_PRIO),
+            boosters: SpinNoIrq::new(None),
+            blocked_on: SpinNoIrq::new(ptr::null()),
+            next_waiter: SpinNoIrq::new(None),
+            next_booster: SpinNoIrq::new(None),
+        }
+    }
+
+    /// Returns the highest priority inherited from the boosters, if any.
+    pub fn inherited_priority(&self) -> Option<isize> {
+        let prio = self.prio.load(Ordering::Acquire);
+        (prio != NO_PRIO).then_some(prio)
+    }
+}
+
+fn push(head: &mut Option<AxTaskRef>, task: &AxTaskRef, link: Link) {
+    *link(task.pi()).lock() = head.take();
+    *head = Some(task.clone());
+}
+
+fn remove(head: &mut Option<AxTaskRef>, task: &AxTaskRef, link: Link) {
+    if head.as_ref().is_some_and(|t| Arc::ptr_eq(t, task)) {
+        *head = link(task.pi()).lock().take();
+        return;
+    }
+    let mut prev = head.clone();
+    while let Some(p) = prev {
+        let mut next = link(p.pi()).lock();
+        if next.as_ref().is_some_and(|t| Arc::ptr_eq(t, task)) {
+            *next = link(task.pi()).lock().take();
+            return;
+        }
+        prev = next.clone();
+    }
+}
+
+/// Calls `f` on each task of the list.
+fn for_each(head: &Option<AxTaskRef>, link: Link, mut f: impl FnMut(&AxTaskRef)) {
+    let mut next = head.clone();
+    while let Some(task) = next {
+        f(&task);
+        next = link(task.pi()).lock().clone();
+    }
+}
+
+impl PiLockInner {
+    fn for_each_waiter(&self, f: impl FnMut(&AxTaskRef)) {
+        for_each(&self.first_waiter, next_waiter, f);
+    }
+}
+
+fn add_booster(owner: &AxTaskRef, waiter: &AxTaskRef) {
+    push(&mut owner.pi().boosters.lock(), waiter, next_booster);
+}
+
+fn remove_booster(owner: &AxTaskRef, waiter: &AxTaskRef) {
+    remove(&mut owner.pi().boosters.lock(), waiter, next_booster);
+}
+
+/// Recomputes the inherited priority of `task` from its boosters.
+///
+/// Returns whether the effective priority of `task` is changed.
+fn update_inherited(task: &AxTaskRef) -> bool {
+    let boosters = task.pi().boosters.lock();
+    let mut prio = NO_PRIO;
+    for_each(&boosters, next_booster, |t| {
+        prio = prio.min(t.priority());
+    });
+    let old = task.priority();
+    task.pi().prio.store(prio, Ordering::Release);
+    task.priority() != old
+}
+
+/// Locks the lock that `task` is waiting for.
+///
+/// The lock is only try-locked, as it is taken before [`TaskPi::blocked_on`]
+/// elsewhere.
+fn lock_blocked_on<'a>(task: &'a AxTaskRef) -> Option<SpinNoIrqGuard<'a, PiLockInner>> {
+    loop {
+        let blocked_on = task.pi().blocked_on.lock();
+        if blocked_on.is_null() {
+            return None;
+        }
+        // Safety: the task stops waiting only after it clears `blocked_on`
+        // with the lock held, so the lock is alive as long as either one is
+        // held.
+        let lock = unsafe { &**blocked_on };
+        if let Some(inner) = lock.inner.try_lock() {
+            return Some(inner);
+        }
+        drop(blocked_on);
+        core::hint::spin_loop();
+    }
+}
+
+/// Applies the change of the effective priority of `task` to the scheduler,
+/// and to the owners along the chain if `task` is blocked.
+fn propagate(task: &AxTaskRef) {
+    let mut task = task.clone();
+    for _ in 0..MAX_CHAIN_LEN {
+        debug!(
+            "task priority change: {} -> {}",
+            task.id_name(),
+            task.priority()
+        );
+        update_task_priority(&task);
+        let owner = {
+            let Some(inner) = lock_blocked_on(&task) else {
+                return;
+            };
+            match &inner.owner {
+                Some(owner) if update_inherited(owner) => owner.clone(),
+                _ => return,
+            }
+        };
+        task = owner;
+    }
+    warn!("priority inheritance chain is too long, deadlock?");
+}
+
+impl PiLock {
+    /// Creates a new [`PiLock`] without the owner and waiters.
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinNoIrq::new(PiLockInner {
+                owner: None,
+                first_waiter: None,
+            }),
+        }
+    }
+
+    /// Records that the current task has acquired the lock, and lets it
+    /// inherit the priorities of the waiters.
+    ///
+    /// # Safety
+    ///
+    /// The current task must have just acquired the lock, and must call
+    /// [`PiLock::released`] after it releases the lock.
+    pub unsafe fn acquired(&self) {
+        let curr = current();
+        let curr = curr.as_task_ref();
+        let mut inner = self.inner.lock();
+        let old = inner.owner.replace(curr.clone());
+        if old.as_ref().is_some_and(|o| Arc::ptr_eq(o, curr)) {
+            return;
+        }
+        // The previous owner may have not called `released` yet.
+        if let Some(old) = &old {
+            inner.for_each_waiter(|w| remove_booster(old, w));
+        }
+        inner.for_each_waiter(|w| add_booster(curr, w));
+        let old_changed = old.as_ref().is_some_and(update_inherited);
+        let curr_changed = update_inherited(curr);
+        drop(inner);
+        if let Some(old) = old.filter(|_| old_changed) {
+            propagate(&old);
+        }
+        if curr_changed {
+            propagate(curr);
+        }
+    }
+
+    /// Drops the priority that the current task inherited from the waiters,
+    /// after it releases the lock.
+    ///
+    /// # Safety
+    ///
+    /// The current task must have just released the lock, which it acquired
+    /// with [`PiLock::acquired`] called.
+    pub unsafe fn released(&self) {
+        let curr = current();
+        let curr = curr.as_task_ref();
+        let mut inner = self.inner.lock();
+        if !inner.owner.as_ref().is_some_and(|o| Arc::ptr_eq(o, curr)) {
+            return; // already acquired by another task
+        }
+        inner.owner = None;
+        inner.for_each_waiter(|w| remove_booster(curr, w));
+        let changed = update_inherited(curr);
+        drop(inner);
+        if changed {
+            propagate(curr);
+        }
+    }
+
+    /// Records that the current task is going to wait for the lock, and lets
+    /// the owner inherit its priority.
+    ///
+    /// `is_locked` returns whether the lock is still held. It is called with
+    /// the ownership changes serialized, i.e., the owner calls
+    /// [`PiLock::released`] after it releases the lock.
+    ///
+    /// Returns `false` without recording anything if the lock is unlocked.
+    ///
+    /// # Safety
+    ///
+    /// If it returns `true`, the current task must call [`PiLock::end_wait`]
+    /// after the waiting, before it waits for another lock or the lock is
+    /// dropped.
+    pub unsafe fn begin_wait(&self, is_locked: impl FnOnce() -> bool) -> bool {
+        let curr = current();
+        let curr = curr.as_task_ref();
+        let mut inner = self.inner.lock();
+        if !is_locked() {
+            return false;
+        }
+        push(&mut inner.first_waiter, curr, next_waiter);
+        *curr.pi().blocked_on.lock() = self;
+        let owner = inner.owner.clone();
+        if let Some(owner) = &owner {
+            add_booster(owner, curr);
+        }
+        let changed = owner.as_ref().is_some_and(update_inherited);
+        drop(inner);
+        if let Some(owner) = owner.filter(|_| changed) {
+            propagate(&owner);
+        }
+        true
+    }
+
+    /// Records that the current task stops waiting for the lock, either to
+    /// acquire it or because the waiting is interrupted. The owner no longer
+    /// inherits the priority of the current task.
+    ///
+    /// # Safety
+    ///
+    /// The current task must be waiting for the lock by
+    /// [`PiLock::begin_wait`].
+    pub unsafe fn end_wait(&self) {
+        let curr = current();
+        let curr = curr.as_task_ref();
+        let mut inner = self.inner.lock();
+        remove(&mut inner.first_waiter, curr, next_waiter);
+        *curr.pi().blocked_on.lock() = ptr::null();
+        let owner = inner.owner.clone();
+        if let Some(owner) = &owner {
+            remove_booster(owner, curr);
+        }
+        let changed = owner.as_ref().is_some_and(update_inherited);
+        drop(inner);
+        if let Some(owner) = owner.filter(|_| changed) {
+            propagate(&owner);
+        }
+    }
+}
+
+impl Default for PiLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sets the base priority of the current task bypassing the scheduler, and
+/// propagates it to the owners of the locks it is waiting for.
+///
+/// It is only available with the `test` feature, for the tests to raise the
+/// priority of a task with any scheduler.
+#[cfg(feature = "test")]
+pub fn set_priority_for_test(prio: isize) {
+    let curr = current();
+    let old = curr.priority();
+    curr.set_base_priority(prio);
+    if curr.priority() != old {
+        propagate(curr.as_task_ref());
+    }
+}
@@ -1,14 +1,31 @@
 use alloc::collections::VecDeque;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
+use core::ops::Deref;
+use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+
+use axhal::cpu::this_cpu_id;
+use kernel_guard::NoPreemptIrqSave;
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use scheduler::BaseScheduler;
 
 use crate::task::{CurrentTask, TaskState};
-use crate::{AxTaskRef, Scheduler, TaskInner, WaitQueue};
+use crate::{AxTask, AxTaskRef, Scheduler, TaskInner, WaitQueue};
 
-// TODO: per-CPU
-pub(crate) static RUN_QUEUE: LazyInit<SpinNoIrq<AxRunQueue>> = LazyInit::new();
+#[allow(clippy::declare_interior_mutable_const)]
+const RUN_QUEUE_UNINIT: LazyInit<AxRunQueue> = LazyInit::new();
+
+/// The run queues of all CPUs, indexed by the CPU ID.
+static RUN_QUEUES: [LazyInit<AxRunQueue>; axconfig::SMP] = [RUN_QUEUE_UNINIT; axconfig::SMP];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Whether each CPU has started scheduling, indexed by the CPU ID.
+///
+/// New tasks are only spread to the online CPUs, but the run queues of the
+/// offline ones still accept the tasks that can not run elsewhere.
+static CPU_ONLINE: [AtomicBool; axconfig::SMP] = [CPU_OFFLINE; axconfig::SMP];
 
 // TODO: per-CPU
 static EXITED_TASKS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::new());
@@ -18,31 +35,155 @@ static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
 #[percpu::def_percpu]
 static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
 
+/// The task that was running on this CPU before the last context switch.
+///
+/// It is used by the next task to clear the `on_cpu` flag of the previous
+/// task, after the context of the previous task has been saved.
+#[percpu::def_percpu]
+static PREV_TASK: Weak<AxTask> = Weak::new();
+
+/// The run queue of a CPU.
+///
+/// Only the scheduler is protected by a lock, and the lock is never held
+/// across a context switch. Ready tasks can be added to the run queue of any
+/// CPU, but only the owner CPU can switch tasks (see [`CurrentRunQueueRef`]).
 pub(crate) struct AxRunQueue {
-    scheduler: Scheduler,
+    cpu_id: usize,
+    scheduler: SpinNoIrq<Scheduler>,
+    /// Set by other CPUs before the IPI, if a woken up task may preempt the
+    /// current task of this CPU.
+    #[cfg(feature = "irq")]
+    resched_requested: AtomicBool,
+    /// Set when a task is added, so that the idle task of this CPU does not
+    /// sleep if the task is added after it has checked the run queue.
+    #[cfg(feature = "irq")]
+    wakeup_pending: AtomicBool,
+}
+
+/// A reference to the run queue of the current CPU.
+///
+/// Local IRQs and preemption are disabled as long as the reference is alive,
+/// so the current task cannot be migrated to another CPU while it is being
+/// scheduled.
+pub(crate) struct CurrentRunQueueRef {
+    inner: &'static AxRunQueue,
+    _guard: NoPreemptIrqSave,
 }
 
 impl AxRunQueue {
-    pub fn new() -> SpinNoIrq<Self> {
-        let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
-        let mut scheduler = Scheduler::new();
-        scheduler.add_task(gc_task);
-        SpinNoIrq::new(Self { scheduler })
+    fn new(cpu_id: usize) -> Self {
+        Self {
+            cpu_id,
+            scheduler: SpinNoIrq::new(Scheduler::new()),
+            #[cfg(feature = "irq")]
+            resched_requested: AtomicBool::new(false),
+            #[cfg(feature = "irq")]
+            wakeup_pending: AtomicBool::new(false),
+        }
     }
 
-    pub fn add_task(&mut self, task: AxTaskRef) {
-        debug!("task spawn: {}", task.id_name());
+    pub fn add_task(&self, task: AxTaskRef) {
+        debug!("task spawn: {} on CPU {}", task.id_name(), self.cpu_id);
         assert!(task.is_ready());
-        self.scheduler.add_task(task);
+        let mut scheduler = self.scheduler.lock();
+        task.set_cpu_id(self.cpu_id);
+        scheduler.add_task(task);
+        drop(scheduler);
+        self.kick(false);
+    }
+
+    pub fn unblock_task(&self, task: AxTaskRef, resched: bool) {
+        // The task may be woken up by several events (e.g., a timer and a
+        // `notify()`) at the same time, only the first one can succeed.
+        if task.transition_state(TaskState::Blocked, TaskState::Ready) {
+            debug!("task unblock: {} on CPU {}", task.id_name(), self.cpu_id);
+            let mut scheduler = self.scheduler.lock();
+            task.set_cpu_id(self.cpu_id);
+            scheduler.add_task(task); // TODO: priority
+            drop(scheduler);
+            self.kick(resched);
+        }
+    }
+
+    /// Notifies the CPU of the run queue that a task has been added.
+    ///
+    /// The idle task is switched out when the preemption is enabled, e.g.,
+    /// when the current IRQ handler returns, instead of sleeping until the
+    /// next timer event. If `resched` is true, other tasks may be preempted
+    /// as well. Other CPUs are notified by an IPI, and check whether to
+    /// preempt their current tasks in [`CurrentRunQueueRef::resched_ipi`].
+    #[cfg_attr(not(feature = "irq"), allow(unused_variables))]
+    fn kick(&self, resched: bool) {
+        #[cfg(feature = "irq")]
+        self.wakeup_pending.store(true, Ordering::Release);
+        if self.cpu_id == this_cpu_id() {
+            #[cfg(feature = "preempt")]
+            {
+                let curr = crate::current();
+                if resched || curr.is_idle() {
+                    curr.set_preempt_pending(true);
+                }
+            }
+        } else {
+            #[cfg(feature = "irq")]
+            {
+                if resched {
+                    self.resched_requested.store(true, Ordering::Release);
+                }
+                axhal::irq::send_ipi(self.cpu_id);
+            }
+        }
+    }
+}
+
+impl CurrentRunQueueRef {
+    /// Returns when a task that is waiting for its next period becomes ready,
+    /// in monotonic nanoseconds.
+    #[cfg(all(feature = "nohz", feature = "sched_rt"))]
+    pub fn scheduler_next_release(&self) -> Option<u64> {
+        self.scheduler.lock().next_release()
+    }
+
+    /// Other schedulers do not throttle tasks.
+    #[cfg(all(feature = "nohz", not(feature = "sched_rt")))]
+    pub fn scheduler_next_release(&self) -> Option<u64> {
+        None
+    }
+
+    /// Handles the IPI sent by other CPUs after they add tasks to the run
+    /// queue of this CPU, by preempting the idle task, or the current task if
+    /// a task that should run first is woken up.
+    #[cfg(feature = "irq")]
+    pub fn resched_ipi(&mut self) {
+        let curr = crate::current();
+        let resched = self.resched_requested.swap(false, Ordering::Acquire);
+        if !curr.is_idle() {
+            if !resched {
+                return;
+            }
+            // Other schedulers cannot compare the tasks, preempt it as on the
+            // CPU that wakes up the task.
+            #[cfg(feature = "sched_rt")]
+            if !self.scheduler.lock().should_preempt(curr.as_task_ref()) {
+                return;
+            }
+        }
+        #[cfg(feature = "preempt")]
+        curr.set_preempt_pending(true);
     }
 
     #[cfg(feature = "irq")]
     pub fn scheduler_timer_tick(&mut self) {
         let curr = crate::current();
-        if !curr.is_idle() && self.scheduler.task_tick(curr.as_task_ref()) {
+        if !curr.is_idle() && self.scheduler.lock().task_tick(curr.as_task_ref()) {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
+        #[cfg(feature = "preempt")]
+        if curr.is_killed() {
+            // Let the killed task exit at the next preemption point.
+            curr.set_preempt_pending(true);
+        }
     }
 
     pub fn yield_current(&mut self) {
@@ -53,8 +194,23 @@ impl AxRunQueue {
     }
 
     pub fn set_current_priority(&mut self, prio: isize) -> bool {
-        self.scheduler
-            .set_priority(crate::current().as_task_ref(), prio)
+        // The priorities below the nice values are only inherited.
+        #[cfg(feature = "sched_rt")]
+        if prio < crate::sched_rt::NICE_MIN {
+            return false;
+        }
+        let curr = crate::current();
+        let mut scheduler = self.scheduler.lock();
+        if !scheduler.set_priority(curr.as_task_ref(), prio) {
+            return false;
+        }
+        curr.set_base_priority(prio);
+        // Keep the inherited priority if it is higher.
+        let effective = curr.priority();
+        if effective != prio {
+            scheduler.set_priority(curr.as_task_ref(), effective);
+        }
+        true
     }
 
     #[cfg(feature = "preempt")]
@@ -62,11 +218,10 @@ impl AxRunQueue {
         let curr = crate::current();
         assert!(curr.is_running());
 
-        // When we get the mutable reference of the run queue, we must
-        // have held the `SpinNoIrq` lock with both IRQs and preemption
-        // disabled. So we need to set `current_disable_count` to 1 in
-        // `can_preempt()` to obtain the preemption permission before
-        //  locking the run queue.
+        // When we get the reference of the current run queue, we must have
+        // disabled both IRQs and preemption. So we need to set
+        // `current_disable_count` to 1 in `can_preempt()` to obtain the
+        // preemption permission.
         let can_preempt = curr.can_preempt(1);
 
         debug!(
@@ -75,6 +230,9 @@ impl AxRunQueue {
             can_preempt
         );
         if can_preempt {
+            if curr.is_killed() {
+                self.exit_current(crate::EXIT_CODE_KILLED);
+            }
             self.resched(true);
         } else {
             curr.set_preempt_pending(true);
@@ -83,6 +241,12 @@ impl AxRunQueue {
 
     pub fn exit_current(&mut self, exit_code: i32) -> ! {
         let curr = crate::current();
+        // A killed task may also return or exit by itself, report it as killed.
+        let exit_code = if curr.is_killed() {
+            crate::EXIT_CODE_KILLED
+        } else {
+            exit_code
+        };
         debug!("task exit: {}, exit_code={}", curr.id_name(), exit_code);
         assert!(curr.is_running());
         assert!(!curr.is_idle());
@@ -91,14 +255,19 @@ impl AxRunQueue {
             axhal::misc::terminate();
         } else {
             curr.set_state(TaskState::Exited);
-            curr.notify_exit(exit_code, self);
+            curr.notify_exit(exit_code);
             EXITED_TASKS.lock().push_back(curr.clone());
-            WAIT_FOR_EXIT.notify_one_locked(false, self);
+            WAIT_FOR_EXIT.notify_one(false);
             self.resched(false);
         }
         unreachable!("task exited!");
     }
 
+    /// Blocks the current task.
+    ///
+    /// `wait_queue_push` is called after the task state is set to `Blocked`,
+    /// it should put the task into a wait queue and release the lock of the
+    /// wait queue.
     pub fn block_current<F>(&mut self, wait_queue_push: F)
     where
         F: FnOnce(AxTaskRef),
@@ -108,25 +277,35 @@ impl AxRunQueue {
         assert!(curr.is_running());
         assert!(!curr.is_idle());
 
-        // we must not block current task with preemption disabled.
-        #[cfg(feature = "preempt")]
-        assert!(curr.can_preempt(1));
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::might_sleep_in_scheduler();
+
+        // A deadline task is charged for its runtime when it is put back into
+        // the scheduler, which a blocked task is not.
+        #[cfg(feature = "sched_rt")]
+        curr.sched_entity()
+            .lock()
+            .charge(axhal::time::monotonic_time_nanos());
 
         curr.set_state(TaskState::Blocked);
         wait_queue_push(curr.clone());
-        self.resched(false);
-    }
 
-    pub fn unblock_task(&mut self, task: AxTaskRef, resched: bool) {
-        debug!("task unblock: {}", task.id_name());
-        if task.is_blocked() {
-            task.set_state(TaskState::Ready);
-            self.scheduler.add_task(task); // TODO: priority
-            if resched {
-                #[cfg(feature = "preempt")]
-                crate::current().set_preempt_pending(true);
-            }
+        // we must not block current task with preemption disabled.
+        #[cfg(feature = "preempt")]
+        assert!(curr.can_preempt(1));
+
+        // The task may be killed or interrupted before its state is set to
+        // `Blocked`, and nobody will wake it up. Pairs with the fence in
+        // `TaskInner::kill()` and `TaskInner::interrupt()`.
+        fence(Ordering::SeqCst);
+        if curr.has_pending_wakeup()
+            && curr.transition_state(TaskState::Blocked, TaskState::Running)
+        {
+            // It is still in the wait queue, the caller should remove it.
+            return;
         }
+
+        self.resched(false);
     }
 
     #[cfg(feature = "irq")]
@@ -135,35 +314,66 @@ impl AxRunQueue {
         debug!("task sleep: {}, deadline={:?}", curr.id_name(), deadline);
         assert!(curr.is_running());
         assert!(!curr.is_idle());
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::might_sleep_in_scheduler();
 
         let now = axhal::time::wall_time();
         if now < deadline {
-            crate::timers::set_alarm_wakeup(deadline, curr.clone());
+            // See `block_current()`.
+            #[cfg(feature = "sched_rt")]
+            curr.sched_entity()
+                .lock()
+                .charge(axhal::time::monotonic_time_nanos());
+
+            // Set the state before arming the timer, as the timer may expire
+            // on another CPU before we are switched out.
             curr.set_state(TaskState::Blocked);
-            self.resched(false);
+            crate::timers::set_alarm_wakeup(deadline, curr.clone());
+
+            // See `block_current()`.
+            fence(Ordering::SeqCst);
+            if !(curr.has_pending_wakeup()
+                && curr.transition_state(TaskState::Blocked, TaskState::Running))
+            {
+                self.resched(false);
+            }
+            if curr.in_timer_list() {
+                // Woken up by `kill()` or `interrupt()` before the deadline.
+                crate::timers::cancel_alarm(curr.as_task_ref());
+            }
         }
     }
 }
 
-impl AxRunQueue {
+impl CurrentRunQueueRef {
     /// Common reschedule subroutine. If `preempt`, keep current task's time
     /// slice, otherwise reset it.
     fn resched(&mut self, preempt: bool) {
         let prev = crate::current();
         if prev.is_running() {
             prev.set_state(TaskState::Ready);
-            if !prev.is_idle() {
-                self.scheduler.put_prev_task(prev.clone(), preempt);
+            if prev.is_idle() {
+                // Idle tasks are not in the scheduler.
+            } else if prev.cpumask().get(self.cpu_id) {
+                self.scheduler.lock().put_prev_task(prev.clone(), preempt);
+            } else {
+                // The CPU affinity has been changed, migrate it to another CPU.
+                debug!("task migrate: {} from CPU {}", prev.id_name(), self.cpu_id);
+                select_run_queue(prev.as_task_ref()).add_task(prev.clone());
             }
         }
-        let next = self.scheduler.pick_next_task().unwrap_or_else(|| unsafe {
-            // Safety: IRQs must be disabled at this time.
-            IDLE_TASK.current_ref_raw().get_unchecked().clone()
-        });
-        self.switch_to(prev, next);
+        // Do not hold the local scheduler lock when stealing from others.
+        let next = self.scheduler.lock().pick_next_task();
+        let next = next
+            .or_else(|| steal_task(self.cpu_id))
+            .unwrap_or_else(|| unsafe {
+                // Safety: IRQs must be disabled at this time.
+                IDLE_TASK.current_ref_raw().get_unchecked().clone()
+            });
+        self.switch_to(prev, next, preempt);
     }
 
-    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef) {
+    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef, preempt: bool) {
         trace!(
             "context switch: {} -> {}",
             prev_task.id_name(),
@@ -171,11 +381,30 @@ impl AxRunQueue {
         );
         #[cfg(feature = "preempt")]
         next_task.set_preempt_pending(false);
-        next_task.set_state(TaskState::Running);
         if prev_task.ptr_eq(&next_task) {
+            next_task.set_state(TaskState::Running);
             return;
         }
 
+        // The next task may have been woken up or stolen while its previous
+        // CPU is still switching it out. Wait until its context is saved.
+        while next_task.on_cpu() {
+            core::hint::spin_loop();
+        }
+        next_task.set_state(TaskState::Running);
+        next_task.set_cpu_id(self.cpu_id);
+        next_task.set_on_cpu(true);
+
+        let now = axhal::time::monotonic_time_nanos();
+        prev_task.times().lock().switch_out(now, preempt);
+        next_task.times().lock().switch_in(now);
+
+        #[cfg(feature = "nohz")]
+        if prev_task.is_idle() {
+            // The tick may have been stopped when the CPU was idle.
+            crate::timers::restart_tick();
+        }
+
         unsafe {
             let prev_ctx_ptr = prev_task.ctx_mut_ptr();
             let next_ctx_ptr = next_task.ctx_mut_ptr();
@@ -185,12 +414,198 @@ impl AxRunQueue {
             assert!(Arc::strong_count(prev_task.as_task_ref()) > 1);
             assert!(Arc::strong_count(&next_task) >= 1);
 
+            *PREV_TASK.current_ref_mut_raw() = Arc::downgrade(prev_task.as_task_ref());
             CurrentTask::set_current(prev_task, next_task);
             (*prev_ctx_ptr).switch_to(&*next_ctx_ptr);
+
+            // We are now running on the stack of `prev_task` again, which may
+            // be on another CPU.
+            clear_prev_task_on_cpu();
         }
     }
 }
 
+#[cfg(feature = "lockdep")]
+impl Drop for CurrentRunQueueRef {
+    fn drop(&mut self) {
+        crate::lockdep::RUN_QUEUE_LOCK.release();
+    }
+}
+
+impl Deref for CurrentRunQueueRef {
+    type Target = AxRunQueue;
+    fn deref(&self) -> &Self::Target {
+        self.inner
+    }
+}
+
+/// Returns the reference to the run queue of the current CPU.
+#[cfg_attr(feature = "lockdep", track_caller)]
+pub(crate) fn current_run_queue() -> CurrentRunQueueRef {
+    // Disable IRQs and preemption first, so we cannot be migrated after
+    // reading the CPU ID.
+    let guard = NoPreemptIrqSave::new();
+    #[cfg(feature = "lockdep")]
+    crate::lockdep::RUN_QUEUE_LOCK.acquire();
+    CurrentRunQueueRef {
+        inner: &RUN_QUEUES[this_cpu_id()],
+        _guard: guard,
+    }
+}
+
+/// Selects a run queue for the given task among the online CPUs in its
+/// affinity mask, in a round-robin fashion.
+///
+/// If the task is only allowed to run on offline CPUs, it is put into the run
+/// queue of one of them, and runs after that CPU is started.
+fn select_run_queue(task: &AxTaskRef) -> &'static AxRunQueue {
+    static NEXT_CPU: AtomicUsize = AtomicUsize::new(0);
+    let start = NEXT_CPU.fetch_add(1, Ordering::Relaxed) % axconfig::SMP;
+    let cpumask = task.cpumask();
+    let cpu_id = (start..axconfig::SMP)
+        .chain(0..start)
+        .find(|&i| cpumask.get(i) && CPU_ONLINE[i].load(Ordering::Acquire))
+        .or_else(|| cpumask.next_index(0))
+        .unwrap_or_else(this_cpu_id); // ignore an empty mask
+    &RUN_QUEUES[cpu_id]
+}
+
+/// Clears the pending wake-up of this CPU, before the idle task checks the
+/// run queues.
+#[cfg(feature = "irq")]
+pub(crate) fn clear_idle_wakeup() {
+    RUN_QUEUES[this_cpu_id()]
+        .wakeup_pending
+        .store(false, Ordering::SeqCst);
+}
+
+/// Sleeps in the idle task until the next IRQ, unless a task has been added
+/// to the run queue of this CPU since [`clear_idle_wakeup`].
+///
+/// The IPI sent by [`AxRunQueue::kick`] may arrive at any time after the run
+/// queue is checked, so the check and the sleep are done with IRQs disabled.
+#[cfg(feature = "irq")]
+pub(crate) fn idle_wait_for_irqs() {
+    axhal::arch::disable_irqs();
+    if RUN_QUEUES[this_cpu_id()]
+        .wakeup_pending
+        .load(Ordering::Acquire)
+    {
+        axhal::arch::enable_irqs();
+    } else {
+        axhal::arch::enable_irqs_and_wait();
+    }
+}
+
+/// Adds a newly spawned task to a run queue.
+pub(crate) fn add_task(task: AxTaskRef) {
+    select_run_queue(&task).add_task(task);
+}
+
+/// Wakes up a blocked task and puts it into the run queue of the CPU it
+/// last ran on, or another allowed CPU if its affinity has been changed.
+///
+/// If `resched` is true and the task is put into the run queue of the current
+/// CPU, the current task will be preempted when the preemption is enabled.
+pub(crate) fn unblock_task(task: AxTaskRef, resched: bool) {
+    let cpu_id = task.cpu_id();
+    if task.cpumask().get(cpu_id) {
+        RUN_QUEUES[cpu_id].unblock_task(task, resched);
+    } else {
+        select_run_queue(&task).unblock_task(task, resched);
+    }
+}
+
+/// Applies the effective priority of the task to the scheduler, after its
+/// inherited priorities are changed.
+///
+/// A ready task is removed from the run queue before the change and put back
+/// after it, as the run queue is ordered by the priorities.
+pub(crate) fn update_task_priority(task: &AxTaskRef) {
+    // The CPU ID of a task is updated with the lock of the run queue it is
+    // added to, so retry if the task is moved to another run queue meanwhile.
+    let mut scheduler = loop {
+        let cpu_id = task.cpu_id();
+        let scheduler = RUN_QUEUES[cpu_id].scheduler.lock();
+        if task.cpu_id() == cpu_id {
+            break scheduler;
+        }
+    };
+    let prio = task.priority();
+    if task.is_ready() {
+        if let Some(task) = scheduler.remove_task(task) {
+            scheduler.set_priority(&task, prio);
+            scheduler.add_task(task);
+            return;
+        }
+    }
+    scheduler.set_priority(task, prio);
+}
+
+/// Moves a ready task out of the run queue of a CPU that is not in its
+/// affinity mask any more.
+pub(crate) fn migrate_task(task: &AxTaskRef) {
+    let cpu_id = task.cpu_id();
+    let removed = {
+        let mut scheduler = RUN_QUEUES[cpu_id].scheduler.lock();
+        // It has been moved to another run queue, which is selected with the
+        // new mask.
+        if task.cpu_id() != cpu_id || task.cpumask().get(cpu_id) {
+            return;
+        }
+        scheduler.remove_task(task)
+    };
+    if let Some(task) = removed {
+        debug!("task migrate: {} from CPU {}", task.id_name(), cpu_id);
+        select_run_queue(&task).add_task(task);
+    }
+}
+
+/// Tries to take a ready task from the run queues of other CPUs.
+///
+/// Other run queues are only try-locked, so an idle CPU does not slow down
+/// busy ones. Only the next task of each run queue is checked, it is put back
+/// if it is not allowed to run on this CPU.
+fn steal_task(this_cpu: usize) -> Option<AxTaskRef> {
+    for i in 1..axconfig::SMP {
+        let cpu_id = (this_cpu + i) % axconfig::SMP;
+        let Some(rq) = RUN_QUEUES[cpu_id].get() else {
+            continue;
+        };
+        let task = rq.scheduler.try_lock().and_then(|mut s| {
+            let task = s.pick_next_task()?;
+            if task.cpumask().get(this_cpu) {
+                Some(task)
+            } else {
+                s.put_prev_task(task, true); // keep its time slice
+                None
+            }
+        });
+        if let Some(task) = task {
+            debug!(
+                "task steal: {} from CPU {} to CPU {}",
+                task.id_name(),
+                cpu_id,
+                this_cpu
+            );
+            return Some(task);
+        }
+    }
+    None
+}
+
+/// Clears the `on_cpu` flag of the task that ran on this CPU before the last
+/// context switch.
+///
+/// # Safety
+///
+/// It must be called with IRQs disabled, right after a context switch.
+pub(crate) unsafe fn clear_prev_task_on_cpu() {
+    if let Some(prev) = core::mem::take(PREV_TASK.current_ref_mut_raw()).upgrade() {
+        prev.set_on_cpu(false);
+    }
+}
+
 fn gc_entry() {
     loop {
         // Drop all exited tasks and recycle resources.
@@ -199,8 +614,9 @@ fn gc_entry() {
             // Do not do the slow drops in the critical section.
             let task = EXITED_TASKS.lock().pop_front();
             if let Some(task) = task {
-                if Arc::strong_count(&task) == 1 {
-                    // If I'm the last holder of the task, drop it immediately.
+                if Arc::strong_count(&task) == 1 && !task.on_cpu() {
+                    // If I'm the last holder of the task, and it has been
+                    // switched out, drop it immediately.
                     drop(task);
                 } else {
                     // Otherwise (e.g, `switch_to` is not compeleted, held by the
@@ -226,7 +642,16 @@ pub(crate) fn init() {
     main_task.set_state(TaskState::Running);
     unsafe { CurrentTask::init_current(main_task) };
 
-    RUN_QUEUE.init_once(AxRunQueue::new());
+    // Initialize the run queues of all CPUs, so that tasks pinned to the
+    // secondary CPUs can be spawned before they are started.
+    for (i, rq) in RUN_QUEUES.iter().enumerate() {
+        rq.init_once(AxRunQueue::new(i));
+    }
+
+    CPU_ONLINE[this_cpu_id()].store(true, Ordering::Release);
+
+    let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
+    RUN_QUEUES[this_cpu_id()].add_task(gc_task);
 }
 
 pub(crate) fn init_secondary() {
@@ -237,4 +662,7 @@ pub(crate) fn init_secondary() {
         i.init_once(idle_task.clone());
     });
     unsafe { CurrentTask::init_current(idle_task) }
+
+    // Tasks can be spread to this CPU from now on.
+    CPU_ONLINE[this_cpu_id()].store(true, Ordering::Release);
 }
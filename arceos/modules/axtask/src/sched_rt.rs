@@ -0,0 +1,464 @@
+//! A layered scheduler with deadline and real-time scheduling classes.
+//!
+//! Tasks are divided into three scheduling classes by their [`SchedPolicy`],
+//! and a ready task in a higher class always runs before any task in a lower
+//! class:
+//!
+//! 1. Deadline: periodic tasks scheduled by the earliest deadline first (EDF)
+//!    algorithm. Each task can run for at most `runtime` in every `period`,
+//!    and its work should be done before `deadline` (relative to the start of
+//!    the period). A task that has used up its runtime is throttled until the
+//!    next period begins.
+//! 2. Real-time: fixed-priority tasks with the [`SchedPolicy::Fifo`] or
+//!    [`SchedPolicy::RoundRobin`] policy. A larger value means a higher
+//!    priority.
+//! 3. Normal: time-sharing tasks scheduled by the [CFS](scheduler::CFScheduler).
+//!
+//! Higher-class tasks that become ready preempt the running task right away
+//! if the preemption is enabled, even on another CPU, which is notified by an
+//! IPI. Otherwise, they preempt it at the next timer tick.
+//!
+//! For the priority inheritance, the real-time priority `p` is mapped to the
+//! priority `NICE_MIN - p`, below all nice values, and the deadline tasks
+//! have the highest real-time priority. A task that inherits such a priority
+//! is scheduled as a real-time task with the inherited priority, if it is
+//! higher than its own.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::time::Duration;
+
+use axhal::time::monotonic_time_nanos;
+use scheduler::{BaseScheduler, CFScheduler};
+
+use crate::{AxTaskRef, TaskInner};
+
+/// The minimum priority of real-time tasks.
+pub const RT_PRIO_MIN: u8 = 1;
+/// The maximum priority of real-time tasks.
+pub const RT_PRIO_MAX: u8 = 99;
+
+/// The minimum nice value of normal tasks.
+pub(crate) const NICE_MIN: isize = -20;
+
+/// Time slice of round-robin real-time tasks, in timer ticks.
+pub(crate) const RR_TIME_SLICE: usize = 5;
+
+/// Parameters of the deadline scheduling class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineParams {
+    /// The maximum CPU time that the task can use in each period.
+    pub runtime: Duration,
+    /// The deadline of the work in each period, relative to the start of the
+    /// period.
+    pub deadline: Duration,
+    /// The length of the period.
+    pub period: Duration,
+}
+
+/// The scheduling policy of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchedPolicy {
+    /// The default time-sharing policy, scheduled by the CFS.
+    #[default]
+    Normal,
+    /// Real-time first-in first-out policy with the given priority.
+    ///
+    /// The task runs until it blocks, yields, or is preempted by a task with
+    /// higher priority.
+    Fifo(u8),
+    /// Real-time round-robin policy with the given priority.
+    ///
+    /// Same as [`SchedPolicy::Fifo`], except that the task is put to the end of
+    /// its priority queue when its time slice is used up.
+    RoundRobin(u8),
+    /// Earliest deadline first policy with the given parameters.
+    ///
+    /// Calling [`yield_now`](crate::yield_now) means the work of the current
+    /// period is done, and the task will not run until the next period.
+    Deadline(DeadlineParams),
+}
+
+impl SchedPolicy {
+    /// Returns whether the parameters of the policy are valid.
+    ///
+    /// Real-time priorities must be in the range [`RT_PRIO_MIN`] to
+    /// [`RT_PRIO_MAX`], and deadline parameters must satisfy
+    /// `0 < runtime <= deadline <= period`.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Self::Normal => true,
+            Self::Fifo(prio) | Self::RoundRobin(prio) => {
+                (RT_PRIO_MIN..=RT_PRIO_MAX).contains(&prio)
+            }
+            Self::Deadline(p) => {
+                !p.runtime.is_zero() && p.runtime <= p.deadline && p.deadline <= p.period
+            }
+        }
+    }
+
+    /// Returns the priority of the policy for the priority inheritance, or
+    /// `None` for normal tasks, whose priorities are their nice values.
+    pub(crate) fn pi_priority(&self) -> Option<isize> {
+        match *self {
+            Self::Normal => None,
+            Self::Fifo(prio) | Self::RoundRobin(prio) => Some(NICE_MIN - prio as isize),
+            Self::Deadline(_) => Some(NICE_MIN - RT_PRIO_MAX as isize),
+        }
+    }
+}
+
+/// Per-task states of the [`RtScheduler`].
+pub(crate) struct SchedEntity {
+    policy: SchedPolicy,
+    /// The real-time priority inherited from the waiters of the locks held by
+    /// the task, or 0 if none.
+    rt_boost: u8,
+    /// Remaining time slice of a round-robin task, in ticks.
+    rr_slice: usize,
+    /// Absolute deadline of the current period, in nanoseconds.
+    dl_deadline: u64,
+    /// Remaining runtime in the current period, in nanoseconds.
+    dl_budget: u64,
+    /// Start time of the next period, in nanoseconds.
+    dl_next_period: u64,
+    /// The last time that the runtime was charged, in nanoseconds.
+    exec_start: u64,
+}
+
+impl SchedEntity {
+    pub const fn new() -> Self {
+        Self {
+            policy: SchedPolicy::Normal,
+            rt_boost: 0,
+            rr_slice: RR_TIME_SLICE,
+            dl_deadline: 0,
+            dl_budget: 0,
+            dl_next_period: 0,
+            exec_start: 0,
+        }
+    }
+
+    pub const fn policy(&self) -> SchedPolicy {
+        self.policy
+    }
+
+    /// Returns the policy that the task is scheduled by, which is raised to
+    /// the inherited real-time priority if that one is higher.
+    pub const fn effective_policy(&self) -> SchedPolicy {
+        let boost = self.rt_boost;
+        match self.policy {
+            SchedPolicy::Normal if boost > 0 => SchedPolicy::Fifo(boost),
+            SchedPolicy::Fifo(prio) if boost > prio => SchedPolicy::Fifo(boost),
+            SchedPolicy::RoundRobin(prio) if boost > prio => SchedPolicy::RoundRobin(boost),
+            policy => policy,
+        }
+    }
+
+    /// Changes the policy and resets all per-class states, except the
+    /// inherited priority.
+    pub fn set_policy(&mut self, policy: SchedPolicy) {
+        *self = Self {
+            rt_boost: self.rt_boost,
+            ..Self::new()
+        };
+        self.policy = policy;
+        self.exec_start = monotonic_time_nanos();
+    }
+
+    /// Charges the time since the last charge to the runtime budget.
+    pub(crate) fn charge(&mut self, now: u64) {
+        let delta = now.saturating_sub(self.exec_start);
+        self.dl_budget = self.dl_budget.saturating_sub(delta);
+        self.exec_start = now;
+    }
+
+    /// Starts a new period if the current one has elapsed.
+    ///
+    /// Returns `false` if the task has used up its runtime and should be
+    /// throttled.
+    fn replenish(&mut self, params: &DeadlineParams, now: u64) -> bool {
+        if now >= self.dl_next_period {
+            let period = params.period.as_nanos() as u64;
+            // Keep the periods aligned if the task is released in time.
+            let start = if self.dl_next_period != 0 && now - self.dl_next_period < period {
+                self.dl_next_period
+            } else {
+                now
+            };
+            self.dl_deadline = start + params.deadline.as_nanos() as u64;
+            self.dl_next_period = start + period;
+            self.dl_budget = params.runtime.as_nanos() as u64;
+        }
+        self.dl_budget > 0
+    }
+}
+
+/// A layered scheduler with deadline, real-time and normal scheduling classes.
+///
+/// See the [module-level documentation](self) for details.
+pub struct RtScheduler {
+    /// Runnable deadline tasks, ordered by (absolute deadline, task ID).
+    dl_queue: BTreeMap<(u64, u64), AxTaskRef>,
+    /// Deadline tasks that are waiting for the next period.
+    dl_throttled: VecDeque<AxTaskRef>,
+    /// Real-time tasks, one queue per priority.
+    rt_queues: [VecDeque<AxTaskRef>; RT_PRIO_MAX as usize + 1],
+    /// Bit `i` is set if `rt_queues[i]` is not empty.
+    rt_bitmap: u128,
+    cfs: CFScheduler<TaskInner>,
+}
+
+impl RtScheduler {
+    /// Creates a new empty [`RtScheduler`].
+    pub fn new() -> Self {
+        Self {
+            dl_queue: BTreeMap::new(),
+            dl_throttled: VecDeque::new(),
+            rt_queues: core::array::from_fn(|_| VecDeque::new()),
+            rt_bitmap: 0,
+            cfs: CFScheduler::new(),
+        }
+    }
+
+    /// get the name of scheduler
+    pub fn scheduler_name() -> &'static str {
+        "Deadline + Real-time + CFS"
+    }
+
+    fn rt_highest_prio(&self) -> Option<u8> {
+        if self.rt_bitmap == 0 {
+            None
+        } else {
+            Some((u128::BITS - 1 - self.rt_bitmap.leading_zeros()) as u8)
+        }
+    }
+
+    fn rt_enqueue(&mut self, task: AxTaskRef, prio: u8, front: bool) {
+        let queue = &mut self.rt_queues[prio as usize];
+        if front {
+            queue.push_front(task);
+        } else {
+            queue.push_back(task);
+        }
+        self.rt_bitmap |= 1 << prio;
+    }
+
+    fn rt_dequeue(&mut self) -> Option<AxTaskRef> {
+        let prio = self.rt_highest_prio()?;
+        let queue = &mut self.rt_queues[prio as usize];
+        let task = queue.pop_front();
+        if queue.is_empty() {
+            self.rt_bitmap &= !(1 << prio);
+        }
+        task
+    }
+
+    fn dl_enqueue(&mut self, task: AxTaskRef, params: &DeadlineParams, now: u64) {
+        let deadline = {
+            let mut se = task.sched_entity().lock();
+            se.replenish(params, now).then_some(se.dl_deadline)
+        };
+        match deadline {
+            Some(deadline) => {
+                let key = (deadline, task.id().as_u64());
+                self.dl_queue.insert(key, task);
+            }
+            None => self.dl_throttled.push_back(task),
+        }
+    }
+
+    /// Moves throttled deadline tasks whose next period has begun to the
+    /// runnable queue.
+    fn dl_release_throttled(&mut self, now: u64) {
+        let mut i = 0;
+        while i < self.dl_throttled.len() {
+            if now >= self.dl_throttled[i].sched_entity().lock().dl_next_period {
+                let task = self.dl_throttled.remove(i).unwrap();
+                if let SchedPolicy::Deadline(params) = effective_policy(&task) {
+                    self.dl_enqueue(task, &params, now);
+                }
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Returns when the first throttled deadline task becomes ready, in
+    /// nanoseconds.
+    #[cfg(feature = "nohz")]
+    pub fn next_release(&self) -> Option<u64> {
+        self.dl_throttled
+            .iter()
+            .map(|task| task.sched_entity().lock().dl_next_period)
+            .min()
+    }
+
+    fn dl_earliest_deadline(&self) -> Option<u64> {
+        self.dl_queue
+            .first_key_value()
+            .map(|(&(deadline, _), _)| deadline)
+    }
+
+    /// Returns whether a ready task should preempt `current`, i.e., it is in a
+    /// higher class, or it has a higher priority or an earlier deadline in the
+    /// same class.
+    pub fn should_preempt(&self, current: &AxTaskRef) -> bool {
+        let dl_ready = !self.dl_queue.is_empty();
+        match effective_policy(current) {
+            SchedPolicy::Normal => dl_ready || self.rt_bitmap != 0,
+            SchedPolicy::Fifo(prio) | SchedPolicy::RoundRobin(prio) => {
+                dl_ready || self.rt_highest_prio() > Some(prio)
+            }
+            SchedPolicy::Deadline(_) => {
+                let deadline = current.sched_entity().lock().dl_deadline;
+                self.dl_earliest_deadline().is_some_and(|d| d < deadline)
+            }
+        }
+    }
+}
+
+/// Returns the policy that the task is scheduled by.
+fn effective_policy(task: &AxTaskRef) -> SchedPolicy {
+    task.sched_entity().lock().effective_policy()
+}
+
+impl Default for RtScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BaseScheduler for RtScheduler {
+    type SchedItem = AxTaskRef;
+
+    fn init(&mut self) {
+        self.cfs.init();
+    }
+
+    fn add_task(&mut self, task: Self::SchedItem) {
+        match effective_policy(&task) {
+            SchedPolicy::Normal => self.cfs.add_task(task),
+            SchedPolicy::Fifo(prio) | SchedPolicy::RoundRobin(prio) => {
+                self.rt_enqueue(task, prio, false)
+            }
+            SchedPolicy::Deadline(params) => self.dl_enqueue(task, &params, monotonic_time_nanos()),
+        }
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        match effective_policy(task) {
+            SchedPolicy::Normal => self.cfs.remove_task(task),
+            SchedPolicy::Fifo(prio) | SchedPolicy::RoundRobin(prio) => {
+                let queue = &mut self.rt_queues[prio as usize];
+                let removed = queue
+                    .iter()
+                    .position(|t| Arc::ptr_eq(t, task))
+                    .and_then(|idx| queue.remove(idx));
+                if queue.is_empty() {
+                    self.rt_bitmap &= !(1 << prio);
+                }
+                removed
+            }
+            SchedPolicy::Deadline(_) => {
+                let key = (task.sched_entity().lock().dl_deadline, task.id().as_u64());
+                self.dl_queue.remove(&key).or_else(|| {
+                    self.dl_throttled
+                        .iter()
+                        .position(|t| Arc::ptr_eq(t, task))
+                        .and_then(|idx| self.dl_throttled.remove(idx))
+                })
+            }
+        }
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        let now = monotonic_time_nanos();
+        self.dl_release_throttled(now);
+        if let Some((_, task)) = self.dl_queue.pop_first() {
+            task.sched_entity().lock().exec_start = now;
+            Some(task)
+        } else if let Some(task) = self.rt_dequeue() {
+            Some(task)
+        } else {
+            self.cfs.pick_next_task()
+        }
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, preempt: bool) {
+        match effective_policy(&prev) {
+            SchedPolicy::Normal => self.cfs.put_prev_task(prev, preempt),
+            SchedPolicy::Fifo(prio) => self.rt_enqueue(prev, prio, preempt),
+            SchedPolicy::RoundRobin(prio) => {
+                let front = {
+                    let mut se = prev.sched_entity().lock();
+                    if preempt && se.rr_slice > 0 {
+                        true
+                    } else {
+                        se.rr_slice = RR_TIME_SLICE;
+                        false
+                    }
+                };
+                self.rt_enqueue(prev, prio, front);
+            }
+            SchedPolicy::Deadline(params) => {
+                let now = monotonic_time_nanos();
+                {
+                    let mut se = prev.sched_entity().lock();
+                    se.charge(now);
+                    if !preempt {
+                        // Yielding means the work of this period is done.
+                        se.dl_budget = 0;
+                    }
+                }
+                self.dl_enqueue(prev, &params, now);
+            }
+        }
+    }
+
+    fn task_tick(&mut self, current: &Self::SchedItem) -> bool {
+        let now = monotonic_time_nanos();
+        self.dl_release_throttled(now);
+        let dl_ready = !self.dl_queue.is_empty();
+        match effective_policy(current) {
+            SchedPolicy::Normal => {
+                let expired = self.cfs.task_tick(current);
+                expired || dl_ready || self.rt_bitmap != 0
+            }
+            SchedPolicy::Fifo(prio) => dl_ready || self.rt_highest_prio() > Some(prio),
+            SchedPolicy::RoundRobin(prio) => {
+                let expired = {
+                    let mut se = current.sched_entity().lock();
+                    se.rr_slice = se.rr_slice.saturating_sub(1);
+                    se.rr_slice == 0
+                };
+                expired || dl_ready || self.rt_highest_prio() > Some(prio)
+            }
+            SchedPolicy::Deadline(_) => {
+                let (exhausted, deadline) = {
+                    let mut se = current.sched_entity().lock();
+                    se.charge(now);
+                    (se.dl_budget == 0, se.dl_deadline)
+                };
+                exhausted || self.dl_earliest_deadline().is_some_and(|d| d < deadline)
+            }
+        }
+    }
+
+    /// Sets the nice value of a normal task, or the inherited real-time
+    /// priority if `prio` is below [`NICE_MIN`].
+    ///
+    /// The task must not be in the run queue, as its class may change.
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        let boost = (NICE_MIN - prio).clamp(0, RT_PRIO_MAX as isize) as u8;
+        let policy = {
+            let mut se = task.sched_entity().lock();
+            se.rt_boost = boost;
+            se.policy()
+        };
+        match policy {
+            SchedPolicy::Normal if boost == 0 => self.cfs.set_priority(task, prio),
+            _ => true,
+        }
+    }
+}
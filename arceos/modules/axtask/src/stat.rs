@@ -0,0 +1,167 @@
+//! Per-task CPU time accounting and the list of all live tasks.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use axhal::time::monotonic_time_nanos;
+use kspin::SpinNoIrq;
+
+use crate::task::TaskState;
+use crate::{AxTask, AxTaskRef, TaskId};
+
+/// All live tasks, indexed by the task ID.
+static TASK_LIST: SpinNoIrq<BTreeMap<u64, Weak<AxTask>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// CPU time and scheduling statistics of a task.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskStat {
+    /// Time spent in user mode.
+    pub user_time: Duration,
+    /// Time spent in kernel mode.
+    pub kernel_time: Duration,
+    /// Number of context switches due to blocking, yielding or exiting.
+    pub voluntary_switches: u64,
+    /// Number of context switches due to preemption.
+    pub involuntary_switches: u64,
+    /// The CPU on which the task is running or last ran.
+    pub last_cpu: usize,
+}
+
+/// A snapshot of a live task, see [`task_snapshot`].
+#[derive(Debug, Clone)]
+pub struct TaskSnapshot {
+    /// The task ID.
+    pub id: TaskId,
+    /// The task name.
+    pub name: String,
+    /// The task state.
+    pub state: TaskState,
+    /// Whether the task is an idle task.
+    pub is_idle: bool,
+    /// CPU time and scheduling statistics.
+    pub stat: TaskStat,
+}
+
+/// Raw counters of [`TaskStat`], updated by the CPU that runs the task.
+#[derive(Clone)]
+pub(crate) struct TaskTimes {
+    user_ns: u64,
+    kernel_ns: u64,
+    nvcsw: u64,
+    nivcsw: u64,
+    in_user: bool,
+    /// The start time of the current accounting period, `None` if the task
+    /// is not running.
+    stamp: Option<u64>,
+}
+
+impl TaskTimes {
+    pub const fn new() -> Self {
+        Self {
+            user_ns: 0,
+            kernel_ns: 0,
+            nvcsw: 0,
+            nivcsw: 0,
+            in_user: false,
+            stamp: None,
+        }
+    }
+
+    /// Charges the time since the last stamp to the current mode.
+    fn update(&mut self, now: u64) {
+        if let Some(stamp) = self.stamp.replace(now) {
+            let delta = now.saturating_sub(stamp);
+            if self.in_user {
+                self.user_ns += delta;
+            } else {
+                self.kernel_ns += delta;
+            }
+        }
+    }
+
+    pub fn switch_in(&mut self, now: u64) {
+        self.stamp = Some(now);
+    }
+
+    pub fn switch_out(&mut self, now: u64, preempt: bool) {
+        self.update(now);
+        self.stamp = None;
+        if preempt {
+            self.nivcsw += 1;
+        } else {
+            self.nvcsw += 1;
+        }
+    }
+
+    pub fn set_user_mode(&mut self, in_user: bool) {
+        self.update(monotonic_time_nanos());
+        self.in_user = in_user;
+    }
+
+    pub fn stat(&self, last_cpu: usize) -> TaskStat {
+        let mut times = self.clone();
+        if times.stamp.is_some() {
+            // Include the time of the current period if the task is running.
+            times.update(monotonic_time_nanos());
+        }
+        TaskStat {
+            user_time: Duration::from_nanos(times.user_ns),
+            kernel_time: Duration::from_nanos(times.kernel_ns),
+            voluntary_switches: times.nvcsw,
+            involuntary_switches: times.nivcsw,
+            last_cpu,
+        }
+    }
+}
+
+pub(crate) fn register_task(task: &AxTaskRef) {
+    TASK_LIST
+        .lock()
+        .insert(task.id().as_u64(), Arc::downgrade(task));
+}
+
+pub(crate) fn unregister_task(id: TaskId) {
+    TASK_LIST.lock().remove(&id.as_u64());
+}
+
+/// Finds a live task by its ID.
+pub(crate) fn find_task(id: TaskId) -> Option<AxTaskRef> {
+    TASK_LIST.lock().get(&id.as_u64()).and_then(Weak::upgrade)
+}
+
+/// Calls `f` on each live task, in the order of task IDs.
+///
+/// Tasks that are spawned or dropped during the iteration may or may not be
+/// visited.
+pub fn for_each_task<F>(mut f: F)
+where
+    F: FnMut(&AxTaskRef),
+{
+    // Do not hold the lock when calling `f`.
+    let tasks: Vec<AxTaskRef> = TASK_LIST
+        .lock()
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect();
+    for task in &tasks {
+        f(task);
+    }
+}
+
+/// Returns the snapshots of all live tasks, in the order of task IDs.
+pub fn task_snapshot() -> Vec<TaskSnapshot> {
+    let mut snapshots = Vec::new();
+    for_each_task(|task| {
+        snapshots.push(TaskSnapshot {
+            id: task.id(),
+            name: String::from(task.name()),
+            state: task.state(),
+            is_idle: task.is_idle(),
+            stat: task.stat(),
+        })
+    });
+    snapshots
+}
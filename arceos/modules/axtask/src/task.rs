@@ -1,19 +1,25 @@
 use alloc::{boxed::Box, string::String, sync::Arc};
 use core::ops::Deref;
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
+use core::sync::atomic::{
+    fence, AtomicBool, AtomicI32, AtomicIsize, AtomicU64, AtomicU8, AtomicUsize, Ordering,
+};
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
 
-#[cfg(feature = "preempt")]
-use core::sync::atomic::AtomicUsize;
-
 #[cfg(feature = "tls")]
 use axhal::tls::TlsArea;
 
 use axhal::arch::TaskContext;
+use kspin::SpinNoIrq;
 use memory_addr::{align_up_4k, VirtAddr};
 
+#[cfg(feature = "stack_guard")]
+use crate::kstack::TaskStack;
+use crate::pi::TaskPi;
+#[cfg(feature = "sched_rt")]
+use crate::sched_rt::{SchedEntity, SchedPolicy};
+use crate::stat::{TaskStat, TaskTimes};
 use crate::task_ext::AxTaskExt;
-use crate::{AxRunQueue, AxTask, AxTaskRef, WaitQueue};
+use crate::{AxCpuMask, AxTask, AxTaskRef, WaitQueue};
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -22,13 +28,22 @@ pub struct TaskId(u64);
 /// The possible states of a task.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub(crate) enum TaskState {
+pub enum TaskState {
+    /// The task is running on a CPU.
     Running = 1,
+    /// The task is ready to run, waiting in a run queue.
     Ready = 2,
+    /// The task is blocked, waiting in a wait queue or for a timer.
     Blocked = 3,
+    /// The task has exited, waiting to be dropped.
     Exited = 4,
 }
 
+/// The error returned by interruptible blocking operations, when the task is
+/// interrupted by [`TaskInner::interrupt`] or [`TaskInner::kill`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Interrupted;
+
 /// The inner task structure.
 pub struct TaskInner {
     id: TaskId,
@@ -39,10 +54,39 @@ pub struct TaskInner {
     entry: Option<*mut dyn FnOnce()>,
     state: AtomicU8,
 
+    /// The CPU on which the task is running or last ran, or the CPU of the run
+    /// queue it is in.
+    cpu_id: AtomicUsize,
+    /// Whether the task is running on a CPU (its context is not saved yet).
+    on_cpu: AtomicBool,
+    /// The CPUs that the task is allowed to run on.
+    cpumask: SpinNoIrq<AxCpuMask>,
+    /// CPU time and context switch counters.
+    times: SpinNoIrq<TaskTimes>,
+    #[cfg(feature = "sched_rt")]
+    sched_entity: SpinNoIrq<SchedEntity>,
+    /// The priority set by [`set_priority`](crate::set_priority).
+    base_prio: AtomicIsize,
+    /// Priority inheritance from the tasks waiting for the locks held by this
+    /// task.
+    pi: TaskPi,
+    /// Locks held by the task, tracked by the lock dependency validator.
+    #[cfg(feature = "lockdep")]
+    held_locks: SpinNoIrq<alloc::vec::Vec<crate::lockdep::HeldLock>>,
+
     in_wait_queue: AtomicBool,
     #[cfg(feature = "irq")]
     in_timer_list: AtomicBool,
 
+    /// Whether the task has been killed.
+    killed: AtomicBool,
+    /// Whether the task has a pending interrupt.
+    interrupted: AtomicBool,
+    /// Whether the task is in an interruptible wait.
+    interruptible: AtomicBool,
+    /// Whether the task is in a wait that is only woken up by a kill.
+    killable: AtomicBool,
+
     #[cfg(feature = "preempt")]
     need_resched: AtomicBool,
     #[cfg(feature = "preempt")]
@@ -65,6 +109,10 @@ impl TaskId {
         Self(ID_COUNTER.fetch_add(1, Ordering::Relaxed))
     }
 
+    pub(crate) const fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
+
     /// Convert the task ID to a `u64`.
     pub const fn as_u64(&self) -> u64 {
         self.0
@@ -95,6 +143,9 @@ impl TaskInner {
     {
         let mut t = Self::new_common(TaskId::new(), name);
         debug!("new task: {}", t.id_name());
+        #[cfg(feature = "stack_guard")]
+        let kstack = TaskStack::alloc(align_up_4k(stack_size), &t.name);
+        #[cfg(not(feature = "stack_guard"))]
         let kstack = TaskStack::alloc(align_up_4k(stack_size));
 
         #[cfg(feature = "tls")]
@@ -126,12 +177,155 @@ impl TaskInner {
         alloc::format!("Task({}, {:?})", self.id.as_u64(), self.name)
     }
 
+    /// Gets the state of the task.
+    #[inline]
+    pub fn state(&self) -> TaskState {
+        self.state.load(Ordering::Acquire).into()
+    }
+
+    /// Gets the CPU time and scheduling statistics of the task.
+    pub fn stat(&self) -> TaskStat {
+        self.times.lock().stat(self.cpu_id())
+    }
+
+    /// Records that the task is about to return to user mode.
+    ///
+    /// Kernels that run user-space tasks should call it before entering user
+    /// mode, and call [`TaskInner::account_kernel_entry`] after trapping into
+    /// the kernel, so that the time is charged to the right mode.
+    pub fn account_user_entry(&self) {
+        self.times.lock().set_user_mode(true);
+    }
+
+    /// Records that the task has trapped from user mode into the kernel.
+    ///
+    /// See [`TaskInner::account_user_entry`].
+    pub fn account_kernel_entry(&self) {
+        self.times.lock().set_user_mode(false);
+    }
+
+    /// Gets the priority of the task set by [`set_priority`].
+    ///
+    /// [`set_priority`]: crate::set_priority
+    pub fn base_priority(&self) -> isize {
+        self.base_prio.load(Ordering::Relaxed)
+    }
+
+    /// Gets the effective priority of the task, which is the highest one of
+    /// its base priority and the priorities inherited from the tasks waiting
+    /// for the locks it holds.
+    ///
+    /// Smaller values mean higher priorities. The priorities of the real-time
+    /// tasks are below all nice values, and a normal task that inherits such a
+    /// priority is scheduled as a real-time task.
+    pub fn priority(&self) -> isize {
+        let base = self.base_priority();
+        #[cfg(feature = "sched_rt")]
+        let base = match self.sched_policy().pi_priority() {
+            Some(prio) => prio,
+            None => base,
+        };
+        match self.pi.inherited_priority() {
+            Some(prio) => base.min(prio),
+            None => base,
+        }
+    }
+
+    /// Gets the CPU affinity mask of the task.
+    pub fn cpumask(&self) -> AxCpuMask {
+        *self.cpumask.lock()
+    }
+
+    /// Sets the CPU affinity mask of the task.
+    ///
+    /// A ready task in the run queue of a CPU that is no longer allowed is
+    /// moved to another run queue right away. A running task is moved the next
+    /// time it is scheduled. The mask should not be empty, otherwise it will be
+    /// ignored by the scheduler.
+    pub fn set_cpumask(&self, cpumask: AxCpuMask) {
+        *self.cpumask.lock() = cpumask;
+        if self.is_ready() && !cpumask.get(self.cpu_id()) {
+            if let Some(task) = crate::stat::find_task(self.id()) {
+                crate::run_queue::migrate_task(&task);
+            }
+        }
+    }
+
+    /// Gets the scheduling policy of the task.
+    #[cfg(feature = "sched_rt")]
+    pub fn sched_policy(&self) -> SchedPolicy {
+        self.sched_entity.lock().policy()
+    }
+
+    /// Kills the task.
+    ///
+    /// If the task is blocked in a [`WaitQueue`] or [`sleep_until`], it is
+    /// woken up: interruptible waits (e.g., [`WaitQueue::wait_interruptible`],
+    /// `axsync::Mutex::lock_interruptible`) return [`Interrupted`], and killable
+    /// waits (e.g., [`WaitQueue::wait`], [`WaitQueue::wait_until_killable`],
+    /// `axsync::Mutex::lock_killable`, [`TaskInner::join`], [`sleep_until`])
+    /// return early. Waits for a condition that can not give up (e.g.,
+    /// [`WaitQueue::wait_until`], `axsync::Mutex::lock`) are not affected, as
+    /// their callers rely on the condition. A running task exits at its next
+    /// preemption point, or before it returns to the user space (see
+    /// [`exit_if_killed`]).
+    ///
+    /// The exit code of a killed task is [`EXIT_CODE_KILLED`], whatever code
+    /// it exits with. Note that the resources held by the task (e.g., locked
+    /// mutexes) are not released if it exits at a preemption point. Returns
+    /// `false` if the task can not be killed, i.e., it is an idle task or the
+    /// init task.
+    ///
+    /// [`sleep_until`]: crate::sleep_until
+    /// [`exit_if_killed`]: crate::exit_if_killed
+    /// [`EXIT_CODE_KILLED`]: crate::EXIT_CODE_KILLED
+    pub fn kill(&self) -> bool {
+        if self.is_idle() || self.is_init() {
+            return false;
+        }
+        self.killed.store(true, Ordering::SeqCst);
+        self.interrupted.store(true, Ordering::SeqCst);
+        // Pairs with the fence in `CurrentRunQueueRef::block_current()`.
+        fence(Ordering::SeqCst);
+        if self.interruptible.load(Ordering::SeqCst) || self.killable.load(Ordering::SeqCst) {
+            self.wake_up_blocked();
+        }
+        #[cfg(feature = "preempt")]
+        if self.is_running() {
+            // Let it exit at the next preemption point.
+            self.set_preempt_pending(true);
+        }
+        true
+    }
+
+    /// Interrupts the task.
+    ///
+    /// If the task is blocked in an interruptible wait (e.g.,
+    /// [`WaitQueue::wait_interruptible`]), it is woken up and the wait returns
+    /// [`Interrupted`]. Otherwise, the interrupt is kept pending, and the next
+    /// interruptible wait of the task returns [`Interrupted`] immediately.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        // Pairs with the fence in `CurrentRunQueueRef::block_current()`.
+        fence(Ordering::SeqCst);
+        if self.interruptible.load(Ordering::SeqCst) {
+            self.wake_up_blocked();
+        }
+    }
+
+    /// Returns whether the task has been killed.
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::SeqCst)
+    }
+
     /// Wait for the task to exit, and return the exit code.
     ///
     /// It will return immediately if the task has already exited (but not dropped).
+    /// Returns `None` if the current task is killed before the task exits.
     pub fn join(&self) -> Option<i32> {
         self.wait_for_exit
-            .wait_until(|| self.state() == TaskState::Exited);
+            .wait_until_killable(|| self.state() == TaskState::Exited)
+            .ok()?;
         Some(self.exit_code.load(Ordering::Acquire))
     }
 
@@ -171,9 +365,23 @@ impl TaskInner {
             is_init: false,
             entry: None,
             state: AtomicU8::new(TaskState::Ready as u8),
+            cpu_id: AtomicUsize::new(0),
+            on_cpu: AtomicBool::new(false),
+            cpumask: SpinNoIrq::new(AxCpuMask::full()),
+            times: SpinNoIrq::new(TaskTimes::new()),
+            #[cfg(feature = "sched_rt")]
+            sched_entity: SpinNoIrq::new(SchedEntity::new()),
+            base_prio: AtomicIsize::new(0),
+            pi: TaskPi::new(),
+            #[cfg(feature = "lockdep")]
+            held_locks: SpinNoIrq::new(alloc::vec::Vec::new()),
             in_wait_queue: AtomicBool::new(false),
             #[cfg(feature = "irq")]
             in_timer_list: AtomicBool::new(false),
+            killed: AtomicBool::new(false),
+            interrupted: AtomicBool::new(false),
+            interruptible: AtomicBool::new(false),
+            killable: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
@@ -206,17 +414,24 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let task = Arc::new(AxTask::new(self));
+        crate::stat::register_task(&task);
+        task
     }
 
     #[inline]
-    pub(crate) fn state(&self) -> TaskState {
-        self.state.load(Ordering::Acquire).into()
+    pub(crate) fn set_state(&self, state: TaskState) {
+        self.state.store(state as u8, Ordering::Release)
     }
 
+    /// Changes the state of the task from `from` to `to` atomically.
+    ///
+    /// Returns `true` if the state was `from` and has been changed.
     #[inline]
-    pub(crate) fn set_state(&self, state: TaskState) {
-        self.state.store(state as u8, Ordering::Release)
+    pub(crate) fn transition_state(&self, from: TaskState, to: TaskState) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
     }
 
     #[inline]
@@ -239,11 +454,59 @@ impl TaskInner {
         self.is_init
     }
 
+    /// Returns whether the task is an idle task.
     #[inline]
-    pub(crate) const fn is_idle(&self) -> bool {
+    pub const fn is_idle(&self) -> bool {
         self.is_idle
     }
 
+    #[inline]
+    pub(crate) fn cpu_id(&self) -> usize {
+        self.cpu_id.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(crate) fn set_cpu_id(&self, cpu_id: usize) {
+        self.cpu_id.store(cpu_id, Ordering::Release)
+    }
+
+    #[inline]
+    pub(crate) fn times(&self) -> &SpinNoIrq<TaskTimes> {
+        &self.times
+    }
+
+    #[inline]
+    pub(crate) fn set_base_priority(&self, prio: isize) {
+        self.base_prio.store(prio, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn pi(&self) -> &TaskPi {
+        &self.pi
+    }
+
+    #[inline]
+    #[cfg(feature = "lockdep")]
+    pub(crate) fn held_locks(&self) -> &SpinNoIrq<alloc::vec::Vec<crate::lockdep::HeldLock>> {
+        &self.held_locks
+    }
+
+    #[inline]
+    pub(crate) fn on_cpu(&self) -> bool {
+        self.on_cpu.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(crate) fn set_on_cpu(&self, on_cpu: bool) {
+        self.on_cpu.store(on_cpu, Ordering::Release)
+    }
+
+    #[inline]
+    #[cfg(feature = "sched_rt")]
+    pub(crate) fn sched_entity(&self) -> &SpinNoIrq<SchedEntity> {
+        &self.sched_entity
+    }
+
     #[inline]
     pub(crate) fn in_wait_queue(&self) -> bool {
         self.in_wait_queue.load(Ordering::Acquire)
@@ -266,6 +529,40 @@ impl TaskInner {
         self.in_timer_list.store(in_timer_list, Ordering::Release);
     }
 
+    #[inline]
+    pub(crate) fn set_interruptible(&self, interruptible: bool) {
+        self.interruptible.store(interruptible, Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub(crate) fn set_killable(&self, killable: bool) {
+        self.killable.store(killable, Ordering::SeqCst);
+    }
+
+    /// Consumes the pending interrupt, returns `true` if the task has been
+    /// interrupted or killed.
+    #[inline]
+    pub(crate) fn check_interrupted(&self) -> bool {
+        self.interrupted.swap(false, Ordering::SeqCst) | self.is_killed()
+    }
+
+    /// Returns `true` if the task should not be blocked, as it has been
+    /// interrupted or killed during an interruptible wait, or killed during a
+    /// killable wait.
+    #[inline]
+    pub(crate) fn has_pending_wakeup(&self) -> bool {
+        (self.interruptible.load(Ordering::SeqCst) && self.interrupted.load(Ordering::SeqCst))
+            || (self.killable.load(Ordering::SeqCst) && self.is_killed())
+    }
+
+    fn wake_up_blocked(&self) {
+        if self.is_blocked() {
+            if let Some(task) = crate::stat::find_task(self.id) {
+                crate::run_queue::unblock_task(task, true);
+            }
+        }
+    }
+
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn set_preempt_pending(&self, pending: bool) {
@@ -297,16 +594,16 @@ impl TaskInner {
     fn current_check_preempt_pending() {
         let curr = crate::current();
         if curr.need_resched.load(Ordering::Acquire) && curr.can_preempt(0) {
-            let mut rq = crate::RUN_QUEUE.lock();
+            let mut rq = crate::current_run_queue();
             if curr.need_resched.load(Ordering::Acquire) {
                 rq.preempt_resched();
             }
         }
     }
 
-    pub(crate) fn notify_exit(&self, exit_code: i32, rq: &mut AxRunQueue) {
+    pub(crate) fn notify_exit(&self, exit_code: i32) {
         self.exit_code.store(exit_code, Ordering::Release);
-        self.wait_for_exit.notify_all_locked(false, rq);
+        self.wait_for_exit.notify_all(false);
     }
 
     #[inline]
@@ -343,14 +640,17 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        crate::stat::unregister_task(self.id);
     }
 }
 
+#[cfg(not(feature = "stack_guard"))]
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
 }
 
+#[cfg(not(feature = "stack_guard"))]
 impl TaskStack {
     pub fn alloc(size: usize) -> Self {
         let layout = Layout::from_size_align(size, 16).unwrap();
@@ -365,6 +665,7 @@ impl TaskStack {
     }
 }
 
+#[cfg(not(feature = "stack_guard"))]
 impl Drop for TaskStack {
     fn drop(&mut self) {
         unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
@@ -407,6 +708,12 @@ impl CurrentTask {
 
     pub(crate) unsafe fn init_current(init_task: AxTaskRef) {
         assert!(init_task.is_init());
+        init_task.set_cpu_id(axhal::cpu::this_cpu_id());
+        init_task.set_on_cpu(true);
+        init_task
+            .times()
+            .lock()
+            .switch_in(axhal::time::monotonic_time_nanos());
         #[cfg(feature = "tls")]
         axhal::arch::write_thread_pointer(init_task.tls.tls_ptr() as usize);
         let ptr = Arc::into_raw(init_task);
@@ -429,13 +736,19 @@ impl Deref for CurrentTask {
 }
 
 extern "C" fn task_entry() -> ! {
-    // release the lock that was implicitly held across the reschedule
-    unsafe { crate::RUN_QUEUE.force_unlock() };
+    // the previous task on this CPU has been switched out
+    unsafe { crate::run_queue::clear_prev_task_on_cpu() };
     #[cfg(feature = "irq")]
     axhal::arch::enable_irqs();
     let task = crate::current();
     if let Some(entry) = task.entry {
-        unsafe { Box::from_raw(entry)() };
+        let entry = unsafe { Box::from_raw(entry) };
+        if task.is_killed() {
+            // Killed before it starts running.
+            drop(entry);
+            crate::exit(crate::EXIT_CODE_KILLED);
+        }
+        entry();
     }
     crate::exit(0);
 }
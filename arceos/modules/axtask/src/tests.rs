@@ -1,7 +1,8 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
 use std::sync::{Mutex, Once};
 
-use crate::{api as axtask, current, WaitQueue};
+use crate::{api as axtask, current, AxCpuMask, Interrupted, PiLock, TaskState, WaitQueue};
 
 static INIT: Once = Once::new();
 static SERIAL: Mutex<()> = Mutex::new(());
@@ -128,3 +129,723 @@ fn test_task_join() {
         assert_eq!(tasks[i].join(), Some(i as _));
     }
 }
+
+#[test]
+fn test_cpu_affinity() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    assert!(!axtask::set_current_affinity(AxCpuMask::new()));
+    assert!(axtask::set_current_affinity(AxCpuMask::one_shot(0)));
+    assert_eq!(current().cpumask(), AxCpuMask::one_shot(0));
+
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+    let task = axtask::spawn_raw_with_cpumask(
+        || {
+            assert_eq!(current().cpumask(), AxCpuMask::one_shot(0));
+            axtask::yield_now();
+            FINISHED.fetch_add(1, Ordering::Relaxed);
+        },
+        "pinned".into(),
+        0x1000,
+        AxCpuMask::one_shot(0),
+    );
+    assert_eq!(task.join(), Some(0));
+    assert_eq!(FINISHED.load(Ordering::Relaxed), 1);
+
+    assert!(axtask::set_current_affinity(AxCpuMask::full()));
+}
+
+#[test]
+fn test_task_snapshot() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let task = axtask::spawn_raw(
+        || {
+            for _ in 0..3 {
+                axtask::yield_now();
+            }
+        },
+        "snapshot".into(),
+        0x1000,
+    );
+    let snapshots = axtask::task_snapshot();
+    assert!(snapshots.iter().any(|t| t.id == current().id()));
+    let snapshot = snapshots.iter().find(|t| t.id == task.id()).unwrap();
+    assert_eq!(snapshot.name, "snapshot");
+    assert_eq!(snapshot.state, TaskState::Ready);
+
+    assert_eq!(task.join(), Some(0));
+    let stat = task.stat();
+    assert!(stat.voluntary_switches >= 4); // 3 yields and 1 exit
+    assert_eq!(stat.involuntary_switches, 0);
+    assert_eq!(stat.user_time, Duration::ZERO); // never in user mode
+}
+
+#[test]
+fn test_task_kill() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+
+    // interrupt a task in an interruptible wait
+    let task = axtask::spawn(|| {
+        assert_eq!(WQ.wait_interruptible(), Err(Interrupted));
+        assert_eq!(WQ.wait_until_interruptible(|| true), Ok(()));
+    });
+    axtask::yield_now(); // let the task block
+    task.interrupt();
+    assert_eq!(task.join(), Some(0));
+
+    // kill a task in a killable wait, which returns early
+    let task = axtask::spawn(|| {
+        WQ.wait();
+        assert!(current().is_killed());
+        assert_eq!(WQ.wait_interruptible(), Err(Interrupted));
+    });
+    axtask::yield_now(); // let the task block
+    assert!(task.kill());
+    assert_eq!(task.join(), Some(axtask::EXIT_CODE_KILLED));
+
+    // kill a task in a condition wait, which is not woken up, and exits at
+    // the next safe point
+    use core::sync::atomic::AtomicBool;
+    static READY: AtomicBool = AtomicBool::new(false);
+    let task = axtask::spawn(|| {
+        WQ.wait_until(|| READY.load(Ordering::Acquire));
+        assert!(current().is_killed());
+        axtask::exit_if_killed();
+        unreachable!("killed task should exit at the safe point");
+    });
+    axtask::yield_now(); // let the task block
+    assert!(task.kill());
+    axtask::yield_now();
+    assert_eq!(task.state(), TaskState::Blocked);
+    READY.store(true, Ordering::Release);
+    assert!(WQ.notify_one(false));
+    assert_eq!(task.join(), Some(axtask::EXIT_CODE_KILLED));
+
+    // a killable condition wait ignores the interrupts, and a join returns
+    // early if the joiner is killed
+    static DONE: AtomicBool = AtomicBool::new(false);
+    READY.store(false, Ordering::Release);
+    let task = axtask::spawn(|| {
+        assert_eq!(
+            WQ.wait_until_killable(|| READY.load(Ordering::Acquire)),
+            Ok(())
+        );
+        let blocker = axtask::spawn(|| WQ.wait_until(|| DONE.load(Ordering::Acquire)));
+        assert_eq!(blocker.join(), None);
+        assert!(current().is_killed());
+    });
+    axtask::yield_now(); // let the task block
+    task.interrupt();
+    assert_eq!(task.state(), TaskState::Blocked);
+    READY.store(true, Ordering::Release);
+    assert!(WQ.notify_one(false));
+    axtask::yield_now(); // let the task block in the join
+    assert!(task.kill());
+    assert_eq!(task.join(), Some(axtask::EXIT_CODE_KILLED));
+    DONE.store(true, Ordering::Release);
+    WQ.notify_all(false);
+    axtask::yield_now(); // let the blocker exit
+
+    // kill a task in an interruptible wait, it returns by itself but is still
+    // reported as killed
+    let task = axtask::spawn(|| {
+        assert_eq!(WQ.wait_interruptible(), Err(Interrupted));
+    });
+    axtask::yield_now();
+    assert!(task.kill());
+    assert_eq!(task.join(), Some(axtask::EXIT_CODE_KILLED));
+
+    // kill a task before it starts running
+    let task = axtask::spawn(|| unreachable!("killed task should not run"));
+    assert!(task.kill());
+    assert_eq!(task.join(), Some(axtask::EXIT_CODE_KILLED));
+
+    // the init task can not be killed
+    assert!(!current().kill());
+    assert!(!current().is_killed());
+}
+
+#[test]
+fn test_executor() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    use crate::future;
+    use core::sync::atomic::AtomicBool;
+    use core::task::{Poll, Waker};
+
+    // run several futures on one task
+    const NUM_FUTURES: usize = 10;
+    let sum = future::block_on(async {
+        let handles: Vec<_> = (0..NUM_FUTURES)
+            .map(|i| {
+                future::spawn(async move {
+                    future::yield_now().await;
+                    i
+                })
+            })
+            .collect();
+        let mut sum = 0;
+        for handle in handles {
+            sum += handle.await;
+        }
+        sum
+    });
+    assert_eq!(sum, (0..NUM_FUTURES).sum());
+
+    // block the executor until another task wakes the future
+    static FLAG: AtomicBool = AtomicBool::new(false);
+    static WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+    let task = axtask::spawn(|| loop {
+        if let Some(waker) = WAKER.lock().unwrap().take() {
+            FLAG.store(true, Ordering::Release);
+            waker.wake();
+            break;
+        }
+        axtask::yield_now();
+    });
+    future::block_on(core::future::poll_fn(|cx| {
+        if FLAG.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            *WAKER.lock().unwrap() = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }));
+    assert_eq!(task.join(), Some(0));
+
+    // run `block_on` on one executor from two tasks at the same time
+    static OTHER_DONE: AtomicBool = AtomicBool::new(false);
+    static OTHER_WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+    let exec = std::sync::Arc::new(future::Executor::new());
+    let other_exec = exec.clone();
+    let task = axtask::spawn(move || {
+        other_exec.block_on(core::future::poll_fn(|cx| {
+            if OTHER_DONE.load(Ordering::Acquire) {
+                Poll::Ready(())
+            } else {
+                *OTHER_WAKER.lock().unwrap() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }));
+    });
+    exec.block_on(async {
+        while OTHER_WAKER.lock().unwrap().is_none() {
+            axtask::yield_now(); // let the other task block in `block_on`
+            future::yield_now().await;
+        }
+        OTHER_DONE.store(true, Ordering::Release);
+        OTHER_WAKER.lock().unwrap().take().unwrap().wake();
+    });
+    assert_eq!(task.join(), Some(0));
+}
+
+#[test]
+fn test_priority_inheritance() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static LOCK1: PiLock = PiLock::new();
+    static LOCK2: PiLock = PiLock::new();
+    static STEP: AtomicUsize = AtomicUsize::new(0);
+    static WAITING: AtomicUsize = AtomicUsize::new(0);
+
+    // The owner holds lock 1 and lock 2, and releases them one by one.
+    let owner = axtask::spawn(|| unsafe {
+        LOCK1.acquired();
+        LOCK2.acquired();
+        STEP.store(1, Ordering::Release);
+        while STEP.load(Ordering::Acquire) == 1 {
+            axtask::yield_now();
+        }
+        LOCK1.released();
+        STEP.store(3, Ordering::Release);
+        while STEP.load(Ordering::Acquire) == 3 {
+            axtask::yield_now();
+        }
+        LOCK2.released();
+    });
+    while STEP.load(Ordering::Acquire) == 0 {
+        axtask::yield_now();
+    }
+
+    let waiter = |lock: &'static PiLock, prio: isize| {
+        axtask::spawn(move || unsafe {
+            axtask::set_priority_for_test(prio);
+            assert!(lock.begin_wait(|| true));
+            WAITING.fetch_add(1, Ordering::Release);
+            while STEP.load(Ordering::Acquire) < 5 {
+                axtask::yield_now();
+            }
+            lock.end_wait();
+            axtask::set_priority_for_test(0);
+        })
+    };
+    let waiters = [
+        waiter(&LOCK1, -10),
+        waiter(&LOCK2, -5),
+        waiter(&LOCK2, 3), // lower than the inherited one
+    ];
+    while WAITING.load(Ordering::Acquire) < waiters.len() {
+        axtask::yield_now();
+    }
+    assert_eq!(owner.priority(), -10);
+    assert_eq!(owner.base_priority(), 0);
+
+    STEP.store(2, Ordering::Release);
+    while STEP.load(Ordering::Acquire) == 2 {
+        axtask::yield_now();
+    }
+    assert_eq!(owner.priority(), -5);
+
+    STEP.store(4, Ordering::Release);
+    assert_eq!(owner.join(), Some(0));
+    assert_eq!(owner.priority(), 0);
+
+    STEP.store(5, Ordering::Release);
+    for waiter in waiters {
+        assert_eq!(waiter.join(), Some(0));
+    }
+}
+
+#[test]
+fn test_priority_inheritance_chain() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static LOCK_A: PiLock = PiLock::new(); // held by task A
+    static LOCK_B: PiLock = PiLock::new(); // held by task B, which waits for lock A
+    const HIGH_PRIO: isize = -10;
+    static STEP: AtomicUsize = AtomicUsize::new(0);
+
+    let a = axtask::spawn(|| unsafe {
+        LOCK_A.acquired();
+        STEP.store(1, Ordering::Release);
+        while STEP.load(Ordering::Acquire) < 4 {
+            axtask::yield_now();
+        }
+        LOCK_A.released();
+    });
+    while STEP.load(Ordering::Acquire) < 1 {
+        axtask::yield_now();
+    }
+    let b = axtask::spawn(|| unsafe {
+        LOCK_B.acquired();
+        assert!(LOCK_A.begin_wait(|| true));
+        STEP.store(2, Ordering::Release);
+        while STEP.load(Ordering::Acquire) < 3 {
+            axtask::yield_now();
+        }
+        LOCK_A.end_wait();
+        LOCK_B.released();
+        STEP.store(4, Ordering::Release);
+    });
+    while STEP.load(Ordering::Acquire) < 2 {
+        axtask::yield_now();
+    }
+
+    // The current task with a high priority waits for lock B, and A inherits
+    // the priority through B.
+    axtask::set_priority_for_test(HIGH_PRIO);
+    assert!(unsafe { LOCK_B.begin_wait(|| true) });
+    assert_eq!(b.priority(), HIGH_PRIO);
+    assert_eq!(a.priority(), HIGH_PRIO);
+
+    // Raised again while waiting.
+    axtask::set_priority_for_test(HIGH_PRIO - 5);
+    assert_eq!(b.priority(), HIGH_PRIO - 5);
+    assert_eq!(a.priority(), HIGH_PRIO - 5);
+
+    // Restored along the chain when the waiting is interrupted.
+    unsafe { LOCK_B.end_wait() };
+    assert_eq!(b.priority(), 0);
+    assert_eq!(a.priority(), 0);
+    axtask::set_priority_for_test(0);
+    assert_eq!(current().priority(), 0);
+
+    // An unlocked lock is not waited for.
+    assert!(!unsafe { LOCK_B.begin_wait(|| false) });
+    assert_eq!(b.priority(), 0);
+
+    STEP.store(3, Ordering::Release);
+    assert_eq!(b.join(), Some(0));
+    assert_eq!(a.join(), Some(0));
+}
+
+#[test]
+fn test_futex() {
+    use crate::futex::{self, FutexError, FutexKey};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 6;
+    static WORD: AtomicUsize = AtomicUsize::new(0);
+    static WOKEN: AtomicUsize = AtomicUsize::new(0);
+    let key = FutexKey::Shared(&WORD as *const _ as usize);
+    // The same address in another address space is another futex.
+    let target = FutexKey::Private {
+        space: 1,
+        addr: &WORD as *const _ as usize,
+    };
+
+    assert_eq!(
+        futex::futex_wait(key, || WORD.load(Ordering::Acquire) == 1),
+        Err(FutexError::WouldBlock)
+    );
+
+    let tasks: Vec<_> = (0..NUM_TASKS)
+        .map(|_| {
+            axtask::spawn(move || {
+                futex::futex_wait(key, || WORD.load(Ordering::Acquire) == 0).unwrap();
+                WOKEN.fetch_add(1, Ordering::Relaxed);
+            })
+        })
+        .collect();
+    // Let all tasks start waiting.
+    axtask::yield_now();
+
+    WORD.store(1, Ordering::Release);
+    assert_eq!(futex::futex_wake(key, 2), 2);
+    axtask::yield_now();
+    assert_eq!(WOKEN.load(Ordering::Relaxed), 2);
+
+    // Wake one, move two to `target`, and leave one on `key`.
+    assert_eq!(futex::futex_requeue(key, 1, target, 2), (1, 2));
+    assert_eq!(futex::futex_wake(target, usize::MAX), 2);
+    assert_eq!(futex::futex_wake(key, usize::MAX), 1);
+    assert_eq!(futex::futex_wake(key, usize::MAX), 0);
+
+    for t in tasks {
+        assert_eq!(t.join(), Some(0));
+    }
+    assert_eq!(WOKEN.load(Ordering::Relaxed), NUM_TASKS);
+}
+
+#[cfg(feature = "irq")]
+mod timer {
+    use core::time::Duration;
+    use std::sync::{Arc, Mutex};
+
+    use axhal::time::TimeValue;
+
+    use super::{INIT, SERIAL};
+    use crate::api as axtask;
+    use crate::timer::{set_periodic_timer, set_timer, TimerHandle};
+    use crate::timers::expire_events;
+
+    fn nanos(ns: u64) -> TimeValue {
+        TimeValue::from_nanos(ns)
+    }
+
+    #[test]
+    fn test_oneshot_order() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(axtask::init_scheduler);
+
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let handles: Vec<_> = [300, 100, 200]
+            .into_iter()
+            .map(|deadline| {
+                let fired = fired.clone();
+                set_timer(nanos(deadline), move || {
+                    fired.lock().unwrap().push(deadline)
+                })
+            })
+            .collect();
+        assert!(handles.iter().all(TimerHandle::is_active));
+
+        expire_events(nanos(50));
+        assert!(fired.lock().unwrap().is_empty());
+        expire_events(nanos(250));
+        assert_eq!(*fired.lock().unwrap(), [100, 200]);
+        assert!(handles[0].is_active());
+        assert!(!handles[1].is_active() && !handles[2].is_active());
+
+        expire_events(nanos(1000));
+        assert_eq!(*fired.lock().unwrap(), [100, 200, 300]);
+        assert!(handles.iter().all(|h| !h.is_active()));
+        // Expired one-shot timers cannot be cancelled.
+        assert!(handles.iter().all(|h| !h.cancel()));
+    }
+
+    #[test]
+    fn test_periodic_rearm() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(axtask::init_scheduler);
+
+        let count = Arc::new(Mutex::new(0));
+        let handle = {
+            let count = count.clone();
+            set_periodic_timer(nanos(100), Duration::from_nanos(100), move || {
+                *count.lock().unwrap() += 1;
+            })
+        };
+
+        expire_events(nanos(100));
+        assert_eq!(*count.lock().unwrap(), 1);
+        assert_eq!(handle.deadline(), nanos(200));
+        expire_events(nanos(200));
+        assert_eq!(*count.lock().unwrap(), 2);
+        assert_eq!(handle.deadline(), nanos(300));
+
+        // The missed periods are skipped.
+        expire_events(nanos(550));
+        assert_eq!(*count.lock().unwrap(), 3);
+        assert_eq!(handle.deadline(), nanos(600));
+
+        assert!(handle.cancel());
+        assert!(!handle.is_active());
+        expire_events(nanos(10_000));
+        assert_eq!(*count.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_cancel_before_expiry() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(axtask::init_scheduler);
+
+        let fired = Arc::new(Mutex::new(false));
+        let handle = {
+            let fired = fired.clone();
+            set_timer(nanos(100), move || *fired.lock().unwrap() = true)
+        };
+        assert!(handle.cancel());
+        assert!(!handle.cancel());
+        expire_events(nanos(1000));
+        assert!(!*fired.lock().unwrap());
+    }
+
+    #[test]
+    fn test_cancel_during_expiry() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(axtask::init_scheduler);
+
+        static HANDLE: Mutex<Option<TimerHandle>> = Mutex::new(None);
+        let count = Arc::new(Mutex::new(0));
+        let handle = {
+            let count = count.clone();
+            set_periodic_timer(nanos(100), Duration::from_nanos(100), move || {
+                let mut count = count.lock().unwrap();
+                *count += 1;
+                if *count == 2 {
+                    // Cancelled by the callback itself, it is not re-armed.
+                    assert!(HANDLE.lock().unwrap().as_ref().unwrap().cancel());
+                }
+            })
+        };
+        *HANDLE.lock().unwrap() = Some(handle);
+
+        expire_events(nanos(100));
+        expire_events(nanos(200));
+        assert_eq!(*count.lock().unwrap(), 2);
+        let handle = HANDLE.lock().unwrap().take().unwrap();
+        assert!(!handle.is_active());
+        expire_events(nanos(10_000));
+        assert_eq!(*count.lock().unwrap(), 2);
+
+        // A one-shot timer is no longer active in its callback.
+        let handle = set_timer(nanos(100), || {
+            assert!(!HANDLE.lock().unwrap().as_ref().unwrap().cancel());
+        });
+        *HANDLE.lock().unwrap() = Some(handle);
+        expire_events(nanos(100));
+        assert!(!HANDLE.lock().unwrap().take().unwrap().is_active());
+    }
+}
+
+#[cfg(feature = "sched_rt")]
+mod sched_rt {
+    use core::time::Duration;
+
+    use scheduler::BaseScheduler;
+
+    use crate::sched_rt::{
+        DeadlineParams, RtScheduler, SchedPolicy, NICE_MIN, RR_TIME_SLICE, RT_PRIO_MAX,
+    };
+    use crate::{AxTaskRef, TaskInner};
+
+    fn new_task(name: &str, policy: SchedPolicy) -> AxTaskRef {
+        let task = TaskInner::new(|| {}, name.into(), 0x1000).into_arc();
+        task.sched_entity().lock().set_policy(policy);
+        task
+    }
+
+    fn deadline(runtime_ms: u64, deadline_ms: u64, period_ms: u64) -> SchedPolicy {
+        SchedPolicy::Deadline(DeadlineParams {
+            runtime: Duration::from_millis(runtime_ms),
+            deadline: Duration::from_millis(deadline_ms),
+            period: Duration::from_millis(period_ms),
+        })
+    }
+
+    fn new_scheduler() -> RtScheduler {
+        let mut sched = RtScheduler::new();
+        sched.init();
+        sched
+    }
+
+    #[test]
+    fn test_fifo_preemption() {
+        let mut sched = new_scheduler();
+        let low = new_task("low", SchedPolicy::Fifo(10));
+        let normal = new_task("normal", SchedPolicy::Normal);
+        sched.add_task(normal.clone());
+        sched.add_task(low.clone());
+
+        let curr = sched.pick_next_task().unwrap();
+        assert!(AxTaskRef::ptr_eq(&curr, &low));
+        // A FIFO task is never preempted by lower tasks, or by its time.
+        for _ in 0..RR_TIME_SLICE * 2 {
+            assert!(!sched.task_tick(&curr));
+        }
+        let same = new_task("same", SchedPolicy::Fifo(10));
+        sched.add_task(same.clone());
+        assert!(!sched.task_tick(&curr));
+
+        // Preempted by a higher priority task, and put back to the front.
+        let high = new_task("high", SchedPolicy::Fifo(20));
+        sched.add_task(high.clone());
+        assert!(sched.task_tick(&curr));
+        sched.put_prev_task(curr, true);
+        let order: Vec<_> = core::iter::from_fn(|| sched.pick_next_task()).collect();
+        let expected = [&high, &low, &same, &normal];
+        assert_eq!(order.len(), expected.len());
+        for (task, expected) in order.iter().zip(expected) {
+            assert!(AxTaskRef::ptr_eq(task, expected));
+        }
+    }
+
+    #[test]
+    fn test_inherited_rt_priority() {
+        let mut sched = new_scheduler();
+        let normal = new_task("normal", SchedPolicy::Normal);
+        let rt = new_task("rt", SchedPolicy::Fifo(10));
+        let low = new_task("low", SchedPolicy::Fifo(5));
+        assert_eq!(normal.priority(), 0);
+        assert_eq!(rt.priority(), NICE_MIN - 10);
+
+        // A normal task that inherits a real-time priority runs as a
+        // real-time task.
+        assert!(sched.set_priority(&normal, rt.priority()));
+        assert!(sched.set_priority(&low, rt.priority() - 1));
+        assert_eq!(normal.sched_policy(), SchedPolicy::Normal);
+        sched.add_task(rt.clone());
+        sched.add_task(normal.clone());
+        sched.add_task(low.clone());
+        let order: Vec<_> = core::iter::from_fn(|| sched.pick_next_task()).collect();
+        let expected = [&low, &rt, &normal];
+        assert_eq!(order.len(), expected.len());
+        for (task, expected) in order.iter().zip(expected) {
+            assert!(AxTaskRef::ptr_eq(task, expected));
+        }
+
+        // Back to the normal class when restored.
+        assert!(sched.set_priority(&normal, 0));
+        sched.add_task(rt.clone());
+        sched.add_task(normal.clone());
+        assert!(sched.remove_task(&normal).is_some());
+        let next = sched.pick_next_task().unwrap();
+        assert!(AxTaskRef::ptr_eq(&next, &rt));
+        assert!(sched.pick_next_task().is_none());
+    }
+
+    #[test]
+    fn test_rr_time_slice() {
+        let mut sched = new_scheduler();
+        let tasks: Vec<_> = (0..3)
+            .map(|i| new_task(&format!("rr{}", i), SchedPolicy::RoundRobin(10)))
+            .collect();
+        for task in &tasks {
+            sched.add_task(task.clone());
+        }
+
+        for round in 0..2 {
+            for expected in &tasks {
+                let curr = sched.pick_next_task().unwrap();
+                assert!(AxTaskRef::ptr_eq(&curr, expected), "round {}", round);
+                for _ in 1..RR_TIME_SLICE {
+                    assert!(!sched.task_tick(&curr));
+                }
+                // The time slice is used up, put to the end of the queue.
+                assert!(sched.task_tick(&curr));
+                sched.put_prev_task(curr, true);
+            }
+        }
+
+        // A task preempted before its time slice is used up keeps its place.
+        let curr = sched.pick_next_task().unwrap();
+        assert!(!sched.task_tick(&curr));
+        let high = new_task("high", SchedPolicy::Fifo(20));
+        sched.add_task(high.clone());
+        assert!(sched.task_tick(&curr));
+        sched.put_prev_task(curr, true);
+        assert!(AxTaskRef::ptr_eq(&sched.pick_next_task().unwrap(), &high));
+        assert!(AxTaskRef::ptr_eq(
+            &sched.pick_next_task().unwrap(),
+            &tasks[0]
+        ));
+    }
+
+    #[test]
+    fn test_edf_order() {
+        let mut sched = new_scheduler();
+        let rt = new_task("rt", SchedPolicy::Fifo(RT_PRIO_MAX));
+        let late = new_task("late", deadline(10, 300, 1000));
+        let early = new_task("early", deadline(10, 100, 1000));
+        let middle = new_task("middle", deadline(10, 200, 1000));
+        sched.add_task(rt.clone());
+        for task in [&late, &early, &middle] {
+            sched.add_task((*task).clone());
+        }
+
+        // Deadline tasks run before real-time tasks, earliest deadline first.
+        let curr = sched.pick_next_task().unwrap();
+        assert!(AxTaskRef::ptr_eq(&curr, &early));
+        assert!(!sched.task_tick(&curr));
+        // Yielding means the work of this period is done, the task is
+        // throttled until the next period.
+        sched.put_prev_task(curr, false);
+        for expected in [&middle, &late, &rt] {
+            let curr = sched.pick_next_task().unwrap();
+            assert!(AxTaskRef::ptr_eq(&curr, expected));
+            sched.put_prev_task(curr, false);
+        }
+        assert!(AxTaskRef::ptr_eq(&sched.pick_next_task().unwrap(), &rt));
+    }
+}
+
+#[cfg(feature = "lockdep")]
+#[test]
+fn test_lockdep_inverse_order() {
+    use crate::lockdep::{self, LockKind, LockdepMap, LockdepReport};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static LOCK_A: LockdepMap = LockdepMap::new("A", LockKind::Sleep);
+    static LOCK_B: LockdepMap = LockdepMap::new("B", LockKind::Sleep);
+
+    // Record the dependency A -> B.
+    LOCK_A.acquire();
+    LOCK_B.acquire();
+    LOCK_B.release();
+    LOCK_A.release();
+    assert_eq!(lockdep::first_report(), None);
+
+    // Acquiring them in the inverse order is reported without panicking.
+    LOCK_B.acquire();
+    LOCK_A.acquire();
+    LOCK_A.release();
+    LOCK_B.release();
+    assert_eq!(
+        lockdep::first_report(),
+        Some(LockdepReport::CircularDependency)
+    );
+}
@@ -0,0 +1,141 @@
+//! High-resolution timers that run callbacks at given deadlines.
+//!
+//! A one-shot timer is set by [`set_timer`], and a periodic timer is set by
+//! [`set_periodic_timer`]. Both return a [`TimerHandle`] which can cancel the
+//! timer. The deadlines are measured by the wall clock
+//! ([`axhal::time::wall_time`]) in nanoseconds. The hardware timer is
+//! programmed for the earliest deadline, so a timer expires on time instead of
+//! at the next periodic tick.
+//!
+//! The callbacks run in the timer interrupt handler after the timer list is
+//! unlocked, similar to the softirq context of Linux. A callback may wake up
+//! tasks, lock [`SpinNoIrq`] spinlocks, and set or cancel timers, but it must
+//! not block.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use axhal::time::TimeValue;
+use kspin::SpinNoIrq;
+
+type TimerCallback = Box<dyn FnMut() + Send>;
+
+pub(crate) struct Timer {
+    callback: SpinNoIrq<TimerCallback>,
+    /// The period in nanoseconds, or 0 for one-shot timers.
+    period: u64,
+    /// The deadline of the next expiration in nanoseconds.
+    deadline: AtomicU64,
+    /// Whether the timer is neither cancelled nor expired (if it is one-shot).
+    active: AtomicBool,
+}
+
+impl Timer {
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn deactivate(&self) -> bool {
+        self.active.swap(false, Ordering::AcqRel)
+    }
+
+    pub(crate) fn set_deadline(&self, deadline: TimeValue) {
+        self.deadline
+            .store(deadline.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Runs the callback when the timer expires, and sets the next expiration
+    /// if it is periodic.
+    pub(crate) fn fire(self: Arc<Self>, now: TimeValue) {
+        if self.period == 0 {
+            if self.deactivate() {
+                (self.callback.lock())();
+            }
+            return;
+        }
+        if !self.is_active() {
+            return;
+        }
+        (self.callback.lock())();
+
+        // Skip the periods that have been missed.
+        let now_ns = now.as_nanos() as u64;
+        let mut next = self.deadline.load(Ordering::Relaxed) + self.period;
+        if next <= now_ns {
+            next += (now_ns - next) / self.period * self.period + self.period;
+        }
+        crate::timers::set_timer_event(TimeValue::from_nanos(next), self);
+    }
+}
+
+/// A handle to a timer set by [`set_timer`] or [`set_periodic_timer`].
+///
+/// Dropping the handle does not cancel the timer.
+pub struct TimerHandle {
+    timer: Arc<Timer>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer.
+    ///
+    /// Returns `true` if the timer was active, i.e., it is periodic or it has
+    /// not expired. Note that it does not wait for the callback if the callback
+    /// is running on another CPU.
+    pub fn cancel(&self) -> bool {
+        crate::timers::cancel_timer_event(&self.timer)
+    }
+
+    /// Returns `true` if the timer has been neither cancelled nor expired (if
+    /// it is one-shot).
+    pub fn is_active(&self) -> bool {
+        self.timer.is_active()
+    }
+
+    /// Returns the deadline of the next expiration, or the last one if the
+    /// timer is not active.
+    pub fn deadline(&self) -> TimeValue {
+        TimeValue::from_nanos(self.timer.deadline.load(Ordering::Relaxed))
+    }
+}
+
+fn new_timer(period: u64, callback: TimerCallback) -> Arc<Timer> {
+    Arc::new(Timer {
+        callback: SpinNoIrq::new(callback),
+        period,
+        deadline: AtomicU64::new(0),
+        active: AtomicBool::new(true),
+    })
+}
+
+/// Sets a one-shot timer, which calls `callback` once at the `deadline`.
+///
+/// The callback is called as soon as possible if the deadline has passed.
+pub fn set_timer<F>(deadline: TimeValue, callback: F) -> TimerHandle
+where
+    F: FnMut() + Send + 'static,
+{
+    let timer = new_timer(0, Box::new(callback));
+    crate::timers::set_timer_event(deadline, timer.clone());
+    TimerHandle { timer }
+}
+
+/// Sets a periodic timer, which calls `callback` at the `deadline` and then
+/// every `period`, until it is cancelled.
+///
+/// If the callback is delayed by more than one period, the missed calls are
+/// skipped.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn set_periodic_timer<F>(deadline: TimeValue, period: Duration, callback: F) -> TimerHandle
+where
+    F: FnMut() + Send + 'static,
+{
+    assert!(!period.is_zero(), "timer period must not be zero");
+    let timer = new_timer(period.as_nanos() as u64, Box::new(callback));
+    crate::timers::set_timer_event(deadline, timer.clone());
+    TimerHandle { timer }
+}
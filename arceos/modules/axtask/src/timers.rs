@@ -1,39 +1,153 @@
 use alloc::sync::Arc;
-use axhal::time::wall_time;
+
+use axhal::time::{epochoffset_nanos, wall_time};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use timer_list::{TimeValue, TimerEvent, TimerList};
 
-use crate::{AxTaskRef, RUN_QUEUE};
+use crate::run_queue::unblock_task;
+use crate::timer::Timer;
+use crate::AxTaskRef;
 
 // TODO: per-CPU
-static TIMER_LIST: LazyInit<SpinNoIrq<TimerList<TaskWakeupEvent>>> = LazyInit::new();
+static TIMER_LIST: LazyInit<SpinNoIrq<TimerList<WakeupEvent>>> = LazyInit::new();
+
+/// The deadline that the hardware timer of the current CPU is programmed for,
+/// in monotonic nanoseconds.
+#[percpu::def_percpu]
+static PROGRAMMED_DEADLINE: u64 = u64::MAX;
+
+/// The interval of the periodic tick, in nanoseconds.
+#[cfg(feature = "nohz")]
+const TICK_INTERVAL_NANOS: u64 = axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+
+/// The maximum time that an idle CPU sleeps without the tick, in nanoseconds.
+#[cfg(feature = "nohz")]
+const NOHZ_MAX_IDLE_NANOS: u64 = axhal::time::NANOS_PER_SEC;
+
+enum WakeupEvent {
+    Task(AxTaskRef),
+    Timer(Arc<Timer>),
+}
+
+impl TimerEvent for WakeupEvent {
+    fn callback(self, now: TimeValue) {
+        match self {
+            Self::Task(task) => {
+                task.set_in_timer_list(false);
+                unblock_task(task, true);
+            }
+            Self::Timer(timer) => timer.fire(now),
+        }
+    }
+}
 
-struct TaskWakeupEvent(AxTaskRef);
+fn to_monotonic_nanos(deadline: TimeValue) -> u64 {
+    (deadline.as_nanos() as u64).saturating_sub(epochoffset_nanos())
+}
 
-impl TimerEvent for TaskWakeupEvent {
-    fn callback(self, _now: TimeValue) {
-        let mut rq = RUN_QUEUE.lock();
-        self.0.set_in_timer_list(false);
-        rq.unblock_task(self.0, true);
+/// Programs the hardware timer of the current CPU for the new event, if it
+/// expires before the programmed deadline.
+///
+/// It must be called with IRQs disabled, e.g., with the timer list locked.
+fn program_earlier(deadline_ns: u64) {
+    // Safety: IRQs and preemption are disabled by the caller.
+    unsafe {
+        if deadline_ns < PROGRAMMED_DEADLINE.read_current_raw() {
+            PROGRAMMED_DEADLINE.write_current_raw(deadline_ns);
+            axhal::time::set_oneshot_timer(deadline_ns);
+        }
     }
 }
 
+/// Programs the hardware timer of the current CPU for the next tick or the
+/// first timer event, whichever is earlier.
+///
+/// If the `nohz` feature is enabled and the current CPU is idle, the tick is
+/// stopped, and the CPU sleeps until the next timer event or the release of a
+/// throttled task.
+pub fn program_next_event(tick_deadline_ns: u64) {
+    #[cfg(feature = "nohz")]
+    let tick_deadline_ns = if crate::current().is_idle() {
+        nohz_idle_deadline()
+    } else {
+        tick_deadline_ns
+    };
+    let timers = TIMER_LIST.lock();
+    let deadline_ns = match timers.next_deadline() {
+        Some(deadline) => tick_deadline_ns.min(to_monotonic_nanos(deadline)),
+        None => tick_deadline_ns,
+    };
+    // Safety: IRQs and preemption are disabled by the timer list lock.
+    unsafe { PROGRAMMED_DEADLINE.write_current_raw(deadline_ns) };
+    axhal::time::set_oneshot_timer(deadline_ns);
+}
+
 pub fn set_alarm_wakeup(deadline: TimeValue, task: AxTaskRef) {
     let mut timers = TIMER_LIST.lock();
     task.set_in_timer_list(true);
-    timers.set(deadline, TaskWakeupEvent(task));
+    timers.set(deadline, WakeupEvent::Task(task));
+    program_earlier(to_monotonic_nanos(deadline));
+}
+
+/// Returns when an idle CPU has to wake up without the periodic tick, in
+/// monotonic nanoseconds. The timer events are not taken into account.
+///
+/// The CPU wakes up when a throttled task of the scheduler is released. It
+/// also wakes up periodically, since the range of the hardware timers is
+/// limited. Other CPUs that put tasks into its run queue wake it up by IPIs.
+#[cfg(feature = "nohz")]
+fn nohz_idle_deadline() -> u64 {
+    let deadline = axhal::time::monotonic_time_nanos() + NOHZ_MAX_IDLE_NANOS;
+    match crate::run_queue::current_run_queue().scheduler_next_release() {
+        Some(release) => deadline.min(release),
+        None => deadline,
+    }
+}
+
+/// Restarts the periodic tick of the current CPU, which may have been stopped
+/// when the CPU was idle.
+#[cfg(feature = "nohz")]
+pub fn restart_tick() {
+    let _timers = TIMER_LIST.lock();
+    program_earlier(axhal::time::monotonic_time_nanos() + TICK_INTERVAL_NANOS);
+}
+
+/// Adds the timer to the timer list if it is still active, returns whether
+/// it is added.
+pub fn set_timer_event(deadline: TimeValue, timer: Arc<Timer>) -> bool {
+    let mut timers = TIMER_LIST.lock();
+    if !timer.is_active() {
+        return false;
+    }
+    timer.set_deadline(deadline);
+    timers.set(deadline, WakeupEvent::Timer(timer));
+    program_earlier(to_monotonic_nanos(deadline));
+    true
+}
+
+/// Deactivates the timer and removes it from the timer list, returns whether
+/// it was active.
+pub fn cancel_timer_event(timer: &Arc<Timer>) -> bool {
+    let mut timers = TIMER_LIST.lock();
+    let active = timer.deactivate();
+    timers.cancel(|e| matches!(e, WakeupEvent::Timer(t) if Arc::ptr_eq(t, timer)));
+    active
 }
 
 pub fn cancel_alarm(task: &AxTaskRef) {
     let mut timers = TIMER_LIST.lock();
     task.set_in_timer_list(false);
-    timers.cancel(|t| Arc::ptr_eq(&t.0, task));
+    timers.cancel(|e| matches!(e, WakeupEvent::Task(t) if Arc::ptr_eq(t, task)));
 }
 
 pub fn check_events() {
+    expire_events(wall_time());
+}
+
+/// Runs the callbacks of all the events that expire at `now`.
+pub(crate) fn expire_events(now: TimeValue) {
     loop {
-        let now = wall_time();
         let event = TIMER_LIST.lock().expire_one(now);
         if let Some((_deadline, event)) = event {
             event.callback(now);
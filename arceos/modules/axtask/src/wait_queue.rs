@@ -1,8 +1,9 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
-use kspin::SpinRaw;
+use kspin::SpinNoIrq;
 
-use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
+use crate::run_queue::unblock_task;
+use crate::{current_run_queue, AxTaskRef, CurrentTask, Interrupted};
 
 /// A queue to store sleeping tasks.
 ///
@@ -27,21 +28,21 @@ use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
 /// assert_eq!(VALUE.load(Ordering::Relaxed), 1);
 /// ```
 pub struct WaitQueue {
-    queue: SpinRaw<VecDeque<AxTaskRef>>, // we already disabled IRQs when lock the `RUN_QUEUE`
+    queue: SpinNoIrq<VecDeque<AxTaskRef>>,
 }
 
 impl WaitQueue {
     /// Creates an empty wait queue.
     pub const fn new() -> Self {
         Self {
-            queue: SpinRaw::new(VecDeque::new()),
+            queue: SpinNoIrq::new(VecDeque::new()),
         }
     }
 
     /// Creates an empty wait queue with space for at least `capacity` elements.
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            queue: SpinRaw::new(VecDeque::with_capacity(capacity)),
+            queue: SpinNoIrq::new(VecDeque::with_capacity(capacity)),
         }
     }
 
@@ -50,8 +51,6 @@ impl WaitQueue {
         // the event from another queue.
         if curr.in_wait_queue() {
             // wake up by timer (timeout).
-            // `RUN_QUEUE` is not locked here, so disable IRQs.
-            let _guard = kernel_guard::IrqSave::new();
             self.queue.lock().retain(|t| !curr.ptr_eq(t));
             curr.set_in_wait_queue(false);
         }
@@ -63,13 +62,45 @@ impl WaitQueue {
     }
 
     /// Blocks the current task and put it into the wait queue, until other task
-    /// notifies it.
+    /// notifies it, or the current task is killed.
     pub fn wait(&self) {
-        RUN_QUEUE.lock().block_current(|task| {
+        let curr = crate::current();
+        curr.set_killable(true);
+        let mut rq = current_run_queue();
+        let mut wq = self.queue.lock();
+        rq.block_current(move |task| {
+            task.set_in_wait_queue(true);
+            wq.push_back(task);
+        });
+        drop(rq);
+        curr.set_killable(false);
+        self.cancel_events(curr);
+    }
+
+    /// Blocks the current task and put it into the wait queue, until other task
+    /// notifies it, or the current task is interrupted.
+    ///
+    /// Returns [`Interrupted`] if the current task is interrupted or killed
+    /// before it is notified.
+    pub fn wait_interruptible(&self) -> Result<(), Interrupted> {
+        let curr = crate::current();
+        curr.set_interruptible(true);
+        let mut rq = current_run_queue();
+        let mut wq = self.queue.lock();
+        rq.block_current(move |task| {
             task.set_in_wait_queue(true);
-            self.queue.lock().push_back(task)
+            wq.push_back(task);
         });
-        self.cancel_events(crate::current());
+        drop(rq);
+        curr.set_interruptible(false);
+        let notified = !curr.in_wait_queue();
+        let interrupted = curr.check_interrupted();
+        self.cancel_events(curr);
+        if notified || !interrupted {
+            Ok(())
+        } else {
+            Err(Interrupted)
+        }
     }
 
     /// Blocks the current task and put it into the wait queue, until the given
@@ -81,21 +112,89 @@ impl WaitQueue {
     where
         F: Fn() -> bool,
     {
+        let curr = crate::current();
         loop {
-            let mut rq = RUN_QUEUE.lock();
+            // Check the condition with the wait queue locked, so that we will
+            // not miss any notification between checking and blocking.
+            let mut rq = current_run_queue();
+            let mut wq = self.queue.lock();
             if condition() {
                 break;
             }
-            rq.block_current(|task| {
+            rq.block_current(move |task| {
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                wq.push_back(task);
             });
         }
-        self.cancel_events(crate::current());
+        self.cancel_events(curr);
+    }
+
+    /// Blocks the current task and put it into the wait queue, until the given
+    /// `condition` becomes true, or the current task is killed.
+    ///
+    /// Returns [`Interrupted`] if the current task is killed before the
+    /// condition becomes true. Unlike [`WaitQueue::wait_until_interruptible`],
+    /// the interrupts of the task are ignored.
+    pub fn wait_until_killable<F>(&self, condition: F) -> Result<(), Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        let curr = crate::current();
+        curr.set_killable(true);
+        let res = loop {
+            let mut rq = current_run_queue();
+            let mut wq = self.queue.lock();
+            if condition() {
+                break Ok(());
+            }
+            if curr.is_killed() {
+                break Err(Interrupted);
+            }
+            rq.block_current(move |task| {
+                task.set_in_wait_queue(true);
+                wq.push_back(task);
+            });
+        };
+        curr.set_killable(false);
+        self.cancel_events(curr);
+        res
+    }
+
+    /// Blocks the current task and put it into the wait queue, until the given
+    /// `condition` becomes true, or the current task is interrupted.
+    ///
+    /// Returns [`Interrupted`] if the current task is interrupted or killed
+    /// before the condition becomes true.
+    pub fn wait_until_interruptible<F>(&self, condition: F) -> Result<(), Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        let curr = crate::current();
+        curr.set_interruptible(true);
+        let res = loop {
+            let mut rq = current_run_queue();
+            let mut wq = self.queue.lock();
+            if condition() {
+                break Ok(());
+            }
+            if curr.check_interrupted() {
+                break Err(Interrupted);
+            }
+            rq.block_current(move |task| {
+                task.set_in_wait_queue(true);
+                wq.push_back(task);
+            });
+        };
+        curr.set_interruptible(false);
+        self.cancel_events(curr);
+        res
     }
 
     /// Blocks the current task and put it into the wait queue, until other tasks
     /// notify it, or the given duration has elapsed.
+    ///
+    /// It also returns early if the current task is killed. Returns `true` if
+    /// the task is not notified.
     #[cfg(feature = "irq")]
     pub fn wait_timeout(&self, dur: core::time::Duration) -> bool {
         let curr = crate::current();
@@ -105,13 +204,18 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        RUN_QUEUE.lock().block_current(|task| {
+        curr.set_killable(true);
+        let mut rq = current_run_queue();
+        let mut wq = self.queue.lock();
+        rq.block_current(move |task| {
             task.set_in_wait_queue(true);
-            self.queue.lock().push_back(task)
+            crate::timers::set_alarm_wakeup(deadline, task.clone());
+            wq.push_back(task);
         });
-        let timeout = curr.in_wait_queue(); // still in the wait queue, must have timed out
+        drop(rq);
+        curr.set_killable(false);
+        let timeout = curr.in_wait_queue(); // still in the wait queue, timed out or killed
         self.cancel_events(curr);
         timeout
     }
@@ -133,32 +237,87 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
         let mut timeout = true;
         while axhal::time::wall_time() < deadline {
-            let mut rq = RUN_QUEUE.lock();
+            let mut rq = current_run_queue();
+            let mut wq = self.queue.lock();
             if condition() {
                 timeout = false;
                 break;
             }
-            rq.block_current(|task| {
+            rq.block_current(move |task| {
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                if !task.in_timer_list() {
+                    crate::timers::set_alarm_wakeup(deadline, task.clone());
+                }
+                wq.push_back(task);
             });
         }
         self.cancel_events(curr);
         timeout
     }
 
+    /// Blocks the current task and put it into the wait queue, until the given
+    /// `condition` becomes true, the given duration has elapsed, or the
+    /// current task is interrupted.
+    ///
+    /// Returns whether the duration has elapsed before the condition becomes
+    /// true, or [`Interrupted`] if the current task is interrupted or killed
+    /// before that.
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout_until_interruptible<F>(
+        &self,
+        dur: core::time::Duration,
+        condition: F,
+    ) -> Result<bool, Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        let curr = crate::current();
+        let deadline = axhal::time::wall_time() + dur;
+        debug!(
+            "task wait_timeout: {}, deadline={:?}",
+            curr.id_name(),
+            deadline
+        );
+
+        curr.set_interruptible(true);
+        let res = loop {
+            let mut rq = current_run_queue();
+            let mut wq = self.queue.lock();
+            if condition() {
+                break Ok(false);
+            }
+            if axhal::time::wall_time() >= deadline {
+                break Ok(true);
+            }
+            if curr.check_interrupted() {
+                break Err(Interrupted);
+            }
+            rq.block_current(move |task| {
+                task.set_in_wait_queue(true);
+                if !task.in_timer_list() {
+                    crate::timers::set_alarm_wakeup(deadline, task.clone());
+                }
+                wq.push_back(task);
+            });
+        };
+        curr.set_interruptible(false);
+        self.cancel_events(curr);
+        res
+    }
+
     /// Wakes up one task in the wait queue, usually the first one.
     ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_one(&self, resched: bool) -> bool {
-        let mut rq = RUN_QUEUE.lock();
-        if !self.queue.lock().is_empty() {
-            self.notify_one_locked(resched, &mut rq)
+        let mut wq = self.queue.lock();
+        if let Some(task) = wq.pop_front() {
+            task.set_in_wait_queue(false);
+            unblock_task(task, resched);
+            true
         } else {
             false
         }
@@ -169,15 +328,10 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_all(&self, resched: bool) {
-        loop {
-            let mut rq = RUN_QUEUE.lock();
-            if let Some(task) = self.queue.lock().pop_front() {
-                task.set_in_wait_queue(false);
-                rq.unblock_task(task, resched);
-            } else {
-                break;
-            }
-            drop(rq); // we must unlock `RUN_QUEUE` after unlocking `self.queue`.
+        let mut wq = self.queue.lock();
+        while let Some(task) = wq.pop_front() {
+            task.set_in_wait_queue(false);
+            unblock_task(task, resched);
         }
     }
 
@@ -185,32 +339,14 @@ impl WaitQueue {
     ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
-    pub fn notify_task(&mut self, resched: bool, task: &AxTaskRef) -> bool {
-        let mut rq = RUN_QUEUE.lock();
+    pub fn notify_task(&self, resched: bool, task: &AxTaskRef) -> bool {
         let mut wq = self.queue.lock();
         if let Some(index) = wq.iter().position(|t| Arc::ptr_eq(t, task)) {
             task.set_in_wait_queue(false);
-            rq.unblock_task(wq.remove(index).unwrap(), resched);
+            unblock_task(wq.remove(index).unwrap(), resched);
             true
         } else {
             false
         }
     }
-
-    pub(crate) fn notify_one_locked(&self, resched: bool, rq: &mut AxRunQueue) -> bool {
-        if let Some(task) = self.queue.lock().pop_front() {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(task, resched);
-            true
-        } else {
-            false
-        }
-    }
-
-    pub(crate) fn notify_all_locked(&self, resched: bool, rq: &mut AxRunQueue) {
-        while let Some(task) = self.queue.lock().pop_front() {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(task, resched);
-        }
-    }
 }
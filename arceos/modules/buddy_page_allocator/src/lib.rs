@@ -0,0 +1,293 @@
+//! A buddy page allocator, which allocates physically contiguous pages with
+//! large alignments, e.g., 2M and 1G huge pages, efficiently.
+//!
+//! The free pages are kept in blocks of `1 << order` pages, which are aligned
+//! to their sizes. There is a free list of the blocks for each order, up to
+//! [`MAX_ORDER`], so an aligned block is found by popping the free list of
+//! the smallest order that is large enough, and splitting it into halves
+//! (buddies). A freed block is merged with its buddy if the buddy is also
+//! free.
+//!
+//! An allocation of `n` pages that is not a power of two takes the first `n`
+//! pages of the block, and the rest pages are freed at once, so no page is
+//! wasted. The pages can be freed by any sub-ranges.
+//!
+//! The free lists are linked through the free pages themselves, and one byte
+//! per page is taken from the start of each region to record the heads of
+//! the free blocks. So the memory must be accessible, and there is no limit
+//! on the number of pages.
+
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(test)]
+mod tests;
+
+use allocator::{AllocError, AllocResult, BaseAllocator, PageAllocator};
+use core::ptr;
+
+/// The maximum order of the blocks, a block of order `k` has `1 << k` pages.
+pub const MAX_ORDER: usize = 20;
+
+const NUM_ORDERS: usize = MAX_ORDER + 1;
+
+/// The maximum number of memory regions that can be added.
+const MAX_REGIONS: usize = 16;
+
+/// The metadata of the pages which are not the heads of free blocks. The
+/// heads of free blocks record their orders.
+const NOT_FREE_HEAD: u8 = u8::MAX;
+
+/// The links at the start of a free block.
+struct FreeBlock {
+    next: *mut FreeBlock,
+    prev: *mut FreeBlock,
+}
+
+/// A memory region managed by the [`BuddyPageAllocator`].
+#[derive(Clone, Copy)]
+struct Region {
+    /// The start of the managed pages, after the metadata.
+    start: usize,
+    end: usize,
+    /// One byte for each managed page.
+    meta: *mut u8,
+}
+
+impl Region {
+    const EMPTY: Self = Self {
+        start: 0,
+        end: 0,
+        meta: ptr::null_mut(),
+    };
+
+    fn contains(&self, pos: usize, size: usize) -> bool {
+        self.start <= pos && pos.saturating_add(size) <= self.end
+    }
+}
+
+const fn block_size<const PAGE_SIZE: usize>(order: usize) -> usize {
+    PAGE_SIZE << order
+}
+
+/// A buddy page allocator.
+///
+/// See the [crate-level documentation](crate) for the details.
+pub struct BuddyPageAllocator<const PAGE_SIZE: usize> {
+    free_lists: [*mut FreeBlock; NUM_ORDERS],
+    regions: [Region; MAX_REGIONS],
+    num_regions: usize,
+    total_pages: usize,
+    used_pages: usize,
+}
+
+unsafe impl<const PAGE_SIZE: usize> Send for BuddyPageAllocator<PAGE_SIZE> {}
+
+impl<const PAGE_SIZE: usize> BuddyPageAllocator<PAGE_SIZE> {
+    /// Creates an empty [`BuddyPageAllocator`].
+    pub const fn new() -> Self {
+        Self {
+            free_lists: [ptr::null_mut(); NUM_ORDERS],
+            regions: [Region::EMPTY; MAX_REGIONS],
+            num_regions: 0,
+            total_pages: 0,
+            used_pages: 0,
+        }
+    }
+
+    /// Returns the number of pages of the largest free block.
+    pub fn max_free_pages(&self) -> usize {
+        match self.free_lists.iter().rposition(|list| !list.is_null()) {
+            Some(order) => 1 << order,
+            None => 0,
+        }
+    }
+
+    fn regions(&self) -> &[Region] {
+        &self.regions[..self.num_regions]
+    }
+
+    fn region_of(&self, pos: usize, size: usize) -> Option<Region> {
+        self.regions()
+            .iter()
+            .find(|r| r.contains(pos, size))
+            .copied()
+    }
+
+    /// Returns the metadata of the page in the region.
+    ///
+    /// # Safety
+    ///
+    /// The page must be in the region.
+    unsafe fn meta(region: &Region, pos: usize) -> *mut u8 {
+        region.meta.add((pos - region.start) / PAGE_SIZE)
+    }
+
+    /// Puts the free block into the free list.
+    unsafe fn push(&mut self, region: &Region, pos: usize, order: usize) {
+        let block = pos as *mut FreeBlock;
+        let head = self.free_lists[order];
+        (*block).next = head;
+        (*block).prev = ptr::null_mut();
+        if !head.is_null() {
+            (*head).prev = block;
+        }
+        self.free_lists[order] = block;
+        *Self::meta(region, pos) = order as u8;
+    }
+
+    /// Removes the free block from the free list.
+    unsafe fn remove(&mut self, region: &Region, pos: usize, order: usize) {
+        let block = pos as *mut FreeBlock;
+        let (prev, next) = ((*block).prev, (*block).next);
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        if prev.is_null() {
+            self.free_lists[order] = next;
+        } else {
+            (*prev).next = next;
+        }
+        *Self::meta(region, pos) = NOT_FREE_HEAD;
+    }
+
+    /// Frees a block, and merges it with its buddies.
+    unsafe fn free_block(&mut self, region: &Region, mut pos: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let size = block_size::<PAGE_SIZE>(order);
+            let buddy = pos ^ size;
+            if !region.contains(buddy, size) || *Self::meta(region, buddy) != order as u8 {
+                break;
+            }
+            self.remove(region, buddy, order);
+            pos = pos.min(buddy);
+            order += 1;
+        }
+        self.push(region, pos, order);
+    }
+
+    /// Frees the pages in `[start, end)`, which are split into aligned blocks.
+    unsafe fn free_range(&mut self, region: &Region, mut start: usize, end: usize) {
+        while start < end {
+            let num_pages = (end - start) / PAGE_SIZE;
+            let order = ((start / PAGE_SIZE).trailing_zeros() as usize)
+                .min(num_pages.ilog2() as usize)
+                .min(MAX_ORDER);
+            self.free_block(region, start, order);
+            start += block_size::<PAGE_SIZE>(order);
+        }
+    }
+}
+
+impl<const PAGE_SIZE: usize> Default for BuddyPageAllocator<PAGE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGE_SIZE: usize> BaseAllocator for BuddyPageAllocator<PAGE_SIZE> {
+    fn init(&mut self, start: usize, size: usize) {
+        *self = Self::new();
+        self.add_memory(start, size).unwrap();
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        let end = start.checked_add(size).ok_or(AllocError::InvalidParam)?;
+        let start = start
+            .checked_next_multiple_of(PAGE_SIZE)
+            .ok_or(AllocError::InvalidParam)?;
+        let end = end / PAGE_SIZE * PAGE_SIZE;
+        if end <= start {
+            return Err(AllocError::InvalidParam);
+        }
+        if self
+            .regions()
+            .iter()
+            .any(|r| start < r.end && (r.meta as usize) < end)
+        {
+            return Err(AllocError::MemoryOverlap);
+        }
+        if self.num_regions == MAX_REGIONS {
+            return Err(AllocError::NoMemory);
+        }
+        // `n` pages need `n / (PAGE_SIZE + 1)` pages (rounded up) of metadata.
+        let num_pages = (end - start) / PAGE_SIZE;
+        let meta_pages = num_pages.div_ceil(PAGE_SIZE + 1);
+        if meta_pages >= num_pages {
+            return Err(AllocError::InvalidParam);
+        }
+        let region = Region {
+            start: start + meta_pages * PAGE_SIZE,
+            end,
+            meta: start as *mut u8,
+        };
+        unsafe {
+            ptr::write_bytes(region.meta, NOT_FREE_HEAD, num_pages - meta_pages);
+            self.free_range(&region, region.start, region.end);
+        }
+        self.regions[self.num_regions] = region;
+        self.num_regions += 1;
+        self.total_pages += num_pages - meta_pages;
+        Ok(())
+    }
+}
+
+impl<const PAGE_SIZE: usize> PageAllocator for BuddyPageAllocator<PAGE_SIZE> {
+    const PAGE_SIZE: usize = PAGE_SIZE;
+
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if num_pages == 0 || !align_pow2.is_power_of_two() || align_pow2 % PAGE_SIZE != 0 {
+            return Err(AllocError::InvalidParam);
+        }
+        let size_order = num_pages
+            .checked_next_power_of_two()
+            .ok_or(AllocError::NoMemory)?
+            .trailing_zeros() as usize;
+        let align_order = (align_pow2 / PAGE_SIZE).trailing_zeros() as usize;
+        let order = size_order.max(align_order);
+        if order > MAX_ORDER {
+            return Err(AllocError::NoMemory);
+        }
+        let mut found = (order..NUM_ORDERS)
+            .find(|&o| !self.free_lists[o].is_null())
+            .ok_or(AllocError::NoMemory)?;
+        let pos = self.free_lists[found] as usize;
+        let region = self.region_of(pos, PAGE_SIZE).unwrap();
+        unsafe {
+            self.remove(&region, pos, found);
+            while found > order {
+                found -= 1;
+                self.push(&region, pos + block_size::<PAGE_SIZE>(found), found);
+            }
+            let end = pos + block_size::<PAGE_SIZE>(order);
+            self.free_range(&region, pos + num_pages * PAGE_SIZE, end);
+        }
+        self.used_pages += num_pages;
+        Ok(pos)
+    }
+
+    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        let Some(size) = num_pages.checked_mul(PAGE_SIZE) else {
+            return;
+        };
+        let Some(region) = self.region_of(pos, size) else {
+            return;
+        };
+        if pos % PAGE_SIZE != 0 {
+            return;
+        }
+        unsafe { self.free_range(&region, pos, pos + size) };
+        self.used_pages -= num_pages;
+    }
+
+    fn total_pages(&self) -> usize {
+        self.total_pages
+    }
+
+    fn used_pages(&self) -> usize {
+        self.used_pages
+    }
+
+    fn available_pages(&self) -> usize {
+        self.total_pages - self.used_pages
+    }
+}
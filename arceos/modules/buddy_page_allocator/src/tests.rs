@@ -0,0 +1,215 @@
+use std::alloc::Layout;
+
+use allocator::{AllocError, BaseAllocator, PageAllocator};
+
+use crate::BuddyPageAllocator;
+
+const PAGE_SIZE: usize = 0x1000;
+const HUGE_PAGE_SIZE: usize = 0x20_0000; // 2M
+
+/// The memory for the allocator, which must be accessible.
+struct Memory {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Memory {
+    fn new(size: usize, align: usize) -> Self {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { std::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+        Self { ptr, layout }
+    }
+
+    fn start(&self) -> usize {
+        self.ptr as usize
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// Creates an allocator of 8M memory aligned to 8M, 1 page is taken by the
+/// metadata.
+fn new_allocator() -> (BuddyPageAllocator<PAGE_SIZE>, Memory) {
+    let mem = Memory::new(4 * HUGE_PAGE_SIZE, 4 * HUGE_PAGE_SIZE);
+    let mut alloc = BuddyPageAllocator::new();
+    alloc.init(mem.start(), 4 * HUGE_PAGE_SIZE);
+    (alloc, mem)
+}
+
+#[test]
+fn test_alloc_and_merge() {
+    let (mut alloc, mem) = new_allocator();
+    let total = 4 * HUGE_PAGE_SIZE / PAGE_SIZE - 1;
+    assert_eq!(alloc.total_pages(), total);
+    assert_eq!(alloc.max_free_pages(), 1024);
+
+    let a = alloc.alloc_pages(1, PAGE_SIZE).unwrap();
+    let b = alloc.alloc_pages(1, PAGE_SIZE).unwrap();
+    assert_eq!(a, mem.start() + PAGE_SIZE);
+    assert_eq!(b, mem.start() + 2 * PAGE_SIZE);
+    assert_eq!(alloc.used_pages(), 2);
+
+    alloc.dealloc_pages(b, 1);
+    alloc.dealloc_pages(a, 1);
+    assert_eq!(alloc.used_pages(), 0);
+    assert_eq!(alloc.available_pages(), total);
+    // The pages are merged back into the same blocks.
+    assert_eq!(alloc.alloc_pages(1, PAGE_SIZE), Ok(a));
+    assert_eq!(alloc.alloc_pages(2, PAGE_SIZE), Ok(b));
+    assert_eq!(
+        alloc.alloc_pages(1024, PAGE_SIZE),
+        Ok(mem.start() + 2 * HUGE_PAGE_SIZE)
+    );
+    assert_eq!(
+        alloc.alloc_pages(1024, PAGE_SIZE),
+        Err(AllocError::NoMemory)
+    );
+}
+
+#[test]
+fn test_huge_page_alignment() {
+    let (mut alloc, mem) = new_allocator();
+    let small = alloc.alloc_pages(1, PAGE_SIZE).unwrap();
+    // The first 2M is taken by the metadata and the small page.
+    let huge: Vec<_> = (0..3)
+        .map(|_| alloc.alloc_pages(1, HUGE_PAGE_SIZE).unwrap())
+        .collect();
+    for (i, &pos) in huge.iter().enumerate() {
+        assert_eq!(pos % HUGE_PAGE_SIZE, 0);
+        assert!(pos >= mem.start() + HUGE_PAGE_SIZE);
+        assert!(!huge[..i].contains(&pos));
+    }
+    assert_eq!(
+        alloc.alloc_pages(512, HUGE_PAGE_SIZE),
+        Err(AllocError::NoMemory)
+    );
+    // The pages before the first huge page are still free.
+    assert_eq!(alloc.used_pages(), 4);
+    assert_eq!(alloc.available_pages(), alloc.total_pages() - 4);
+    assert_eq!(alloc.max_free_pages(), 256);
+
+    for pos in huge {
+        alloc.dealloc_pages(pos, 1);
+    }
+    let huge = alloc.alloc_pages(1024, 2 * HUGE_PAGE_SIZE).unwrap();
+    assert_eq!(huge, mem.start() + 2 * HUGE_PAGE_SIZE);
+    alloc.dealloc_pages(small, 1);
+}
+
+#[test]
+fn test_no_waste() {
+    let (mut alloc, _mem) = new_allocator();
+    let total = alloc.total_pages();
+    let mut blocks = Vec::new();
+    while let Ok(pos) = alloc.alloc_pages(3, PAGE_SIZE) {
+        blocks.push(pos);
+    }
+    // The last page of each block is freed, and can be allocated later.
+    assert_eq!(alloc.used_pages(), blocks.len() * 3);
+    let mut pages = Vec::new();
+    while let Ok(pos) = alloc.alloc_pages(1, PAGE_SIZE) {
+        pages.push(pos);
+    }
+    assert_eq!(alloc.used_pages(), total);
+
+    // Free by sub-ranges.
+    for pos in blocks {
+        alloc.dealloc_pages(pos, 1);
+        alloc.dealloc_pages(pos + PAGE_SIZE, 2);
+    }
+    for pos in pages {
+        alloc.dealloc_pages(pos, 1);
+    }
+    assert_eq!(alloc.used_pages(), 0);
+    assert_eq!(alloc.max_free_pages(), 1024);
+}
+
+#[test]
+fn test_invalid_params() {
+    let (mut alloc, mem) = new_allocator();
+    assert_eq!(
+        alloc.alloc_pages(0, PAGE_SIZE),
+        Err(AllocError::InvalidParam)
+    );
+    assert_eq!(
+        alloc.alloc_pages(1, PAGE_SIZE / 2),
+        Err(AllocError::InvalidParam)
+    );
+    assert_eq!(
+        alloc.alloc_pages(1, 3 * PAGE_SIZE),
+        Err(AllocError::InvalidParam)
+    );
+    assert_eq!(
+        alloc.alloc_pages(1, PAGE_SIZE << 40),
+        Err(AllocError::NoMemory)
+    );
+    assert_eq!(
+        alloc.add_memory(mem.start() + PAGE_SIZE, PAGE_SIZE),
+        Err(AllocError::MemoryOverlap)
+    );
+}
+
+#[test]
+fn test_multiple_regions() {
+    let (mut alloc, _mem) = new_allocator();
+    let extra = Memory::new(16 * PAGE_SIZE, PAGE_SIZE);
+    alloc.add_memory(extra.start(), 16 * PAGE_SIZE).unwrap();
+    assert_eq!(alloc.total_pages(), 4 * HUGE_PAGE_SIZE / PAGE_SIZE - 1 + 15);
+
+    let mut pages = Vec::new();
+    while let Ok(pos) = alloc.alloc_pages(1, PAGE_SIZE) {
+        unsafe { (pos as *mut usize).write(pos) };
+        pages.push(pos);
+    }
+    assert_eq!(pages.len(), alloc.total_pages());
+    for &pos in &pages {
+        assert_eq!(unsafe { (pos as *const usize).read() }, pos);
+    }
+    for pos in pages {
+        alloc.dealloc_pages(pos, 1);
+    }
+    assert_eq!(alloc.used_pages(), 0);
+}
+
+#[test]
+fn test_random() {
+    let (mut alloc, _mem) = new_allocator();
+    let mut live: Vec<(usize, usize)> = Vec::new();
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut rand = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as usize
+    };
+    for _ in 0..20000 {
+        if rand() % 2 == 0 {
+            let num_pages = rand() % 64 + 1;
+            let align = PAGE_SIZE << (rand() % 6);
+            if let Ok(pos) = alloc.alloc_pages(num_pages, align) {
+                assert_eq!(pos % align, 0);
+                for i in 0..num_pages {
+                    unsafe { ((pos + i * PAGE_SIZE) as *mut usize).write(pos) };
+                }
+                live.push((pos, num_pages));
+            }
+        } else if !live.is_empty() {
+            let (pos, num_pages) = live.swap_remove(rand() % live.len());
+            for i in 0..num_pages {
+                let data = unsafe { ((pos + i * PAGE_SIZE) as *const usize).read() };
+                assert_eq!(data, pos, "pages overlapped");
+            }
+            alloc.dealloc_pages(pos, num_pages);
+        }
+    }
+    for (pos, num_pages) in live {
+        alloc.dealloc_pages(pos, num_pages);
+    }
+    assert_eq!(alloc.used_pages(), 0);
+    assert_eq!(alloc.max_free_pages(), 1024);
+}
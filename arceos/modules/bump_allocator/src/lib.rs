@@ -1,6 +1,74 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
-use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
+#[cfg(test)]
+mod tests;
+
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// The maximum number of memory regions that can be added.
+const MAX_REGIONS: usize = 16;
+
+const fn align_down(pos: usize, align: usize) -> usize {
+    pos & !(align - 1)
+}
+
+const fn align_up(pos: usize, align: usize) -> Option<usize> {
+    match pos.checked_add(align - 1) {
+        Some(pos) => Some(align_down(pos, align)),
+        None => None,
+    }
+}
+
+/// A memory region managed by the [`EarlyAllocator`].
+#[derive(Clone, Copy)]
+struct Region {
+    start: usize,
+    end: usize,
+    b_pos: usize,
+    p_pos: usize,
+    /// The number of live byte allocations in this region.
+    count: usize,
+}
+
+impl Region {
+    const EMPTY: Self = Self::new(0, 0);
+
+    const fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            b_pos: start,
+            p_pos: end,
+            count: 0,
+        }
+    }
+
+    fn alloc_bytes(&mut self, layout: Layout) -> Option<usize> {
+        let start = align_up(self.b_pos, layout.align())?;
+        let end = start.checked_add(layout.size())?;
+        if end > self.p_pos {
+            return None;
+        }
+        self.b_pos = end;
+        self.count += 1;
+        Some(start)
+    }
+
+    fn alloc_pages(&mut self, size: usize, align: usize) -> Option<usize> {
+        let start = align_down(self.p_pos.checked_sub(size)?, align);
+        if start < self.b_pos {
+            return None;
+        }
+        self.p_pos = start;
+        Some(start)
+    }
+
+    fn contains_bytes(&self, pos: usize, size: usize) -> bool {
+        self.start <= pos && pos.saturating_add(size) <= self.b_pos
+    }
+}
 
 /// Early memory allocator
 /// Use it before formal bytes-allocator and pages-allocator can work!
@@ -16,16 +84,141 @@ use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
 /// When it goes down to ZERO, free bytes-used area.
 /// For pages area, it will never be freed!
 ///
-pub struct EarlyAllocator;
+/// Up to 16 memory regions can be added, each of them is managed as above.
+/// An allocation is taken from the first region that has enough space.
+pub struct EarlyAllocator<const PAGE_SIZE: usize> {
+    regions: [Region; MAX_REGIONS],
+    num_regions: usize,
+}
 
-impl EarlyAllocator {
+impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
+    /// Creates an empty [`EarlyAllocator`].
+    pub const fn new() -> Self {
+        Self {
+            regions: [Region::EMPTY; MAX_REGIONS],
+            num_regions: 0,
+        }
+    }
+
+    fn regions(&self) -> &[Region] {
+        &self.regions[..self.num_regions]
+    }
+
+    fn regions_mut(&mut self) -> &mut [Region] {
+        &mut self.regions[..self.num_regions]
+    }
 }
 
-impl BaseAllocator for EarlyAllocator {
+impl<const PAGE_SIZE: usize> Default for EarlyAllocator<PAGE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl ByteAllocator for EarlyAllocator {
+impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
+    fn init(&mut self, start: usize, size: usize) {
+        self.num_regions = 0;
+        self.add_memory(start, size).unwrap();
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        let end = start.checked_add(size).ok_or(AllocError::InvalidParam)?;
+        if size == 0 {
+            return Err(AllocError::InvalidParam);
+        }
+        if self
+            .regions()
+            .iter()
+            .any(|r| start < r.end && r.start < end)
+        {
+            return Err(AllocError::MemoryOverlap);
+        }
+        if self.num_regions == MAX_REGIONS {
+            return Err(AllocError::NoMemory);
+        }
+        self.regions[self.num_regions] = Region::new(start, end);
+        self.num_regions += 1;
+        Ok(())
+    }
+}
+
+impl<const PAGE_SIZE: usize> ByteAllocator for EarlyAllocator<PAGE_SIZE> {
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        self.regions_mut()
+            .iter_mut()
+            .find_map(|r| r.alloc_bytes(layout))
+            .and_then(|pos| NonNull::new(pos as *mut u8))
+            .ok_or(AllocError::NoMemory)
+    }
+
+    fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+        let pos = pos.as_ptr() as usize;
+        if let Some(r) = self
+            .regions_mut()
+            .iter_mut()
+            .find(|r| r.count > 0 && r.contains_bytes(pos, layout.size()))
+        {
+            r.count -= 1;
+            if r.count == 0 {
+                r.b_pos = r.start;
+            }
+        }
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.regions().iter().map(|r| r.end - r.start).sum()
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.regions().iter().map(|r| r.b_pos - r.start).sum()
+    }
+
+    fn available_bytes(&self) -> usize {
+        self.regions().iter().map(|r| r.p_pos - r.b_pos).sum()
+    }
 }
 
-impl PageAllocator for EarlyAllocator {
+impl<const PAGE_SIZE: usize> PageAllocator for EarlyAllocator<PAGE_SIZE> {
+    const PAGE_SIZE: usize = PAGE_SIZE;
+
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if !align_pow2.is_power_of_two() || align_pow2 % PAGE_SIZE != 0 {
+            return Err(AllocError::InvalidParam);
+        }
+        let size = num_pages
+            .checked_mul(PAGE_SIZE)
+            .ok_or(AllocError::InvalidParam)?;
+        self.regions_mut()
+            .iter_mut()
+            .find_map(|r| r.alloc_pages(size, align_pow2))
+            .ok_or(AllocError::NoMemory)
+    }
+
+    fn dealloc_pages(&mut self, _pos: usize, _num_pages: usize) {
+        // Pages are never freed.
+    }
+
+    fn total_pages(&self) -> usize {
+        self.regions()
+            .iter()
+            .map(|r| (r.end - r.start) / PAGE_SIZE)
+            .sum()
+    }
+
+    fn used_pages(&self) -> usize {
+        self.regions()
+            .iter()
+            .map(|r| (r.end - r.p_pos) / PAGE_SIZE)
+            .sum()
+    }
+
+    fn available_pages(&self) -> usize {
+        self.regions()
+            .iter()
+            .map(|r| {
+                let start = align_up(r.b_pos, PAGE_SIZE).unwrap_or(usize::MAX);
+                align_down(r.p_pos, PAGE_SIZE).saturating_sub(start) / PAGE_SIZE
+            })
+            .sum()
+    }
 }
@@ -0,0 +1,119 @@
+use core::alloc::Layout;
+
+use allocator::{AllocError, BaseAllocator, ByteAllocator, PageAllocator};
+
+use crate::EarlyAllocator;
+
+const PAGE_SIZE: usize = 0x1000;
+
+// The allocator never accesses the memory, so any address can be used.
+const START: usize = 0x8000_0000;
+const SIZE: usize = 16 * PAGE_SIZE;
+
+fn new_allocator() -> EarlyAllocator<PAGE_SIZE> {
+    let mut alloc = EarlyAllocator::new();
+    alloc.init(START, SIZE);
+    alloc
+}
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+#[test]
+fn test_bytes_forward() {
+    let mut alloc = new_allocator();
+    let a = alloc.alloc(layout(10, 1)).unwrap().as_ptr() as usize;
+    let b = alloc.alloc(layout(8, 8)).unwrap().as_ptr() as usize;
+    let c = alloc.alloc(layout(1, 64)).unwrap().as_ptr() as usize;
+    assert_eq!(a, START);
+    assert_eq!(b, START + 16);
+    assert_eq!(c, START + 64);
+    assert_eq!(alloc.used_bytes(), 65);
+    assert_eq!(alloc.available_bytes(), SIZE - 65);
+    assert_eq!(alloc.total_bytes(), SIZE);
+}
+
+#[test]
+fn test_bytes_freed_when_count_drops_to_zero() {
+    let mut alloc = new_allocator();
+    let l = layout(100, 4);
+    let a = alloc.alloc(l).unwrap();
+    let b = alloc.alloc(l).unwrap();
+    alloc.dealloc(a, l);
+    assert_eq!(alloc.used_bytes(), 200);
+    alloc.dealloc(b, l);
+    assert_eq!(alloc.used_bytes(), 0);
+    assert_eq!(alloc.alloc(l).unwrap(), a);
+}
+
+#[test]
+fn test_pages_backward() {
+    let mut alloc = new_allocator();
+    assert_eq!(alloc.total_pages(), 16);
+    assert_eq!(
+        alloc.alloc_pages(1, PAGE_SIZE),
+        Ok(START + SIZE - PAGE_SIZE)
+    );
+    assert_eq!(
+        alloc.alloc_pages(2, PAGE_SIZE),
+        Ok(START + SIZE - 3 * PAGE_SIZE)
+    );
+    // Aligned down to 8 pages, skipping 4 pages.
+    assert_eq!(
+        alloc.alloc_pages(1, 8 * PAGE_SIZE),
+        Ok(START + SIZE - 8 * PAGE_SIZE)
+    );
+    assert_eq!(alloc.used_pages(), 8);
+    assert_eq!(alloc.available_pages(), 8);
+
+    // Pages are never freed.
+    alloc.dealloc_pages(START + SIZE - PAGE_SIZE, 1);
+    assert_eq!(alloc.used_pages(), 8);
+    assert_eq!(
+        alloc.alloc_pages(1, PAGE_SIZE + 1),
+        Err(AllocError::InvalidParam)
+    );
+}
+
+#[test]
+fn test_bytes_and_pages_meet() {
+    let mut alloc = new_allocator();
+    alloc.alloc_pages(15, PAGE_SIZE).unwrap();
+    assert_eq!(alloc.available_bytes(), PAGE_SIZE);
+    assert!(alloc.alloc(layout(PAGE_SIZE, 1)).is_ok());
+    assert_eq!(alloc.available_bytes(), 0);
+    assert_eq!(alloc.alloc(layout(1, 1)), Err(AllocError::NoMemory));
+    assert_eq!(alloc.alloc_pages(1, PAGE_SIZE), Err(AllocError::NoMemory));
+}
+
+#[test]
+fn test_multiple_regions() {
+    let mut alloc = new_allocator();
+    let start2 = START + 2 * SIZE;
+    alloc.add_memory(start2, SIZE).unwrap();
+    assert_eq!(
+        alloc.add_memory(START + PAGE_SIZE, SIZE),
+        Err(AllocError::MemoryOverlap)
+    );
+    assert_eq!(alloc.total_bytes(), 2 * SIZE);
+    assert_eq!(alloc.total_pages(), 32);
+
+    // The first region is filled up, then the second one is used.
+    let l = layout(SIZE, 1);
+    let a = alloc.alloc(l).unwrap();
+    let b = alloc.alloc(l).unwrap();
+    assert_eq!(a.as_ptr() as usize, START);
+    assert_eq!(b.as_ptr() as usize, start2);
+    assert_eq!(alloc.alloc_pages(1, PAGE_SIZE), Err(AllocError::NoMemory));
+
+    // Each region is freed by its own count.
+    alloc.dealloc(b, l);
+    assert_eq!(alloc.used_bytes(), SIZE);
+    assert_eq!(
+        alloc.alloc_pages(1, PAGE_SIZE),
+        Ok(start2 + SIZE - PAGE_SIZE)
+    );
+    alloc.dealloc(a, l);
+    assert_eq!(alloc.used_bytes(), 0);
+}
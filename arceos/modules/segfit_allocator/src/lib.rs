@@ -0,0 +1,364 @@
+//! A segregated-fit byte allocator, tuned for workloads that allocate and free
+//! objects of many different sizes in an interleaved order.
+//!
+//! Each free chunk is kept in one of the size-segregated free lists (bins):
+//!
+//! - Small chunks (less than [`SMALL_LIMIT`] bytes) have one bin per size, so
+//!   a small allocation takes the first chunk of its exact bin if it is not
+//!   empty, without splitting or searching.
+//! - Large chunks are binned by their most significant bits, four bins per
+//!   power of two. The first bin is searched for the best fit, and any chunk
+//!   of the larger bins fits.
+//!
+//! A bitmap of the non-empty bins is used to find the next larger bin in
+//! constant time. Freed chunks are immediately coalesced with their free
+//! neighbours by the boundary tags, which keeps the fragmentation low when
+//! objects of different lifetimes are interleaved.
+//!
+//! Unlike [`bump_allocator`](../bump_allocator/index.html), it writes its
+//! metadata into the managed memory, so the memory must be accessible.
+
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(test)]
+mod tests;
+
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator};
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::{self, NonNull};
+
+/// The header of a chunk, the last two fields are only valid in free chunks.
+#[repr(C)]
+struct Chunk {
+    /// The size of the previous chunk, only valid if it is free.
+    prev_size: usize,
+    /// The size of this chunk (including the header), and the flags.
+    size: usize,
+    next_free: *mut Chunk,
+    prev_free: *mut Chunk,
+}
+
+/// The size of the header before each allocation.
+const HEADER_SIZE: usize = 2 * size_of::<usize>();
+
+/// Chunk sizes and the allocations are aligned to this.
+const GRANULE: usize = HEADER_SIZE;
+
+/// The minimum size of a chunk, which can hold the free list links.
+const MIN_CHUNK_SIZE: usize = size_of::<Chunk>();
+
+/// The chunk is allocated.
+const USED: usize = 1;
+/// The previous chunk is allocated, so `prev_size` is not valid.
+const PREV_USED: usize = 2;
+const FLAGS: usize = GRANULE - 1;
+
+/// The number of bins of the small chunks, one for each size.
+const SMALL_BINS: usize = 64;
+
+/// The chunks smaller than this are small chunks.
+pub const SMALL_LIMIT: usize = SMALL_BINS * GRANULE;
+
+const LARGE_SHIFT: usize = SMALL_LIMIT.trailing_zeros() as usize;
+const SUB_SHIFT: usize = 2;
+const SUB_BINS: usize = 1 << SUB_SHIFT;
+
+const NUM_BINS: usize = SMALL_BINS + (usize::BITS as usize - LARGE_SHIFT) * SUB_BINS;
+const BITMAP_LEN: usize = NUM_BINS.div_ceil(usize::BITS as usize);
+
+const fn align_down(pos: usize, align: usize) -> usize {
+    pos & !(align - 1)
+}
+
+const fn align_up(pos: usize, align: usize) -> Option<usize> {
+    match pos.checked_add(align - 1) {
+        Some(pos) => Some(align_down(pos, align)),
+        None => None,
+    }
+}
+
+/// Returns the bin of the free chunks of the given size.
+const fn bin_index(size: usize) -> usize {
+    if size < SMALL_LIMIT {
+        size / GRANULE
+    } else {
+        let fl = (usize::BITS - 1 - size.leading_zeros()) as usize;
+        let sl = (size >> (fl - SUB_SHIFT)) & (SUB_BINS - 1);
+        SMALL_BINS + (fl - LARGE_SHIFT) * SUB_BINS + sl
+    }
+}
+
+impl Chunk {
+    unsafe fn size(this: *mut Chunk) -> usize {
+        (*this).size & !FLAGS
+    }
+
+    unsafe fn is_used(this: *mut Chunk) -> bool {
+        (*this).size & USED != 0
+    }
+
+    unsafe fn is_prev_used(this: *mut Chunk) -> bool {
+        (*this).size & PREV_USED != 0
+    }
+
+    unsafe fn next(this: *mut Chunk) -> *mut Chunk {
+        this.byte_add(Self::size(this))
+    }
+
+    unsafe fn payload(this: *mut Chunk) -> *mut u8 {
+        this.byte_add(HEADER_SIZE).cast()
+    }
+
+    unsafe fn from_payload(ptr: *mut u8) -> *mut Chunk {
+        ptr.sub(HEADER_SIZE).cast()
+    }
+
+    /// Sets the size of a free chunk, and updates the boundary tag in the
+    /// next chunk.
+    unsafe fn set_free(this: *mut Chunk, size: usize) {
+        (*this).size = size | ((*this).size & PREV_USED);
+        let next = Self::next(this);
+        (*next).prev_size = size;
+        (*next).size &= !PREV_USED;
+    }
+
+    /// Sets the size of an allocated chunk, and updates the flag in the next
+    /// chunk.
+    unsafe fn set_used(this: *mut Chunk, size: usize) {
+        (*this).size = size | USED | ((*this).size & PREV_USED);
+        (*Self::next(this)).size |= PREV_USED;
+    }
+}
+
+/// A segregated-fit byte allocator.
+///
+/// See the [crate-level documentation](crate) for the details.
+pub struct SegFitByteAllocator {
+    bins: [*mut Chunk; NUM_BINS],
+    bitmap: [usize; BITMAP_LEN],
+    total_bytes: usize,
+    used_bytes: usize,
+}
+
+unsafe impl Send for SegFitByteAllocator {}
+
+impl SegFitByteAllocator {
+    /// Creates an empty [`SegFitByteAllocator`].
+    pub const fn new() -> Self {
+        Self {
+            bins: [ptr::null_mut(); NUM_BINS],
+            bitmap: [0; BITMAP_LEN],
+            total_bytes: 0,
+            used_bytes: 0,
+        }
+    }
+
+    unsafe fn insert(&mut self, chunk: *mut Chunk) {
+        let idx = bin_index(Chunk::size(chunk));
+        let head = self.bins[idx];
+        (*chunk).prev_free = ptr::null_mut();
+        (*chunk).next_free = head;
+        if !head.is_null() {
+            (*head).prev_free = chunk;
+        }
+        self.bins[idx] = chunk;
+        self.bitmap[idx / usize::BITS as usize] |= 1 << (idx % usize::BITS as usize);
+    }
+
+    unsafe fn remove(&mut self, chunk: *mut Chunk) {
+        let (prev, next) = ((*chunk).prev_free, (*chunk).next_free);
+        if !next.is_null() {
+            (*next).prev_free = prev;
+        }
+        if !prev.is_null() {
+            (*prev).next_free = next;
+        } else {
+            let idx = bin_index(Chunk::size(chunk));
+            self.bins[idx] = next;
+            if next.is_null() {
+                self.bitmap[idx / usize::BITS as usize] &= !(1 << (idx % usize::BITS as usize));
+            }
+        }
+    }
+
+    /// Returns the first non-empty bin starting from `idx`.
+    fn next_bin(&self, idx: usize) -> Option<usize> {
+        let bits = usize::BITS as usize;
+        let mut word = idx / bits;
+        if word >= BITMAP_LEN {
+            return None;
+        }
+        let mut mask = self.bitmap[word] & (usize::MAX << (idx % bits));
+        while mask == 0 {
+            word += 1;
+            if word >= BITMAP_LEN {
+                return None;
+            }
+            mask = self.bitmap[word];
+        }
+        Some(word * bits + mask.trailing_zeros() as usize)
+    }
+
+    /// Finds a free chunk of at least `size` bytes and removes it from its
+    /// bin.
+    unsafe fn take_chunk(&mut self, size: usize) -> Option<*mut Chunk> {
+        let idx = bin_index(size);
+        let mut found = ptr::null_mut();
+        if idx >= SMALL_BINS {
+            // The sizes in a large bin vary, find the best fit in it.
+            let mut chunk = self.bins[idx];
+            let mut best_size = usize::MAX;
+            while !chunk.is_null() {
+                let chunk_size = Chunk::size(chunk);
+                if chunk_size >= size && chunk_size < best_size {
+                    found = chunk;
+                    best_size = chunk_size;
+                    if chunk_size == size {
+                        break;
+                    }
+                }
+                chunk = (*chunk).next_free;
+            }
+        } else {
+            found = self.bins[idx];
+        }
+        if found.is_null() {
+            // Any chunk in the larger bins fits.
+            found = self.bins[self.next_bin(idx + 1)?];
+        }
+        self.remove(found);
+        Some(found)
+    }
+
+    /// Splits the free chunk to `size` bytes if the rest is large enough to
+    /// be a chunk, and marks it as allocated.
+    unsafe fn use_chunk(&mut self, chunk: *mut Chunk, size: usize) {
+        let chunk_size = Chunk::size(chunk);
+        if chunk_size - size >= MIN_CHUNK_SIZE {
+            let rest = chunk.byte_add(size);
+            (*rest).size = PREV_USED;
+            Chunk::set_free(rest, chunk_size - size);
+            self.insert(rest);
+            Chunk::set_used(chunk, size);
+        } else {
+            Chunk::set_used(chunk, chunk_size);
+        }
+        self.used_bytes += Chunk::size(chunk);
+    }
+
+    /// Gives the free space before the aligned allocation in the chunk back
+    /// to the bins, returns the chunk of the allocation.
+    unsafe fn split_front(&mut self, chunk: *mut Chunk, align: usize) -> *mut Chunk {
+        let payload = Chunk::payload(chunk) as usize;
+        let mut aligned = align_up(payload, align).unwrap();
+        if aligned == payload {
+            return chunk;
+        }
+        while aligned - payload < MIN_CHUNK_SIZE {
+            aligned += align;
+        }
+        let front_size = aligned - payload;
+        let rest_size = Chunk::size(chunk) - front_size;
+        let rest = chunk.byte_add(front_size);
+        Chunk::set_free(chunk, front_size);
+        (*rest).size = rest_size;
+        self.insert(chunk);
+        rest
+    }
+}
+
+impl Default for SegFitByteAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BaseAllocator for SegFitByteAllocator {
+    fn init(&mut self, start: usize, size: usize) {
+        *self = Self::new();
+        self.add_memory(start, size).unwrap();
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        let end = start.checked_add(size).ok_or(AllocError::InvalidParam)?;
+        let start = align_up(start, GRANULE).ok_or(AllocError::InvalidParam)?;
+        let end = align_down(end, GRANULE);
+        if end < start || end - start < MIN_CHUNK_SIZE + HEADER_SIZE {
+            return Err(AllocError::InvalidParam);
+        }
+        // The region is a free chunk followed by an allocated chunk of size 0,
+        // so that the chunks are never coalesced across the regions.
+        let chunk_size = end - start - HEADER_SIZE;
+        unsafe {
+            let chunk = start as *mut Chunk;
+            let fence = (end - HEADER_SIZE) as *mut Chunk;
+            (*chunk).size = PREV_USED;
+            (*fence).size = USED;
+            Chunk::set_free(chunk, chunk_size);
+            self.insert(chunk);
+        }
+        self.total_bytes += chunk_size;
+        Ok(())
+    }
+}
+
+impl ByteAllocator for SegFitByteAllocator {
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let size = layout
+            .size()
+            .checked_add(HEADER_SIZE)
+            .and_then(|size| align_up(size, GRANULE))
+            .ok_or(AllocError::NoMemory)?
+            .max(MIN_CHUNK_SIZE);
+        let align = layout.align();
+        unsafe {
+            let chunk = if align <= GRANULE {
+                self.take_chunk(size).ok_or(AllocError::NoMemory)?
+            } else {
+                let search_size = size
+                    .checked_add(align + MIN_CHUNK_SIZE)
+                    .ok_or(AllocError::NoMemory)?;
+                let chunk = self.take_chunk(search_size).ok_or(AllocError::NoMemory)?;
+                self.split_front(chunk, align)
+            };
+            self.use_chunk(chunk, size);
+            Ok(NonNull::new_unchecked(Chunk::payload(chunk)))
+        }
+    }
+
+    fn dealloc(&mut self, pos: NonNull<u8>, _layout: Layout) {
+        unsafe {
+            let mut chunk = Chunk::from_payload(pos.as_ptr());
+            debug_assert!(Chunk::is_used(chunk), "double free: {:p}", pos);
+            let mut size = Chunk::size(chunk);
+            self.used_bytes -= size;
+
+            let next = Chunk::next(chunk);
+            if !Chunk::is_used(next) {
+                self.remove(next);
+                size += Chunk::size(next);
+            }
+            if !Chunk::is_prev_used(chunk) {
+                let prev = chunk.byte_sub((*chunk).prev_size);
+                self.remove(prev);
+                size += Chunk::size(prev);
+                chunk = prev;
+            }
+            Chunk::set_free(chunk, size);
+            self.insert(chunk);
+        }
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    fn available_bytes(&self) -> usize {
+        self.total_bytes - self.used_bytes
+    }
+}
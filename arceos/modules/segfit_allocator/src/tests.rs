@@ -0,0 +1,146 @@
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use allocator::{AllocError, BaseAllocator, ByteAllocator};
+
+use crate::{SegFitByteAllocator, HEADER_SIZE, MIN_CHUNK_SIZE};
+
+const SIZE: usize = 0x10000;
+
+/// A memory region for the allocator, which must be accessible.
+#[repr(align(4096))]
+struct Arena([u8; SIZE]);
+
+fn new_allocator() -> (SegFitByteAllocator, Box<Arena>) {
+    let mut arena = Box::new(Arena([0; SIZE]));
+    let mut alloc = SegFitByteAllocator::new();
+    alloc.init(arena.0.as_mut_ptr() as usize, SIZE);
+    (alloc, arena)
+}
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+fn addr(ptr: NonNull<u8>) -> usize {
+    ptr.as_ptr() as usize
+}
+
+#[test]
+fn test_alloc_and_coalesce() {
+    let (mut alloc, arena) = new_allocator();
+    let start = arena.0.as_ptr() as usize;
+    let total = SIZE - HEADER_SIZE;
+    assert_eq!(alloc.total_bytes(), total);
+
+    let l = layout(100, 8);
+    let a = alloc.alloc(l).unwrap();
+    let b = alloc.alloc(l).unwrap();
+    let c = alloc.alloc(l).unwrap();
+    assert_eq!(addr(a), start + HEADER_SIZE);
+    assert_eq!(addr(b), addr(a) + 128);
+    assert_eq!(addr(c), addr(b) + 128);
+    assert_eq!(alloc.used_bytes(), 3 * 128);
+
+    // Coalesced with the next free chunk, then with the previous one.
+    alloc.dealloc(a, l);
+    alloc.dealloc(c, l);
+    alloc.dealloc(b, l);
+    assert_eq!(alloc.used_bytes(), 0);
+    assert_eq!(alloc.available_bytes(), total);
+
+    // The whole region is a single chunk again.
+    let big = layout(total - HEADER_SIZE, 8);
+    assert_eq!(alloc.alloc(big), Ok(a));
+    assert_eq!(alloc.alloc(layout(1, 1)), Err(AllocError::NoMemory));
+}
+
+#[test]
+fn test_small_exact_fit() {
+    let (mut alloc, _arena) = new_allocator();
+    let small = layout(24, 8);
+    let a = alloc.alloc(small).unwrap();
+    let _guard = alloc.alloc(small).unwrap();
+    alloc.dealloc(a, small);
+    // A freed small chunk is reused for the same size class, and a smaller
+    // chunk is never taken for a larger allocation.
+    assert_eq!(alloc.alloc(layout(32, 16)), Ok(a));
+    alloc.dealloc(a, small);
+    assert_ne!(alloc.alloc(layout(64, 8)), Ok(a));
+    assert_eq!(alloc.alloc(layout(1, 1)), Ok(a));
+}
+
+#[test]
+fn test_alignment() {
+    let (mut alloc, _arena) = new_allocator();
+    let _ = alloc.alloc(layout(8, 8)).unwrap();
+    for align in [32, 64, 256, 4096] {
+        let l = layout(align / 2, align);
+        let ptr = alloc.alloc(l).unwrap();
+        assert_eq!(addr(ptr) % align, 0);
+        // The space before the aligned allocation is reusable.
+        let before = alloc.alloc(layout(1, 1)).unwrap();
+        assert!(addr(before) < addr(ptr));
+        alloc.dealloc(before, layout(1, 1));
+        alloc.dealloc(ptr, l);
+    }
+    assert_eq!(alloc.used_bytes(), MIN_CHUNK_SIZE);
+}
+
+#[test]
+fn test_multiple_regions() {
+    let (mut alloc, _arena) = new_allocator();
+    let mut extra = Box::new(Arena([0; SIZE]));
+    alloc
+        .add_memory(extra.0.as_mut_ptr() as usize, SIZE)
+        .unwrap();
+    assert_eq!(alloc.total_bytes(), 2 * (SIZE - HEADER_SIZE));
+    assert_eq!(
+        alloc.add_memory(extra.0.as_mut_ptr() as usize, 8),
+        Err(AllocError::InvalidParam)
+    );
+
+    // Each region can only hold one of them.
+    let l = layout(SIZE / 2 + 1, 8);
+    let a = alloc.alloc(l).unwrap();
+    let b = alloc.alloc(l).unwrap();
+    assert!(addr(a).abs_diff(addr(b)) >= SIZE / 2);
+    assert_eq!(alloc.alloc(l), Err(AllocError::NoMemory));
+    alloc.dealloc(a, l);
+    alloc.dealloc(b, l);
+    assert_eq!(alloc.used_bytes(), 0);
+}
+
+#[test]
+fn test_random_no_overlap() {
+    let (mut alloc, _arena) = new_allocator();
+    let mut live: Vec<(NonNull<u8>, Layout, u8)> = Vec::new();
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut rand = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as usize
+    };
+    for round in 0..20000 {
+        if live.len() < 64 && rand() % 3 != 0 {
+            let l = layout(rand() % 1500 + 1, 1 << (rand() % 7));
+            if let Ok(ptr) = alloc.alloc(l) {
+                assert_eq!(addr(ptr) % l.align(), 0);
+                let tag = round as u8;
+                unsafe { ptr.as_ptr().write_bytes(tag, l.size()) };
+                live.push((ptr, l, tag));
+            }
+        } else if !live.is_empty() {
+            let (ptr, l, tag) = live.swap_remove(rand() % live.len());
+            let data = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), l.size()) };
+            assert!(data.iter().all(|&b| b == tag), "memory corrupted");
+            alloc.dealloc(ptr, l);
+        }
+    }
+    for (ptr, l, _) in live {
+        alloc.dealloc(ptr, l);
+    }
+    assert_eq!(alloc.used_bytes(), 0);
+    assert!(alloc.alloc(layout(SIZE - 2 * HEADER_SIZE, 8)).is_ok());
+}
@@ -0,0 +1,156 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include <fcntl.h>
+#include <sys/mman.h>
+
+#define PAGE_SIZE 4096
+#define NUM_PAGES 3
+
+static char file_buf[NUM_PAGES * PAGE_SIZE];
+
+void check(int cond, const char *what)
+{
+    if (!cond) {
+        printf("Check %s failed!\n", what);
+        exit(-1);
+    }
+}
+
+/* Creates a file of `NUM_PAGES` pages, filled with 'a', 'b', 'c', ... */
+void create_pages(const char *fname)
+{
+    int fd;
+    int i;
+
+    for (i = 0; i < NUM_PAGES; i++) {
+        memset(file_buf + i * PAGE_SIZE, 'a' + i, PAGE_SIZE);
+    }
+    fd = creat(fname, 0600);
+    check(fd >= 0, "creat");
+    check(write(fd, file_buf, sizeof(file_buf)) == sizeof(file_buf), "write");
+    close(fd);
+}
+
+/* Reads the whole file into `file_buf`. */
+void read_pages(const char *fname)
+{
+    int fd;
+    int n;
+    int read_bytes = 0;
+
+    memset(file_buf, 0, sizeof(file_buf));
+    fd = open(fname, O_RDONLY);
+    check(fd >= 0, "open");
+    while (read_bytes < sizeof(file_buf)) {
+        n = read(fd, file_buf + read_bytes, sizeof(file_buf) - read_bytes);
+        check(n >= 0, "read");
+        if (n == 0) {
+            break;
+        }
+        read_bytes += n;
+    }
+    close(fd);
+}
+
+char *map_pages(const char *fname, int flags)
+{
+    int fd;
+    char *addr;
+
+    fd = open(fname, O_RDWR);
+    check(fd >= 0, "open");
+    addr = mmap(NULL, NUM_PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, flags, fd, 0);
+    check(addr != MAP_FAILED, "mmap");
+    /* The mapping is still valid after the file is closed. */
+    close(fd);
+    return addr;
+}
+
+/* The changes of a private mapping are never written back. */
+void test_private_mapping(const char *fname)
+{
+    char *addr;
+
+    create_pages(fname);
+    addr = map_pages(fname, MAP_PRIVATE);
+    check(addr[0] == 'a' && addr[PAGE_SIZE] == 'b', "private read");
+    addr[0] = 'X';
+    addr[PAGE_SIZE] = 'Y';
+    check(addr[0] == 'X' && addr[PAGE_SIZE] == 'Y', "private write");
+    check(msync(addr, NUM_PAGES * PAGE_SIZE, MS_SYNC) == 0, "private msync");
+    check(munmap(addr, NUM_PAGES * PAGE_SIZE) == 0, "private munmap");
+
+    read_pages(fname);
+    check(file_buf[0] == 'a' && file_buf[PAGE_SIZE] == 'b', "private write-back");
+    printf("Private mapping ok!\n");
+}
+
+/* The changes of a shared mapping are written back by msync and munmap. */
+void test_shared_mapping(const char *fname)
+{
+    char *addr;
+
+    create_pages(fname);
+    addr = map_pages(fname, MAP_SHARED);
+    addr[0] = 'S';
+    addr[PAGE_SIZE] = 'T';
+
+    /* Only the synchronized page is written back. */
+    check(msync(addr, PAGE_SIZE, MS_SYNC) == 0, "shared msync");
+    read_pages(fname);
+    check(file_buf[0] == 'S', "msync write-back");
+    check(file_buf[PAGE_SIZE] == 'b', "msync range");
+
+    /* A page written again after msync is dirty again. */
+    addr[1] = 'U';
+    check(munmap(addr, NUM_PAGES * PAGE_SIZE) == 0, "shared munmap");
+    read_pages(fname);
+    check(file_buf[0] == 'S' && file_buf[1] == 'U', "munmap write-back");
+    check(file_buf[PAGE_SIZE] == 'T', "munmap write-back");
+    check(file_buf[2 * PAGE_SIZE] == 'c', "clean page");
+    printf("Shared mapping ok!\n");
+}
+
+/* Unmapping a part of a shared mapping writes back that part only. */
+void test_partial_munmap(const char *fname)
+{
+    char *addr;
+
+    create_pages(fname);
+    addr = map_pages(fname, MAP_SHARED);
+    addr[0] = 'L';
+    addr[PAGE_SIZE] = 'M';
+    addr[2 * PAGE_SIZE] = 'N';
+
+    check(munmap(addr + PAGE_SIZE, PAGE_SIZE) == 0, "partial munmap");
+    read_pages(fname);
+    check(file_buf[PAGE_SIZE] == 'M', "partial munmap write-back");
+    check(file_buf[0] == 'a' && file_buf[2 * PAGE_SIZE] == 'c', "partial munmap range");
+
+    /* The rest of the mapping is still accessible. */
+    check(addr[0] == 'L' && addr[2 * PAGE_SIZE] == 'N', "rest of mapping");
+    addr[2 * PAGE_SIZE + 1] = 'O';
+    check(munmap(addr, PAGE_SIZE) == 0, "munmap head");
+    check(munmap(addr + 2 * PAGE_SIZE, PAGE_SIZE) == 0, "munmap tail");
+    read_pages(fname);
+    check(file_buf[0] == 'L', "head write-back");
+    check(file_buf[2 * PAGE_SIZE] == 'N' && file_buf[2 * PAGE_SIZE + 1] == 'O',
+          "tail write-back");
+    printf("Partial munmap ok!\n");
+}
+
+int main()
+{
+    char fname[] = "test_file";
+
+    printf("MmapTest ...\n");
+
+    test_private_mapping(fname);
+    test_shared_mapping(fname);
+    test_partial_munmap(fname);
+
+    printf("MmapTest ok!\n");
+    return 0;
+}
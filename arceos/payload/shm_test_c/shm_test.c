@@ -0,0 +1,89 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+#include <fcntl.h>
+#include <sys/mman.h>
+#include <sys/shm.h>
+
+#define PAGE_SIZE 4096
+
+void check(int cond, const char *what)
+{
+    if (!cond) {
+        printf("Check %s failed!\n", what);
+        exit(-1);
+    }
+}
+
+/* A System V segment is kept after `IPC_RMID` until it is detached. */
+void test_sysv_shm(void)
+{
+    int shmid;
+    char *addr;
+    char *other;
+
+    shmid = shmget(IPC_PRIVATE, 2 * PAGE_SIZE, IPC_CREAT | 0600);
+    check(shmid >= 0, "shmget");
+    addr = shmat(shmid, NULL, 0);
+    check(addr != (char *)-1, "shmat");
+    other = shmat(shmid, NULL, SHM_RDONLY);
+    check(other != (char *)-1 && other != addr, "shmat again");
+
+    addr[PAGE_SIZE] = 'V';
+    check(other[PAGE_SIZE] == 'V', "shm visible");
+    check(shmctl(shmid, IPC_RMID, NULL) == 0, "IPC_RMID");
+    check(shmat(shmid, NULL, 0) == (char *)-1, "shmat removed");
+
+    /* Still attached. */
+    addr[0] = 'W';
+    check(other[0] == 'W', "shm after IPC_RMID");
+    check(shmdt(addr) == 0, "shmdt");
+    check(other[0] == 'W' && other[PAGE_SIZE] == 'V', "shm after shmdt");
+    check(shmdt(other) == 0, "shmdt again");
+    printf("System V shared memory ok!\n");
+}
+
+/* A POSIX object is kept after `shm_unlink` until it is unmapped. */
+void test_posix_shm(void)
+{
+    const char *name = "/shm_test";
+    int fd;
+    char *addr;
+    char *other;
+
+    fd = shm_open(name, O_RDWR | O_CREAT | O_EXCL, 0600);
+    check(fd >= 0, "shm_open");
+    check(ftruncate(fd, PAGE_SIZE) == 0, "ftruncate");
+    addr = mmap(NULL, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    check(addr != MAP_FAILED, "mmap shm");
+    close(fd);
+
+    fd = shm_open(name, O_RDWR, 0);
+    check(fd >= 0, "shm_open again");
+    other = mmap(NULL, PAGE_SIZE, PROT_READ, MAP_SHARED, fd, 0);
+    check(other != MAP_FAILED && other != addr, "mmap shm again");
+    close(fd);
+
+    addr[0] = 'P';
+    check(other[0] == 'P', "shm visible");
+    check(shm_unlink(name) == 0, "shm_unlink");
+    check(shm_open(name, O_RDWR, 0) < 0, "shm_open unlinked");
+
+    /* Still mapped. */
+    addr[1] = 'Q';
+    check(munmap(addr, PAGE_SIZE) == 0, "munmap shm");
+    check(other[0] == 'P' && other[1] == 'Q', "shm after unlink");
+    check(munmap(other, PAGE_SIZE) == 0, "munmap shm again");
+    printf("POSIX shared memory ok!\n");
+}
+
+int main()
+{
+    printf("ShmTest ...\n");
+
+    test_sysv_shm();
+    test_posix_shm();
+
+    printf("ShmTest ok!\n");
+    return 0;
+}
@@ -48,6 +48,10 @@ else
   $(error "NET_DEV" must be one of "user", "tap", or "bridge")
 endif
 
+ifneq ($(CMDLINE),)
+  qemu_args-y += -append "$(CMDLINE)"
+endif
+
 ifneq ($(VFIO_PCI),)
   qemu_args-y += --device vfio-pci,host=$(VFIO_PCI)
   QEMU := sudo $(QEMU)
@@ -2,5 +2,9 @@
 
 define unit_test
   $(call run_cmd,cargo test,-p axfs $(1) --features "myfs" -- --nocapture)
+  $(call run_cmd,cargo test,-p axmm $(1) --features "swap" -- tests::swap --nocapture)
+  $(call run_cmd,cargo test,-p axtask $(1) --features "irq" -- tests::timer --nocapture)
+  $(call run_cmd,cargo test,-p axtask $(1) --features "sched_rt" -- tests::sched_rt --nocapture)
+  $(call run_cmd,cargo test,-p axtask $(1) --features "lockdep" -- test_lockdep --nocapture)
   $(call run_cmd,cargo test,--workspace $(1) -- --nocapture)
 endef
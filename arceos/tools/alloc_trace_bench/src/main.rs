@@ -0,0 +1,254 @@
+//! Replays allocation traces against the byte allocators of ArceOS on the
+//! host, to compare their speed and fragmentation.
+
+#![deny(warnings)]
+
+mod trace;
+
+use std::alloc::Layout;
+use std::env;
+use std::process::exit;
+use std::ptr::NonNull;
+use std::time::{Duration, Instant};
+
+use allocator::{BuddyByteAllocator, ByteAllocator, SlabByteAllocator, TlsfByteAllocator};
+use segfit_allocator::SegFitByteAllocator;
+
+use crate::trace::{Op, Trace};
+
+const DEFAULT_HEAP_SIZE: usize = 16 << 20; // 16 M
+const DEFAULT_OPS: usize = 100_000;
+const ALLOCATORS: &[&str] = &["tlsf", "slab", "buddy", "segfit"];
+
+const USAGE: &str = "\
+Usage:
+    alloc_trace_bench run <TRACE> [--heap <BYTES>] [--alloc <NAME,...>] [--repeat <N>]
+    alloc_trace_bench gen <PATTERN> [--ops <N>] [--seed <N>]
+
+Allocators: tlsf, slab, buddy, segfit
+Patterns: random, fragment, sawtooth, lab1";
+
+fn new_allocator(name: &str) -> Option<Box<dyn ByteAllocator>> {
+    Some(match name {
+        "tlsf" => Box::new(TlsfByteAllocator::new()),
+        "slab" => Box::new(SlabByteAllocator::new()),
+        "buddy" => Box::new(BuddyByteAllocator::new()),
+        "segfit" => Box::new(SegFitByteAllocator::new()),
+        _ => return None,
+    })
+}
+
+/// The memory managed by the allocators, which is allocated from the host.
+struct Heap {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Heap {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, 4096).unwrap();
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        assert!(!ptr.is_null(), "failed to allocate the heap");
+        Self { ptr, layout }
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    elapsed: Duration,
+    failed: usize,
+    /// The index of the first failed operation.
+    first_failure: Option<usize>,
+    corrupted: usize,
+    peak_used: usize,
+    peak_live: usize,
+    /// The bytes still used after freeing everything.
+    leaked: usize,
+}
+
+/// Replays the trace on a new allocator of the heap.
+fn replay(name: &str, trace: &Trace, heap: &Heap) -> Report {
+    let mut alloc = new_allocator(name).unwrap();
+    alloc.init(heap.ptr as usize, heap.layout.size());
+    let mut slots: Vec<Option<(NonNull<u8>, Layout)>> = vec![None; trace.num_slots];
+    let mut report = Report::default();
+    let mut live = 0;
+
+    let start = Instant::now();
+    for (i, op) in trace.ops.iter().enumerate() {
+        match *op {
+            Op::Alloc { slot, layout } => match alloc.alloc(layout) {
+                Ok(ptr) => {
+                    if layout.size() > 0 {
+                        // Touch the memory, and check it when freed.
+                        unsafe { ptr.as_ptr().write(slot as u8) };
+                    }
+                    slots[slot] = Some((ptr, layout));
+                    live += layout.size();
+                    report.peak_live = report.peak_live.max(live);
+                    report.peak_used = report.peak_used.max(alloc.used_bytes());
+                }
+                Err(_) => {
+                    report.failed += 1;
+                    report.first_failure.get_or_insert(i);
+                }
+            },
+            Op::Free { slot } => {
+                // Skip the allocations that have failed.
+                if let Some((ptr, layout)) = slots[slot].take() {
+                    if layout.size() > 0 && unsafe { ptr.as_ptr().read() } != slot as u8 {
+                        report.corrupted += 1;
+                    }
+                    alloc.dealloc(ptr, layout);
+                    live -= layout.size();
+                }
+            }
+        }
+    }
+    report.elapsed = start.elapsed();
+
+    for (ptr, layout) in slots.into_iter().flatten() {
+        alloc.dealloc(ptr, layout);
+    }
+    report.leaked = alloc.used_bytes();
+    report
+}
+
+fn run(path: &str, heap_size: usize, allocators: &[&str], repeat: usize) {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path, e);
+        exit(1)
+    });
+    let trace = Trace::parse(&text).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        exit(1)
+    });
+    let heap = Heap::new(heap_size);
+    println!(
+        "{}: {} operations, heap {} bytes, best of {} runs",
+        path,
+        trace.ops.len(),
+        heap_size,
+        repeat
+    );
+    println!(
+        "{:<8} {:>10} {:>8} {:>8} {:>10} {:>12} {:>12} {:>9}",
+        "alloc", "time(us)", "ns/op", "failed", "first_fail", "peak_used", "peak_live", "overhead"
+    );
+    for &name in allocators {
+        let mut report = replay(name, &trace, &heap);
+        for _ in 1..repeat {
+            let elapsed = replay(name, &trace, &heap).elapsed;
+            report.elapsed = report.elapsed.min(elapsed);
+        }
+        let ns_per_op = report.elapsed.as_nanos() as f64 / trace.ops.len().max(1) as f64;
+        let overhead = report.peak_used as f64 / report.peak_live.max(1) as f64;
+        println!(
+            "{:<8} {:>10} {:>8.1} {:>8} {:>10} {:>12} {:>12} {:>8.2}x",
+            name,
+            report.elapsed.as_micros(),
+            ns_per_op,
+            report.failed,
+            report
+                .first_failure
+                .map_or("-".into(), |i: usize| i.to_string()),
+            report.peak_used,
+            report.peak_live,
+            overhead
+        );
+        if report.corrupted > 0 {
+            println!("  error: {} allocations corrupted", report.corrupted);
+        }
+        if report.leaked > 0 {
+            println!(
+                "  warning: {} bytes still used after freeing all",
+                report.leaked
+            );
+        }
+    }
+}
+
+/// Parses the `--name value` options.
+fn parse_options(args: &[String]) -> Vec<(&str, &str)> {
+    let mut options = Vec::new();
+    for pair in args.chunks(2) {
+        match pair {
+            [name, value] if name.starts_with("--") => options.push((&name[2..], value.as_str())),
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(1)
+            }
+        }
+    }
+    options
+}
+
+fn parse_num(value: &str) -> usize {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("invalid number: {}", value);
+        exit(1)
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        exit(1)
+    }
+    let options = parse_options(&args[2..]);
+    match args[0].as_str() {
+        "run" => {
+            let mut heap_size = DEFAULT_HEAP_SIZE;
+            let mut allocators = ALLOCATORS.to_vec();
+            let mut repeat = 1;
+            for (name, value) in options {
+                match name {
+                    "heap" => heap_size = parse_num(value),
+                    "alloc" => allocators = value.split(',').collect(),
+                    "repeat" => repeat = parse_num(value).max(1),
+                    _ => {
+                        eprintln!("{}", USAGE);
+                        exit(1)
+                    }
+                }
+            }
+            if let Some(name) = allocators.iter().find(|n| !ALLOCATORS.contains(n)) {
+                eprintln!("unknown allocator: {}", name);
+                exit(1)
+            }
+            run(&args[1], heap_size, &allocators, repeat);
+        }
+        "gen" => {
+            let mut num_ops = DEFAULT_OPS;
+            let mut seed = 1;
+            for (name, value) in options {
+                match name {
+                    "ops" => num_ops = parse_num(value),
+                    "seed" => seed = parse_num(value) as u64,
+                    _ => {
+                        eprintln!("{}", USAGE);
+                        exit(1)
+                    }
+                }
+            }
+            match trace::generate(&args[1], num_ops, seed) {
+                Some(text) => print!("{}", text),
+                None => {
+                    eprintln!("unknown pattern: {}, see {:?}", args[1], trace::PATTERNS);
+                    exit(1)
+                }
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(1)
+        }
+    }
+}
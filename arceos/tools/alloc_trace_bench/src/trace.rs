@@ -0,0 +1,200 @@
+//! Allocation traces, and the generators of synthetic traces.
+//!
+//! A trace is a text file with one operation per line:
+//!
+//! - `a <id> <size> <align>`: allocates `size` bytes aligned to `align`, and
+//!   names the allocation by `id`.
+//! - `f <id>`: frees the allocation named by `id`, which can be reused by a
+//!   later allocation.
+//!
+//! Empty lines and lines starting with `#` are ignored.
+
+use std::alloc::Layout;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// An operation of a trace. The allocations are identified by the slot
+/// indices, which are never reused in a trace.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Alloc { slot: usize, layout: Layout },
+    Free { slot: usize },
+}
+
+/// A parsed trace.
+pub struct Trace {
+    pub ops: Vec<Op>,
+    pub num_slots: usize,
+}
+
+impl Trace {
+    /// Parses a trace from the text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut ops = Vec::new();
+        let mut live = HashMap::new();
+        let mut num_slots = 0;
+        for (lineno, line) in text.lines().enumerate() {
+            let err = |msg: &str| format!("line {}: {}: {:?}", lineno + 1, msg, line);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let num = |i: usize| -> Result<usize, String> {
+                let field = fields.get(i).ok_or_else(|| err("missing field"))?;
+                field.parse().map_err(|_| err("invalid number"))
+            };
+            match fields[0] {
+                "a" => {
+                    let (id, size, align) = (num(1)?, num(2)?, num(3)?);
+                    let layout =
+                        Layout::from_size_align(size, align).map_err(|_| err("invalid layout"))?;
+                    if live.insert(id, num_slots).is_some() {
+                        return Err(err("id is in use"));
+                    }
+                    ops.push(Op::Alloc {
+                        slot: num_slots,
+                        layout,
+                    });
+                    num_slots += 1;
+                }
+                "f" => {
+                    let slot = live
+                        .remove(&num(1)?)
+                        .ok_or_else(|| err("id is not in use"))?;
+                    ops.push(Op::Free { slot });
+                }
+                _ => return Err(err("unknown operation")),
+            }
+        }
+        Ok(Self { ops, num_slots })
+    }
+}
+
+/// A xorshift random number generator.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+
+    fn range(&mut self, low: usize, high: usize) -> usize {
+        low + self.next() % (high - low)
+    }
+}
+
+/// Builds the text of a trace, reusing the freed ids.
+#[derive(Default)]
+struct TraceWriter {
+    text: String,
+    free_ids: Vec<usize>,
+    next_id: usize,
+    num_ops: usize,
+}
+
+impl TraceWriter {
+    fn alloc(&mut self, size: usize, align: usize) -> usize {
+        let id = self.free_ids.pop().unwrap_or_else(|| {
+            self.next_id += 1;
+            self.next_id - 1
+        });
+        writeln!(self.text, "a {} {} {}", id, size, align).unwrap();
+        self.num_ops += 1;
+        id
+    }
+
+    fn free(&mut self, id: usize) {
+        writeln!(self.text, "f {}", id).unwrap();
+        self.free_ids.push(id);
+        self.num_ops += 1;
+    }
+}
+
+/// The names of the synthetic trace patterns.
+pub const PATTERNS: &[&str] = &["random", "fragment", "sawtooth", "lab1"];
+
+/// Generates a trace of about `num_ops` operations by the pattern.
+pub fn generate(pattern: &str, num_ops: usize, seed: u64) -> Option<String> {
+    let mut rng = Rng(seed.max(1));
+    let mut w = TraceWriter::default();
+    writeln!(w.text, "# pattern: {}, seed: {}", pattern, seed).unwrap();
+    match pattern {
+        // Random sizes and lifetimes, with at most 1024 live allocations.
+        "random" => {
+            let mut live = Vec::new();
+            while w.num_ops < num_ops {
+                if live.len() < 1024 && rng.next() % 2 == 0 {
+                    let size = 1 << rng.range(3, 13);
+                    live.push(w.alloc(rng.range(1, size + 1), 8));
+                } else if !live.is_empty() {
+                    let idx = rng.range(0, live.len());
+                    w.free(live.swap_remove(idx));
+                }
+            }
+        }
+        // Short-lived small objects interleaved with long-lived ones, so the
+        // freed space is scattered in small holes, then larger objects which
+        // do not fit in the holes.
+        "fragment" => {
+            let mut long_lived = Vec::new();
+            while w.num_ops < num_ops {
+                let mut short_lived = Vec::new();
+                for i in 0..256 {
+                    short_lived.push(w.alloc(rng.range(16, 256), 8));
+                    if i % 8 == 0 {
+                        long_lived.push(w.alloc(rng.range(16, 128), 8));
+                    }
+                }
+                for id in short_lived {
+                    w.free(id);
+                }
+                let large: Vec<_> = (0..16)
+                    .map(|_| w.alloc(rng.range(1024, 8192), 16))
+                    .collect();
+                for id in large {
+                    w.free(id);
+                }
+                if long_lived.len() > 2048 {
+                    for id in long_lived.drain(..1024) {
+                        w.free(id);
+                    }
+                }
+            }
+        }
+        // Allocates objects of mixed sizes and alignments, then frees all of
+        // them in a random order.
+        "sawtooth" => {
+            while w.num_ops < num_ops {
+                let mut live = Vec::new();
+                for _ in 0..rng.range(256, 2048) {
+                    let align = 1 << rng.range(0, 7);
+                    live.push(w.alloc(rng.range(1, 4096), align));
+                }
+                while !live.is_empty() {
+                    let idx = rng.range(0, live.len());
+                    w.free(live.swap_remove(idx));
+                }
+            }
+        }
+        // Like the lab1 application: each round allocates a group of objects
+        // with growing sizes, then frees every other one of them.
+        "lab1" => {
+            let mut round = 0;
+            while w.num_ops < num_ops {
+                let ids: Vec<_> = (0..30)
+                    .map(|i| w.alloc((1 << (i % 15)) + round, 8))
+                    .collect();
+                for id in ids.into_iter().step_by(2) {
+                    w.free(id);
+                }
+                round += 1;
+            }
+        }
+        _ => return None,
+    }
+    Some(w.text)
+}
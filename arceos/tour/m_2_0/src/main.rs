@@ -71,6 +71,7 @@ fn init_user_stack(uspace: &mut AddrSpace, populating: bool) -> io::Result<VirtA
 #[register_trap_handler(PAGE_FAULT)]
 fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool) -> bool {
     if is_user {
+        axtask::current().account_kernel_entry();
         if !axtask::current()
             .task_ext()
             .aspace
@@ -82,6 +83,7 @@ fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool)
         } else {
             ax_println!("{}: handle page fault OK!", axtask::current().id_name());
         }
+        axtask::current().account_user_entry();
         true
     } else {
         false
@@ -8,6 +8,7 @@ const SYS_EXIT: usize = 93;
 
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
+    axtask::current().account_kernel_entry();
     ax_println!("handle_syscall ...");
     let ret = match syscall_num {
         SYS_EXIT => {
@@ -19,5 +20,8 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
             -LinuxError::ENOSYS.code() as _
         }
     };
+    // Returning to the user space, where a killed task holds no resources.
+    axtask::exit_if_killed();
+    axtask::current().account_user_entry();
     ret
 }
@@ -16,6 +16,7 @@ const SYS_SET_TID_ADDRESS: usize = 96;
 
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
+    axtask::current().account_kernel_entry();
     ax_println!("handle_syscall [{}] ...", syscall_num);
     let ret = match syscall_num {
          SYS_IOCTL => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
@@ -34,6 +35,9 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
             -LinuxError::ENOSYS.code() as _
         }
     };
+    // Returning to the user space, where a killed task holds no resources.
+    axtask::exit_if_killed();
+    axtask::current().account_user_entry();
     ret
 }
 
@@ -1,11 +1,17 @@
 #![allow(dead_code)]
 
 use core::ffi::{c_void, c_char, c_int};
+use core::sync::atomic::{AtomicU32, Ordering};
+use alloc::sync::Arc;
 use axhal::arch::TrapFrame;
+use axhal::mem::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+use axhal::paging::MappingFlags;
 use axhal::trap::{register_trap_handler, SYSCALL};
 use axerrno::LinuxError;
+use axmm::AddrSpace;
 use axtask::current;
 use axtask::TaskExtRef;
+use axtask::futex::{self, FutexError, FutexKey};
 use arceos_posix_api as api;
 
 const SYS_IOCTL: usize = 29;
@@ -17,11 +23,19 @@ const SYS_WRITEV: usize = 66;
 const SYS_EXIT: usize = 93;
 const SYS_EXIT_GROUP: usize = 94;
 const SYS_SET_TID_ADDRESS: usize = 96;
+const SYS_FUTEX: usize = 98;
 
 const AT_FDCWD: i32 = -100;
 
+const FUTEX_WAIT: i32 = 0;
+const FUTEX_WAKE: i32 = 1;
+const FUTEX_REQUEUE: i32 = 3;
+const FUTEX_PRIVATE_FLAG: i32 = 128;
+const FUTEX_CLOCK_REALTIME: i32 = 256;
+
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
+    axtask::current().account_kernel_entry();
     ax_println!("handle_syscall [{}] ...", syscall_num);
     let ret = match syscall_num {
          SYS_IOCTL => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
@@ -31,12 +45,14 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
         SYS_READ => sys_read(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         SYS_WRITE => sys_write(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         SYS_WRITEV => sys_writev(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        SYS_FUTEX => sys_futex(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _, tf.arg3() as _, tf.arg4() as _),
         SYS_EXIT_GROUP => {
             ax_println!("[SYS_EXIT_GROUP]: system is exiting ..");
             axtask::exit(tf.arg0() as _)
         },
         SYS_EXIT => {
             ax_println!("[SYS_EXIT]: system is exiting ..");
+            clear_child_tid();
             axtask::exit(tf.arg0() as _)
         },
         _ => {
@@ -44,6 +60,9 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
             -LinuxError::ENOSYS.code() as _
         }
     };
+    // Returning to the user space, where a killed task holds no resources.
+    axtask::exit_if_killed();
+    axtask::current().account_user_entry();
     ret
 }
 
@@ -78,3 +97,112 @@ fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> i32 {
     ax_println!("Ignore SYS_IOCTL");
     0
 }
+
+/// Checks that the user memory `[addr, addr + size)` can be accessed with
+/// `flags`, and faults in the pages that are not mapped yet (e.g., the lazily
+/// allocated ones), so that the kernel can access the memory directly.
+fn check_user_range(aspace: &mut AddrSpace, addr: usize, size: usize, flags: MappingFlags) -> Result<(), LinuxError> {
+    let flags = flags | MappingFlags::USER;
+    let end = addr.checked_add(size).ok_or(LinuxError::EFAULT)?;
+    let mut vaddr = VirtAddr::from(addr).align_down_4k();
+    while vaddr < VirtAddr::from(end) {
+        let mapped = |aspace: &AddrSpace| {
+            matches!(aspace.page_table().query(vaddr), Ok((_, f, _)) if f.contains(flags))
+        };
+        if !mapped(aspace) && !(aspace.handle_page_fault(vaddr, flags) && mapped(aspace)) {
+            return Err(LinuxError::EFAULT);
+        }
+        vaddr += PAGE_SIZE_4K;
+    }
+    Ok(())
+}
+
+/// Returns the futex key of the user address.
+///
+/// A private futex is keyed by the address space and the virtual address. A
+/// shared one is keyed by the physical address, so that the threads sharing
+/// the memory share the futex. Its page is faulted in for writing, so that a
+/// copy-on-write frame shared by other address spaces is not the key.
+fn futex_key(uaddr: usize, private: bool) -> Result<FutexKey, LinuxError> {
+    if uaddr % 4 != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let aspace = current().task_ext().aspace.clone();
+    let mut guard = aspace.lock();
+    if private {
+        check_user_range(&mut guard, uaddr, 4, MappingFlags::READ)?;
+        return Ok(FutexKey::Private {
+            space: Arc::as_ptr(&aspace) as usize,
+            addr: uaddr,
+        });
+    }
+    check_user_range(&mut guard, uaddr, 4, MappingFlags::READ | MappingFlags::WRITE)?;
+    let (paddr, _, _) = guard
+        .page_table()
+        .query(uaddr.into())
+        .map_err(|_| LinuxError::EFAULT)?;
+    Ok(FutexKey::Shared(paddr.as_usize()))
+}
+
+fn sys_futex(uaddr: usize, op: i32, val: u32, timeout: usize, uaddr2: usize) -> isize {
+    match futex_op(uaddr, op, val, timeout, uaddr2) {
+        Ok(n) => n as isize,
+        Err(e) => -e.code() as isize,
+    }
+}
+
+fn futex_op(uaddr: usize, op: i32, val: u32, timeout: usize, uaddr2: usize) -> Result<usize, LinuxError> {
+    let private = op & FUTEX_PRIVATE_FLAG != 0;
+    let key = futex_key(uaddr, private)?;
+    match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+        FUTEX_WAIT => {
+            let word = unsafe { &*(uaddr as *const AtomicU32) };
+            let check = || word.load(Ordering::SeqCst) == val;
+            let res = if timeout == 0 {
+                futex::futex_wait(key, check)
+            } else {
+                let size = core::mem::size_of::<api::ctypes::timespec>();
+                let aspace = current().task_ext().aspace.clone();
+                check_user_range(&mut aspace.lock(), timeout, size, MappingFlags::READ)?;
+                let ts = unsafe { (timeout as *const api::ctypes::timespec).read_unaligned() };
+                if ts.tv_sec < 0 || !(0..1_000_000_000).contains(&ts.tv_nsec) {
+                    return Err(LinuxError::EINVAL);
+                }
+                let dur = core::time::Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+                futex::futex_wait_timeout(key, check, dur)
+            };
+            res.map(|_| 0).map_err(|e| match e {
+                FutexError::WouldBlock => LinuxError::EAGAIN,
+                FutexError::TimedOut => LinuxError::ETIMEDOUT,
+                FutexError::Interrupted => LinuxError::EINTR,
+            })
+        }
+        FUTEX_WAKE => Ok(futex::futex_wake(key, val as usize)),
+        FUTEX_REQUEUE => {
+            // The 4th argument is the maximum number of waiters to requeue.
+            let (woken, requeued) = futex::futex_requeue(key, val as usize, futex_key(uaddr2, private)?, timeout);
+            Ok(woken + requeued)
+        }
+        _ => {
+            ax_println!("Unimplemented futex op: {}", op);
+            Err(LinuxError::ENOSYS)
+        }
+    }
+}
+
+/// Clears the word at the `clear_child_tid` address and wakes up a waiter on
+/// it, which is how the threads waiting for the current thread are notified.
+///
+/// The waiter may wait on either the private or the shared futex, so both of
+/// them are woken up.
+fn clear_child_tid() {
+    let tid_ptr = current().task_ext().clear_child_tid() as usize;
+    if tid_ptr != 0 {
+        // The address is checked when the word is faulted in for the key.
+        if let (Ok(shared), Ok(private)) = (futex_key(tid_ptr, false), futex_key(tid_ptr, true)) {
+            unsafe { (*(tid_ptr as *const AtomicU32)).store(0, Ordering::SeqCst) };
+            futex::futex_wake(private, 1);
+            futex::futex_wake(shared, 1);
+        }
+    }
+}
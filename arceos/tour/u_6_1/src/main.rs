@@ -26,7 +26,7 @@ fn main() {
         ax_println!("worker1 ...");
         for i in 0..=LOOP_NUM {
             ax_println!("worker1 [{i}]");
-            q1.lock().push_back(i);
+            q1.lock().unwrap().push_back(i);
             WQ.notify_one(true);
         }
         ax_println!("worker1 ok!");
@@ -35,7 +35,7 @@ fn main() {
     let worker2 = thread::spawn(move || {
         ax_println!("worker2 ...");
         loop {
-            if let Some(num) = q2.lock().pop_front() {
+            if let Some(num) = q2.lock().unwrap().pop_front() {
                 ax_println!("worker2 [{num}]");
                 if num == LOOP_NUM {
                     break;
@@ -1,4 +1,4 @@
-use arceos_posix_api::{sys_clock_gettime, sys_nanosleep};
+use arceos_posix_api::{sys_clock_gettime, sys_nanosleep, sys_times};
 use core::ffi::c_int;
 
 use crate::{ctypes, utils::e};
@@ -19,3 +19,9 @@ pub unsafe extern "C" fn nanosleep(
 ) -> c_int {
     e(sys_nanosleep(req, rem))
 }
+
+/// Get process times
+#[no_mangle]
+pub unsafe extern "C" fn times(buf: *mut ctypes::tms) -> ctypes::clock_t {
+    sys_times(buf)
+}
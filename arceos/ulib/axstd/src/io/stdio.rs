@@ -1,5 +1,5 @@
 use crate::io::{self, prelude::*, BufReader};
-use crate::sync::{Mutex, MutexGuard};
+use crate::sync::{RawMutex as Mutex, RawMutexGuard as MutexGuard};
 
 #[cfg(feature = "alloc")]
 use alloc::{string::String, vec::Vec};
@@ -15,18 +15,31 @@
 //!     - `fp_simd`: Enable floating point and SIMD support.
 //! - Interrupts:
 //!     - `irq`: Enable interrupt handling support.
+//!     - `nohz`: Stop the timer tick on idle CPUs (tickless idle).
 //! - Memory
 //!     - `alloc`: Enable dynamic memory allocation.
 //!     - `alloc-tlsf`: Use the TLSF allocator.
 //!     - `alloc-slab`: Use the slab allocator.
 //!     - `alloc-buddy`: Use the buddy system allocator.
+//!     - `alloc-segfit`: Use the segregated-fit allocator.
+//!     - `alloc-percpu-cache`: Cache small objects per CPU to reduce the lock contention.
+//!     - `alloc-stats`: Record heap statistics, and print them at shutdown.
+//!     - `alloc-stats-callsite`: Also track live allocations by call sites to find leaks.
+//!     - `alloc-debug`: Detect heap corruption by red zones and use-after-free poisoning.
+//!     - `alloc-debug-guard-pages`: Also place large allocations on their own pages with guard pages.
+//!     - `oom-return-null`: Return null on out of memory instead of panicking, for C apps.
+//!     - `oom-kill-task`: Kill the allocating task on out of memory instead of panicking.
 //!     - `paging`: Enable page table manipulation.
 //!     - `tls`: Enable thread-local storage.
+//!     - `swap`: Swap the cold anonymous pages to a block device (the second one with `fs`).
 //! - Task management
 //!     - `multitask`: Enable multi-threading support.
 //!     - `sched_fifo`: Use the FIFO cooperative scheduler.
 //!     - `sched_rr`: Use the Round-robin preemptive scheduler.
 //!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler.
+//!     - `sched_rt`: Use the layered scheduler with deadline and real-time classes on top of CFS.
+//!     - `lockdep`: Validate the lock acquisition order and report possible deadlocks at runtime.
+//!     - `stack_guard`: Place a guard page below each kernel stack to catch stack overflows.
 //! - Upperlayer stacks
 //!     - `fs`: Enable file system support.
 //!     - `myfs`: Allow users to define their custom filesystems to override the default.
@@ -0,0 +1,112 @@
+//! A condition variable with the same interface as [`std::sync::Condvar`].
+//!
+//! [`std::sync::Condvar`]: https://doc.rust-lang.org/std/sync/struct.Condvar.html
+
+use core::fmt;
+#[cfg(feature = "irq")]
+use core::time::Duration;
+
+use arceos_api::modules::axsync;
+
+#[cfg(feature = "irq")]
+use super::WaitTimeoutResult;
+use super::{LockResult, MutexGuard};
+
+/// A condition variable that works with the [`Mutex`](super::Mutex) here.
+///
+/// It is the one from [`axsync`], but the waiting methods return
+/// [`LockResult`] as in `std`. Like the one in `std`, a waiting task may be
+/// woken up spuriously.
+pub struct Condvar {
+    inner: axsync::Condvar,
+}
+
+impl Condvar {
+    /// Creates a new condition variable which is ready to be waited on and
+    /// notified.
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            inner: axsync::Condvar::new(),
+        }
+    }
+
+    /// Blocks the current task until this condition variable receives a
+    /// notification.
+    ///
+    /// The mutex of the `guard` is unlocked before blocking, and is locked
+    /// again before returning.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        Ok(MutexGuard {
+            inner: self.inner.wait(guard.inner),
+        })
+    }
+
+    /// Blocks the current task while the `condition` returns `true`.
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        Ok(MutexGuard {
+            inner: self.inner.wait_while(guard.inner, condition),
+        })
+    }
+
+    /// Waits on this condition variable for a notification, timing out after
+    /// the specified duration.
+    #[cfg(feature = "irq")]
+    #[doc(cfg(feature = "irq"))]
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let (inner, res) = self.inner.wait_timeout(guard.inner, dur);
+        Ok((MutexGuard { inner }, res))
+    }
+
+    /// Waits on this condition variable while the `condition` returns `true`,
+    /// timing out after the specified duration.
+    #[cfg(feature = "irq")]
+    #[doc(cfg(feature = "irq"))]
+    pub fn wait_timeout_while<'a, T: ?Sized, F>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+        condition: F,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let (inner, res) = self.inner.wait_timeout_while(guard.inner, dur, condition);
+        Ok((MutexGuard { inner }, res))
+    }
+
+    /// Wakes up one blocked task on this condition variable.
+    #[inline(always)]
+    pub fn notify_one(&self) {
+        self.inner.notify_one();
+    }
+
+    /// Wakes up all blocked tasks on this condition variable.
+    #[inline(always)]
+    pub fn notify_all(&self) {
+        self.inner.notify_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Condvar").finish_non_exhaustive()
+    }
+}
@@ -1,4 +1,9 @@
 //! Useful synchronization primitives.
+//!
+//! [`Mutex`], [`RwLock`] and [`Condvar`] have the same interfaces as the ones
+//! in `std`, i.e., their locking methods return [`LockResult`]. The locks are
+//! never poisoned, see [`PoisonError`]. The other primitives are the ones
+//! from [`axsync`](arceos_api::modules::axsync).
 
 #[doc(no_inline)]
 pub use core::sync::atomic;
@@ -8,12 +13,36 @@ pub use core::sync::atomic;
 pub use alloc::sync::{Arc, Weak};
 
 #[cfg(feature = "multitask")]
+mod condvar;
 mod mutex;
+mod poison;
+#[cfg(feature = "multitask")]
+mod rwlock;
+
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::poison::{LockResult, PoisonError, TryLockError, TryLockResult};
 
 #[cfg(feature = "multitask")]
 #[doc(cfg(feature = "multitask"))]
-pub use self::mutex::{Mutex, MutexGuard};
+pub use self::{
+    condvar::Condvar,
+    rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 
+// The sleeping primitives are all from `axsync`, so that `Condvar` works with
+// the `Mutex` here.
+#[cfg(feature = "multitask")]
+#[doc(cfg(feature = "multitask"))]
+pub use arceos_api::modules::axsync::{
+    Barrier, BarrierWaitResult, LazyLock, Once, Semaphore, SemaphoreGuard,
+};
+
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[doc(cfg(all(feature = "multitask", feature = "irq")))]
+pub use arceos_api::modules::axsync::WaitTimeoutResult;
+
+// The locks without poisoning, which the wrappers are built on.
+#[cfg(feature = "multitask")]
+pub(crate) use arceos_api::modules::axsync::{Mutex as RawMutex, MutexGuard as RawMutexGuard};
 #[cfg(not(feature = "multitask"))]
-#[doc(cfg(not(feature = "multitask")))]
-pub use kspin::{SpinRaw as Mutex, SpinRawGuard as MutexGuard}; // never used in IRQ context
+pub(crate) use kspin::{SpinRaw as RawMutex, SpinRawGuard as RawMutexGuard}; // never used in IRQ context
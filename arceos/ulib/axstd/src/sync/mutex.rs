@@ -1,198 +1,138 @@
-//! A naïve sleeping mutex.
+//! A mutex with the same interface as [`std::sync::Mutex`].
+//!
+//! [`std::sync::Mutex`]: https://doc.rust-lang.org/std/sync/struct.Mutex.html
 
-use core::cell::UnsafeCell;
 use core::fmt;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicU64, Ordering};
 
-use arceos_api::task::{self as api, AxWaitQueueHandle};
+use super::{LockResult, RawMutex, RawMutexGuard, TryLockError, TryLockResult};
 
-/// A mutual exclusion primitive useful for protecting shared data, similar to
-/// [`std::sync::Mutex`](https://doc.rust-lang.org/std/sync/struct.Mutex.html).
+/// A mutual exclusion primitive useful for protecting shared data.
 ///
-/// When the mutex is locked, the current task will block and be put into the
-/// wait queue. When the mutex is unlocked, all tasks waiting on the queue
-/// will be woken up.
+/// It is a sleeping mutex from [`axsync`] if the `multitask` feature is
+/// enabled, otherwise a spinlock. Unlike the one in [`axsync`], the locking
+/// methods return [`LockResult`] as in `std`, but the mutex is never
+/// poisoned.
+///
+/// [`axsync`]: arceos_api::modules::axsync
 pub struct Mutex<T: ?Sized> {
-    wq: AxWaitQueueHandle,
-    owner_id: AtomicU64,
-    data: UnsafeCell<T>,
+    inner: RawMutex<T>,
 }
 
-/// A guard that provides mutable data access.
-///
-/// When the guard falls out of scope it will release the lock.
+/// An RAII implementation of a "scoped lock" of a mutex. When this structure
+/// is dropped (falls out of scope), the lock will be unlocked.
 pub struct MutexGuard<'a, T: ?Sized + 'a> {
-    lock: &'a Mutex<T>,
-    data: *mut T,
+    pub(super) inner: RawMutexGuard<'a, T>,
 }
 
-// Same unsafe impls as `std::sync::Mutex`
-unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
-unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
-
 impl<T> Mutex<T> {
-    /// Creates a new [`Mutex`] wrapping the supplied data.
+    /// Creates a new mutex in an unlocked state ready for use.
     #[inline(always)]
-    pub const fn new(data: T) -> Self {
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(t: T) -> Self {
         Self {
-            wq: AxWaitQueueHandle::new(),
-            owner_id: AtomicU64::new(0),
-            data: UnsafeCell::new(data),
+            inner: RawMutex::new(t),
         }
     }
 
-    /// Consumes this [`Mutex`] and unwraps the underlying data.
+    /// Consumes this mutex, returning the underlying data.
     #[inline(always)]
-    pub fn into_inner(self) -> T {
-        // We know statically that there are no outstanding references to
-        // `self` so there's no need to lock.
-        let Mutex { data, .. } = self;
-        data.into_inner()
+    pub fn into_inner(self) -> LockResult<T> {
+        Ok(self.inner.into_inner())
     }
 }
 
 impl<T: ?Sized> Mutex<T> {
-    /// Returns `true` if the lock is currently held.
-    ///
-    /// # Safety
-    ///
-    /// This function provides no synchronization guarantees and so its result should be considered 'out of date'
-    /// the instant it is called. Do not use it for synchronization purposes. However, it may be useful as a heuristic.
+    /// Acquires a mutex, blocking the current task until it is able to do so.
     #[inline(always)]
-    pub fn is_locked(&self) -> bool {
-        self.owner_id.load(Ordering::Relaxed) != 0
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        Ok(MutexGuard {
+            inner: self.inner.lock(),
+        })
     }
 
-    /// Locks the [`Mutex`] and returns a guard that permits access to the inner data.
+    /// Attempts to acquire this lock, without blocking.
     ///
-    /// The returned value may be dereferenced for data access
-    /// and the lock will be dropped when the guard falls out of scope.
-    pub fn lock(&self) -> MutexGuard<T> {
-        let current_id = api::ax_current_task_id();
-        loop {
-            // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
-            // when called in a loop.
-            match self.owner_id.compare_exchange_weak(
-                0,
-                current_id,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => break,
-                Err(owner_id) => {
-                    assert_ne!(
-                        owner_id, current_id,
-                        "Thread({}) tried to acquire mutex it already owns.",
-                        current_id,
-                    );
-                    // Wait until the lock looks unlocked before retrying
-                    api::ax_wait_queue_wait(&self.wq, || !self.is_locked(), None);
-                }
-            }
-        }
-        MutexGuard {
-            lock: self,
-            data: unsafe { &mut *self.data.get() },
+    /// Returns [`TryLockError::WouldBlock`] if the lock is held by others.
+    #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        match self.inner.try_lock() {
+            Some(inner) => Ok(MutexGuard { inner }),
+            None => Err(TryLockError::WouldBlock),
         }
     }
 
-    /// Try to lock this [`Mutex`], returning a lock guard if successful.
+    /// Determines whether the mutex is poisoned, which is always `false`.
     #[inline(always)]
-    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        let current_id = api::ax_current_task_id();
-        // The reason for using a strong compare_exchange is explained here:
-        // https://github.com/Amanieu/parking_lot/pull/207#issuecomment-575869107
-        if self
-            .owner_id
-            .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
-        {
-            Some(MutexGuard {
-                lock: self,
-                data: unsafe { &mut *self.data.get() },
-            })
-        } else {
-            None
-        }
+    pub fn is_poisoned(&self) -> bool {
+        false
     }
 
-    /// Force unlock the [`Mutex`].
-    ///
-    /// # Safety
-    ///
-    /// This is *extremely* unsafe if the lock is not held by the current
-    /// thread. However, this can be useful in some instances for exposing
-    /// the lock to FFI that doesn’t know how to deal with RAII.
-    pub unsafe fn force_unlock(&self) {
-        let owner_id = self.owner_id.swap(0, Ordering::Release);
-        let current_id = api::ax_current_task_id();
-        assert_eq!(
-            owner_id, current_id,
-            "Thread({}) tried to release mutex it doesn't own",
-            current_id,
-        );
-        // wake up one waiting thread.
-        api::ax_wait_queue_wake(&self.wq, 1);
-    }
+    /// Clears the poisoned state from a mutex, which does nothing.
+    #[inline(always)]
+    pub fn clear_poison(&self) {}
 
     /// Returns a mutable reference to the underlying data.
     ///
-    /// Since this call borrows the [`Mutex`] mutably, and a mutable reference is guaranteed to be exclusive in
-    /// Rust, no actual locking needs to take place -- the mutable borrow statically guarantees no locks exist. As
-    /// such, this is a 'zero-cost' operation.
+    /// No locking needs to take place, as the mutable borrow statically
+    /// guarantees no locks exist.
     #[inline(always)]
-    pub fn get_mut(&mut self) -> &mut T {
-        // We know statically that there are no other references to `self`, so
-        // there's no need to lock the inner mutex.
-        unsafe { &mut *self.data.get() }
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        Ok(self.inner.get_mut())
     }
 }
 
-impl<T: ?Sized + Default> Default for Mutex<T> {
-    #[inline(always)]
+impl<T: Default> Default for Mutex<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
     fn default() -> Self {
-        Self::new(Default::default())
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    fn from(t: T) -> Self {
+        Self::new(t)
     }
 }
 
 impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
         match self.try_lock() {
-            Some(guard) => write!(f, "Mutex {{ data: ")
-                .and_then(|()| (*guard).fmt(f))
-                .and_then(|()| write!(f, "}}")),
-            None => write!(f, "Mutex {{ <locked> }}"),
-        }
+            Ok(guard) => d.field("data", &&*guard),
+            Err(_) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &false).finish_non_exhaustive()
     }
 }
 
-impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
     type Target = T;
+
     #[inline(always)]
     fn deref(&self) -> &T {
-        // We know statically that only we are referencing data
-        unsafe { &*self.data }
+        &self.inner
     }
 }
 
-impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
     #[inline(always)]
     fn deref_mut(&mut self) -> &mut T {
-        // We know statically that only we are referencing data
-        unsafe { &mut *self.data }
+        &mut self.inner
     }
 }
 
-impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'a, T> {
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Debug::fmt(&**self, f)
     }
 }
 
-impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
-    /// The dropping of the [`MutexGuard`] will release the lock it was created from.
-    fn drop(&mut self) {
-        unsafe { self.lock.force_unlock() }
+impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
     }
 }
@@ -0,0 +1,93 @@
+//! The lock poisoning, for the compatibility with `std::sync`.
+//!
+//! A lock in `std` is poisoned if a thread panics while holding it. Panics
+//! never unwind in ArceOS, so the locks here are never poisoned, and the
+//! [`Err`] variants of [`LockResult`] and [`TryLockResult`] are never returned
+//! for poisoning. They only exist so that the code written for `std` works
+//! without changes.
+
+use core::fmt;
+
+/// A type of error which can be returned whenever a lock is acquired.
+///
+/// Never returned in ArceOS, see the [module-level documentation](self).
+pub struct PoisonError<T> {
+    guard: T,
+}
+
+/// An enumeration of possible errors associated with a [`TryLockResult`].
+pub enum TryLockError<T> {
+    /// The lock could not be acquired because another task failed while
+    /// holding it. Never returned in ArceOS.
+    Poisoned(PoisonError<T>),
+    /// The lock could not be acquired at this time because the operation
+    /// would otherwise block.
+    WouldBlock,
+}
+
+/// A type alias for the result of a lock method which can be poisoned.
+pub type LockResult<Guard> = Result<Guard, PoisonError<Guard>>;
+
+/// A type alias for the result of a nonblocking locking method.
+pub type TryLockResult<Guard> = Result<Guard, TryLockError<Guard>>;
+
+impl<T> PoisonError<T> {
+    /// Creates a [`PoisonError`].
+    pub fn new(guard: T) -> Self {
+        Self { guard }
+    }
+
+    /// Consumes this error, returning the underlying guard to allow access
+    /// regardless.
+    pub fn into_inner(self) -> T {
+        self.guard
+    }
+
+    /// Reaches into this error, returning a reference to the underlying
+    /// guard.
+    pub fn get_ref(&self) -> &T {
+        &self.guard
+    }
+
+    /// Reaches into this error, returning a mutable reference to the
+    /// underlying guard.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> fmt::Debug for PoisonError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for PoisonError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        "poisoned lock: another task failed inside".fmt(f)
+    }
+}
+
+impl<T> From<PoisonError<T>> for TryLockError<T> {
+    fn from(err: PoisonError<T>) -> Self {
+        Self::Poisoned(err)
+    }
+}
+
+impl<T> fmt::Debug for TryLockError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Poisoned(..) => "Poisoned(..)".fmt(f),
+            Self::WouldBlock => "WouldBlock".fmt(f),
+        }
+    }
+}
+
+impl<T> fmt::Display for TryLockError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Poisoned(err) => err.fmt(f),
+            Self::WouldBlock => "try_lock failed because the operation would block".fmt(f),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! A readers-writer lock with the same interface as [`std::sync::RwLock`].
+//!
+//! [`std::sync::RwLock`]: https://doc.rust-lang.org/std/sync/struct.RwLock.html
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+use arceos_api::modules::axsync;
+
+use super::{LockResult, TryLockError, TryLockResult};
+
+/// A reader-writer lock.
+///
+/// It is the sleeping lock from [`axsync`], but the locking methods return
+/// [`LockResult`] as in `std`. The lock is never poisoned.
+pub struct RwLock<T: ?Sized> {
+    inner: axsync::RwLock<T>,
+}
+
+/// RAII structure used to release the shared read access of a lock when
+/// dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    inner: axsync::RwLockReadGuard<'a, T>,
+}
+
+/// RAII structure used to release the exclusive write access of a lock when
+/// dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    inner: axsync::RwLockWriteGuard<'a, T>,
+}
+
+impl<T> RwLock<T> {
+    /// Creates a new instance of an `RwLock<T>` which is unlocked.
+    #[inline(always)]
+    pub const fn new(t: T) -> Self {
+        Self {
+            inner: axsync::RwLock::new(t),
+        }
+    }
+
+    /// Consumes this `RwLock`, returning the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> LockResult<T> {
+        Ok(self.inner.into_inner())
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Locks this `RwLock` with shared read access, blocking the current
+    /// task until it can be acquired.
+    #[inline(always)]
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        Ok(RwLockReadGuard {
+            inner: self.inner.read(),
+        })
+    }
+
+    /// Attempts to acquire this `RwLock` with shared read access, without
+    /// blocking.
+    #[inline(always)]
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        match self.inner.try_read() {
+            Some(inner) => Ok(RwLockReadGuard { inner }),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Locks this `RwLock` with exclusive write access, blocking the current
+    /// task until it can be acquired.
+    #[inline(always)]
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        Ok(RwLockWriteGuard {
+            inner: self.inner.write(),
+        })
+    }
+
+    /// Attempts to lock this `RwLock` with exclusive write access, without
+    /// blocking.
+    #[inline(always)]
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        match self.inner.try_write() {
+            Some(inner) => Ok(RwLockWriteGuard { inner }),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Determines whether the lock is poisoned, which is always `false`.
+    #[inline(always)]
+    pub fn is_poisoned(&self) -> bool {
+        false
+    }
+
+    /// Clears the poisoned state from a lock, which does nothing.
+    #[inline(always)]
+    pub fn clear_poison(&self) {}
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// No locking needs to take place, as the mutable borrow statically
+    /// guarantees no locks exist.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        Ok(self.inner.get_mut())
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(t: T) -> Self {
+        Self::new(t)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        match self.try_read() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(_) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &false).finish_non_exhaustive()
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}